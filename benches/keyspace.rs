@@ -0,0 +1,138 @@
+//Throughput as threads are added, with a single shard, which is how every command used to
+//serialize on one lock, and with more shards. The first table runs commands on the keyspace
+//alone, the second runs them through `process` as a connection does, with its ACL checks,
+//pause, stats, slowlog and latency monitor, without the network.
+//
+//  cargo bench --bench keyspace [seconds per run]
+
+use std::{sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Barrier}, thread, time::{Duration, Instant}};
+
+use redis_rust::{command::{execute_command, shards_for, Commands}, resp::{serializer::serializer, RespValue}, server::{tcp::process, value::{Config, Connection, ServerState}}, store::{eviction::xorshift, value::Shards}};
+
+const KEYS: usize = 100_000;
+//Requests each thread prepares up front, so the loop measures commands and not formatting
+const REQUESTS_PER_THREAD: usize = 4096;
+const SHARD_COUNTS: [usize; 3] = [1, 16, 64];
+
+///A request both parsed, for the keyspace, and serialized, for `process`
+struct Request {
+    command: Commands,
+    parsed: RespValue,
+    bytes: Vec<u8>,
+}
+
+fn request(command: Commands, parts: &[&[u8]]) -> Request {
+    let parsed = RespValue::Arrays(Some(parts.iter().map(|p| RespValue::BulkString(Some(p.to_vec()))).collect()));
+    let bytes = serializer(&parsed).unwrap();
+    Request { command, parsed, bytes }
+}
+
+fn run(shards: &Shards, request: &Request) {
+    let RespValue::Arrays(Some(args)) = &request.parsed else { unreachable!() };
+    let mut locked = shards.lock(&shards_for(request.command, args, shards));
+    execute_command(request.command, &request.parsed, &mut locked, 0).unwrap();
+}
+
+///GETs and SETs on random keys, one in five being a write, and an MGET of ten keys every
+///hundred commands
+fn workload(seed: u64) -> Vec<Request> {
+    let mut state = seed | 1;
+    let mut key = || format!("key:{}", xorshift(&mut state) as usize % KEYS).into_bytes();
+    (0..REQUESTS_PER_THREAD).map(|i| match i % 100 {
+        0 => {
+            let keys: Vec<Vec<u8>> = (0..10).map(|_| key()).collect();
+            let mut parts: Vec<&[u8]> = vec![b"MGET"];
+            parts.extend(keys.iter().map(Vec::as_slice));
+            request(Commands::MGET, &parts)
+        },
+        i if i % 5 == 0 => request(Commands::SET, &[b"SET", &key(), b"value"]),
+        _ => request(Commands::GET, &[b"GET", &key()])
+    }).collect()
+}
+
+///Commands per second run by `threads` threads during `duration`. Each thread gets its own
+///runner from `runner`, e.g. to connect its own client
+fn throughput<R: FnMut(&Request)>(threads: usize, duration: Duration, runner: impl Fn() -> R + Sync) -> f64 {
+    let stop = AtomicBool::new(false);
+    let done = AtomicU64::new(0);
+    let ready = Barrier::new(threads + 1);
+    let elapsed = thread::scope(|scope| {
+        for thread in 0..threads {
+            let (stop, done, ready, runner) = (&stop, &done, &ready, &runner);
+            scope.spawn(move || {
+                let requests = workload(thread as u64 + 1);
+                let mut run = runner();
+                let mut count = 0;
+                ready.wait();
+                while !stop.load(Ordering::Relaxed) {
+                    for request in &requests[count % REQUESTS_PER_THREAD..][..64] {
+                        run(request);
+                    }
+                    count += 64;
+                }
+                done.fetch_add(count as u64, Ordering::Relaxed);
+            });
+        }
+        ready.wait();
+        let started = Instant::now();
+        thread::sleep(duration);
+        stop.store(true, Ordering::Relaxed);
+        started.elapsed()
+    });
+    done.load(Ordering::Relaxed) as f64 / elapsed.as_secs_f64()
+}
+
+fn print_header(title: &str) {
+    println!();
+    println!("{}", title);
+    print!("{:>8}", "threads");
+    for count in SHARD_COUNTS {
+        print!("{:>14}", format!("{} shard{}", count, if count == 1 { "" } else { "s" }));
+    }
+    println!();
+}
+
+fn main() {
+    //cargo passes --bench, the only other argument is the length of each run
+    let seconds = std::env::args().skip(1).find_map(|arg| arg.parse::<f64>().ok()).unwrap_or(1.0);
+    let duration = Duration::from_secs_f64(seconds);
+    let cores = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut threads = vec![1];
+    while threads.last().is_some_and(|&t| t < cores.max(8)) {
+        threads.push(threads.last().unwrap() * 2);
+    }
+    println!("{} cores, {} keys, {:.1}s per run, commands per second", cores, KEYS, seconds);
+
+    let states: Vec<ServerState> = SHARD_COUNTS.iter().map(|&count| {
+        let state = ServerState::new(Config { keyspace_shards: count, ..Default::default() });
+        for i in 0..KEYS {
+            run(&state.keyspace, &request(Commands::SET, &[b"SET", format!("key:{}", i).as_bytes(), b"value"]));
+        }
+        state
+    }).collect();
+
+    print_header("keyspace");
+    for &count in &threads {
+        print!("{:>8}", count);
+        for state in &states {
+            print!("{:>14.0}", throughput(count, duration, || |request: &Request| run(&state.keyspace, request)));
+        }
+        println!();
+    }
+
+    print_header("process");
+    for &count in &threads {
+        print!("{:>8}", count);
+        for state in &states {
+            let connect = || {
+                let mut client = state.register_client(Connection::new(Box::new(std::io::sink()), "127.0.0.1:0".to_string()));
+                move |request: &Request| {
+                    process(&request.bytes, state, &mut client).unwrap();
+                    client.finish_command();
+                }
+            };
+            print!("{:>14.0}", throughput(count, duration, connect));
+        }
+        println!();
+    }
+}
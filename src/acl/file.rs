@@ -0,0 +1,74 @@
+use std::{collections::BTreeMap, fs};
+
+use crate::acl::{users::default_user, value::{Acl, User}};
+
+///Parses an ACL file, one `user <name> <rules...>` per line. Nothing is returned unless every
+///line is valid, so a broken file never leaves the server half configured
+pub fn parse_acl_file(contents: &str) -> Result<BTreeMap<Vec<u8>, User>, String> {
+    let mut users = BTreeMap::new();
+    for (number, line) in contents.lines().enumerate() {
+        let tokens: Vec<Vec<u8>> = line.split_whitespace().map(|t| t.as_bytes().to_vec()).collect();
+        let (name, rules) = match tokens.as_slice() {
+            [] => continue,
+            [keyword, name, rules @ ..] if keyword == b"user" => (name, rules),
+            _ => return Err(format!("line {}: should start with user keyword", number + 1))
+        };
+        if users.contains_key(name) {
+            return Err(format!("line {}: Duplicate user '{}' found", number + 1, String::from_utf8_lossy(name)));
+        }
+        let mut user = User::new(name);
+        if let Err((rule, e)) = user.apply_rules(rules) {
+            return Err(format!("line {}: Error in applying operation '{}': {}", number + 1, String::from_utf8_lossy(&rule), e.message()));
+        }
+        users.insert(name.clone(), user);
+    }
+    users.entry(b"default".to_vec()).or_insert_with(default_user);
+    Ok(users)
+}
+
+impl Acl {
+    pub fn to_acl_file(&self) -> String {
+        self.users.values().map(|u| u.describe() + "\n").collect()
+    }
+
+    pub fn load_file(&mut self, path: &str) -> Result<(), String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("Error loading ACLs, opening file '{}': {}", path, e))?;
+        self.users = parse_acl_file(&contents).map_err(|e| format!("{}:{}", path, e))?;
+        Ok(())
+    }
+
+    ///Writes to a temporary file first so a crash never leaves a truncated ACL file behind
+    pub fn save_file(&self, path: &str) -> Result<(), String> {
+        let tmp = format!("{}.tmp", path);
+        fs::write(&tmp, self.to_acl_file()).map_err(|e| format!("Opening temp ACL file for ACL SAVE: {}", e))?;
+        fs::rename(&tmp, path).map_err(|e| format!("Renaming ACL file for ACL SAVE: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_users_and_default() {
+        let users = parse_acl_file("user alice on >pw ~cache:* +get\n\nuser bob off\n").unwrap();
+        assert_eq!(users.len(), 3);
+        assert!(users[b"alice".as_slice()].enabled);
+        assert!(users.contains_key(b"default".as_slice()));
+    }
+
+    #[test]
+    fn parse_rejects_bad_lines() {
+        assert!(parse_acl_file("alice on\n").is_err());
+        assert!(parse_acl_file("user alice +nosuchcommand\n").is_err());
+        assert!(parse_acl_file("user alice on\nuser alice off\n").is_err());
+    }
+
+    #[test]
+    fn saved_file_loads_back() {
+        let mut acl = Acl::new();
+        acl.set_user(b"alice", &[b"on".to_vec(), b">pw".to_vec(), b"%R~c:*".to_vec(), b"+@read".to_vec(), b"(+set ~w:*)".to_vec()]).unwrap();
+        let users = parse_acl_file(&acl.to_acl_file()).unwrap();
+        assert_eq!(users, acl.users);
+    }
+}
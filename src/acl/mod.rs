@@ -0,0 +1,7 @@
+pub mod value;
+pub mod rules;
+pub mod users;
+pub mod file;
+pub mod sha256;
+
+pub use value::*;
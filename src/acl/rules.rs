@@ -0,0 +1,501 @@
+use crate::{acl::{sha256::sha256_hex, value::{AclDenial, AclError, KeyPattern, Selector, User}}, command::{spec::CATEGORIES, Commands, KeyAccess}, glob::string_match, resp::RespValue};
+
+impl AclError {
+    pub fn message(&self) -> &'static str {
+        match self {
+            AclError::Syntax => "Syntax error",
+            AclError::UnknownCommand => "Unknown command or category name in ACL",
+            AclError::BadPasswordHash => "The password hash must be exactly 64 characters and contain only lowercase hexadecimal characters",
+            AclError::NoSuchPassword => "The password you are trying to remove from the user does not exist",
+            AclError::PatternAfterAll => "Adding a pattern after the * pattern (or the 'allkeys' flag) is not valid and does nothing.",
+            AclError::SelectorRule => "Selector rules can only contain command, key and channel rules",
+        }
+    }
+}
+
+///Joins arguments of a selector split by whitespace, e.g. `(+get` `~key*)`, back into one rule
+pub fn merge_selector_args(args: &[Vec<u8>]) -> Result<Vec<Vec<u8>>, AclError> {
+    let mut merged = Vec::new();
+    let mut open: Option<Vec<u8>> = None;
+    for arg in args {
+        match open.as_mut() {
+            Some(selector) => {
+                selector.push(b' ');
+                selector.extend(arg);
+            },
+            None if arg.first() == Some(&b'(') => open = Some(arg.clone()),
+            None => merged.push(arg.clone())
+        }
+        if open.as_ref().is_some_and(|s| s.ends_with(b")")) {
+            merged.extend(open.take());
+        }
+    }
+    match open {
+        Some(_) => Err(AclError::Syntax),
+        None => Ok(merged)
+    }
+}
+
+impl Selector {
+    pub fn new() -> Self {
+        Self { command_rules: vec!["-@all".to_string()], ..Default::default() }
+    }
+
+    fn allow_command(&mut self, command: Commands, allow: bool) {
+        if allow {
+            self.commands.insert(command);
+        } else {
+            self.commands.remove(&command);
+        }
+        self.allowed_subcommands.remove(&command);
+        self.denied_subcommands.remove(&command);
+    }
+
+    fn allow_subcommand(&mut self, command: Commands, subcommand: Vec<u8>, allow: bool) {
+        if self.commands.contains(&command) {
+            let denied = self.denied_subcommands.entry(command).or_default();
+            if allow {
+                denied.remove(&subcommand);
+            } else {
+                denied.insert(subcommand);
+            }
+        } else {
+            let allowed = self.allowed_subcommands.entry(command).or_default();
+            if allow {
+                allowed.insert(subcommand);
+            } else {
+                allowed.remove(&subcommand);
+            }
+        }
+    }
+
+    fn set_all_commands(&mut self, allow: bool) {
+        self.commands.clear();
+        self.allowed_subcommands.clear();
+        self.denied_subcommands.clear();
+        if allow {
+            self.commands.extend(Commands::ALL);
+        }
+        self.command_rules = vec![if allow { "+@all" } else { "-@all" }.to_string()];
+    }
+
+    fn add_key_pattern(&mut self, pattern: &[u8], read: bool, write: bool) -> Result<(), AclError> {
+        if self.keys.iter().any(|k| k.pattern == b"*" && k.read && k.write) {
+            return Err(AclError::PatternAfterAll);
+        }
+        if pattern == b"*" && read && write {
+            self.keys.clear();
+        }
+        self.keys.push(KeyPattern { pattern: pattern.to_vec(), read, write });
+        Ok(())
+    }
+
+    fn add_channel_pattern(&mut self, pattern: &[u8]) -> Result<(), AclError> {
+        if self.channels.iter().any(|c| c == b"*") {
+            return Err(AclError::PatternAfterAll);
+        }
+        if pattern == b"*" {
+            self.channels.clear();
+        }
+        self.channels.push(pattern.to_vec());
+        Ok(())
+    }
+
+    ///Applies a command, key or channel rule. Returns false when the rule is not one of those
+    pub fn apply_rule(&mut self, rule: &[u8]) -> Result<bool, AclError> {
+        let lower = rule.to_ascii_lowercase();
+        match lower.as_slice() {
+            b"allcommands" | b"+@all" => self.set_all_commands(true),
+            b"nocommands" | b"-@all" => self.set_all_commands(false),
+            b"allkeys" => self.add_key_pattern(b"*", true, true)?,
+            b"resetkeys" => self.keys.clear(),
+            b"allchannels" => self.add_channel_pattern(b"*")?,
+            b"resetchannels" => self.channels.clear(),
+            [b'~', pattern @ ..] => self.add_key_pattern(pattern, true, true)?,
+            [b'%', ..] => {
+                //%R~, %W~ or %RW~ followed by the pattern, the pattern keeps its case
+                let tilde = rule.iter().position(|&b| b == b'~').ok_or(AclError::Syntax)?;
+                let flags = &lower[1..tilde];
+                if flags.is_empty() || flags.iter().any(|f| *f != b'r' && *f != b'w') {
+                    return Err(AclError::Syntax);
+                }
+                self.add_key_pattern(&rule[tilde + 1..], flags.contains(&b'r'), flags.contains(&b'w'))?;
+            },
+            [b'&', ..] => self.add_channel_pattern(&rule[1..])?,
+            [sign @ (b'+' | b'-'), b'@', category @ ..] => {
+                if category != b"all" && !CATEGORIES.iter().any(|c| c.as_bytes() == category) {
+                    return Err(AclError::UnknownCommand);
+                }
+                for command in Commands::ALL {
+                    if command.categories().iter().any(|c| c.as_bytes() == category) {
+                        self.allow_command(command, *sign == b'+');
+                    }
+                }
+                self.command_rules.push(String::from_utf8_lossy(&lower).into_owned());
+            },
+            [sign @ (b'+' | b'-'), name @ ..] => {
+                let allow = *sign == b'+';
+                match name.iter().position(|&b| b == b'|') {
+                    Some(bar) => {
+                        let command = Commands::from_bytes(&name[..bar]).ok_or(AclError::UnknownCommand)?;
+                        if bar + 1 == name.len() || name[bar + 1..].contains(&b'|') {
+                            return Err(AclError::Syntax);
+                        }
+                        self.allow_subcommand(command, name[bar + 1..].to_vec(), allow);
+                    },
+                    None => {
+                        let command = Commands::from_bytes(name).ok_or(AclError::UnknownCommand)?;
+                        self.allow_command(command, allow);
+                    }
+                }
+                self.command_rules.push(String::from_utf8_lossy(&lower).into_owned());
+            },
+            _ => return Ok(false)
+        }
+        Ok(true)
+    }
+
+    pub fn allows_command(&self, command: Commands, subcommand: Option<&[u8]>) -> bool {
+        let subcommand = subcommand.map(|s| s.to_ascii_lowercase());
+        if self.commands.contains(&command) {
+            match (&subcommand, self.denied_subcommands.get(&command)) {
+                (Some(sub), Some(denied)) => !denied.contains(sub),
+                _ => true
+            }
+        } else {
+            match (&subcommand, self.allowed_subcommands.get(&command)) {
+                (Some(sub), Some(allowed)) => allowed.contains(sub),
+                _ => false
+            }
+        }
+    }
+
+    pub fn allows_key(&self, key: &[u8], access: KeyAccess) -> bool {
+        let (read, write) = match access {
+            KeyAccess::Read => (true, false),
+            KeyAccess::Write => (false, true),
+            KeyAccess::ReadWrite => (true, true)
+        };
+        self.keys.iter().any(|k| (k.read || !read) && (k.write || !write) && string_match(&k.pattern, key, false))
+    }
+
+    pub fn allows_channel(&self, channel: &[u8]) -> bool {
+        self.channels.iter().any(|c| string_match(c, channel, false))
+    }
+
+    ///Checks a full request, command name included, against the selector
+    pub fn check(&self, command: Commands, args: &[RespValue]) -> Result<(), AclDenial> {
+        let subcommand = args.get(1).and_then(|a| a.as_bytes());
+        if !self.allows_command(command, subcommand) {
+            return Err(AclDenial::Command);
+        }
+        //Each key needs the permissions of what the command does with it, e.g. COPY only reads
+        //its source
+        for (position, key) in command.keys(args).into_iter().enumerate() {
+            let Some(key) = key.as_bytes() else { continue };
+            if !self.allows_key(key, command.key_access(args, position)) {
+                return Err(AclDenial::Key(key.to_vec()));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn describe_keys(&self) -> String {
+        self.keys.iter().map(|k| {
+            let prefix = match (k.read, k.write) {
+                (true, true) => "~".to_string(),
+                (true, false) => "%R~".to_string(),
+                _ => "%W~".to_string()
+            };
+            format!("{}{}", prefix, String::from_utf8_lossy(&k.pattern))
+        }).collect::<Vec<_>>().join(" ")
+    }
+
+    pub fn describe_channels(&self) -> String {
+        self.channels.iter().map(|c| format!("&{}", String::from_utf8_lossy(c))).collect::<Vec<_>>().join(" ")
+    }
+
+    pub fn describe_commands(&self) -> String {
+        self.command_rules.join(" ")
+    }
+
+    ///Rules recreating the selector, in the form ACL LIST and the ACL file use
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.keys.is_empty() {
+            parts.push(self.describe_keys());
+        }
+        if self.channels.is_empty() {
+            parts.push("resetchannels".to_string());
+        } else {
+            parts.push(self.describe_channels());
+        }
+        parts.push(self.describe_commands());
+        parts.join(" ")
+    }
+}
+
+fn is_password_hash(hash: &[u8]) -> bool {
+    hash.len() == 64 && hash.iter().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(b))
+}
+
+impl User {
+    ///A new user is disabled, has no password and cannot run anything
+    pub fn new(name: &[u8]) -> Self {
+        Self {
+            name: name.to_vec(),
+            enabled: false,
+            nopass: false,
+            passwords: Vec::new(),
+            root: Selector::new(),
+            selectors: Vec::new(),
+        }
+    }
+
+    fn add_password_hash(&mut self, hash: String) {
+        if !self.passwords.contains(&hash) {
+            self.passwords.push(hash);
+        }
+        self.nopass = false;
+    }
+
+    fn remove_password_hash(&mut self, hash: &str) -> Result<(), AclError> {
+        let before = self.passwords.len();
+        self.passwords.retain(|p| p != hash);
+        if self.passwords.len() == before {
+            return Err(AclError::NoSuchPassword);
+        }
+        Ok(())
+    }
+
+    pub fn apply_rule(&mut self, rule: &[u8]) -> Result<(), AclError> {
+        match rule.to_ascii_lowercase().as_slice() {
+            b"on" => self.enabled = true,
+            b"off" => self.enabled = false,
+            b"nopass" => {
+                self.nopass = true;
+                self.passwords.clear();
+            },
+            b"resetpass" => {
+                self.nopass = false;
+                self.passwords.clear();
+            },
+            b"reset" => {
+                *self = User::new(&self.name);
+            },
+            b"clearselectors" => self.selectors.clear(),
+            _ => match rule {
+                [b'>', password @ ..] => self.add_password_hash(sha256_hex(password)),
+                [b'<', password @ ..] => self.remove_password_hash(&sha256_hex(password))?,
+                [b'#', hash @ ..] => {
+                    if !is_password_hash(hash) {
+                        return Err(AclError::BadPasswordHash);
+                    }
+                    self.add_password_hash(String::from_utf8_lossy(hash).into_owned());
+                },
+                [b'!', hash @ ..] => {
+                    if !is_password_hash(hash) {
+                        return Err(AclError::BadPasswordHash);
+                    }
+                    self.remove_password_hash(&String::from_utf8_lossy(hash))?;
+                },
+                [b'(', inner @ .., b')'] => {
+                    let mut selector = Selector::new();
+                    for selector_rule in inner.split(|b| *b == b' ').filter(|r| !r.is_empty()) {
+                        if !selector.apply_rule(selector_rule)? {
+                            return Err(AclError::SelectorRule);
+                        }
+                    }
+                    self.selectors.push(selector);
+                },
+                _ => {
+                    if !self.root.apply_rule(rule)? {
+                        return Err(AclError::Syntax);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///Applies rules in order, leaving the user untouched if any of them fails. On error returns
+    ///the offending rule
+    pub fn apply_rules(&mut self, rules: &[Vec<u8>]) -> Result<(), (Vec<u8>, AclError)> {
+        let mut updated = self.clone();
+        let rules = merge_selector_args(rules).map_err(|e| (rules.last().cloned().unwrap_or_default(), e))?;
+        for rule in rules {
+            updated.apply_rule(&rule).map_err(|e| (rule.clone(), e))?;
+        }
+        *self = updated;
+        Ok(())
+    }
+
+    pub fn check(&self, command: Commands, args: &[RespValue]) -> Result<(), AclDenial> {
+        let denial = match self.root.check(command, args) {
+            Ok(()) => return Ok(()),
+            Err(denial) => denial
+        };
+        if self.selectors.iter().any(|s| s.check(command, args).is_ok()) {
+            return Ok(());
+        }
+        Err(denial)
+    }
+
+    pub fn check_password(&self, password: &[u8]) -> bool {
+        if self.nopass {
+            return true;
+        }
+        let hash = sha256_hex(password);
+        //Compared without short circuiting so the time taken does not depend on the match
+        self.passwords.iter().fold(false, |found, p| found | (p.as_bytes() == hash.as_bytes()))
+    }
+
+    pub fn flags(&self) -> Vec<&'static str> {
+        let mut flags = vec![if self.enabled { "on" } else { "off" }];
+        if self.nopass {
+            flags.push("nopass");
+        }
+        flags
+    }
+
+    ///The user as a line of ACL LIST or of the ACL file
+    pub fn describe(&self) -> String {
+        let mut parts = vec![format!("user {}", String::from_utf8_lossy(&self.name))];
+        parts.extend(self.flags().iter().map(|f| f.to_string()));
+        parts.extend(self.passwords.iter().map(|p| format!("#{}", p)));
+        parts.push(self.root.describe());
+        parts.extend(self.selectors.iter().map(|s| format!("({})", s.describe())));
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::bulk;
+
+    fn rules(v: &[&str]) -> Vec<Vec<u8>> {
+        v.iter().map(|s| s.as_bytes().to_vec()).collect()
+    }
+
+    fn user(v: &[&str]) -> User {
+        let mut user = User::new(b"alice");
+        user.apply_rules(&rules(v)).unwrap();
+        user
+    }
+
+    #[test]
+    fn new_user_can_do_nothing() {
+        let user = User::new(b"alice");
+        assert!(!user.enabled);
+        assert_eq!(user.check(Commands::PING, &[bulk("PING")]), Err(AclDenial::Command));
+    }
+
+    #[test]
+    fn categories_and_commands() {
+        let reader = user(&["on", "+@read", "~*"]);
+        assert!(reader.check(Commands::GET, &[bulk("GET"), bulk("k")]).is_ok());
+        assert_eq!(reader.check(Commands::SET, &[bulk("SET"), bulk("k"), bulk("v")]), Err(AclDenial::Command));
+
+        let safe = user(&["+@all", "-@dangerous", "allkeys"]);
+        assert!(safe.check(Commands::SET, &[bulk("SET"), bulk("k"), bulk("v")]).is_ok());
+        assert_eq!(safe.check(Commands::ACL, &[bulk("ACL"), bulk("LIST")]), Err(AclDenial::Command));
+    }
+
+    #[test]
+    fn subcommand_rules() {
+        let whoami_only = user(&["+acl|whoami"]);
+        assert!(whoami_only.check(Commands::ACL, &[bulk("ACL"), bulk("WHOAMI")]).is_ok());
+        assert!(whoami_only.check(Commands::ACL, &[bulk("ACL"), bulk("LIST")]).is_err());
+
+        let no_deluser = user(&["+acl", "-acl|deluser"]);
+        assert!(no_deluser.check(Commands::ACL, &[bulk("ACL"), bulk("LIST")]).is_ok());
+        assert!(no_deluser.check(Commands::ACL, &[bulk("ACL"), bulk("DELUSER"), bulk("x")]).is_err());
+    }
+
+    #[test]
+    fn key_patterns_by_access() {
+        let user = user(&["+get", "+set", "%R~cache:*", "~own:*"]);
+        assert!(user.check(Commands::GET, &[bulk("GET"), bulk("cache:1")]).is_ok());
+        assert_eq!(
+            user.check(Commands::SET, &[bulk("SET"), bulk("cache:1"), bulk("v")]),
+            Err(AclDenial::Key(b"cache:1".to_vec()))
+        );
+        assert!(user.check(Commands::SET, &[bulk("SET"), bulk("own:1"), bulk("v")]).is_ok());
+        assert!(user.check(Commands::GET, &[bulk("GET"), bulk("other")]).is_err());
+    }
+
+    #[test]
+    fn each_key_is_checked_for_its_own_access() {
+        let copy = |src: &str, dst: &str| [bulk("COPY"), bulk(src), bulk(dst)];
+        //Sources of a write command only need read access, destinations only write access
+        let reader = user(&["+copy", "+bitop", "%R~src*", "%W~dst*"]);
+        assert!(reader.check(Commands::COPY, &copy("src1", "dst1")).is_ok());
+        assert!(reader.check(Commands::BITOP, &[bulk("BITOP"), bulk("OR"), bulk("dst"), bulk("src1"), bulk("src2")]).is_ok());
+        assert_eq!(reader.check(Commands::COPY, &copy("dst1", "dst2")), Err(AclDenial::Key(b"dst1".to_vec())));
+        assert_eq!(reader.check(Commands::COPY, &copy("src1", "src2")), Err(AclDenial::Key(b"src2".to_vec())));
+        //RENAME deletes its source, so it needs both
+        assert_eq!(reader.check(Commands::RENAME, &[bulk("RENAME"), bulk("src1"), bulk("dst1")]), Err(AclDenial::Command));
+        let renamer = user(&["+rename", "%R~src*", "%W~dst*"]);
+        assert_eq!(renamer.check(Commands::RENAME, &[bulk("RENAME"), bulk("src1"), bulk("dst1")]), Err(AclDenial::Key(b"src1".to_vec())));
+        let writer = user(&["+pfmerge", "%W~*"]);
+        assert_eq!(writer.check(Commands::PFMERGE, &[bulk("PFMERGE"), bulk("dst"), bulk("src")]), Err(AclDenial::Key(b"dst".to_vec())));
+    }
+
+    #[test]
+    fn channel_patterns_are_kept_and_matched() {
+        let mut user = user(&["&news:*", "&alerts"]);
+        assert!(user.root.allows_channel(b"news:sport") && user.root.allows_channel(b"alerts"));
+        assert!(!user.root.allows_channel(b"weather"));
+        assert_eq!(user.root.describe_channels(), "&news:* &alerts");
+        user.apply_rules(&rules(&["allchannels"])).unwrap();
+        assert_eq!(user.root.describe_channels(), "&*");
+        assert_eq!(user.apply_rules(&rules(&["&more"])), Err((b"&more".to_vec(), AclError::PatternAfterAll)));
+        user.apply_rules(&rules(&["resetchannels"])).unwrap();
+        assert!(!user.root.allows_channel(b"news:sport"));
+    }
+
+    #[test]
+    fn selectors_extend_root() {
+        let user = user(&["+get", "~a*", "(+set", "~b*)"]);
+        assert_eq!(user.selectors.len(), 1);
+        assert!(user.check(Commands::SET, &[bulk("SET"), bulk("b1"), bulk("v")]).is_ok());
+        assert!(user.check(Commands::SET, &[bulk("SET"), bulk("a1"), bulk("v")]).is_err());
+    }
+
+    #[test]
+    fn passwords() {
+        let mut user = user(&["on", ">secret", ">other"]);
+        assert!(user.check_password(b"secret"));
+        assert!(!user.check_password(b"nope"));
+        user.apply_rules(&rules(&["<other"])).unwrap();
+        assert!(!user.check_password(b"other"));
+        assert!(user.apply_rules(&rules(&["<other"])).is_err());
+        user.apply_rules(&rules(&["nopass"])).unwrap();
+        assert!(user.check_password(b"anything"));
+    }
+
+    #[test]
+    fn failed_rules_leave_user_untouched() {
+        let mut user = user(&["on"]);
+        let err = user.apply_rules(&rules(&["+get", "+nosuchcommand"])).unwrap_err();
+        assert_eq!(err, (b"+nosuchcommand".to_vec(), AclError::UnknownCommand));
+        assert!(user.check(Commands::GET, &[bulk("GET"), bulk("k")]).is_err());
+    }
+
+    #[test]
+    fn invalid_rules() {
+        let mut user = User::new(b"alice");
+        assert!(user.apply_rules(&rules(&["#abc"])).is_err());
+        assert!(user.apply_rules(&rules(&["bogus"])).is_err());
+        assert!(user.apply_rules(&rules(&["(on)"])).is_err());
+        assert!(user.apply_rules(&rules(&["(+get"])).is_err());
+        assert!(user.apply_rules(&rules(&["~*", "~foo"])).is_err());
+    }
+
+    #[test]
+    fn describe_user() {
+        let user = user(&["on", "nopass", "%R~cache:*", "&news", "+@read", "-get", "(+set ~b*)"]);
+        assert_eq!(user.describe(), "user alice on nopass %R~cache:* &news -@all +@read -get (~b* resetchannels -@all +set)");
+    }
+}
@@ -0,0 +1,162 @@
+use std::{collections::{BTreeMap, VecDeque}, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::{acl::value::{Acl, AclDenial, AclError, AclLogEntry, User}, command::Commands, resp::RespValue};
+
+//Denials of the same kind within this window are counted in a single log entry
+const LOG_GROUPING_MS: u128 = 60_000;
+
+pub fn now_ms() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0)
+}
+
+///The user every connection starts as: enabled, no password and full access
+pub fn default_user() -> User {
+    let mut user = User::new(b"default");
+    for rule in [&b"on"[..], b"nopass", b"~*", b"&*", b"+@all"] {
+        user.apply_rule(rule).unwrap();
+    }
+    user
+}
+
+impl Default for Acl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Acl {
+    pub fn new() -> Self {
+        let mut users = BTreeMap::new();
+        users.insert(b"default".to_vec(), default_user());
+        Self { users, log: VecDeque::new(), next_log_id: 0, log_max_len: 128 }
+    }
+
+    ///requirepass is the password of the default user, none making it passwordless again
+    pub fn set_requirepass(&mut self, password: Option<&[u8]>) {
+        let user = self.users.entry(b"default".to_vec()).or_insert_with(default_user);
+        let mut rule = b">".to_vec();
+        let rules = match password {
+            Some(password) => {
+                rule.extend(password);
+                vec![b"resetpass".to_vec(), rule]
+            },
+            None => vec![b"nopass".to_vec()]
+        };
+        user.apply_rules(&rules).unwrap();
+    }
+
+    pub fn user(&self, name: &[u8]) -> Option<&User> {
+        self.users.get(name)
+    }
+
+    ///Creates the user if needed and applies the rules, all or nothing
+    pub fn set_user(&mut self, name: &[u8], rules: &[Vec<u8>]) -> Result<(), (Vec<u8>, AclError)> {
+        let mut user = self.users.get(name).cloned().unwrap_or_else(|| User::new(name));
+        user.apply_rules(rules)?;
+        self.users.insert(name.to_vec(), user);
+        Ok(())
+    }
+
+    pub fn del_user(&mut self, name: &[u8]) -> bool {
+        self.users.remove(name).is_some()
+    }
+
+    pub fn authenticate(&self, username: &[u8], password: &[u8]) -> bool {
+        match self.users.get(username) {
+            Some(user) => user.enabled && user.check_password(password),
+            None => false
+        }
+    }
+
+    ///A user that no longer exists can run nothing
+    pub fn check(&self, username: &[u8], command: Commands, args: &[RespValue]) -> Result<(), AclDenial> {
+        match self.users.get(username) {
+            Some(user) => user.check(command, args),
+            None => Err(AclDenial::Command)
+        }
+    }
+
+    pub fn add_log_entry(&mut self, reason: &'static str, object: &[u8], username: &[u8], client_info: String) {
+        let now = now_ms();
+        let existing = self.log.iter().position(|e| {
+            e.reason == reason && e.object == object && e.username == username && now - e.updated < LOG_GROUPING_MS
+        });
+        if let Some(mut entry) = existing.and_then(|i| self.log.remove(i)) {
+            entry.count += 1;
+            entry.updated = now;
+            entry.client_info = client_info;
+            self.log.push_front(entry);
+            return;
+        }
+
+        self.log.push_front(AclLogEntry {
+            count: 1,
+            reason,
+            object: object.to_vec(),
+            username: username.to_vec(),
+            client_info,
+            entry_id: self.next_log_id,
+            created: now,
+            updated: now,
+        });
+        self.next_log_id += 1;
+        self.log.truncate(self.log_max_len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::bulk;
+
+    #[test]
+    fn default_user_allows_everything() {
+        let acl = Acl::new();
+        assert!(acl.authenticate(b"default", b"whatever"));
+        assert!(acl.check(b"default", Commands::SET, &[bulk("SET"), bulk("k"), bulk("v")]).is_ok());
+    }
+
+    #[test]
+    fn requirepass_protects_default_user() {
+        let mut acl = Acl::new();
+        acl.set_requirepass(Some(b"secret"));
+        assert!(acl.authenticate(b"default", b"secret"));
+        assert!(!acl.authenticate(b"default", b"whatever"));
+        acl.set_requirepass(None);
+        assert!(acl.authenticate(b"default", b"whatever"));
+    }
+
+    #[test]
+    fn disabled_and_missing_users_cannot_authenticate() {
+        let mut acl = Acl::new();
+        acl.set_user(b"bob", &[b">pw".to_vec()]).unwrap();
+        assert!(!acl.authenticate(b"bob", b"pw"));
+        acl.set_user(b"bob", &[b"on".to_vec()]).unwrap();
+        assert!(acl.authenticate(b"bob", b"pw"));
+        assert!(!acl.authenticate(b"carol", b"pw"));
+    }
+
+    #[test]
+    fn log_groups_repeated_denials() {
+        let mut acl = Acl::new();
+        acl.add_log_entry("command", b"get", b"bob", "id=1".to_string());
+        acl.add_log_entry("key", b"k", b"bob", "id=1".to_string());
+        acl.add_log_entry("command", b"get", b"bob", "id=2".to_string());
+
+        assert_eq!(acl.log.len(), 2);
+        assert_eq!(acl.log[0].count, 2);
+        assert_eq!(acl.log[0].entry_id, 0);
+        assert_eq!(acl.log[0].client_info, "id=2");
+    }
+
+    #[test]
+    fn log_is_bounded() {
+        let mut acl = Acl::new();
+        acl.log_max_len = 2;
+        for key in ["a", "b", "c"] {
+            acl.add_log_entry("key", key.as_bytes(), b"bob", String::new());
+        }
+        assert_eq!(acl.log.len(), 2);
+        assert_eq!(acl.log[0].object, b"c".to_vec());
+    }
+}
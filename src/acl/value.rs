@@ -0,0 +1,76 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use crate::command::Commands;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyPattern {
+    pub pattern: Vec<u8>,
+    pub read: bool,
+    pub write: bool,
+}
+
+///A set of command, key and channel permissions. Every user has a root selector and may have
+///more, a command is allowed when any of them allows it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Selector {
+    pub commands: HashSet<Commands>,
+    //Subcommands allowed for commands that are not allowed as a whole
+    pub allowed_subcommands: HashMap<Commands, HashSet<Vec<u8>>>,
+    //Subcommands denied for commands that are otherwise allowed
+    pub denied_subcommands: HashMap<Commands, HashSet<Vec<u8>>>,
+    //Command rules as applied since the last +@all or -@all, used to describe the selector
+    pub command_rules: Vec<String>,
+    pub keys: Vec<KeyPattern>,
+    //Kept so users and ACL files round-trip, nothing enforces them until there is Pub/Sub
+    pub channels: Vec<Vec<u8>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct User {
+    pub name: Vec<u8>,
+    pub enabled: bool,
+    pub nopass: bool,
+    //Lowercase hex SHA-256 of each accepted password
+    pub passwords: Vec<String>,
+    pub root: Selector,
+    pub selectors: Vec<Selector>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AclError {
+    Syntax,
+    UnknownCommand,
+    BadPasswordHash,
+    NoSuchPassword,
+    PatternAfterAll,
+    SelectorRule,
+}
+
+///Why a command was refused
+#[derive(Debug, Clone, PartialEq)]
+pub enum AclDenial {
+    Command,
+    Key(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AclLogEntry {
+    pub count: u64,
+    //"command", "key" or "auth"
+    pub reason: &'static str,
+    pub object: Vec<u8>,
+    pub username: Vec<u8>,
+    pub client_info: String,
+    pub entry_id: u64,
+    //Milliseconds since the epoch
+    pub created: u128,
+    pub updated: u128,
+}
+
+pub struct Acl {
+    pub users: BTreeMap<Vec<u8>, User>,
+    //Newest entry first
+    pub log: VecDeque<AclLogEntry>,
+    pub next_log_id: u64,
+    pub log_max_len: usize,
+}
@@ -0,0 +1,252 @@
+use crate::{command::{string::{arg_bytes, integer_arg}, CommandError}, resp::RespValue, store::{bitmap::MAX_BIT_OFFSET, string::parse_integer, value::{BitOp, BitUnit, BitfieldOp, BitfieldOverflow, BitfieldType, Store}}};
+
+fn error(message: &str) -> CommandError {
+    CommandError::Custom(message.to_string())
+}
+
+///Bit offset of SETBIT and GETBIT, which must fit in a string
+fn bit_offset(arg: &RespValue) -> Result<u64, CommandError> {
+    parse_integer(arg_bytes(arg)?)
+        .filter(|&offset| offset >= 0 && (offset as u64) < MAX_BIT_OFFSET)
+        .map(|offset| offset as u64)
+        .ok_or_else(|| error("ERR bit offset is not an integer or out of range"))
+}
+
+fn bit_unit(arg: &RespValue) -> Result<BitUnit, CommandError> {
+    match arg_bytes(arg)?.to_ascii_uppercase().as_slice() {
+        b"BYTE" => Ok(BitUnit::Byte),
+        b"BIT" => Ok(BitUnit::Bit),
+        _ => Err(CommandError::Syntax)
+    }
+}
+
+pub fn handle_setbit(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, offset, bit] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let offset = bit_offset(offset)?;
+    let bit = match arg_bytes(bit)? {
+        b"0" => false,
+        b"1" => true,
+        _ => return Err(error("ERR bit is not an integer or out of range"))
+    };
+    Ok(RespValue::Integer(store.setbit(arg_bytes(key)?, offset, bit)? as i64))
+}
+
+pub fn handle_getbit(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, offset] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let offset = bit_offset(offset)?;
+    Ok(RespValue::Integer(store.getbit(arg_bytes(key)?, offset)? as i64))
+}
+
+///BITCOUNT key [start end [BYTE|BIT]]
+pub fn handle_bitcount(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let range = match args {
+        [] => return Err(CommandError::WrongArity),
+        [_] => None,
+        [_, start, end, unit @ ..] if unit.len() <= 1 => {
+            let unit = unit.first().map_or(Ok(BitUnit::Byte), bit_unit)?;
+            Some((integer_arg(start)?, integer_arg(end)?, unit))
+        },
+        _ => return Err(CommandError::Syntax)
+    };
+    Ok(RespValue::Integer(store.bitcount(arg_bytes(&args[0])?, range)? as i64))
+}
+
+///BITPOS key bit [start [end [BYTE|BIT]]]
+pub fn handle_bitpos(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, bit, range @ ..] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let bit = match integer_arg(bit)? {
+        0 => false,
+        1 => true,
+        _ => return Err(error("ERR The bit argument must be 1 or 0."))
+    };
+    let range = match range {
+        [] => None,
+        [start] => Some((integer_arg(start)?, None, BitUnit::Byte)),
+        [start, end, unit @ ..] if unit.len() <= 1 => {
+            let unit = unit.first().map_or(Ok(BitUnit::Byte), bit_unit)?;
+            Some((integer_arg(start)?, Some(integer_arg(end)?), unit))
+        },
+        _ => return Err(CommandError::Syntax)
+    };
+    Ok(RespValue::Integer(store.bitpos(arg_bytes(key)?, bit, range)?))
+}
+
+///BITOP operation destkey key [key ...]
+pub fn handle_bitop(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [op, destination, sources @ ..] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    if sources.is_empty() {
+        return Err(CommandError::WrongArity);
+    }
+    let name = arg_bytes(op)?.to_ascii_uppercase();
+    let op = match name.as_slice() {
+        b"AND" => BitOp::And,
+        b"OR" => BitOp::Or,
+        b"XOR" => BitOp::Xor,
+        b"NOT" => BitOp::Not,
+        b"DIFF" => BitOp::Diff,
+        b"ANDOR" => BitOp::AndOr,
+        b"ONE" => BitOp::One,
+        _ => return Err(CommandError::Syntax)
+    };
+    match op {
+        BitOp::Not if sources.len() != 1 => return Err(error("ERR BITOP NOT must be called with a single source key.")),
+        BitOp::Diff | BitOp::AndOr if sources.len() < 2 => return Err(CommandError::Custom(format!(
+            "ERR BITOP {} must be called with at least two source keys.", String::from_utf8_lossy(&name)
+        ))),
+        _ => {}
+    }
+    let sources = sources.iter().map(arg_bytes).collect::<Result<Vec<_>, _>>()?;
+    Ok(RespValue::Integer(store.bitop(op, arg_bytes(destination)?, &sources)? as i64))
+}
+
+///Field type such as i16 or u8. Unsigned fields stop at 63 bits, so every value fits in the
+///signed integers replies carry
+fn bitfield_type(arg: &RespValue) -> Result<BitfieldType, CommandError> {
+    let invalid = || error("ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.");
+    let (signed, bits) = match arg_bytes(arg)? {
+        [b'i', bits @ ..] => (true, bits),
+        [b'u', bits @ ..] => (false, bits),
+        _ => return Err(invalid())
+    };
+    match parse_integer(bits) {
+        Some(bits) if bits >= 1 && bits <= if signed { 64 } else { 63 } => Ok(BitfieldType { signed, bits: bits as u32 }),
+        _ => Err(invalid())
+    }
+}
+
+///Offset of a field in bits, `#N` meaning the Nth field of its width
+fn bitfield_offset(arg: &RespValue, field: BitfieldType) -> Result<u64, CommandError> {
+    let arg = arg_bytes(arg)?;
+    let offset = match arg.strip_prefix(b"#") {
+        Some(index) => parse_integer(index).and_then(|index| index.checked_mul(field.bits as i64)),
+        None => parse_integer(arg)
+    };
+    offset.filter(|&offset| offset >= 0 && offset as u64 + field.bits as u64 <= MAX_BIT_OFFSET)
+        .map(|offset| offset as u64)
+        .ok_or_else(|| error("ERR bit offset is not an integer or out of range"))
+}
+
+///BITFIELD, or BITFIELD_RO when `read_only` is set which only takes GET subcommands
+pub fn handle_bitfield(args: &[RespValue], store: &mut Store, read_only: bool) -> Result<RespValue, CommandError> {
+    let [key, subcommands @ ..] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let mut ops = Vec::new();
+    //OVERFLOW applies to the SET and INCRBY after it
+    let mut overflow = BitfieldOverflow::Wrap;
+    let mut subcommands = subcommands.iter();
+    while let Some(subcommand) = subcommands.next() {
+        let name = arg_bytes(subcommand)?.to_ascii_uppercase();
+        let arity = match name.as_slice() {
+            b"GET" => 2,
+            b"SET" | b"INCRBY" => 3,
+            b"OVERFLOW" => 1,
+            _ => return Err(CommandError::Syntax)
+        };
+        let operands: Vec<&RespValue> = subcommands.by_ref().take(arity).collect();
+        if operands.len() < arity {
+            return Err(CommandError::Syntax);
+        }
+        if name == b"OVERFLOW" {
+            overflow = match arg_bytes(operands[0])?.to_ascii_uppercase().as_slice() {
+                b"WRAP" => BitfieldOverflow::Wrap,
+                b"SAT" => BitfieldOverflow::Sat,
+                b"FAIL" => BitfieldOverflow::Fail,
+                _ => return Err(error("ERR Invalid OVERFLOW type specified"))
+            };
+            continue;
+        }
+        let field = bitfield_type(operands[0])?;
+        let offset = bitfield_offset(operands[1], field)?;
+        ops.push(match name.as_slice() {
+            b"GET" => BitfieldOp::Get(field, offset),
+            b"SET" => BitfieldOp::Set(field, offset, integer_arg(operands[2])?, overflow),
+            _ => BitfieldOp::IncrBy(field, offset, integer_arg(operands[2])?, overflow)
+        });
+    }
+    if read_only && ops.iter().any(|op| !matches!(op, BitfieldOp::Get(..))) {
+        return Err(error("ERR BITFIELD_RO only supports the GET subcommand"));
+    }
+    let replies = store.bitfield(arg_bytes(key)?, &ops)?;
+    Ok(RespValue::Arrays(Some(replies.into_iter()
+        .map(|reply| reply.map_or(RespValue::BulkString(None), RespValue::Integer))
+        .collect())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::args;
+
+    fn custom(message: &str) -> Result<RespValue, CommandError> {
+        Err(CommandError::Custom(message.to_string()))
+    }
+
+    #[test]
+    fn setbit_validates_offset_and_bit() {
+        let mut store = Store::new();
+        assert_eq!(handle_setbit(&args(&["k", "7", "1"]), &mut store), Ok(RespValue::Integer(0)));
+        assert_eq!(handle_getbit(&args(&["k", "7"]), &mut store), Ok(RespValue::Integer(1)));
+        let offset_error = custom("ERR bit offset is not an integer or out of range");
+        assert_eq!(handle_setbit(&args(&["k", "-1", "1"]), &mut store), offset_error);
+        assert_eq!(handle_setbit(&args(&["k", "4294967296", "1"]), &mut store), offset_error);
+        assert_eq!(handle_getbit(&args(&["k", "x"]), &mut store), offset_error);
+        assert_eq!(handle_setbit(&args(&["k", "0", "2"]), &mut store), custom("ERR bit is not an integer or out of range"));
+    }
+
+    #[test]
+    fn bitcount_and_bitpos_arguments() {
+        let mut store = Store::new();
+        store.set(b"k", b"foobar".to_vec());
+        assert_eq!(handle_bitcount(&args(&["k"]), &mut store), Ok(RespValue::Integer(26)));
+        assert_eq!(handle_bitcount(&args(&["k", "1", "1", "bit"]), &mut store), Ok(RespValue::Integer(1)));
+        assert_eq!(handle_bitcount(&args(&["k", "1"]), &mut store), Err(CommandError::Syntax));
+        assert_eq!(handle_bitcount(&args(&["k", "0", "1", "NIBBLE"]), &mut store), Err(CommandError::Syntax));
+        assert_eq!(handle_bitpos(&args(&["k", "1", "1"]), &mut store), Ok(RespValue::Integer(9)));
+        assert_eq!(handle_bitpos(&args(&["k", "0", "0", "-1", "BIT"]), &mut store), Ok(RespValue::Integer(0)));
+        assert_eq!(handle_bitpos(&args(&["k", "2"]), &mut store), custom("ERR The bit argument must be 1 or 0."));
+        assert_eq!(handle_bitpos(&args(&["k", "1", "0", "1", "BIT", "x"]), &mut store), Err(CommandError::Syntax));
+    }
+
+    #[test]
+    fn bitop_checks_source_count() {
+        let mut store = Store::new();
+        store.set(b"a", vec![0x0F]);
+        assert_eq!(handle_bitop(&args(&["not", "d", "a"]), &mut store), Ok(RespValue::Integer(1)));
+        assert_eq!(handle_bitop(&args(&["NOT", "d", "a", "b"]), &mut store), custom("ERR BITOP NOT must be called with a single source key."));
+        assert_eq!(handle_bitop(&args(&["andor", "d", "a"]), &mut store), custom("ERR BITOP ANDOR must be called with at least two source keys."));
+        assert_eq!(handle_bitop(&args(&["NAND", "d", "a"]), &mut store), Err(CommandError::Syntax));
+        assert_eq!(handle_bitop(&args(&["AND", "d"]), &mut store), Err(CommandError::WrongArity));
+    }
+
+    #[test]
+    fn bitfield_subcommands() {
+        let mut store = Store::new();
+        let reply = handle_bitfield(&args(&["k", "SET", "u8", "#1", "255", "OVERFLOW", "FAIL", "INCRBY", "u8", "8", "1", "GET", "u16", "0"]), &mut store, false);
+        assert_eq!(reply, Ok(RespValue::Arrays(Some(vec![RespValue::Integer(0), RespValue::BulkString(None), RespValue::Integer(255)]))));
+        assert_eq!(handle_bitfield(&args(&["k"]), &mut store, false), Ok(RespValue::Arrays(Some(vec![]))));
+        let type_error = custom("ERR Invalid bitfield type. Use something like i16 u8. Note that u64 is not supported but i64 is.");
+        assert_eq!(handle_bitfield(&args(&["k", "GET", "u64", "0"]), &mut store, false), type_error);
+        assert_eq!(handle_bitfield(&args(&["k", "GET", "i0", "0"]), &mut store, false), type_error);
+        assert_eq!(handle_bitfield(&args(&["k", "OVERFLOW", "NONE"]), &mut store, false), custom("ERR Invalid OVERFLOW type specified"));
+        assert_eq!(handle_bitfield(&args(&["k", "GET", "i8"]), &mut store, false), Err(CommandError::Syntax));
+        assert_eq!(handle_bitfield(&args(&["k", "GET", "i8", "#-1"]), &mut store, false), custom("ERR bit offset is not an integer or out of range"));
+    }
+
+    #[test]
+    fn bitfield_ro_only_reads() {
+        let mut store = Store::new();
+        store.set(b"k", vec![0x80]);
+        assert_eq!(handle_bitfield(&args(&["k", "GET", "i8", "0"]), &mut store, true), Ok(RespValue::Arrays(Some(vec![RespValue::Integer(-128)]))));
+        assert_eq!(handle_bitfield(&args(&["k", "SET", "i8", "0", "1"]), &mut store, true), custom("ERR BITFIELD_RO only supports the GET subcommand"));
+        assert_eq!(store.get(b"k"), Ok(Some(vec![0x80])));
+    }
+}
@@ -1,6 +1,24 @@
-use crate::{command::{CommandError, Commands}, resp::RespValue, store::value::Store};
+use crate::{command::{bitmap::*, hyperloglog::*, keyspace::*, scan::*, string::*, CommandError, Commands}, resp::RespValue, store::value::{Databases, LockedShards, Shards, Store}};
 
-pub fn execute_command(command: Commands, parsed_data: &RespValue, store: &mut Store) -> Result<RespValue, CommandError>{
+///Shards a request has to lock: all of them for commands on a whole database, otherwise those
+///holding the keys it names
+pub fn shards_for(command: Commands, args: &[RespValue], shards: &Shards) -> Vec<usize> {
+    if command.spans_keyspace() {
+        return (0..shards.len()).collect();
+    }
+    let mut indexes: Vec<usize> = command.keys(args).into_iter()
+        .filter_map(|key| key.as_bytes())
+        .map(|key| shards.shard_of(key))
+        .collect();
+    //A request missing its keys fails the same on any shard
+    if indexes.is_empty() && !matches!(command, Commands::PING | Commands::ECHO | Commands::PFSELFTEST) {
+        indexes.push(0);
+    }
+    indexes
+}
+
+///Runs a command against the shards locked for it, `db` being the database the client selected
+pub fn execute_command(command: Commands, parsed_data: &RespValue, shards: &mut LockedShards, db: usize) -> Result<RespValue, CommandError>{
 
     match command {
         Commands::PING => {
@@ -12,6 +30,96 @@ pub fn execute_command(command: Commands, parsed_data: &RespValue, store: &mut S
                 _ => Err(CommandError::InvalidRequest)
             }
         },
+        Commands::PFSELFTEST => {
+            match parsed_data {
+                RespValue::Arrays(Some(v)) => handle_pfselftest(&v[1..]),
+                _ => Err(CommandError::InvalidRequest)
+            }
+        },
+        _ if command.spans_keyspace() => execute_on_every_shard(command, parsed_data, shards, db),
+        _ if shards.len() == 1 => execute_databases_command(command, parsed_data, shards.iter_mut().next().unwrap(), db),
+        _ => {
+            let args = match parsed_data {
+                RespValue::Arrays(Some(v)) => v.as_slice(),
+                _ => return Err(CommandError::InvalidRequest)
+            };
+            let keys = command.keys(args).into_iter()
+                .map(|key| arg_bytes(key).map(<[u8]>::to_vec))
+                .collect::<Result<Vec<_>, _>>()?;
+            //COPY is the only command on several keys that can write to another database
+            let databases: Vec<usize> = match command {
+                Commands::COPY => (0..shards.owner.databases).collect(),
+                _ => vec![db]
+            };
+            let mut scratch = shards.gather(&keys, &databases);
+            let result = execute_databases_command(command, parsed_data, &mut scratch, db);
+            shards.scatter(scratch, &keys, &databases);
+            result
+        }
+    }
+}
+
+///Runs a command on a whole database in every shard, combining what each shard replies
+fn execute_on_every_shard(command: Commands, parsed_data: &RespValue, shards: &mut LockedShards, db: usize) -> Result<RespValue, CommandError> {
+    let args = match parsed_data {
+        RespValue::Arrays(Some(v)) => &v[1..],
+        _ => return Err(CommandError::InvalidRequest)
+    };
+    match command {
+        Commands::SCAN => handle_scan(args, shards, db),
+        Commands::RANDOMKEY => handle_randomkey(args, shards, db),
+        _ => {
+            //Arguments are checked before anything is changed, an error comes from the first shard
+            let mut replies = Vec::new();
+            for shard in shards.iter_mut() {
+                replies.push(execute_databases_command(command, parsed_data, shard, db)?);
+            }
+            Ok(merge_replies(replies))
+        }
+    }
+}
+
+///Arrays are concatenated and integers added up, any other reply is the same from every shard
+fn merge_replies(replies: Vec<RespValue>) -> RespValue {
+    replies.into_iter()
+        .reduce(|merged, reply| match (merged, reply) {
+            (RespValue::Arrays(Some(mut merged)), RespValue::Arrays(Some(reply))) => {
+                merged.extend(reply);
+                RespValue::Arrays(Some(merged))
+            },
+            (RespValue::Integer(merged), RespValue::Integer(reply)) => RespValue::Integer(merged + reply),
+            (_, reply) => reply
+        })
+        .expect("the keyspace has at least one shard")
+}
+
+///Runs a command against the databases of one shard, or the scratch databases of a command
+///spanning shards
+fn execute_databases_command(command: Commands, parsed_data: &RespValue, dbs: &mut Databases, db: usize) -> Result<RespValue, CommandError>{
+
+    match command {
+        Commands::COPY | Commands::MOVE | Commands::SWAPDB | Commands::FLUSHDB | Commands::FLUSHALL => {
+            match parsed_data {
+                RespValue::Arrays(Some(v)) => {
+                    let args = &v[1..];
+                    match command {
+                        Commands::COPY => handle_copy(args, dbs, db),
+                        Commands::MOVE => handle_move(args, dbs, db),
+                        Commands::SWAPDB => handle_swapdb(args, dbs),
+                        Commands::FLUSHDB => handle_flush(args, dbs, Some(db)),
+                        _ => handle_flush(args, dbs, None)
+                    }
+                },
+                _ => Err(CommandError::InvalidRequest)
+            }
+        },
+        _ => execute_store_command(command, parsed_data, dbs.db(db))
+    }
+}
+
+fn execute_store_command(command: Commands, parsed_data: &RespValue, store: &mut Store) -> Result<RespValue, CommandError>{
+
+    match command {
         Commands::SET => {
             match parsed_data {
                 RespValue::Arrays(Some(v)) => handle_set(&v[1..], store),
@@ -23,7 +131,121 @@ pub fn execute_command(command: Commands, parsed_data: &RespValue, store: &mut S
                 RespValue::Arrays(Some(v)) => handle_get(&v[1..], store),
                 _ => Err(CommandError::InvalidRequest)
             }
-        }
+        },
+        Commands::OBJECT => {
+            match parsed_data {
+                RespValue::Arrays(Some(v)) => handle_object(&v[1..], store),
+                _ => Err(CommandError::InvalidRequest)
+            }
+        },
+        Commands::GETDEL | Commands::GETEX | Commands::GETSET | Commands::SETNX | Commands::SETEX
+            | Commands::PSETEX | Commands::MGET | Commands::MSET | Commands::MSETNX => {
+            match parsed_data {
+                RespValue::Arrays(Some(v)) => {
+                    let args = &v[1..];
+                    match command {
+                        Commands::GETDEL => handle_getdel(args, store),
+                        Commands::GETEX => handle_getex(args, store),
+                        Commands::GETSET => handle_getset(args, store),
+                        Commands::SETNX => handle_setnx(args, store),
+                        Commands::SETEX => handle_setex(args, store, false),
+                        Commands::PSETEX => handle_setex(args, store, true),
+                        Commands::MGET => handle_mget(args, store),
+                        Commands::MSET => handle_mset(args, store, false),
+                        _ => handle_mset(args, store, true)
+                    }
+                },
+                _ => Err(CommandError::InvalidRequest)
+            }
+        },
+        Commands::DEL | Commands::UNLINK | Commands::EXISTS | Commands::TYPE | Commands::RENAME
+            | Commands::RENAMENX | Commands::TOUCH | Commands::DBSIZE | Commands::KEYS => {
+            match parsed_data {
+                RespValue::Arrays(Some(v)) => {
+                    let args = &v[1..];
+                    match command {
+                        Commands::DEL => handle_del(args, store, false),
+                        Commands::UNLINK => handle_del(args, store, true),
+                        Commands::EXISTS => handle_exists(args, store),
+                        Commands::TYPE => handle_type(args, store),
+                        Commands::RENAME => handle_rename(args, store),
+                        Commands::RENAMENX => handle_renamenx(args, store),
+                        Commands::TOUCH => handle_touch(args, store),
+                        Commands::KEYS => handle_keys(args, store),
+                        _ => handle_dbsize(args, store)
+                    }
+                },
+                _ => Err(CommandError::InvalidRequest)
+            }
+        },
+        Commands::HSCAN => {
+            match parsed_data {
+                RespValue::Arrays(Some(v)) => handle_hscan(&v[1..], store),
+                _ => Err(CommandError::InvalidRequest)
+            }
+        },
+        Commands::INCR | Commands::DECR | Commands::INCRBY | Commands::DECRBY | Commands::INCRBYFLOAT
+            | Commands::APPEND | Commands::STRLEN | Commands::GETRANGE | Commands::SUBSTR
+            | Commands::SETRANGE | Commands::LCS => {
+            match parsed_data {
+                RespValue::Arrays(Some(v)) => {
+                    let args = &v[1..];
+                    match command {
+                        Commands::INCR => handle_incr(args, store, false, 1),
+                        Commands::DECR => handle_incr(args, store, false, -1),
+                        Commands::INCRBY => handle_incr(args, store, true, 1),
+                        Commands::DECRBY => handle_incr(args, store, true, -1),
+                        Commands::INCRBYFLOAT => handle_incrbyfloat(args, store),
+                        Commands::APPEND => handle_append(args, store),
+                        Commands::STRLEN => handle_strlen(args, store),
+                        Commands::SETRANGE => handle_setrange(args, store),
+                        Commands::LCS => handle_lcs(args, store),
+                        _ => handle_getrange(args, store)
+                    }
+                },
+                _ => Err(CommandError::InvalidRequest)
+            }
+        },
+        Commands::SETBIT | Commands::GETBIT | Commands::BITCOUNT | Commands::BITPOS | Commands::BITOP
+            | Commands::BITFIELD | Commands::BITFIELD_RO => {
+            match parsed_data {
+                RespValue::Arrays(Some(v)) => {
+                    let args = &v[1..];
+                    match command {
+                        Commands::SETBIT => handle_setbit(args, store),
+                        Commands::GETBIT => handle_getbit(args, store),
+                        Commands::BITCOUNT => handle_bitcount(args, store),
+                        Commands::BITPOS => handle_bitpos(args, store),
+                        Commands::BITOP => handle_bitop(args, store),
+                        Commands::BITFIELD => handle_bitfield(args, store, false),
+                        _ => handle_bitfield(args, store, true)
+                    }
+                },
+                _ => Err(CommandError::InvalidRequest)
+            }
+        },
+        Commands::PFADD | Commands::PFCOUNT | Commands::PFMERGE | Commands::PFDEBUG => {
+            match parsed_data {
+                RespValue::Arrays(Some(v)) => {
+                    let args = &v[1..];
+                    match command {
+                        Commands::PFADD => handle_pfadd(args, store),
+                        Commands::PFCOUNT => handle_pfcount(args, store),
+                        Commands::PFMERGE => handle_pfmerge(args, store),
+                        _ => handle_pfdebug(args, store)
+                    }
+                },
+                _ => Err(CommandError::InvalidRequest)
+            }
+        },
+        //Commands needing no store or several shards are run by execute_command
+        Commands::PING | Commands::ECHO | Commands::PFSELFTEST | Commands::RANDOMKEY | Commands::SCAN => Err(CommandError::UnknownCommand),
+        //Commands spanning several databases are run by execute_databases_command
+        Commands::COPY | Commands::MOVE | Commands::SWAPDB | Commands::FLUSHDB | Commands::FLUSHALL => Err(CommandError::UnknownCommand),
+        //Connection level commands need the client state and are handled by the server
+        Commands::HELLO | Commands::CLIENT | Commands::AUTH | Commands::QUIT
+            | Commands::ACL | Commands::CONFIG | Commands::INFO | Commands::SLOWLOG
+            | Commands::LATENCY | Commands::MONITOR | Commands::SELECT => Err(CommandError::UnknownCommand)
     }
 }
 
@@ -31,24 +253,36 @@ fn handle_echo(parsed_data: &RespValue) -> Result<RespValue, CommandError> {
     Ok(parsed_data.clone())
 }
 
-fn handle_set(parsed_data: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError>{
-    if parsed_data.len() < 2 {
-        return Err(CommandError::InvalidRequest);
-    }
-    let key = &parsed_data[0];
-    let value = &parsed_data[1];
+const POLICY_SWITCH_NOTE: &str = "Please note that when switching between policies at runtime LRU and LFU data will take some time to adjust.";
 
-    match store.set(key, value) {
-        Ok(n) => Ok(n),
-        Err(_) => Err(CommandError::InvalidRequest)
+///OBJECT FREQ and IDLETIME, neither counts as an access to the key
+fn handle_object(parsed_data: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let subcommand = match parsed_data.first().and_then(|a| a.as_bytes()) {
+        Some(arg) => arg.to_ascii_uppercase(),
+        None => return Err(CommandError::WrongArity)
+    };
+    if let [_, key] = parsed_data {
+        store.expire_if_needed(arg_bytes(key)?);
     }
-}
-
-fn handle_get(parsed_data: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
-    let key = &parsed_data[0];
-    match store.get(key) {
-        Ok(n) => Ok(n),
-        Err(_) => Err(CommandError::InvalidRequest)
+    let lfu = store.eviction.policy.is_lfu();
+    match (subcommand.as_slice(), &parsed_data[1..]) {
+        (b"FREQ", [key]) => match lfu {
+            true => Ok(store.lfu_frequency(arg_bytes(key)?).map_or(RespValue::BulkString(None), |f| RespValue::Integer(f as i64))),
+            false => Err(CommandError::Custom(format!(
+                "ERR An LFU maxmemory policy is not selected, access frequency not tracked. {}", POLICY_SWITCH_NOTE
+            )))
+        },
+        (b"IDLETIME", [key]) => match lfu {
+            true => Err(CommandError::Custom(format!(
+                "ERR An LFU maxmemory policy is selected, idle time not tracked. {}", POLICY_SWITCH_NOTE
+            ))),
+            false => Ok(store.idle_time_ms(arg_bytes(key)?).map_or(RespValue::BulkString(None), |ms| RespValue::Integer((ms / 1000) as i64)))
+        },
+        (b"FREQ" | b"IDLETIME", _) => Err(CommandError::WrongArity),
+        _ => Err(CommandError::Custom(format!(
+            "ERR unknown subcommand '{}'. Try OBJECT HELP.",
+            String::from_utf8_lossy(&subcommand).to_ascii_lowercase()
+        )))
     }
 }
 
@@ -57,7 +291,7 @@ mod tests {
     use super::*;
     use crate::resp::RespValue;
     use crate::command::Commands;
-    use crate::store::value::Store;
+    use crate::store::{eviction::LFU_INIT_VAL, value::{EvictionSettings, MaxmemoryPolicy}};
 
     fn bulk(v: &str) -> RespValue {
         RespValue::BulkString(Some(v.as_bytes().to_vec()))
@@ -67,14 +301,27 @@ mod tests {
         RespValue::Arrays(Some(v))
     }
 
+    fn shards() -> Shards {
+        Shards::new(4, 2, Default::default())
+    }
+
+    //Locks what the request needs as the server does
+    fn run(shards: &Shards, command: Commands, parsed_data: &RespValue) -> Result<RespValue, CommandError> {
+        let args = match parsed_data {
+            RespValue::Arrays(Some(v)) => v.as_slice(),
+            _ => &[]
+        };
+        execute_command(command, parsed_data, &mut shards.lock(&shards_for(command, args, shards)), 0)
+    }
+
+    fn request(parts: &[&str]) -> RespValue {
+        array(parts.iter().map(|p| bulk(p)).collect())
+    }
+
     #[test]
     fn ping_returns_pong() {
-        let mut store = Store::new();
-        let result = execute_command(
-            Commands::PING,
-            &RespValue::SimpleString(vec![]),
-            &mut store,
-        )
+        let shards = shards();
+        let result = run(&shards, Commands::PING, &RespValue::SimpleString(vec![]))
         .unwrap();
 
         assert_eq!(result, RespValue::SimpleString(b"PONG".to_vec()));
@@ -82,20 +329,20 @@ mod tests {
 
     #[test]
     fn echo_returns_same_value() {
-        let mut store = Store::new();
+        let shards = shards();
 
         let input = array(vec![
             RespValue::SimpleString(b"ECHO".to_vec()),
             bulk("hello"),
         ]);
 
-        let result = execute_command(Commands::ECHO, &input, &mut store).unwrap();
+        let result = run(&shards, Commands::ECHO, &input).unwrap();
         assert_eq!(result, bulk("hello"));
     }
 
     #[test]
     fn set_then_get_returns_value() {
-        let mut store = Store::new();
+        let shards = shards();
 
         let set_cmd = array(vec![
             RespValue::SimpleString(b"SET".to_vec()),
@@ -103,7 +350,7 @@ mod tests {
             bulk("value"),
         ]);
 
-        let set_res = execute_command(Commands::SET, &set_cmd, &mut store).unwrap();
+        let set_res = run(&shards, Commands::SET, &set_cmd).unwrap();
         assert_eq!(set_res, RespValue::SimpleString(b"OK".to_vec()));
 
         let get_cmd = array(vec![
@@ -111,41 +358,200 @@ mod tests {
             bulk("key"),
         ]);
 
-        let get_res = execute_command(Commands::GET, &get_cmd, &mut store).unwrap();
+        let get_res = run(&shards, Commands::GET, &get_cmd).unwrap();
         assert_eq!(get_res, bulk("value"));
     }
 
     #[test]
     fn get_non_existing_key_returns_null() {
-        let mut store = Store::new();
+        let shards = shards();
 
         let get_cmd = array(vec![
             RespValue::SimpleString(b"GET".to_vec()),
             bulk("missing"),
         ]);
 
-        let result = execute_command(Commands::GET, &get_cmd, &mut store).unwrap();
+        let result = run(&shards, Commands::GET, &get_cmd).unwrap();
         assert_eq!(result, RespValue::BulkString(None));
     }
 
+    #[test]
+    fn keys_match_whatever_resp_type_carries_them() {
+        let shards = shards();
+        let set_cmd = array(vec![bulk("SET"), bulk("key"), bulk("value")]);
+        run(&shards, Commands::SET, &set_cmd).unwrap();
+
+        let get_cmd = array(vec![bulk("GET"), RespValue::SimpleString(b"key".to_vec())]);
+        assert_eq!(run(&shards, Commands::GET, &get_cmd), Ok(bulk("value")));
+        let nested = array(vec![bulk("GET"), array(vec![bulk("key")])]);
+        assert!(run(&shards, Commands::GET, &nested).is_err());
+    }
+
     #[test]
     fn set_with_invalid_args_fails() {
-        let mut store = Store::new();
+        let shards = shards();
 
         let bad_set = array(vec![
             RespValue::SimpleString(b"SET".to_vec()),
             bulk("only_key"),
         ]);
 
-        let result = execute_command(Commands::SET, &bad_set, &mut store);
+        let result = run(&shards, Commands::SET, &bad_set);
         assert!(result.is_err());
     }
-}
-
-
-
 
+    #[test]
+    fn object_freq_and_idletime_depend_on_policy() {
+        let shards = shards();
+        let object = |sub: &str, key: &str| array(vec![bulk("OBJECT"), bulk(sub), bulk(key)]);
+        run(&shards, Commands::SET, &array(vec![bulk("SET"), bulk("key"), bulk("value")])).unwrap();
+
+        assert_eq!(run(&shards, Commands::OBJECT, &object("IDLETIME", "key")), Ok(RespValue::Integer(0)));
+        assert_eq!(run(&shards, Commands::OBJECT, &object("idletime", "missing")), Ok(RespValue::BulkString(None)));
+        assert!(run(&shards, Commands::OBJECT, &object("FREQ", "key")).is_err());
+
+        shards.set_eviction(EvictionSettings { policy: MaxmemoryPolicy::AllKeysLfu, ..Default::default() });
+        assert_eq!(run(&shards, Commands::OBJECT, &object("FREQ", "key")), Ok(RespValue::Integer(LFU_INIT_VAL as i64)));
+        assert!(run(&shards, Commands::OBJECT, &object("IDLETIME", "key")).is_err());
+        assert_eq!(run(&shards, Commands::OBJECT, &array(vec![bulk("OBJECT"), bulk("FREQ")])), Err(CommandError::WrongArity));
+    }
 
+    #[test]
+    fn commands_on_several_keys_span_shards() {
+        let shards = shards();
+        let keys: Vec<String> = (0..20).map(|i| format!("key:{}", i)).collect();
+        assert!(keys.iter().any(|k| shards.shard_of(k.as_bytes()) != shards.shard_of(b"key:0")));
+        let mut mset = vec!["MSET"];
+        for key in &keys {
+            mset.extend([key.as_str(), key.as_str()]);
+        }
+        assert_eq!(run(&shards, Commands::MSET, &request(&mset)), Ok(RespValue::SimpleString(b"OK".to_vec())));
+        let reply = run(&shards, Commands::MGET, &request(&["MGET", "key:3", "missing", "key:17"]));
+        assert_eq!(reply, Ok(array(vec![bulk("key:3"), RespValue::BulkString(None), bulk("key:17")])));
+        assert_eq!(run(&shards, Commands::MSETNX, &request(&["MSETNX", "new", "v", "key:5", "v"])), Ok(RespValue::Integer(0)));
+        assert_eq!(run(&shards, Commands::EXISTS, &request(&["EXISTS", "key:1", "key:2", "new"])), Ok(RespValue::Integer(2)));
+
+        //The destination lands in its own shard whatever shard the source was in
+        let to = (0..).map(|i| format!("to:{}", i)).find(|k| shards.shard_of(k.as_bytes()) != shards.shard_of(b"key:0")).unwrap();
+        assert_eq!(run(&shards, Commands::RENAME, &request(&["RENAME", "key:0", &to])), Ok(RespValue::SimpleString(b"OK".to_vec())));
+        assert!(shards.lock_all().for_key(to.as_bytes()).db(0).exists(to.as_bytes()));
+        assert_eq!(run(&shards, Commands::COPY, &request(&["COPY", &to, "key:9", "DB", "1"])), Ok(RespValue::Integer(1)));
+        assert!(shards.lock_all().for_key(b"key:9").db(1).exists(b"key:9"));
+        assert_eq!(run(&shards, Commands::LCS, &request(&["LCS", "key:1", &to])), Ok(bulk("key:")));
+        assert_eq!(run(&shards, Commands::DEL, &request(&["DEL", "key:1", "key:2", "key:1", "missing"])), Ok(RespValue::Integer(2)));
+        assert_eq!(shards.db_sizes(), [(18, 0), (1, 0)]);
+
+        //BITOP reads and writes keys of several shards
+        assert_eq!(run(&shards, Commands::BITOP, &request(&["BITOP", "OR", &to, "key:3", "key:17"])), Ok(RespValue::Integer(6)));
+        assert_eq!(run(&shards, Commands::GET, &request(&["GET", &to])), Ok(bulk("key:37")));
+
+        //As do PFCOUNT and PFMERGE over several HyperLogLogs
+        let hlls: Vec<String> = (0..8).map(|i| format!("hll:{}", i)).collect();
+        for (i, hll) in hlls.iter().enumerate() {
+            assert_eq!(run(&shards, Commands::PFADD, &request(&["PFADD", hll, &i.to_string(), "shared"])), Ok(RespValue::Integer(1)));
+        }
+        let mut pfcount = vec!["PFCOUNT"];
+        pfcount.extend(hlls.iter().map(String::as_str));
+        assert_eq!(run(&shards, Commands::PFCOUNT, &request(&pfcount)), Ok(RespValue::Integer(9)));
+        pfcount[0] = "PFMERGE";
+        assert_eq!(run(&shards, Commands::PFMERGE, &request(&pfcount)), Ok(RespValue::SimpleString(b"OK".to_vec())));
+        assert_eq!(run(&shards, Commands::PFCOUNT, &request(&["PFCOUNT", "hll:0"])), Ok(RespValue::Integer(9)));
+        assert!(shards_for(Commands::PFSELFTEST, &[bulk("PFSELFTEST")], &shards).is_empty());
+    }
 
+    #[test]
+    fn msetnx_across_shards_writes_nothing_when_any_key_exists() {
+        let shards = shards();
+        //One key in each shard, the existing one in the last shard so the others come first
+        let keys: Vec<String> = (0..shards.len())
+            .map(|shard| (0..).map(|i| format!("key:{}", i)).find(|k| shards.shard_of(k.as_bytes()) == shard).unwrap())
+            .collect();
+        let msetnx = |value: &str| {
+            let mut parts = vec!["MSETNX"];
+            for key in &keys {
+                parts.extend([key.as_str(), value]);
+            }
+            run(&shards, Commands::MSETNX, &request(&parts))
+        };
+        run(&shards, Commands::SET, &request(&["SET", &keys[3], "old"])).unwrap();
+        assert_eq!(msetnx("new"), Ok(RespValue::Integer(0)));
+        assert_eq!(shards.db_sizes()[0], (1, 0));
+        assert_eq!(run(&shards, Commands::GET, &request(&["GET", &keys[3]])), Ok(bulk("old")));
+
+        run(&shards, Commands::DEL, &request(&["DEL", &keys[3]])).unwrap();
+        assert_eq!(msetnx("new"), Ok(RespValue::Integer(1)));
+        let mut mget = vec!["MGET"];
+        mget.extend(keys.iter().map(String::as_str));
+        assert_eq!(run(&shards, Commands::MGET, &request(&mget)), Ok(array(vec![bulk("new"); 4])));
+
+        //Racing on the same keys, exactly one MSETNX wins and sets every key
+        run(&shards, Commands::FLUSHALL, &request(&["FLUSHALL"])).unwrap();
+        let won: Vec<String> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..4).map(|thread| {
+                let msetnx = &msetnx;
+                scope.spawn(move || (msetnx(&thread.to_string()) == Ok(RespValue::Integer(1))).then(|| thread.to_string()))
+            }).collect();
+            handles.into_iter().filter_map(|h| h.join().unwrap()).collect()
+        });
+        assert_eq!(won.len(), 1);
+        assert_eq!(run(&shards, Commands::MGET, &request(&mget)), Ok(array(vec![bulk(&won[0]); 4])));
+    }
 
+    #[test]
+    fn whole_database_commands_cover_every_shard() {
+        let shards = shards();
+        for i in 0..50 {
+            run(&shards, Commands::SET, &request(&["SET", &format!("key:{}", i), "v"])).unwrap();
+        }
+        assert_eq!(run(&shards, Commands::DBSIZE, &request(&["DBSIZE"])), Ok(RespValue::Integer(50)));
+        let Ok(RespValue::Arrays(Some(keys))) = run(&shards, Commands::KEYS, &request(&["KEYS", "key:1*"])) else { panic!() };
+        assert_eq!(keys.len(), 11);
+        let (mut cursor, mut seen) = ("0".to_string(), 0);
+        loop {
+            let Ok(RespValue::Arrays(Some(reply))) = run(&shards, Commands::SCAN, &request(&["SCAN", &cursor, "COUNT", "3"])) else { panic!() };
+            let RespValue::Arrays(Some(batch)) = &reply[1] else { panic!() };
+            seen += batch.len();
+            cursor = String::from_utf8(reply[0].as_bytes().unwrap().to_vec()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(seen, 50);
+        assert!(matches!(run(&shards, Commands::RANDOMKEY, &request(&["RANDOMKEY"])), Ok(RespValue::BulkString(Some(_)))));
+
+        assert_eq!(run(&shards, Commands::SWAPDB, &request(&["SWAPDB", "0", "2"])).map_err(|_| ()), Err(()));
+        assert_eq!(run(&shards, Commands::SWAPDB, &request(&["SWAPDB", "0", "1"])), Ok(RespValue::SimpleString(b"OK".to_vec())));
+        assert_eq!(shards.db_sizes(), [(0, 0), (50, 0)]);
+        assert_eq!(run(&shards, Commands::FLUSHALL, &request(&["FLUSHALL", "NOW"])), Err(CommandError::Syntax));
+        assert_eq!(run(&shards, Commands::FLUSHALL, &request(&["FLUSHALL"])), Ok(RespValue::SimpleString(b"OK".to_vec())));
+        assert_eq!(shards.db_sizes(), [(0, 0), (0, 0)]);
+        assert_eq!(run(&shards, Commands::RANDOMKEY, &request(&["RANDOMKEY"])), Ok(RespValue::BulkString(None)));
+    }
 
+    #[test]
+    fn concurrent_clients_do_not_deadlock_or_lose_writes() {
+        let shards = shards();
+        std::thread::scope(|scope| {
+            for thread in 0..4 {
+                let shards = &shards;
+                scope.spawn(move || {
+                    for i in 0..400 {
+                        let counter = format!("counter:{}", i % 8);
+                        run(shards, Commands::INCR, &request(&["INCR", &counter])).unwrap();
+                        //Commands taking several shards, in opposite key orders on each thread
+                        let (a, b) = (format!("a:{}", thread), format!("b:{}", thread));
+                        let (first, second) = if i % 2 == 0 { (&a, &b) } else { (&b, &a) };
+                        run(shards, Commands::MSET, &request(&["MSET", first, "1", second, "2"])).unwrap();
+                        run(shards, Commands::RENAME, &request(&["RENAME", first, second])).unwrap();
+                        run(shards, Commands::MGET, &request(&["MGET", "counter:0", "counter:7", second])).unwrap();
+                    }
+                });
+            }
+        });
+        let mut mget = vec!["MGET".to_string()];
+        mget.extend((0..8).map(|i| format!("counter:{}", i)));
+        let mget = array(mget.iter().map(|k| bulk(k)).collect());
+        assert_eq!(run(&shards, Commands::MGET, &mget), Ok(array(vec![bulk("200"); 8])));
+        assert_eq!(run(&shards, Commands::DBSIZE, &request(&["DBSIZE"])), Ok(RespValue::Integer(8 + 4)));
+    }
+}
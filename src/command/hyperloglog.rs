@@ -0,0 +1,122 @@
+use crate::{command::{string::arg_bytes, CommandError}, resp::RespValue, store::{expire::unix_time_ms, hyperloglog::selftest, value::{Store, StoreError}}};
+
+//Rounds of register writes and elements added by PFSELFTEST, as in Redis
+const SELFTEST_REGISTER_ROUNDS: usize = 1000;
+const SELFTEST_ELEMENTS: u64 = 10_000_000;
+
+fn keys(args: &[RespValue]) -> Result<Vec<&[u8]>, CommandError> {
+    args.iter().map(arg_bytes).collect()
+}
+
+///PFADD key [element ...]
+pub fn handle_pfadd(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, elements @ ..] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let elements = keys(elements)?;
+    Ok(RespValue::Integer(store.pfadd(arg_bytes(key)?, &elements)? as i64))
+}
+
+///PFCOUNT key [key ...]
+pub fn handle_pfcount(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    if args.is_empty() {
+        return Err(CommandError::WrongArity);
+    }
+    Ok(RespValue::Integer(store.pfcount(&keys(args)?)? as i64))
+}
+
+///PFMERGE destkey [sourcekey ...]
+pub fn handle_pfmerge(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [destination, sources @ ..] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    store.pfmerge(arg_bytes(destination)?, &keys(sources)?)?;
+    Ok(RespValue::SimpleString(b"OK".to_vec()))
+}
+
+///PFDEBUG GETREG|DECODE|ENCODING|TODENSE key
+pub fn handle_pfdebug(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [subcommand, key] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let subcommand = arg_bytes(subcommand)?;
+    let key = arg_bytes(key)?;
+    let missing = |e| match e {
+        StoreError::NotFound => CommandError::Custom("ERR The specified key does not exist".to_string()),
+        e => e.into()
+    };
+    match subcommand.to_ascii_uppercase().as_slice() {
+        b"GETREG" => Ok(RespValue::Arrays(Some(store.hll_registers(key).map_err(missing)?.into_iter()
+            .map(|register| RespValue::Integer(register as i64))
+            .collect()))),
+        b"DECODE" => match store.hll_decode(key).map_err(missing)? {
+            Some(decoded) => Ok(RespValue::SimpleString(decoded.into_bytes())),
+            None => Err(CommandError::Custom("ERR HLL encoding is not sparse".to_string()))
+        },
+        b"ENCODING" => Ok(RespValue::SimpleString(store.hll_encoding(key).map_err(missing)?.as_bytes().to_vec())),
+        b"TODENSE" => Ok(RespValue::Integer(store.hll_to_dense(key).map_err(missing)? as i64)),
+        _ => Err(CommandError::Custom(format!("ERR Unknown PFDEBUG subcommand '{}'", String::from_utf8_lossy(subcommand))))
+    }
+}
+
+///PFSELFTEST, which needs no keyspace and runs without holding any shard
+pub fn handle_pfselftest(args: &[RespValue]) -> Result<RespValue, CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::WrongArity);
+    }
+    match selftest(unix_time_ms(), SELFTEST_REGISTER_ROUNDS, SELFTEST_ELEMENTS) {
+        Ok(()) => Ok(RespValue::SimpleString(b"OK".to_vec())),
+        Err(message) => Err(CommandError::Custom(format!("ERR {}", message)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::args;
+
+    fn status(s: &str) -> Result<RespValue, CommandError> {
+        Ok(RespValue::SimpleString(s.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn pfadd_pfcount_pfmerge() {
+        let mut store = Store::new();
+        assert_eq!(handle_pfadd(&args(&["a", "x", "y", "z"]), &mut store), Ok(RespValue::Integer(1)));
+        assert_eq!(handle_pfadd(&args(&["a", "x"]), &mut store), Ok(RespValue::Integer(0)));
+        assert_eq!(handle_pfadd(&args(&["b", "z", "w"]), &mut store), Ok(RespValue::Integer(1)));
+        assert_eq!(handle_pfcount(&args(&["a"]), &mut store), Ok(RespValue::Integer(3)));
+        assert_eq!(handle_pfcount(&args(&["a", "b"]), &mut store), Ok(RespValue::Integer(4)));
+        assert_eq!(handle_pfmerge(&args(&["c", "a", "b"]), &mut store), status("OK"));
+        assert_eq!(handle_pfcount(&args(&["c"]), &mut store), Ok(RespValue::Integer(4)));
+        assert_eq!(handle_pfmerge(&args(&["empty"]), &mut store), status("OK"));
+        assert_eq!(handle_pfcount(&args(&["empty"]), &mut store), Ok(RespValue::Integer(0)));
+        assert_eq!(handle_pfcount(&args(&[]), &mut store), Err(CommandError::WrongArity));
+    }
+
+    #[test]
+    fn invalid_values_reply_errors() {
+        let mut store = Store::new();
+        store.set(b"s", b"string".to_vec());
+        let error = Err(CommandError::Custom("WRONGTYPE Key is not a valid HyperLogLog string value.".to_string()));
+        assert_eq!(handle_pfadd(&args(&["s", "x"]), &mut store), error);
+        assert_eq!(handle_pfcount(&args(&["missing", "s"]), &mut store), error);
+        assert_eq!(handle_pfmerge(&args(&["d", "s"]), &mut store), error);
+    }
+
+    #[test]
+    fn pfdebug_subcommands() {
+        let mut store = Store::new();
+        handle_pfadd(&args(&["h"]), &mut store).unwrap();
+        assert_eq!(handle_pfdebug(&args(&["DECODE", "h"]), &mut store), status("Z:16384"));
+        assert_eq!(handle_pfdebug(&args(&["encoding", "h"]), &mut store), status("sparse"));
+        let Ok(RespValue::Arrays(Some(registers))) = handle_pfdebug(&args(&["GETREG", "h"]), &mut store) else { panic!() };
+        assert_eq!(registers.len(), 16384);
+        assert_eq!(handle_pfdebug(&args(&["ENCODING", "h"]), &mut store), status("dense"));
+        assert_eq!(handle_pfdebug(&args(&["TODENSE", "h"]), &mut store), Ok(RespValue::Integer(0)));
+        assert_eq!(handle_pfdebug(&args(&["DECODE", "h"]), &mut store), Err(CommandError::Custom("ERR HLL encoding is not sparse".to_string())));
+        assert_eq!(handle_pfdebug(&args(&["ENCODING", "missing"]), &mut store), Err(CommandError::Custom("ERR The specified key does not exist".to_string())));
+        assert_eq!(handle_pfdebug(&args(&["DUMP", "h"]), &mut store), Err(CommandError::Custom("ERR Unknown PFDEBUG subcommand 'DUMP'".to_string())));
+        assert_eq!(handle_pfdebug(&args(&["ENCODING"]), &mut store), Err(CommandError::WrongArity));
+    }
+}
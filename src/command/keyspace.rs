@@ -0,0 +1,283 @@
+use crate::{command::{string::{arg_bytes, integer_arg}, CommandError}, glob::string_match, resp::RespValue, store::{expire::unix_time_ms, value::{Databases, LockedShards, Store}}};
+
+fn ok() -> RespValue {
+    RespValue::SimpleString(b"OK".to_vec())
+}
+
+fn count(n: usize) -> RespValue {
+    RespValue::Integer(n as i64)
+}
+
+///DEL, or UNLINK when `lazy` is set. Returns the number of keys deleted
+pub fn handle_del(args: &[RespValue], store: &mut Store, lazy: bool) -> Result<RespValue, CommandError> {
+    if args.is_empty() {
+        return Err(CommandError::WrongArity);
+    }
+    let mut deleted = 0;
+    for key in args {
+        let key = arg_bytes(key)?;
+        let existed = match lazy {
+            true => store.unlink(key),
+            false => store.exists(key) && store.remove(key).is_some()
+        };
+        deleted += existed as usize;
+    }
+    Ok(count(deleted))
+}
+
+///EXISTS, a key given twice is counted twice
+pub fn handle_exists(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    if args.is_empty() {
+        return Err(CommandError::WrongArity);
+    }
+    let mut existing = 0;
+    for key in args {
+        existing += store.exists(arg_bytes(key)?) as usize;
+    }
+    Ok(count(existing))
+}
+
+pub fn handle_touch(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    if args.is_empty() {
+        return Err(CommandError::WrongArity);
+    }
+    let mut touched = 0;
+    for key in args {
+        touched += store.lookup_read(arg_bytes(key)?).is_some() as usize;
+    }
+    Ok(count(touched))
+}
+
+pub fn handle_type(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    Ok(RespValue::SimpleString(store.key_type(arg_bytes(key)?).as_bytes().to_vec()))
+}
+
+pub fn handle_rename(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [from, to] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let (from, to) = (arg_bytes(from)?, arg_bytes(to)?);
+    match store.rename(from, to) {
+        true => Ok(ok()),
+        false => Err(CommandError::Custom("ERR no such key".to_string()))
+    }
+}
+
+pub fn handle_renamenx(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [from, to] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let (from, to) = (arg_bytes(from)?, arg_bytes(to)?);
+    if !store.exists(from) {
+        return Err(CommandError::Custom("ERR no such key".to_string()));
+    }
+    //Renaming a key to itself counts as the destination existing
+    if store.exists(to) {
+        return Ok(count(0));
+    }
+    Ok(count(store.rename(from, to) as usize))
+}
+
+fn same_objects() -> CommandError {
+    CommandError::Custom("ERR source and destination objects are the same".to_string())
+}
+
+///Index of one of the `count` databases, as SELECT, MOVE and COPY take it
+pub fn db_index(arg: &RespValue, count: usize) -> Result<usize, CommandError> {
+    match usize::try_from(integer_arg(arg)?) {
+        Ok(index) if index < count => Ok(index),
+        _ => Err(CommandError::Custom("ERR DB index is out of range".to_string()))
+    }
+}
+
+///COPY source destination [DB destination-db] [REPLACE]
+pub fn handle_copy(args: &[RespValue], dbs: &mut Databases, db: usize) -> Result<RespValue, CommandError> {
+    let [from, to, options @ ..] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let (from, to) = (arg_bytes(from)?, arg_bytes(to)?);
+    let mut replace = false;
+    let mut destination = db;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.as_bytes().map(|o| o.to_ascii_uppercase()).as_deref() {
+            Some(b"REPLACE") => replace = true,
+            Some(b"DB") => destination = db_index(options.next().ok_or(CommandError::Syntax)?, dbs.len())?,
+            _ => return Err(CommandError::Syntax)
+        }
+    }
+    if from == to && destination == db {
+        return Err(same_objects());
+    }
+    Ok(count(dbs.copy(from, db, to, destination, replace) as usize))
+}
+
+///MOVE key db, only when the key does not exist in the destination
+pub fn handle_move(args: &[RespValue], dbs: &mut Databases, db: usize) -> Result<RespValue, CommandError> {
+    let [key, destination] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    let destination = db_index(destination, dbs.len())?;
+    if destination == db {
+        return Err(same_objects());
+    }
+    Ok(count(dbs.move_key(key, db, destination) as usize))
+}
+
+pub fn handle_swapdb(args: &[RespValue], dbs: &mut Databases) -> Result<RespValue, CommandError> {
+    let [first, second] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let first = integer_arg(first).map_err(|_| CommandError::Custom("ERR invalid first DB index".to_string()))?;
+    let second = integer_arg(second).map_err(|_| CommandError::Custom("ERR invalid second DB index".to_string()))?;
+    let range = 0..dbs.len() as i64;
+    if !range.contains(&first) || !range.contains(&second) {
+        return Err(CommandError::Custom("ERR DB index is out of range".to_string()));
+    }
+    dbs.swap(first as usize, second as usize);
+    Ok(ok())
+}
+
+///FLUSHDB, or FLUSHALL when `db` is None, with an optional SYNC or ASYNC mode
+pub fn handle_flush(args: &[RespValue], dbs: &mut Databases, db: Option<usize>) -> Result<RespValue, CommandError> {
+    let lazy = match args {
+        [] => false,
+        [mode] => match mode.as_bytes().map(|m| m.to_ascii_uppercase()).as_deref() {
+            Some(b"SYNC") => false,
+            Some(b"ASYNC") => true,
+            _ => return Err(CommandError::Syntax)
+        },
+        _ => return Err(CommandError::Syntax)
+    };
+    dbs.flush(db, lazy);
+    Ok(ok())
+}
+
+pub fn handle_randomkey(args: &[RespValue], shards: &mut LockedShards, db: usize) -> Result<RespValue, CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::WrongArity);
+    }
+    Ok(RespValue::BulkString(shards.random_live_key(db)))
+}
+
+///Every key matching a glob pattern. Walks the whole keyspace, SCAN is the incremental way
+pub fn handle_keys(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [pattern] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let pattern = arg_bytes(pattern)?;
+    let all = pattern == b"*";
+    let now = unix_time_ms();
+    let keys = store.map.keys()
+        .filter(|key| store.expires.get(*key).is_none_or(|at| *at > now))
+        .filter(|key| all || string_match(pattern, key, false))
+        .map(|key| RespValue::BulkString(Some(key.clone())))
+        .collect();
+    Ok(RespValue::Arrays(Some(keys)))
+}
+
+pub fn handle_dbsize(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    if !args.is_empty() {
+        return Err(CommandError::WrongArity);
+    }
+    Ok(count(store.map.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::value::Shards;
+    use crate::test_helpers::{args, bulk};
+
+    fn store_with(keys: &[&str]) -> Store {
+        let mut store = Store::new();
+        for key in keys {
+            store.set(key.as_bytes(), b"v".to_vec());
+        }
+        store
+    }
+
+    #[test]
+    fn del_exists_touch() {
+        let mut store = store_with(&["a", "b", "c"]);
+        assert_eq!(handle_exists(&args(&["a", "a", "missing"]), &mut store), Ok(count(2)));
+        assert_eq!(handle_touch(&args(&["a", "missing"]), &mut store), Ok(count(1)));
+        assert_eq!(handle_del(&args(&["a", "missing", "a"]), &mut store, false), Ok(count(1)));
+        assert_eq!(handle_del(&args(&["b", "c"]), &mut store, true), Ok(count(2)));
+        assert_eq!(handle_dbsize(&[], &mut store), Ok(count(0)));
+        assert_eq!(handle_del(&[], &mut store, false), Err(CommandError::WrongArity));
+    }
+
+    #[test]
+    fn rename_variants() {
+        let mut store = store_with(&["a", "b"]);
+        assert_eq!(handle_renamenx(&args(&["a", "b"]), &mut store), Ok(count(0)));
+        assert_eq!(handle_renamenx(&args(&["a", "a"]), &mut store), Ok(count(0)));
+        assert_eq!(handle_renamenx(&args(&["a", "c"]), &mut store), Ok(count(1)));
+        assert_eq!(handle_rename(&args(&["c", "b"]), &mut store), Ok(ok()));
+        assert_eq!(handle_rename(&args(&["b", "b"]), &mut store), Ok(ok()));
+        assert!(handle_rename(&args(&["c", "d"]), &mut store).is_err());
+        assert_eq!(handle_type(&args(&["b"]), &mut store), Ok(RespValue::SimpleString(b"string".to_vec())));
+        assert_eq!(handle_type(&args(&["c"]), &mut store), Ok(RespValue::SimpleString(b"none".to_vec())));
+        let shards = Shards::new(2, 2, Default::default());
+        shards.lock_all().for_key(b"b").db(0).set(b"b", b"v".to_vec());
+        assert_eq!(handle_randomkey(&[], &mut shards.lock_all(), 0), Ok(bulk("b")));
+        assert_eq!(handle_randomkey(&[], &mut shards.lock_all(), 1), Ok(RespValue::BulkString(None)));
+    }
+
+    #[test]
+    fn keys_matches_pattern_and_skips_expired() {
+        let mut store = store_with(&["user:1", "user:2", "User:3", "item:1"]);
+        store.expires.insert(b"user:2".to_vec(), 1);
+        let RespValue::Arrays(Some(mut keys)) = handle_keys(&args(&["user:*"]), &mut store).unwrap() else { panic!() };
+        keys.sort_by_key(|k| k.as_bytes().map(<[u8]>::to_vec));
+        assert_eq!(keys, args(&["user:1"]));
+        let RespValue::Arrays(Some(keys)) = handle_keys(&args(&["*"]), &mut store).unwrap() else { panic!() };
+        assert_eq!(keys.len(), 3);
+        assert_eq!(handle_keys(&args(&["[uU]ser:[^1]"]), &mut store), Ok(RespValue::Arrays(Some(args(&["User:3"])))));
+    }
+
+    fn databases_with(keys: &[&str]) -> Databases {
+        let mut dbs = Databases::new(2, Default::default());
+        dbs.dbs[0] = store_with(keys);
+        dbs
+    }
+
+    #[test]
+    fn copy_options() {
+        let mut dbs = databases_with(&["a", "b"]);
+        assert_eq!(handle_copy(&args(&["a", "b"]), &mut dbs, 0), Ok(count(0)));
+        assert_eq!(handle_copy(&args(&["a", "b", "DB", "0", "REPLACE"]), &mut dbs, 0), Ok(count(1)));
+        assert_eq!(handle_copy(&args(&["a", "a", "DB", "1"]), &mut dbs, 0), Ok(count(1)));
+        assert!(dbs.db(1).exists(b"a"));
+        assert!(handle_copy(&args(&["a", "b", "DB", "2"]), &mut dbs, 0).is_err());
+        assert_eq!(handle_copy(&args(&["a", "b", "DB"]), &mut dbs, 0), Err(CommandError::Syntax));
+        assert!(handle_copy(&args(&["a", "a"]), &mut dbs, 0).is_err());
+    }
+
+    #[test]
+    fn move_swapdb_and_flush() {
+        let mut dbs = databases_with(&["a", "b"]);
+        assert_eq!(handle_move(&args(&["a", "1"]), &mut dbs, 0), Ok(count(1)));
+        assert_eq!(handle_move(&args(&["a", "1"]), &mut dbs, 0), Ok(count(0)));
+        assert_eq!(handle_move(&args(&["b", "0"]), &mut dbs, 0), Err(same_objects()));
+        assert_eq!(handle_move(&args(&["b", "-1"]), &mut dbs, 0), Err(CommandError::Custom("ERR DB index is out of range".to_string())));
+        assert_eq!(handle_move(&args(&["b", "x"]), &mut dbs, 0), Err(CommandError::Custom("ERR value is not an integer or out of range".to_string())));
+
+        assert_eq!(handle_swapdb(&args(&["0", "1"]), &mut dbs), Ok(ok()));
+        assert!(dbs.db(0).exists(b"a") && dbs.db(1).exists(b"b"));
+        assert_eq!(handle_swapdb(&args(&["x", "1"]), &mut dbs), Err(CommandError::Custom("ERR invalid first DB index".to_string())));
+        assert_eq!(handle_swapdb(&args(&["0", "x"]), &mut dbs), Err(CommandError::Custom("ERR invalid second DB index".to_string())));
+        assert!(handle_swapdb(&args(&["0", "2"]), &mut dbs).is_err());
+
+        assert_eq!(handle_flush(&args(&["LAZY"]), &mut dbs, Some(0)), Err(CommandError::Syntax));
+        assert_eq!(handle_flush(&args(&["async"]), &mut dbs, Some(0)), Ok(ok()));
+        assert_eq!((dbs.db(0).map.len(), dbs.db(1).map.len()), (0, 1));
+        assert_eq!(handle_flush(&[], &mut dbs, None), Ok(ok()));
+        assert_eq!(dbs.db(1).map.len(), 0);
+    }
+}
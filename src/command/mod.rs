@@ -1,7 +1,13 @@
 pub mod parser;
+pub mod scan;
 pub mod value;
+pub mod bitmap;
 pub mod execute;
+pub mod hyperloglog;
+pub mod keyspace;
+pub mod spec;
+pub mod string;
 
 pub use value::*;
 pub use parser::get_command;
-pub use execute::execute_command;
+pub use execute::{execute_command, shards_for};
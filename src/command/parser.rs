@@ -8,6 +8,67 @@ impl Commands {
             b"ECHO" => Some(Commands::ECHO),
             b"SET" => Some(Commands::SET),
             b"GET" => Some(Commands::GET),
+            b"HELLO" => Some(Commands::HELLO),
+            b"CLIENT" => Some(Commands::CLIENT),
+            b"AUTH" => Some(Commands::AUTH),
+            b"QUIT" => Some(Commands::QUIT),
+            b"ACL" => Some(Commands::ACL),
+            b"CONFIG" => Some(Commands::CONFIG),
+            b"INFO" => Some(Commands::INFO),
+            b"SLOWLOG" => Some(Commands::SLOWLOG),
+            b"LATENCY" => Some(Commands::LATENCY),
+            b"MONITOR" => Some(Commands::MONITOR),
+            b"OBJECT" => Some(Commands::OBJECT),
+            b"INCR" => Some(Commands::INCR),
+            b"DECR" => Some(Commands::DECR),
+            b"INCRBY" => Some(Commands::INCRBY),
+            b"DECRBY" => Some(Commands::DECRBY),
+            b"INCRBYFLOAT" => Some(Commands::INCRBYFLOAT),
+            b"APPEND" => Some(Commands::APPEND),
+            b"STRLEN" => Some(Commands::STRLEN),
+            b"GETRANGE" => Some(Commands::GETRANGE),
+            b"SUBSTR" => Some(Commands::SUBSTR),
+            b"SETRANGE" => Some(Commands::SETRANGE),
+            b"LCS" => Some(Commands::LCS),
+            b"GETDEL" => Some(Commands::GETDEL),
+            b"GETEX" => Some(Commands::GETEX),
+            b"GETSET" => Some(Commands::GETSET),
+            b"SETNX" => Some(Commands::SETNX),
+            b"SETEX" => Some(Commands::SETEX),
+            b"PSETEX" => Some(Commands::PSETEX),
+            b"MGET" => Some(Commands::MGET),
+            b"MSET" => Some(Commands::MSET),
+            b"MSETNX" => Some(Commands::MSETNX),
+            b"DEL" => Some(Commands::DEL),
+            b"UNLINK" => Some(Commands::UNLINK),
+            b"EXISTS" => Some(Commands::EXISTS),
+            b"TYPE" => Some(Commands::TYPE),
+            b"RENAME" => Some(Commands::RENAME),
+            b"RENAMENX" => Some(Commands::RENAMENX),
+            b"COPY" => Some(Commands::COPY),
+            b"TOUCH" => Some(Commands::TOUCH),
+            b"RANDOMKEY" => Some(Commands::RANDOMKEY),
+            b"DBSIZE" => Some(Commands::DBSIZE),
+            b"SCAN" => Some(Commands::SCAN),
+            b"HSCAN" => Some(Commands::HSCAN),
+            b"KEYS" => Some(Commands::KEYS),
+            b"SELECT" => Some(Commands::SELECT),
+            b"MOVE" => Some(Commands::MOVE),
+            b"SWAPDB" => Some(Commands::SWAPDB),
+            b"FLUSHDB" => Some(Commands::FLUSHDB),
+            b"FLUSHALL" => Some(Commands::FLUSHALL),
+            b"SETBIT" => Some(Commands::SETBIT),
+            b"GETBIT" => Some(Commands::GETBIT),
+            b"BITCOUNT" => Some(Commands::BITCOUNT),
+            b"BITPOS" => Some(Commands::BITPOS),
+            b"BITOP" => Some(Commands::BITOP),
+            b"BITFIELD" => Some(Commands::BITFIELD),
+            b"BITFIELD_RO" => Some(Commands::BITFIELD_RO),
+            b"PFADD" => Some(Commands::PFADD),
+            b"PFCOUNT" => Some(Commands::PFCOUNT),
+            b"PFMERGE" => Some(Commands::PFMERGE),
+            b"PFDEBUG" => Some(Commands::PFDEBUG),
+            b"PFSELFTEST" => Some(Commands::PFSELFTEST),
             _ => None
         }
     }
@@ -0,0 +1,207 @@
+use crate::{command::{string::{arg_bytes, integer_arg}, CommandError}, glob::string_match, resp::RespValue, store::{keyspace::type_name, value::{LockedShards, Store, StoreError, Value}}};
+
+const TYPES: [&str; 6] = ["string", "list", "set", "zset", "hash", "stream"];
+
+fn bulk(bytes: &[u8]) -> RespValue {
+    RespValue::BulkString(Some(bytes.to_vec()))
+}
+
+///Options shared by the SCAN family
+struct ScanOptions {
+    pattern: Option<Vec<u8>>,
+    count: usize,
+    key_type: Option<String>,
+    novalues: bool,
+}
+
+impl ScanOptions {
+    fn matches(&self, element: &[u8]) -> bool {
+        self.pattern.as_ref().is_none_or(|pattern| string_match(pattern, element, false))
+    }
+}
+
+fn parse_cursor(arg: &RespValue) -> Result<u64, CommandError> {
+    arg.as_bytes()
+        .and_then(|c| std::str::from_utf8(c).ok())
+        .and_then(|c| c.parse().ok())
+        .ok_or_else(|| CommandError::Custom("ERR invalid cursor".to_string()))
+}
+
+///Parses MATCH and COUNT, plus TYPE for SCAN and NOVALUES for HSCAN
+fn parse_scan_options(options: &[RespValue], command: &[u8]) -> Result<ScanOptions, CommandError> {
+    let mut parsed = ScanOptions { pattern: None, count: 10, key_type: None, novalues: false };
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let option = option.as_bytes().ok_or(CommandError::Syntax)?.to_ascii_uppercase();
+        match (option.as_slice(), command) {
+            (b"MATCH", _) => {
+                let pattern = options.next().and_then(|p| p.as_bytes()).ok_or(CommandError::Syntax)?;
+                //A lone * matches everything, skip the matcher
+                parsed.pattern = (pattern != b"*").then(|| pattern.to_vec());
+            },
+            (b"COUNT", _) => {
+                let count = integer_arg(options.next().ok_or(CommandError::Syntax)?)?;
+                if count < 1 {
+                    return Err(CommandError::Syntax);
+                }
+                parsed.count = count as usize;
+            },
+            (b"TYPE", b"SCAN") => {
+                let name = options.next().and_then(|t| t.as_bytes()).ok_or(CommandError::Syntax)?;
+                let name = String::from_utf8_lossy(name).to_ascii_lowercase();
+                if !TYPES.contains(&name.as_str()) {
+                    return Err(CommandError::Custom(format!("ERR unknown type name '{}'", name)));
+                }
+                parsed.key_type = Some(name);
+            },
+            (b"NOVALUES", b"HSCAN") => parsed.novalues = true,
+            _ => return Err(CommandError::Syntax)
+        }
+    }
+    Ok(parsed)
+}
+
+//Collections this small are returned whole in one call whatever the cursor, as Redis does for
+//those it keeps compact (hash-max-listpack-entries)
+const COMPACT_MAX_ENTRIES: usize = 128;
+
+fn scan_reply(cursor: u64, elements: Vec<Vec<u8>>) -> RespValue {
+    RespValue::Arrays(Some(vec![
+        bulk(cursor.to_string().as_bytes()),
+        RespValue::Arrays(Some(elements.into_iter().map(|e| RespValue::BulkString(Some(e))).collect())),
+    ]))
+}
+
+///SCAN cursor [MATCH pattern] [COUNT count] [TYPE type], over every shard of database `db`
+pub fn handle_scan(args: &[RespValue], shards: &mut LockedShards, db: usize) -> Result<RespValue, CommandError> {
+    let [cursor, options @ ..] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let cursor = parse_cursor(cursor)?;
+    let options = parse_scan_options(options, b"SCAN")?;
+    let (next, keys) = shards.scan(db, cursor, options.count);
+    //Filters apply after the batch is taken, as in Redis, so a batch may come back empty
+    let keys = keys.into_iter()
+        .filter(|key| options.matches(key))
+        .filter(|key| options.key_type.as_deref().is_none_or(|t| shards.for_key(key).db(db).map.get(key).is_some_and(|e| type_name(&e.value) == t)))
+        .collect();
+    Ok(scan_reply(next, keys))
+}
+
+///HSCAN key cursor [MATCH pattern] [COUNT count] [NOVALUES]. SSCAN and ZSCAN wait for sets
+///and sorted sets to be stored
+pub fn handle_hscan(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, cursor, options @ ..] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let cursor = parse_cursor(cursor)?;
+    let options = parse_scan_options(options, b"HSCAN")?;
+    let elements = match store.lookup_read(arg_bytes(key)?) {
+        None => return Ok(scan_reply(0, Vec::new())),
+        Some(Value::Hash(hash)) => {
+            let (next, pairs) = match hash.len() <= COMPACT_MAX_ENTRIES {
+                true => (0, hash.iter().collect()),
+                false => hash.scan(cursor, options.count)
+            };
+            let mut elements = Vec::new();
+            for (field, value) in pairs.into_iter().filter(|(field, _)| options.matches(field)) {
+                elements.push(field.to_vec());
+                if !options.novalues {
+                    elements.push(value.to_vec());
+                }
+            }
+            (next, elements)
+        },
+        Some(_) => return Err(StoreError::WrongType.into())
+    };
+    Ok(scan_reply(elements.0, elements.1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::value::Shards;
+    use crate::test_helpers::{args, bulk};
+    use std::collections::HashSet;
+
+    fn reply_parts(reply: RespValue) -> (String, Vec<RespValue>) {
+        let RespValue::Arrays(Some(parts)) = reply else { panic!() };
+        let cursor = String::from_utf8(parts[0].as_bytes().unwrap().to_vec()).unwrap();
+        let RespValue::Arrays(Some(elements)) = parts[1].clone() else { panic!() };
+        (cursor, elements)
+    }
+
+    #[test]
+    fn scan_with_match_and_type() {
+        let shards = Shards::new(4, 1, Default::default());
+        let mut locked = shards.lock_all();
+        for i in 0..30 {
+            for key in [format!("user:{}", i), format!("item:{}", i)] {
+                locked.for_key(key.as_bytes()).db(0).set(key.as_bytes(), b"v".to_vec());
+            }
+        }
+        locked.for_key(b"user:list").db(0).write(b"user:list", Value::List(Default::default()));
+        let (mut cursor, mut found) = ("0".to_string(), HashSet::new());
+        loop {
+            let request = args(&[&cursor, "MATCH", "user:*", "COUNT", "7", "TYPE", "STRING"]);
+            let (next, keys) = reply_parts(handle_scan(&request, &mut locked, 0).unwrap());
+            found.extend(keys);
+            if next == "0" {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(found.len(), 30);
+        assert!(found.iter().all(|k| k.as_bytes().unwrap().starts_with(b"user:")));
+        assert!(!found.contains(&bulk("user:list")));
+    }
+
+    #[test]
+    fn scan_rejects_bad_arguments() {
+        let shards = Shards::new(1, 1, Default::default());
+        let mut store = shards.lock_all();
+        assert_eq!(handle_scan(&args(&["x"]), &mut store, 0), Err(CommandError::Custom("ERR invalid cursor".to_string())));
+        assert_eq!(handle_scan(&args(&["0", "COUNT", "0"]), &mut store, 0), Err(CommandError::Syntax));
+        assert_eq!(handle_scan(&args(&["0", "NOVALUES"]), &mut store, 0), Err(CommandError::Syntax));
+        assert!(handle_scan(&args(&["0", "TYPE", "nope"]), &mut store, 0).is_err());
+        assert_eq!(reply_parts(handle_scan(&args(&["0"]), &mut store, 0).unwrap()), ("0".to_string(), vec![]));
+    }
+
+    #[test]
+    fn hash_scans() {
+        let mut store = Store::new();
+        let pairs = (0..5).map(|i| (format!("f{}", i).into_bytes(), i.to_string().into_bytes())).collect();
+        store.write(b"hash", Value::Hash(pairs));
+        let (cursor, elements) = reply_parts(handle_hscan(&args(&["hash", "0", "COUNT", "100"]), &mut store).unwrap());
+        assert_eq!((cursor.as_str(), elements.len()), ("0", 10));
+        let (_, fields) = reply_parts(handle_hscan(&args(&["hash", "0", "MATCH", "f1", "NOVALUES"]), &mut store).unwrap());
+        assert_eq!(fields, vec![bulk("f1")]);
+        assert_eq!(reply_parts(handle_hscan(&args(&["missing", "0"]), &mut store).unwrap()).1, vec![]);
+        store.write(b"string", Value::String(b"v".to_vec()));
+        assert_eq!(handle_hscan(&args(&["string", "0"]), &mut store), Err(StoreError::WrongType.into()));
+        assert_eq!(handle_hscan(&args(&["hash", "0", "TYPE", "hash"]), &mut store), Err(CommandError::Syntax));
+        let (cursor, elements) = reply_parts(handle_hscan(&args(&["hash", "0", "COUNT", "1"]), &mut store).unwrap());
+        assert_eq!((cursor.as_str(), elements.len()), ("0", 10));
+    }
+
+    #[test]
+    fn large_hashes_are_scanned_incrementally() {
+        let mut store = Store::new();
+        let pairs = (0..1000).map(|i| (format!("f{}", i).into_bytes(), b"v".to_vec())).collect();
+        store.write(b"hash", Value::Hash(pairs));
+        let (mut cursor, mut fields, mut calls) = ("0".to_string(), HashSet::new(), 0);
+        loop {
+            let reply = handle_hscan(&args(&["hash", &cursor, "COUNT", "50", "NOVALUES"]), &mut store).unwrap();
+            let (next, batch) = reply_parts(reply);
+            assert!(batch.len() < 100);
+            fields.extend(batch);
+            calls += 1;
+            if next == "0" {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(fields.len(), 1000);
+        assert!(calls >= 15);
+    }
+}
@@ -0,0 +1,376 @@
+use crate::{command::{Commands, KeyAccess}, resp::RespValue};
+
+///Every ACL category, in the order ACL CAT lists them
+pub const CATEGORIES: [&str; 21] = [
+    "keyspace", "read", "write", "set", "sortedset", "list", "hash", "string", "bitmap",
+    "hyperloglog", "geo", "stream", "pubsub", "admin", "fast", "slow", "blocking", "dangerous",
+    "connection", "transaction", "scripting",
+];
+
+impl Commands {
+    pub const ALL: [Commands; 65] = [
+        Commands::PING,
+        Commands::ECHO,
+        Commands::SET,
+        Commands::GET,
+        Commands::HELLO,
+        Commands::CLIENT,
+        Commands::AUTH,
+        Commands::QUIT,
+        Commands::ACL,
+        Commands::CONFIG,
+        Commands::INFO,
+        Commands::SLOWLOG,
+        Commands::LATENCY,
+        Commands::MONITOR,
+        Commands::OBJECT,
+        Commands::INCR,
+        Commands::DECR,
+        Commands::INCRBY,
+        Commands::DECRBY,
+        Commands::INCRBYFLOAT,
+        Commands::APPEND,
+        Commands::STRLEN,
+        Commands::GETRANGE,
+        Commands::SUBSTR,
+        Commands::SETRANGE,
+        Commands::LCS,
+        Commands::GETDEL,
+        Commands::GETEX,
+        Commands::GETSET,
+        Commands::SETNX,
+        Commands::SETEX,
+        Commands::PSETEX,
+        Commands::MGET,
+        Commands::MSET,
+        Commands::MSETNX,
+        Commands::DEL,
+        Commands::UNLINK,
+        Commands::EXISTS,
+        Commands::TYPE,
+        Commands::RENAME,
+        Commands::RENAMENX,
+        Commands::COPY,
+        Commands::TOUCH,
+        Commands::RANDOMKEY,
+        Commands::DBSIZE,
+        Commands::SCAN,
+        Commands::HSCAN,
+        Commands::KEYS,
+        Commands::SELECT,
+        Commands::MOVE,
+        Commands::SWAPDB,
+        Commands::FLUSHDB,
+        Commands::FLUSHALL,
+        Commands::SETBIT,
+        Commands::GETBIT,
+        Commands::BITCOUNT,
+        Commands::BITPOS,
+        Commands::BITOP,
+        Commands::BITFIELD,
+        Commands::BITFIELD_RO,
+        Commands::PFADD,
+        Commands::PFCOUNT,
+        Commands::PFMERGE,
+        Commands::PFDEBUG,
+        Commands::PFSELFTEST,
+    ];
+
+    ///Lowercase name, as used in ACL rules and error messages
+    pub fn name(&self) -> &'static str {
+        match self {
+            Commands::PING => "ping",
+            Commands::ECHO => "echo",
+            Commands::SET => "set",
+            Commands::GET => "get",
+            Commands::HELLO => "hello",
+            Commands::CLIENT => "client",
+            Commands::AUTH => "auth",
+            Commands::QUIT => "quit",
+            Commands::ACL => "acl",
+            Commands::CONFIG => "config",
+            Commands::INFO => "info",
+            Commands::SLOWLOG => "slowlog",
+            Commands::LATENCY => "latency",
+            Commands::MONITOR => "monitor",
+            Commands::OBJECT => "object",
+            Commands::INCR => "incr",
+            Commands::DECR => "decr",
+            Commands::INCRBY => "incrby",
+            Commands::DECRBY => "decrby",
+            Commands::INCRBYFLOAT => "incrbyfloat",
+            Commands::APPEND => "append",
+            Commands::STRLEN => "strlen",
+            Commands::GETRANGE => "getrange",
+            Commands::SUBSTR => "substr",
+            Commands::SETRANGE => "setrange",
+            Commands::LCS => "lcs",
+            Commands::GETDEL => "getdel",
+            Commands::GETEX => "getex",
+            Commands::GETSET => "getset",
+            Commands::SETNX => "setnx",
+            Commands::SETEX => "setex",
+            Commands::PSETEX => "psetex",
+            Commands::MGET => "mget",
+            Commands::MSET => "mset",
+            Commands::MSETNX => "msetnx",
+            Commands::DEL => "del",
+            Commands::UNLINK => "unlink",
+            Commands::EXISTS => "exists",
+            Commands::TYPE => "type",
+            Commands::RENAME => "rename",
+            Commands::RENAMENX => "renamenx",
+            Commands::COPY => "copy",
+            Commands::TOUCH => "touch",
+            Commands::RANDOMKEY => "randomkey",
+            Commands::DBSIZE => "dbsize",
+            Commands::SCAN => "scan",
+            Commands::HSCAN => "hscan",
+            Commands::KEYS => "keys",
+            Commands::SELECT => "select",
+            Commands::MOVE => "move",
+            Commands::SWAPDB => "swapdb",
+            Commands::FLUSHDB => "flushdb",
+            Commands::FLUSHALL => "flushall",
+            Commands::SETBIT => "setbit",
+            Commands::GETBIT => "getbit",
+            Commands::BITCOUNT => "bitcount",
+            Commands::BITPOS => "bitpos",
+            Commands::BITOP => "bitop",
+            Commands::BITFIELD => "bitfield",
+            Commands::BITFIELD_RO => "bitfield_ro",
+            Commands::PFADD => "pfadd",
+            Commands::PFCOUNT => "pfcount",
+            Commands::PFMERGE => "pfmerge",
+            Commands::PFDEBUG => "pfdebug",
+            Commands::PFSELFTEST => "pfselftest",
+        }
+    }
+
+    ///ACL categories the command belongs to
+    pub fn categories(&self) -> &'static [&'static str] {
+        match self {
+            Commands::PING | Commands::ECHO | Commands::HELLO | Commands::AUTH | Commands::QUIT => &["fast", "connection"],
+            Commands::SET => &["write", "string", "slow"],
+            Commands::GET => &["read", "string", "fast"],
+            Commands::CLIENT => &["slow", "connection"],
+            Commands::ACL | Commands::CONFIG | Commands::SLOWLOG | Commands::LATENCY
+                | Commands::MONITOR => &["admin", "slow", "dangerous"],
+            Commands::INFO => &["slow", "dangerous"],
+            Commands::OBJECT => &["keyspace", "read", "slow"],
+            Commands::INCR | Commands::DECR | Commands::INCRBY | Commands::DECRBY | Commands::INCRBYFLOAT
+                | Commands::APPEND => &["write", "string", "fast"],
+            Commands::GETDEL | Commands::GETEX | Commands::GETSET | Commands::SETNX => &["write", "string", "fast"],
+            Commands::SETRANGE | Commands::SETEX | Commands::PSETEX | Commands::MSET | Commands::MSETNX => &["write", "string", "slow"],
+            Commands::MGET => &["read", "string", "fast"],
+            Commands::DEL | Commands::RENAME | Commands::COPY => &["keyspace", "write", "slow"],
+            Commands::UNLINK | Commands::RENAMENX => &["keyspace", "write", "fast"],
+            Commands::EXISTS | Commands::TYPE | Commands::TOUCH | Commands::DBSIZE => &["keyspace", "read", "fast"],
+            Commands::RANDOMKEY | Commands::SCAN => &["keyspace", "read", "slow"],
+            Commands::HSCAN => &["read", "hash", "slow"],
+            Commands::KEYS => &["keyspace", "read", "slow", "dangerous"],
+            Commands::SELECT => &["fast", "connection"],
+            Commands::MOVE => &["keyspace", "write", "fast"],
+            Commands::SWAPDB => &["keyspace", "write", "fast", "dangerous"],
+            Commands::FLUSHDB | Commands::FLUSHALL => &["keyspace", "write", "slow", "dangerous"],
+            Commands::STRLEN => &["read", "string", "fast"],
+            Commands::GETRANGE | Commands::SUBSTR | Commands::LCS => &["read", "string", "slow"],
+            Commands::SETBIT | Commands::BITOP | Commands::BITFIELD => &["write", "bitmap", "slow"],
+            Commands::GETBIT | Commands::BITFIELD_RO => &["read", "bitmap", "fast"],
+            Commands::BITCOUNT | Commands::BITPOS => &["read", "bitmap", "slow"],
+            Commands::PFADD => &["write", "hyperloglog", "fast"],
+            Commands::PFCOUNT => &["read", "hyperloglog", "slow"],
+            Commands::PFMERGE => &["write", "hyperloglog", "slow"],
+            Commands::PFDEBUG => &["write", "hyperloglog", "admin", "slow", "dangerous"],
+            Commands::PFSELFTEST => &["hyperloglog", "admin", "slow", "dangerous"],
+        }
+    }
+
+    ///Container commands whose first argument selects what they do, e.g. CLIENT ID
+    pub fn has_subcommands(&self) -> bool {
+        matches!(self, Commands::CLIENT | Commands::ACL | Commands::CONFIG | Commands::SLOWLOG | Commands::LATENCY
+            | Commands::OBJECT)
+    }
+
+    ///Name including the subcommand for container commands, e.g. `client|id`
+    pub fn full_name(&self, args: &[RespValue]) -> String {
+        match args.get(1).and_then(|a| a.as_bytes()) {
+            Some(sub) if self.has_subcommands() => format!("{}|{}", self.name(), String::from_utf8_lossy(sub).to_ascii_lowercase()),
+            _ => self.name().to_string()
+        }
+    }
+
+    ///Whether the command can modify the keyspace
+    pub fn is_write(&self) -> bool {
+        matches!(self, Commands::SET | Commands::INCR | Commands::DECR | Commands::INCRBY | Commands::DECRBY
+            | Commands::INCRBYFLOAT | Commands::APPEND | Commands::SETRANGE | Commands::GETDEL | Commands::GETEX
+            | Commands::GETSET | Commands::SETNX | Commands::SETEX | Commands::PSETEX | Commands::MSET | Commands::MSETNX
+            | Commands::DEL | Commands::UNLINK | Commands::RENAME | Commands::RENAMENX | Commands::COPY | Commands::MOVE
+            | Commands::SWAPDB | Commands::FLUSHDB | Commands::FLUSHALL | Commands::SETBIT | Commands::BITOP
+            | Commands::BITFIELD | Commands::PFADD | Commands::PFMERGE | Commands::PFDEBUG)
+    }
+
+    ///Whether the command is refused once maxmemory is reached and nothing can be evicted.
+    ///Writes that only delete or move keys still run, they are how memory gets freed
+    pub fn denies_oom(&self) -> bool {
+        self.is_write() && !matches!(self, Commands::DEL | Commands::UNLINK | Commands::GETDEL | Commands::RENAME
+            | Commands::RENAMENX | Commands::MOVE | Commands::SWAPDB | Commands::FLUSHDB | Commands::FLUSHALL)
+    }
+
+    ///Whether the command works on a whole database rather than on the keys it names, so it
+    ///needs every shard of the keyspace
+    pub fn spans_keyspace(&self) -> bool {
+        matches!(self, Commands::KEYS | Commands::SCAN | Commands::RANDOMKEY | Commands::DBSIZE
+            | Commands::SWAPDB | Commands::FLUSHDB | Commands::FLUSHALL)
+    }
+
+    ///Returns the arguments of a request that are keys, `args` being the full request including
+    ///the command name
+    pub fn keys<'a>(&self, args: &'a [RespValue]) -> Vec<&'a RespValue> {
+        match self {
+            Commands::GET | Commands::SET | Commands::INCR | Commands::DECR | Commands::INCRBY
+                | Commands::DECRBY | Commands::INCRBYFLOAT | Commands::APPEND | Commands::STRLEN
+                | Commands::GETRANGE | Commands::SUBSTR | Commands::SETRANGE | Commands::GETDEL | Commands::GETEX
+                | Commands::GETSET | Commands::SETNX | Commands::SETEX | Commands::PSETEX | Commands::TYPE | Commands::HSCAN
+                | Commands::MOVE | Commands::SETBIT | Commands::GETBIT
+                | Commands::BITCOUNT | Commands::BITPOS | Commands::BITFIELD | Commands::BITFIELD_RO | Commands::PFADD => args.get(1).into_iter().collect(),
+            Commands::LCS | Commands::RENAME | Commands::RENAMENX | Commands::COPY => args.get(1..3).unwrap_or_default().iter().collect(),
+            Commands::MGET | Commands::DEL | Commands::UNLINK | Commands::EXISTS | Commands::TOUCH | Commands::PFCOUNT
+                | Commands::PFMERGE => args.iter().skip(1).collect(),
+            //BITOP <operation> destkey key [key ...]
+            Commands::BITOP => args.iter().skip(2).collect(),
+            //Keys and values alternate
+            Commands::MSET | Commands::MSETNX => args.iter().skip(1).step_by(2).collect(),
+            //OBJECT <subcommand> key, and the same for PFDEBUG
+            Commands::OBJECT | Commands::PFDEBUG => args.get(2).into_iter().collect(),
+            Commands::PING | Commands::ECHO | Commands::HELLO | Commands::CLIENT
+                | Commands::AUTH | Commands::QUIT | Commands::ACL | Commands::CONFIG
+                | Commands::INFO | Commands::SLOWLOG | Commands::LATENCY | Commands::MONITOR | Commands::RANDOMKEY
+                | Commands::DBSIZE | Commands::SCAN | Commands::KEYS | Commands::SELECT | Commands::SWAPDB
+                | Commands::FLUSHDB | Commands::FLUSHALL | Commands::PFSELFTEST => Vec::new()
+        }
+    }
+
+    ///What the command does with the key at `position` among those `keys` returns. As in Redis
+    ///a key is read when its value is returned or used, and written when it is inserted, updated
+    ///or deleted. Keys only overwritten, like the destination of COPY, are never read
+    pub fn key_access(&self, args: &[RespValue], position: usize) -> KeyAccess {
+        match self {
+            //SET only returns the old value with GET
+            Commands::SET => match args.iter().skip(3).any(|a| a.as_bytes().is_some_and(|a| a.eq_ignore_ascii_case(b"GET"))) {
+                true => KeyAccess::ReadWrite,
+                false => KeyAccess::Write
+            },
+            Commands::GET | Commands::STRLEN | Commands::GETRANGE | Commands::SUBSTR | Commands::LCS
+                | Commands::MGET | Commands::EXISTS | Commands::TYPE | Commands::TOUCH | Commands::HSCAN
+                | Commands::GETBIT | Commands::BITCOUNT | Commands::BITPOS
+                | Commands::BITFIELD_RO | Commands::PFCOUNT | Commands::OBJECT | Commands::PFDEBUG => KeyAccess::Read,
+            Commands::APPEND | Commands::SETRANGE | Commands::SETNX | Commands::SETEX | Commands::PSETEX
+                | Commands::MSET | Commands::MSETNX | Commands::DEL | Commands::UNLINK | Commands::PFADD => KeyAccess::Write,
+            Commands::INCR | Commands::DECR | Commands::INCRBY | Commands::DECRBY | Commands::INCRBYFLOAT
+                | Commands::GETDEL | Commands::GETEX | Commands::GETSET | Commands::MOVE | Commands::SETBIT
+                | Commands::BITFIELD => KeyAccess::ReadWrite,
+            //The source is read then deleted, the destination only written
+            Commands::RENAME | Commands::RENAMENX => match position {
+                0 => KeyAccess::ReadWrite,
+                _ => KeyAccess::Write
+            },
+            Commands::COPY => match position {
+                0 => KeyAccess::Read,
+                _ => KeyAccess::Write
+            },
+            //Destination first, then the sources
+            Commands::BITOP => match position {
+                0 => KeyAccess::Write,
+                _ => KeyAccess::Read
+            },
+            //The destination's own registers are merged in
+            Commands::PFMERGE => match position {
+                0 => KeyAccess::ReadWrite,
+                _ => KeyAccess::Read
+            },
+            Commands::PING | Commands::ECHO | Commands::HELLO | Commands::CLIENT
+                | Commands::AUTH | Commands::QUIT | Commands::ACL | Commands::CONFIG
+                | Commands::INFO | Commands::SLOWLOG | Commands::LATENCY | Commands::MONITOR | Commands::RANDOMKEY
+                | Commands::DBSIZE | Commands::SCAN | Commands::KEYS | Commands::SELECT | Commands::SWAPDB
+                | Commands::FLUSHDB | Commands::FLUSHALL | Commands::PFSELFTEST => KeyAccess::Read
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::{args, bulk};
+
+    #[test]
+    fn set_is_write_get_is_not() {
+        assert!(Commands::SET.is_write());
+        assert!(!Commands::GET.is_write());
+    }
+
+    #[test]
+    fn deletions_are_allowed_when_out_of_memory() {
+        assert!(Commands::SET.denies_oom());
+        assert!(!Commands::FLUSHALL.denies_oom());
+        assert!(!Commands::DEL.denies_oom());
+        assert!(!Commands::GET.denies_oom());
+    }
+
+    #[test]
+    fn keyspace_wide_commands_name_no_keys() {
+        for command in Commands::ALL.into_iter().filter(Commands::spans_keyspace) {
+            assert!(command.keys(&[bulk(command.name()), bulk("arg"), bulk("arg")]).is_empty());
+        }
+        assert!(!Commands::MGET.spans_keyspace());
+    }
+
+    #[test]
+    fn keys_of_set() {
+        let args = vec![bulk("SET"), bulk("key"), bulk("value")];
+        assert_eq!(Commands::SET.keys(&args), vec![&bulk("key")]);
+    }
+
+    #[test]
+    fn keys_are_read_or_written_by_position() {
+        let access = |command: Commands, parts: &[&str]| {
+            let args = args(parts);
+            (0..command.keys(&args).len()).map(|i| command.key_access(&args, i)).collect::<Vec<_>>()
+        };
+        assert_eq!(access(Commands::SET, &["SET", "k", "v"]), [KeyAccess::Write]);
+        assert_eq!(access(Commands::SET, &["SET", "k", "v", "get"]), [KeyAccess::ReadWrite]);
+        assert_eq!(access(Commands::COPY, &["COPY", "src", "dst"]), [KeyAccess::Read, KeyAccess::Write]);
+        assert_eq!(access(Commands::RENAME, &["RENAME", "src", "dst"]), [KeyAccess::ReadWrite, KeyAccess::Write]);
+        assert_eq!(access(Commands::BITOP, &["BITOP", "AND", "dst", "a", "b"]), [KeyAccess::Write, KeyAccess::Read, KeyAccess::Read]);
+        assert_eq!(access(Commands::PFMERGE, &["PFMERGE", "dst", "a"]), [KeyAccess::ReadWrite, KeyAccess::Read]);
+        assert_eq!(access(Commands::LCS, &["LCS", "a", "b"]), [KeyAccess::Read, KeyAccess::Read]);
+    }
+
+    #[test]
+    fn keys_of_ping_is_empty() {
+        let args = vec![bulk("PING")];
+        assert!(Commands::PING.keys(&args).is_empty());
+    }
+
+    #[test]
+    fn full_name_includes_subcommand() {
+        assert_eq!(Commands::ACL.full_name(&[bulk("ACL"), bulk("WHOAMI")]), "acl|whoami");
+        assert_eq!(Commands::GET.full_name(&[bulk("GET"), bulk("k")]), "get");
+    }
+
+    #[test]
+    fn name_round_trips_through_from_bytes() {
+        for command in Commands::ALL {
+            assert_eq!(Commands::from_bytes(command.name().as_bytes()), Some(command));
+        }
+    }
+
+    #[test]
+    fn categories_are_known() {
+        for command in Commands::ALL {
+            for category in command.categories() {
+                assert!(CATEGORIES.contains(category), "{:?} has unknown category {}", command, category);
+            }
+        }
+    }
+}
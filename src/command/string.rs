@@ -0,0 +1,586 @@
+use crate::{command::CommandError, resp::RespValue, store::{expire::unix_time_ms, string::{encode_string, parse_float, parse_integer, string_bytes, substring, MAX_STRING_LEN}, value::{Store, StoreError}}};
+
+impl From<StoreError> for CommandError {
+    fn from(e: StoreError) -> Self {
+        let message = match e {
+            StoreError::Failed | StoreError::NotFound => return CommandError::InvalidRequest,
+            StoreError::WrongType => "WRONGTYPE Operation against a key holding the wrong kind of value",
+            StoreError::NotInteger => "ERR value is not an integer or out of range",
+            StoreError::NotFloat => "ERR value is not a valid float",
+            StoreError::Overflow => "ERR increment or decrement would overflow",
+            StoreError::NotFinite => "ERR increment would produce NaN or Infinity",
+            StoreError::TooLarge => "ERR string exceeds maximum allowed size (proto-max-bulk-len)",
+            StoreError::InvalidHll => "WRONGTYPE Key is not a valid HyperLogLog string value.",
+            StoreError::CorruptHll => "INVALIDOBJ Corrupted HLL object detected",
+        };
+        CommandError::Custom(message.to_string())
+    }
+}
+
+fn bulk(bytes: Vec<u8>) -> RespValue {
+    RespValue::BulkString(Some(bytes))
+}
+
+///Bytes of a string argument, which is how keys are passed to the store
+pub fn arg_bytes(arg: &RespValue) -> Result<&[u8], CommandError> {
+    arg.as_bytes().ok_or(CommandError::InvalidRequest)
+}
+
+pub fn integer_arg(arg: &RespValue) -> Result<i64, CommandError> {
+    parse_integer(arg_bytes(arg)?).ok_or_else(|| StoreError::NotInteger.into())
+}
+
+fn null_or_bulk(value: Option<Vec<u8>>) -> RespValue {
+    RespValue::BulkString(value)
+}
+
+fn ok() -> RespValue {
+    RespValue::SimpleString(b"OK".to_vec())
+}
+
+///Time to live option of SET and GETEX
+#[derive(Debug, PartialEq)]
+enum Expiry {
+    Keep,
+    Persist,
+    //Unix time in milliseconds
+    At(u64),
+}
+
+///Unix time in milliseconds from an EX, PX, EXAT or PXAT argument
+fn expire_at(unit: &[u8], value: &RespValue, command: &str) -> Result<u64, CommandError> {
+    let value = integer_arg(value)?;
+    let invalid = || CommandError::Custom(format!("ERR invalid expire time in '{}' command", command));
+    if value <= 0 {
+        return Err(invalid());
+    }
+    let ms = match unit {
+        b"EX" | b"EXAT" => value.checked_mul(1000).ok_or_else(invalid)?,
+        _ => value
+    };
+    let at = match unit {
+        b"EX" | b"PX" => ms.checked_add(unix_time_ms() as i64).ok_or_else(invalid)?,
+        _ => ms
+    };
+    Ok(at as u64)
+}
+
+///Options of SET and GETEX
+struct StringOptions {
+    //NX or XX
+    condition: Option<Vec<u8>>,
+    get: bool,
+    expiry: Option<Expiry>,
+}
+
+///Parses the options of SET, or of GETEX when `set` is false. An option may be repeated but
+///not combined with one it conflicts with
+fn parse_string_options(options: &[RespValue], set: bool, command: &str) -> Result<StringOptions, CommandError> {
+    let mut condition: Option<Vec<u8>> = None;
+    let mut get = false;
+    let mut expiry: Option<(Vec<u8>, Option<&RespValue>)> = None;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        let option = arg_bytes(option)?.to_ascii_uppercase();
+        let condition_allowed = condition.as_ref().is_none_or(|c| *c == option);
+        let expiry_allowed = expiry.as_ref().is_none_or(|(e, _)| *e == option);
+        match option.as_slice() {
+            b"NX" | b"XX" if set && condition_allowed => condition = Some(option),
+            b"GET" if set => get = true,
+            b"KEEPTTL" if set && expiry_allowed => expiry = Some((option, None)),
+            b"PERSIST" if !set && expiry_allowed => expiry = Some((option, None)),
+            b"EX" | b"PX" | b"EXAT" | b"PXAT" if expiry_allowed => {
+                let value = options.next().ok_or(CommandError::Syntax)?;
+                expiry = Some((option, Some(value)));
+            },
+            _ => return Err(CommandError::Syntax)
+        }
+    }
+    let expiry = match expiry {
+        None => None,
+        Some((unit, Some(value))) => Some(Expiry::At(expire_at(&unit, value, command)?)),
+        Some((unit, None)) if unit == b"KEEPTTL" => Some(Expiry::Keep),
+        Some(_) => Some(Expiry::Persist),
+    };
+    Ok(StringOptions { condition, get, expiry })
+}
+
+fn apply_expiry(store: &mut Store, key: &[u8], expiry: Expiry) {
+    match expiry {
+        Expiry::Keep => {},
+        Expiry::Persist => { store.persist(key); },
+        Expiry::At(at) => store.set_expire(key, at),
+    }
+}
+
+///Writes a string value, which loses its time to live unless told otherwise
+fn write_string(store: &mut Store, key: &[u8], value: &[u8], expiry: Option<Expiry>) {
+    store.write(key, encode_string(value.to_vec()));
+    apply_expiry(store, key, expiry.unwrap_or(Expiry::Persist));
+}
+
+pub fn handle_get(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    Ok(null_or_bulk(store.get(key)?))
+}
+
+///SET key value [NX|XX] [GET] [EX seconds|PX milliseconds|EXAT timestamp|PXAT timestamp|KEEPTTL]
+pub fn handle_set(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, value, options @ ..] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    let value = arg_bytes(value)?;
+    let StringOptions { condition, get, expiry } = parse_string_options(options, true, "set")?;
+    //With GET a key of another type is an error and is left untouched
+    let old = match get {
+        true => Some(store.get(key)?),
+        false => None
+    };
+    let exists = store.lookup_write(key).is_some();
+    let skip = match condition.as_deref() {
+        Some(b"NX") => exists,
+        Some(_) => !exists,
+        None => false
+    };
+    if !skip {
+        write_string(store, key, value, expiry);
+    }
+    Ok(match (old, skip) {
+        (Some(old), _) => null_or_bulk(old),
+        (None, true) => RespValue::BulkString(None),
+        (None, false) => ok()
+    })
+}
+
+pub fn handle_getdel(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    let value = store.get(key)?;
+    if value.is_some() {
+        store.remove(key);
+    }
+    Ok(null_or_bulk(value))
+}
+
+///GETEX key [EX seconds|PX milliseconds|EXAT timestamp|PXAT timestamp|PERSIST]
+pub fn handle_getex(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, options @ ..] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    let expiry = parse_string_options(options, false, "getex")?.expiry;
+    let value = store.get(key)?;
+    //Without options GETEX is a plain GET
+    if value.is_some() && let Some(expiry) = expiry {
+        apply_expiry(store, key, expiry);
+    }
+    Ok(null_or_bulk(value))
+}
+
+pub fn handle_getset(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, value] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    let old = store.get(key)?;
+    write_string(store, key, arg_bytes(value)?, None);
+    Ok(null_or_bulk(old))
+}
+
+pub fn handle_setnx(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, value] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    if store.lookup_write(key).is_some() {
+        return Ok(RespValue::Integer(0));
+    }
+    write_string(store, key, arg_bytes(value)?, None);
+    Ok(RespValue::Integer(1))
+}
+
+///Values of several keys, keys missing or of another type are null
+pub fn handle_mget(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    if args.is_empty() {
+        return Err(CommandError::WrongArity);
+    }
+    let values = args.iter()
+        .map(|key| Ok(null_or_bulk(store.get(arg_bytes(key)?).ok().flatten())))
+        .collect::<Result<_, CommandError>>()?;
+    Ok(RespValue::Arrays(Some(values)))
+}
+
+///MSET, or MSETNX when `nx` is set, which writes every pair or none of them if one of the
+///keys exists
+pub fn handle_mset(args: &[RespValue], store: &mut Store, nx: bool) -> Result<RespValue, CommandError> {
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Err(CommandError::WrongArity);
+    }
+    let pairs = args.chunks(2)
+        .map(|pair| Ok((arg_bytes(&pair[0])?, arg_bytes(&pair[1])?)))
+        .collect::<Result<Vec<_>, CommandError>>()?;
+    if nx && pairs.iter().any(|(key, _)| store.lookup_write(key).is_some()) {
+        return Ok(RespValue::Integer(0));
+    }
+    for (key, value) in pairs {
+        write_string(store, key, value, None);
+    }
+    Ok(match nx {
+        true => RespValue::Integer(1),
+        false => ok()
+    })
+}
+
+///SETEX, or PSETEX when the time to live is in milliseconds
+pub fn handle_setex(args: &[RespValue], store: &mut Store, milliseconds: bool) -> Result<RespValue, CommandError> {
+    let [key, ttl, value] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    let at = match milliseconds {
+        true => expire_at(b"PX", ttl, "psetex")?,
+        false => expire_at(b"EX", ttl, "setex")?
+    };
+    write_string(store, key, arg_bytes(value)?, Some(Expiry::At(at)));
+    Ok(ok())
+}
+
+///INCR, DECR, INCRBY and DECRBY, `sign` being -1 for the DECR variants
+pub fn handle_incr(args: &[RespValue], store: &mut Store, by_argument: bool, sign: i64) -> Result<RespValue, CommandError> {
+    let delta = match (args, by_argument) {
+        ([_], false) => 1,
+        ([_, delta], true) => integer_arg(delta)?,
+        _ => return Err(CommandError::WrongArity)
+    };
+    //DECRBY of i64::MIN cannot be negated
+    let delta = delta.checked_mul(sign).ok_or(StoreError::Overflow)?;
+    Ok(RespValue::Integer(store.incr_by(arg_bytes(&args[0])?, delta)?))
+}
+
+pub fn handle_incrbyfloat(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, delta] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    let delta = parse_float(arg_bytes(delta)?).ok_or(StoreError::NotFloat)?;
+    Ok(bulk(store.incr_by_float(key, delta)?))
+}
+
+pub fn handle_append(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, value] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    Ok(RespValue::Integer(store.append(key, arg_bytes(value)?)? as i64))
+}
+
+pub fn handle_strlen(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    Ok(RespValue::Integer(store.strlen(key)? as i64))
+}
+
+///GETRANGE and its deprecated alias SUBSTR
+pub fn handle_getrange(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, start, end] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    let (start, end) = (integer_arg(start)?, integer_arg(end)?);
+    let value = store.get(key)?.unwrap_or_default();
+    Ok(bulk(substring(&value, start, end).to_vec()))
+}
+
+pub fn handle_setrange(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    let [key, offset, value] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    let key = arg_bytes(key)?;
+    let offset = integer_arg(offset)?;
+    if offset < 0 {
+        return Err(CommandError::Custom("ERR offset is out of range".to_string()));
+    }
+    Ok(RespValue::Integer(store.setrange(key, offset as usize, arg_bytes(value)?)? as i64))
+}
+
+///A common substring of the LCS, as inclusive ranges in both strings
+#[derive(Debug, PartialEq)]
+struct LcsMatch {
+    a: (usize, usize),
+    b: (usize, usize),
+}
+
+///Longest common subsequence of two strings, with the contiguous ranges it is made of from the
+///end of the strings to their start
+fn lcs(a: &[u8], b: &[u8]) -> (Vec<u8>, Vec<LcsMatch>) {
+    let width = b.len() + 1;
+    //table[i * width + j] is the LCS length of a[..i] and b[..j]
+    let mut table = vec![0u32; (a.len() + 1) * width];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            table[i * width + j] = match a[i - 1] == b[j - 1] {
+                true => table[(i - 1) * width + j - 1] + 1,
+                false => table[(i - 1) * width + j].max(table[i * width + j - 1])
+            };
+        }
+    }
+
+    let mut result = Vec::with_capacity(table[a.len() * width + b.len()] as usize);
+    let mut matches = Vec::new();
+    let mut range: Option<LcsMatch> = None;
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 && j > 0 {
+        let mut emit = false;
+        if a[i - 1] == b[j - 1] {
+            result.push(a[i - 1]);
+            match &mut range {
+                //Contiguous with the current range, which grows backwards
+                Some(current) if current.a.0 == i && current.b.0 == j => {
+                    current.a.0 -= 1;
+                    current.b.0 -= 1;
+                },
+                Some(_) => emit = true,
+                None => range = Some(LcsMatch { a: (i - 1, i - 1), b: (j - 1, j - 1) })
+            }
+            i -= 1;
+            j -= 1;
+            emit |= i == 0 || j == 0;
+        } else {
+            match table[(i - 1) * width + j] > table[i * width + j - 1] {
+                true => i -= 1,
+                false => j -= 1
+            }
+            emit = range.is_some();
+        }
+        if emit && let Some(current) = range.take() {
+            matches.push(current);
+        }
+    }
+    result.reverse();
+    (result, matches)
+}
+
+///LCS key1 key2 [LEN] [IDX] [MINMATCHLEN len] [WITHMATCHLEN]
+pub fn handle_lcs(args: &[RespValue], store: &mut Store) -> Result<RespValue, CommandError> {
+    if args.len() < 2 {
+        return Err(CommandError::WrongArity);
+    }
+    let (mut len, mut idx, mut with_match_len, mut min_match_len) = (false, false, false, 0);
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        match arg_bytes(option)?.to_ascii_uppercase().as_slice() {
+            b"LEN" => len = true,
+            b"IDX" => idx = true,
+            b"WITHMATCHLEN" => with_match_len = true,
+            b"MINMATCHLEN" => {
+                let value = integer_arg(options.next().ok_or(CommandError::Syntax)?)?;
+                min_match_len = value.max(0) as usize;
+            },
+            _ => return Err(CommandError::Syntax)
+        }
+    }
+    if len && idx {
+        return Err(CommandError::Custom("ERR If you want both the length and indexes, please just use IDX.".to_string()));
+    }
+
+    let mut strings = Vec::with_capacity(2);
+    for key in &args[..2] {
+        //Missing keys are empty strings, but other types are still an error
+        strings.push(match store.lookup_read(arg_bytes(key)?) {
+            Some(value) => string_bytes(value)?.into_owned(),
+            None => Vec::new()
+        });
+    }
+    //The table holds a length for every pair of positions, bounded as Redis bounds it so two
+    //large strings cannot exhaust memory
+    let cells = (strings[0].len() + 1).checked_mul(strings[1].len() + 1);
+    if cells.is_none_or(|cells| cells > u32::MAX as usize || cells.saturating_mul(size_of::<u32>()) > MAX_STRING_LEN) {
+        return Err(CommandError::Custom("ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len".to_string()));
+    }
+    let (result, matches) = lcs(&strings[0], &strings[1]);
+
+    if len {
+        return Ok(RespValue::Integer(result.len() as i64));
+    }
+    if !idx {
+        return Ok(bulk(result));
+    }
+    let range = |(start, end): (usize, usize)| RespValue::Arrays(Some(vec![
+        RespValue::Integer(start as i64),
+        RespValue::Integer(end as i64),
+    ]));
+    let matches = matches.into_iter()
+        .filter(|m| m.a.1 - m.a.0 + 1 >= min_match_len)
+        .map(|m| {
+            let mut reply = vec![range(m.a), range(m.b)];
+            if with_match_len {
+                reply.push(RespValue::Integer((m.a.1 - m.a.0 + 1) as i64));
+            }
+            RespValue::Arrays(Some(reply))
+        })
+        .collect();
+    Ok(RespValue::Map(vec![
+        (bulk(b"matches".to_vec()), RespValue::Arrays(Some(matches))),
+        (bulk(b"len".to_vec()), RespValue::Integer(result.len() as i64)),
+    ]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::value::Value;
+    use crate::test_helpers::{args, bulk};
+
+    #[test]
+    fn set_conditions_and_get() {
+        let mut store = Store::new();
+        let null = RespValue::BulkString(None);
+        assert_eq!(handle_set(&args(&["k", "v1", "XX"]), &mut store), Ok(null.clone()));
+        assert_eq!(handle_set(&args(&["k", "v1", "nx"]), &mut store), Ok(ok()));
+        assert_eq!(handle_set(&args(&["k", "v2", "NX", "GET"]), &mut store), Ok(bulk("v1")));
+        assert_eq!(handle_set(&args(&["k", "v2", "XX", "GET"]), &mut store), Ok(bulk("v1")));
+        assert_eq!(handle_get(&args(&["k"]), &mut store), Ok(bulk("v2")));
+        assert_eq!(handle_set(&args(&["new", "v", "GET"]), &mut store), Ok(null));
+        store.write(b"list", Value::List(Default::default()));
+        assert_eq!(handle_set(&args(&["list", "v", "GET"]), &mut store), Err(StoreError::WrongType.into()));
+        assert_eq!(handle_set(&args(&["list", "v"]), &mut store), Ok(ok()));
+    }
+
+    #[test]
+    fn set_rejects_conflicting_options() {
+        let mut store = Store::new();
+        for options in [&["NX", "XX"][..], &["EX", "10", "PX", "100"], &["EX", "10", "KEEPTTL"], &["EX"], &["PERSIST"], &["FOO"]] {
+            let request = [&["k", "v"][..], options].concat();
+            assert_eq!(handle_set(&args(&request), &mut store), Err(CommandError::Syntax), "{:?}", options);
+        }
+        assert_eq!(handle_set(&args(&["k", "v", "NX", "NX", "EX", "1", "EX", "10"]), &mut store), Ok(ok()));
+        assert_eq!(handle_set(&args(&["k", "v", "EX", "x"]), &mut store), Err(StoreError::NotInteger.into()));
+        assert_eq!(
+            handle_set(&args(&["k", "v", "PX", "0"]), &mut store),
+            Err(CommandError::Custom("ERR invalid expire time in 'set' command".to_string()))
+        );
+        assert!(handle_set(&args(&["k", "v", "EX", &(i64::MAX / 100).to_string()]), &mut store).is_err());
+    }
+
+    #[test]
+    fn set_expiry_options() {
+        let mut store = Store::new();
+        handle_set(&args(&["k", "v", "EX", "100"]), &mut store).unwrap();
+        assert!(store.ttl_ms(b"k").is_some_and(|ttl| ttl > 99_000 && ttl <= 100_000));
+        handle_set(&args(&["k", "v2", "KEEPTTL"]), &mut store).unwrap();
+        assert!(store.ttl_ms(b"k").is_some());
+        handle_incr(&args(&["k"]), &mut store, false, 1).unwrap_err();
+        handle_set(&args(&["k", "v3"]), &mut store).unwrap();
+        assert_eq!(store.ttl_ms(b"k"), None);
+        let at = (unix_time_ms() + 5000).to_string();
+        handle_set(&args(&["k", "v", "PXAT", &at]), &mut store).unwrap();
+        assert_eq!(store.expires[&b"k"[..]].to_string(), at);
+        //An absolute time in the past deletes the key
+        handle_set(&args(&["k", "v", "EXAT", "1"]), &mut store).unwrap();
+        assert_eq!(handle_get(&args(&["k"]), &mut store), Ok(RespValue::BulkString(None)));
+    }
+
+    #[test]
+    fn get_and_set_variants() {
+        let mut store = Store::new();
+        assert_eq!(handle_setnx(&args(&["k", "1"]), &mut store), Ok(RespValue::Integer(1)));
+        assert_eq!(handle_setnx(&args(&["k", "2"]), &mut store), Ok(RespValue::Integer(0)));
+        assert_eq!(handle_getset(&args(&["k", "3"]), &mut store), Ok(bulk("1")));
+        assert_eq!(handle_setex(&args(&["k", "10", "4"]), &mut store, false), Ok(ok()));
+        assert!(store.ttl_ms(b"k").is_some_and(|ttl| ttl > 9_000));
+        assert_eq!(handle_getex(&args(&["k"]), &mut store), Ok(bulk("4")));
+        assert!(store.ttl_ms(b"k").is_some());
+        assert_eq!(handle_getex(&args(&["k", "PERSIST"]), &mut store), Ok(bulk("4")));
+        assert_eq!(store.ttl_ms(b"k"), None);
+        assert_eq!(handle_getex(&args(&["k", "PX", "5000"]), &mut store), Ok(bulk("4")));
+        assert!(store.ttl_ms(b"k").is_some_and(|ttl| ttl <= 5_000));
+        assert_eq!(handle_getex(&args(&["k", "KEEPTTL"]), &mut store), Err(CommandError::Syntax));
+        assert!(handle_setex(&args(&["k", "-1", "v"]), &mut store, true).is_err());
+        assert_eq!(handle_getdel(&args(&["k"]), &mut store), Ok(bulk("4")));
+        assert_eq!(handle_getdel(&args(&["k"]), &mut store), Ok(RespValue::BulkString(None)));
+        assert!(store.map.is_empty() && store.expires.is_empty());
+    }
+
+    #[test]
+    fn multi_key_commands() {
+        let mut store = Store::new();
+        assert_eq!(handle_mset(&args(&["a", "1", "b"]), &mut store, false), Err(CommandError::WrongArity));
+        assert_eq!(handle_mset(&args(&["a", "1", "b", "2"]), &mut store, false), Ok(ok()));
+        assert_eq!(handle_mset(&args(&["c", "3", "a", "x"]), &mut store, true), Ok(RespValue::Integer(0)));
+        assert!(!store.map.contains_key(&b"c"[..]));
+        assert_eq!(handle_mset(&args(&["c", "3", "d", "4"]), &mut store, true), Ok(RespValue::Integer(1)));
+        store.write(b"list", Value::List(Default::default()));
+        assert_eq!(handle_mget(&args(&["a", "missing", "list", "d"]), &mut store), Ok(RespValue::Arrays(Some(vec![
+            bulk("1"), RespValue::BulkString(None), RespValue::BulkString(None), bulk("4"),
+        ]))));
+    }
+
+    #[test]
+    fn incr_family() {
+        let mut store = Store::new();
+        assert_eq!(handle_incr(&args(&["n"]), &mut store, false, 1), Ok(RespValue::Integer(1)));
+        assert_eq!(handle_incr(&args(&["n", "10"]), &mut store, true, -1), Ok(RespValue::Integer(-9)));
+        assert_eq!(handle_incr(&args(&["n", "x"]), &mut store, true, 1), Err(StoreError::NotInteger.into()));
+        assert_eq!(handle_incr(&args(&["n", &i64::MIN.to_string()]), &mut store, true, -1), Err(StoreError::Overflow.into()));
+        assert_eq!(handle_incr(&args(&["n", "1"]), &mut store, false, 1), Err(CommandError::WrongArity));
+        assert_eq!(handle_incrbyfloat(&args(&["n", "1.5"]), &mut store), Ok(bulk("-7.5")));
+        assert_eq!(handle_incrbyfloat(&args(&["n", "nan"]), &mut store), Err(StoreError::NotFloat.into()));
+    }
+
+    #[test]
+    fn ranges() {
+        let mut store = Store::new();
+        assert_eq!(handle_setrange(&args(&["k", "6", "Redis"]), &mut store), Ok(RespValue::Integer(11)));
+        assert_eq!(handle_setrange(&args(&["k", "0", "Hello"]), &mut store), Ok(RespValue::Integer(11)));
+        assert_eq!(handle_getrange(&args(&["k", "0", "-1"]), &mut store), Ok(bulk("Hello\0Redis")));
+        assert_eq!(handle_getrange(&args(&["missing", "0", "-1"]), &mut store), Ok(bulk("")));
+        assert!(handle_setrange(&args(&["k", "-1", "x"]), &mut store).is_err());
+        assert_eq!(handle_append(&args(&["k", "!"]), &mut store), Ok(RespValue::Integer(12)));
+        assert_eq!(handle_strlen(&args(&["k"]), &mut store), Ok(RespValue::Integer(12)));
+    }
+
+    #[test]
+    fn lcs_matches_redis() {
+        let mut store = Store::new();
+        store.set(b"key1", b"ohmytext".to_vec());
+        store.set(b"key2", b"mynewtext".to_vec());
+        assert_eq!(handle_lcs(&args(&["key1", "key2"]), &mut store), Ok(bulk("mytext")));
+        assert_eq!(handle_lcs(&args(&["key1", "key2", "LEN"]), &mut store), Ok(RespValue::Integer(6)));
+        assert!(handle_lcs(&args(&["key1", "key2", "LEN", "IDX"]), &mut store).is_err());
+
+        let pair = |a: i64, b: i64| RespValue::Arrays(Some(vec![RespValue::Integer(a), RespValue::Integer(b)]));
+        let reply = handle_lcs(&args(&["key1", "key2", "IDX", "MINMATCHLEN", "4", "WITHMATCHLEN"]), &mut store);
+        assert_eq!(reply, Ok(RespValue::Map(vec![
+            (bulk("matches"), RespValue::Arrays(Some(vec![
+                RespValue::Arrays(Some(vec![pair(4, 7), pair(5, 8), RespValue::Integer(4)])),
+            ]))),
+            (bulk("len"), RespValue::Integer(6)),
+        ])));
+        let reply = handle_lcs(&args(&["key1", "key2", "IDX"]), &mut store).unwrap();
+        let RespValue::Map(pairs) = reply else { panic!() };
+        assert_eq!(pairs[0].1, RespValue::Arrays(Some(vec![
+            RespValue::Arrays(Some(vec![pair(4, 7), pair(5, 8)])),
+            RespValue::Arrays(Some(vec![pair(2, 3), pair(0, 1)])),
+        ])));
+    }
+
+    #[test]
+    fn lcs_refuses_tables_over_proto_max_bulk_len() {
+        let mut store = Store::new();
+        //12000 by 12000 positions need a 576MB table
+        store.set(b"a", vec![b'a'; 12_000]);
+        store.set(b"b", vec![b'b'; 12_000]);
+        let error = Err(CommandError::Custom("ERR Insufficient memory, transient memory for LCS exceeds proto-max-bulk-len".to_string()));
+        assert_eq!(handle_lcs(&args(&["a", "b", "LEN"]), &mut store), error);
+        store.set(b"b", vec![b'a'; 100]);
+        assert_eq!(handle_lcs(&args(&["a", "b", "LEN"]), &mut store), Ok(RespValue::Integer(100)));
+    }
+}
@@ -1,15 +1,92 @@
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 pub enum Commands {
     PING,
     ECHO,
     SET,
-    GET
+    GET,
+    HELLO,
+    CLIENT,
+    AUTH,
+    QUIT,
+    ACL,
+    CONFIG,
+    INFO,
+    SLOWLOG,
+    LATENCY,
+    MONITOR,
+    OBJECT,
+    INCR,
+    DECR,
+    INCRBY,
+    DECRBY,
+    INCRBYFLOAT,
+    APPEND,
+    STRLEN,
+    GETRANGE,
+    SUBSTR,
+    SETRANGE,
+    LCS,
+    GETDEL,
+    GETEX,
+    GETSET,
+    SETNX,
+    SETEX,
+    PSETEX,
+    MGET,
+    MSET,
+    MSETNX,
+    DEL,
+    UNLINK,
+    EXISTS,
+    TYPE,
+    RENAME,
+    RENAMENX,
+    COPY,
+    TOUCH,
+    RANDOMKEY,
+    DBSIZE,
+    SCAN,
+    HSCAN,
+    KEYS,
+    SELECT,
+    MOVE,
+    SWAPDB,
+    FLUSHDB,
+    FLUSHALL,
+    SETBIT,
+    GETBIT,
+    BITCOUNT,
+    BITPOS,
+    BITOP,
+    BITFIELD,
+    //Named like the command, which has an underscore
+    #[allow(non_camel_case_types)]
+    BITFIELD_RO,
+    PFADD,
+    PFCOUNT,
+    PFMERGE,
+    PFDEBUG,
+    PFSELFTEST
+}
+
+///What a command does with one of its keys, which decides the ACL key permissions it needs
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum KeyAccess {
+    Read,
+    Write,
+    ReadWrite
 }
 
 #[derive(Debug, PartialEq)]
 pub enum CommandError{
     ParseFailed,
     InvalidRequest,
-    UnknownCommand
+    UnknownCommand,
+    WrongArity,
+    Syntax,
+    NoAuth,
+    NoPerm(String),
+    //Errors carrying their own message, including the error prefix
+    Custom(String)
 }
 
@@ -0,0 +1,181 @@
+//Glob matching with the semantics of stringmatchlen in Redis: `*`, `?`, `[a-z]`, `[^x]` and
+//backslash escapes. Used for key patterns in KEYS, SCAN and ACL rules, and for CONFIG GET
+
+//Deepest `*` recursion allowed, deeper patterns never match instead of exhausting the stack
+const MAX_NESTING: usize = 1000;
+
+///Whether `string` matches the glob `pattern`, ignoring ASCII case when `nocase` is set
+pub fn string_match(pattern: &[u8], string: &[u8], nocase: bool) -> bool {
+    let mut skip_longer_matches = false;
+    match_from(pattern, string, nocase, &mut skip_longer_matches, 0)
+}
+
+fn eq(a: u8, b: u8, nocase: bool) -> bool {
+    match nocase {
+        true => a.eq_ignore_ascii_case(&b),
+        false => a == b
+    }
+}
+
+//`skip_longer_matches` is set once a `*` failed against every suffix, which means no later
+//`*` can succeed either and keeps patterns like `a*a*a*b` from going exponential
+fn match_from(mut pattern: &[u8], mut string: &[u8], nocase: bool, skip_longer_matches: &mut bool, nesting: usize) -> bool {
+    if nesting > MAX_NESTING {
+        return false;
+    }
+    while let Some(&p) = pattern.first() {
+        match p {
+            b'*' => {
+                while pattern.len() > 1 && pattern[1] == b'*' {
+                    pattern = &pattern[1..];
+                }
+                if pattern.len() == 1 {
+                    return true;
+                }
+                while !string.is_empty() {
+                    if match_from(&pattern[1..], string, nocase, skip_longer_matches, nesting + 1) {
+                        return true;
+                    }
+                    if *skip_longer_matches {
+                        return false;
+                    }
+                    string = &string[1..];
+                }
+                *skip_longer_matches = true;
+                return false;
+            },
+            b'?' => {
+                if string.is_empty() {
+                    return false;
+                }
+                string = &string[1..];
+            },
+            b'[' => {
+                let c = match string.first() {
+                    Some(&c) => c,
+                    None => return false
+                };
+                pattern = &pattern[1..];
+                let not = pattern.first() == Some(&b'^');
+                if not {
+                    pattern = &pattern[1..];
+                }
+                let mut matched = false;
+                loop {
+                    match pattern {
+                        //Unterminated class, the rest of the pattern belongs to it
+                        [] => break,
+                        [b'\\', escaped, ..] => {
+                            pattern = &pattern[1..];
+                            if eq(*escaped, c, nocase) {
+                                matched = true;
+                            }
+                        },
+                        [b']', ..] => break,
+                        [start, b'-', end, ..] => {
+                            let (start, end, c) = match nocase {
+                                true => (start.to_ascii_lowercase(), end.to_ascii_lowercase(), c.to_ascii_lowercase()),
+                                false => (*start, *end, c)
+                            };
+                            let (low, high) = if start <= end { (start, end) } else { (end, start) };
+                            pattern = &pattern[2..];
+                            if (low..=high).contains(&c) {
+                                matched = true;
+                            }
+                        },
+                        [literal, ..] => {
+                            if eq(*literal, c, nocase) {
+                                matched = true;
+                            }
+                        }
+                    }
+                    pattern = &pattern[1..];
+                }
+                if matched == not {
+                    return false;
+                }
+                string = &string[1..];
+                if pattern.is_empty() {
+                    return string.is_empty();
+                }
+            },
+            _ => {
+                if p == b'\\' && pattern.len() >= 2 {
+                    pattern = &pattern[1..];
+                }
+                match string.first() {
+                    Some(&c) if eq(c, pattern[0], nocase) => string = &string[1..],
+                    _ => return false
+                }
+            }
+        }
+        pattern = &pattern[1..];
+        if string.is_empty() {
+            while pattern.first() == Some(&b'*') {
+                pattern = &pattern[1..];
+            }
+            break;
+        }
+    }
+    pattern.is_empty() && string.is_empty()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &[u8], string: &[u8]) -> bool {
+        string_match(pattern, string, false)
+    }
+
+    #[test]
+    fn star_and_question() {
+        assert!(matches(b"*", b""));
+        assert!(matches(b"cache:*", b"cache:user:1"));
+        assert!(!matches(b"cache:*", b"other:1"));
+        assert!(matches(b"h?llo", b"hello"));
+        assert!(!matches(b"h?llo", b"hllo"));
+        assert!(matches(b"a*b*c", b"aXXbYYc"));
+    }
+
+    #[test]
+    fn classes() {
+        assert!(matches(b"h[ae]llo", b"hallo"));
+        assert!(!matches(b"h[ae]llo", b"hillo"));
+        assert!(matches(b"h[^e]llo", b"hallo"));
+        assert!(!matches(b"h[^e]llo", b"hello"));
+        assert!(matches(b"h[a-b]llo", b"hbllo"));
+        assert!(matches(b"h[b-a]llo", b"hallo"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(matches(b"a\\*", b"a*"));
+        assert!(!matches(b"a\\*", b"ab"));
+        assert!(matches(b"[\\]]", b"]"));
+    }
+
+    #[test]
+    fn pathological_pattern_terminates() {
+        let pattern = b"a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b";
+        let string = [b'a'; 64];
+        assert!(!matches(pattern, &string));
+    }
+
+    #[test]
+    fn nocase() {
+        assert!(string_match(b"MaxMemory*", b"maxmemory-policy", true));
+        assert!(!string_match(b"MaxMemory*", b"maxmemory-policy", false));
+        assert!(string_match(b"[A-C]x", b"bX", true));
+        assert!(string_match(b"[^A]", b"b", true));
+        assert!(!string_match(b"[^A]", b"a", true));
+    }
+
+    #[test]
+    fn deep_nesting_does_not_match() {
+        let pattern = [b"a*".repeat(MAX_NESTING + 1), b"b".to_vec()].concat();
+        let string = [&[b'a'; MAX_NESTING + 1][..], b"b"].concat();
+        assert!(!string_match(&pattern, &string, false));
+        assert!(string_match(b"a*a*b", b"aaab", false));
+    }
+}
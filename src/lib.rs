@@ -2,3 +2,7 @@ pub mod resp;
 pub mod server;
 pub mod command;
 pub mod store;
+pub mod acl;
+pub mod glob;
+#[cfg(test)]
+pub mod test_helpers;
@@ -1,6 +1,12 @@
-use redis_rust::server;
+use redis_rust::server::{self, value::Config};
 
 fn main() {
-    println!("Starting server on 127.0.0.1:6379");
-    server::tcp::create_connection();
+    let config = match Config::from_args(std::env::args().skip(1)) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            std::process::exit(1);
+        }
+    };
+    server::tcp::create_connection(config);
 }
@@ -13,6 +13,8 @@ impl PartialEq for RespValue{
             (BulkString(None), BulkString(None)) => true,
             (Arrays(Some(a)), Arrays(Some(b))) => a == b,
             (Arrays(None), Arrays(None)) => true,
+            (Map(a), Map(b)) => a == b,
+            (Push(a), Push(b)) => a == b,
             _ => false
         }
 
@@ -50,11 +52,35 @@ impl Hash for RespValue{
             },
             Arrays(None) => {
                 6.hash(state);
+            },
+            Map(entries) => {
+                7.hash(state);
+                entries.hash(state);
+            },
+            Push(arr) => {
+                8.hash(state);
+                arr.hash(state);
             }
         }
     }
 }
 
+impl RespValue {
+    ///Returns the raw bytes of string like values, which is how command arguments and keys are
+    ///compared regardless of the wire type they arrived as
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            RespValue::SimpleString(v) | RespValue::BulkString(Some(v)) => Some(v),
+            _ => None
+        }
+    }
+
+    ///Parses a string like value holding a base 10 integer, as clients send numeric arguments
+    pub fn as_i64(&self) -> Option<i64> {
+        std::str::from_utf8(self.as_bytes()?).ok()?.parse().ok()
+    }
+}
+
 fn read_line(line: &[u8], min_len_before_crlf: usize) -> Result<(&[u8], &[u8]), ParseError> {
     let mut i = 0;
     
@@ -2,6 +2,14 @@ use crate::resp::ParseError;
 use super::value::RespValue;
 
 pub fn serializer(value: &RespValue) -> Result<Vec<u8>, ParseError> {
+    serialize_with_protocol(value, 2)
+}
+
+///Serializes a value for a client speaking the given protocol version.
+///RESP2 clients receive maps as flat arrays and pushes as plain arrays, RESP3 clients get the
+///native map, push and null types
+pub fn serialize_with_protocol(value: &RespValue, protocol: u8) -> Result<Vec<u8>, ParseError> {
+    let resp3 = protocol >= 3;
 
     match value {
         RespValue::SimpleString(v) => {
@@ -25,20 +33,45 @@ pub fn serializer(value: &RespValue) -> Result<Vec<u8>, ParseError> {
             out.extend(b"\r\n");
             Ok(out)
         },
+        RespValue::BulkString(None) | RespValue::Arrays(None) if resp3 => {
+            Ok(b"_\r\n".to_vec())
+        },
         RespValue::BulkString(None) => {
             Ok(b"$-1\r\n".to_vec())
         },
         RespValue::Arrays(Some(arr)) => {
             let mut out = format!("*{}\r\n", arr.len()).into_bytes();
             for elem in arr{
-                out.extend(serializer(elem)?);
+                out.extend(serialize_with_protocol(elem, protocol)?);
             }
             Ok(out)
         },
         RespValue::Arrays(None) => {
             Ok(b"*-1\r\n".to_vec())
+        },
+        RespValue::Map(entries) => {
+            let mut out = if resp3 {
+                format!("%{}\r\n", entries.len()).into_bytes()
+            } else {
+                format!("*{}\r\n", entries.len() * 2).into_bytes()
+            };
+            for (key, value) in entries {
+                out.extend(serialize_with_protocol(key, protocol)?);
+                out.extend(serialize_with_protocol(value, protocol)?);
+            }
+            Ok(out)
+        },
+        RespValue::Push(arr) => {
+            let mut out = if resp3 {
+                format!(">{}\r\n", arr.len()).into_bytes()
+            } else {
+                format!("*{}\r\n", arr.len()).into_bytes()
+            };
+            for elem in arr{
+                out.extend(serialize_with_protocol(elem, protocol)?);
+            }
+            Ok(out)
         }
-        
     }
 
 }
@@ -169,4 +202,38 @@ mod tests{
         );
     }
 
+    #[test]
+    fn serialize_map_resp2_is_flat_array() {
+        let value = RespValue::Map(vec![
+            (RespValue::BulkString(Some(b"proto".to_vec())), RespValue::Integer(2)),
+        ]);
+        let res = serializer(&value).unwrap();
+        assert_eq!(res, b"*2\r\n$5\r\nproto\r\n:2\r\n".to_vec());
+    }
+
+    #[test]
+    fn serialize_map_resp3() {
+        let value = RespValue::Map(vec![
+            (RespValue::BulkString(Some(b"proto".to_vec())), RespValue::Integer(3)),
+        ]);
+        let res = serialize_with_protocol(&value, 3).unwrap();
+        assert_eq!(res, b"%1\r\n$5\r\nproto\r\n:3\r\n".to_vec());
+    }
+
+    #[test]
+    fn serialize_push_resp3() {
+        let value = RespValue::Push(vec![
+            RespValue::BulkString(Some(b"invalidate".to_vec())),
+            RespValue::Arrays(Some(vec![RespValue::BulkString(Some(b"k".to_vec()))])),
+        ]);
+        let res = serialize_with_protocol(&value, 3).unwrap();
+        assert_eq!(res, b">2\r\n$10\r\ninvalidate\r\n*1\r\n$1\r\nk\r\n".to_vec());
+    }
+
+    #[test]
+    fn serialize_null_resp3() {
+        let res = serialize_with_protocol(&RespValue::BulkString(None), 3).unwrap();
+        assert_eq!(res, b"_\r\n".to_vec());
+    }
+
 }
@@ -21,6 +21,9 @@ pub enum RespValue{
     Error(Vec<u8>),
     Integer(i64),
     BulkString(Option<Vec<u8>>),
-    Arrays(Option<Vec<RespValue>>)
+    Arrays(Option<Vec<RespValue>>),
+    //RESP3 only types, downgraded to their RESP2 equivalent when the client speaks RESP2
+    Map(Vec<(RespValue, RespValue)>),
+    Push(Vec<RespValue>)
 }
 
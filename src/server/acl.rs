@@ -0,0 +1,277 @@
+use crate::{acl::{users::now_ms, AclDenial, AclLogEntry, Selector}, command::{get_command, spec::CATEGORIES, CommandError, Commands}, resp::RespValue, server::value::{Client, ServerState}};
+
+fn bulk(s: &[u8]) -> RespValue {
+    RespValue::BulkString(Some(s.to_vec()))
+}
+
+fn ok() -> RespValue {
+    RespValue::SimpleString(b"OK".to_vec())
+}
+
+fn arg_bytes(arg: &RespValue) -> Result<&[u8], CommandError> {
+    arg.as_bytes().ok_or(CommandError::InvalidRequest)
+}
+
+fn no_acl_file() -> CommandError {
+    CommandError::Custom("ERR This Redis instance is not configured to use an ACL file. You may want to specify users via the ACL SETUSER command and then issue a CONFIG REWRITE (assuming you have a Redis configuration file set) in order to store users in the Redis configuration.".to_string())
+}
+
+///Checks a request against the client's user, recording denials in the ACL log
+pub fn check_permissions(command: Commands, args: &[RespValue], state: &ServerState, client: &Client) -> Result<(), CommandError> {
+    //Checked under the read lock so commands never wait on each other, only a denial is logged
+    let denial = state.acl.read().unwrap().check(&client.user, command, args);
+    match denial {
+        Ok(()) => Ok(()),
+        Err(AclDenial::Command) => {
+            let name = command.full_name(args);
+            state.acl.write().unwrap().add_log_entry("command", name.as_bytes(), &client.user, client.info());
+            Err(CommandError::NoPerm(format!(
+                "User {} has no permissions to run the '{}' command",
+                String::from_utf8_lossy(&client.user),
+                name
+            )))
+        },
+        Err(AclDenial::Key(key)) => {
+            state.acl.write().unwrap().add_log_entry("key", &key, &client.user, client.info());
+            Err(CommandError::NoPerm("No permissions to access a key".to_string()))
+        }
+    }
+}
+
+///Logs in a TLS client as the ACL user named by its certificate, if there is such a user
+pub fn authenticate_certificate_user(name: &[u8], state: &ServerState, client: &mut Client) {
+    let acl = state.acl.read().unwrap();
+    if acl.user(name).is_some_and(|u| u.enabled) {
+        client.user = name.to_vec();
+        client.authenticated = true;
+    }
+}
+
+pub fn handle_acl(args: &[RespValue], state: &ServerState, client: &mut Client) -> Result<RespValue, CommandError> {
+    let subcommand = match args.first() {
+        Some(arg) => arg_bytes(arg)?.to_ascii_uppercase(),
+        None => return Err(CommandError::WrongArity)
+    };
+    let args = &args[1..];
+
+    match subcommand.as_slice() {
+        b"SETUSER" => acl_setuser(args, state),
+        b"GETUSER" => match args {
+            [name] => Ok(acl_getuser(arg_bytes(name)?, state)),
+            _ => Err(CommandError::WrongArity)
+        },
+        b"DELUSER" => acl_deluser(args, state, client),
+        b"LIST" => {
+            let acl = state.acl.read().unwrap();
+            Ok(RespValue::Arrays(Some(acl.users.values().map(|u| bulk(u.describe().as_bytes())).collect())))
+        },
+        b"USERS" => {
+            let acl = state.acl.read().unwrap();
+            Ok(RespValue::Arrays(Some(acl.users.keys().map(|name| bulk(name)).collect())))
+        },
+        b"WHOAMI" => Ok(bulk(&client.user)),
+        b"CAT" => acl_cat(args),
+        b"LOG" => acl_log(args, state),
+        b"DRYRUN" => acl_dryrun(args, state),
+        b"LOAD" => {
+            let path = state.config.lock().unwrap().aclfile.clone().ok_or_else(no_acl_file)?;
+            state.acl.write().unwrap().load_file(&path).map_err(|e| CommandError::Custom(format!(
+                "ERR {}. WARNING: ACL errors detected, no change to the previously active ACL rules was performed",
+                e
+            )))?;
+            Ok(ok())
+        },
+        b"SAVE" => {
+            let path = state.config.lock().unwrap().aclfile.clone().ok_or_else(no_acl_file)?;
+            state.acl.write().unwrap().save_file(&path).map_err(|e| CommandError::Custom(format!("ERR {}", e)))?;
+            Ok(ok())
+        },
+        _ => Err(CommandError::Custom(format!(
+            "ERR unknown subcommand '{}'. Try ACL HELP.",
+            String::from_utf8_lossy(&subcommand)
+        )))
+    }
+}
+
+fn acl_setuser(args: &[RespValue], state: &ServerState) -> Result<RespValue, CommandError> {
+    let (name, rules) = match args {
+        [name, rules @ ..] => (arg_bytes(name)?, rules),
+        [] => return Err(CommandError::WrongArity)
+    };
+    let rules = rules.iter().map(|r| arg_bytes(r).map(|r| r.to_vec())).collect::<Result<Vec<_>, _>>()?;
+
+    state.acl.write().unwrap().set_user(name, &rules).map_err(|(rule, e)| CommandError::Custom(format!(
+        "ERR Error in ACL SETUSER modifier '{}': {}",
+        String::from_utf8_lossy(&rule),
+        e.message()
+    )))?;
+    Ok(ok())
+}
+
+fn describe_selector(selector: &Selector) -> Vec<(RespValue, RespValue)> {
+    vec![
+        (bulk(b"commands"), bulk(selector.describe_commands().as_bytes())),
+        (bulk(b"keys"), bulk(selector.describe_keys().as_bytes())),
+        (bulk(b"channels"), bulk(selector.describe_channels().as_bytes())),
+    ]
+}
+
+fn acl_getuser(name: &[u8], state: &ServerState) -> RespValue {
+    let acl = state.acl.read().unwrap();
+    let user = match acl.user(name) {
+        Some(user) => user,
+        None => return RespValue::BulkString(None)
+    };
+
+    let mut fields = vec![
+        (bulk(b"flags"), RespValue::Arrays(Some(user.flags().iter().map(|f| bulk(f.as_bytes())).collect()))),
+        (bulk(b"passwords"), RespValue::Arrays(Some(user.passwords.iter().map(|p| bulk(p.as_bytes())).collect()))),
+    ];
+    fields.extend(describe_selector(&user.root));
+    fields.push((
+        bulk(b"selectors"),
+        RespValue::Arrays(Some(user.selectors.iter().map(|s| RespValue::Map(describe_selector(s))).collect()))
+    ));
+    RespValue::Map(fields)
+}
+
+fn acl_deluser(args: &[RespValue], state: &ServerState, client: &mut Client) -> Result<RespValue, CommandError> {
+    if args.is_empty() {
+        return Err(CommandError::WrongArity);
+    }
+    let names = args.iter().map(arg_bytes).collect::<Result<Vec<_>, _>>()?;
+    //Every name is checked before any user is deleted, so a refused command changes nothing
+    if names.contains(&&b"default"[..]) {
+        return Err(CommandError::Custom("ERR The 'default' user cannot be removed".to_string()));
+    }
+    let deleted: Vec<&[u8]> = {
+        let mut acl = state.acl.write().unwrap();
+        names.into_iter().filter(|name| acl.del_user(name)).collect()
+    };
+    //Clients authenticated as a deleted user are disconnected, this one once it is answered
+    for handle in state.client_handles() {
+        if handle.id != client.id() && deleted.contains(&handle.details.lock().unwrap().user.as_slice()) {
+            handle.kill();
+        }
+    }
+    if deleted.contains(&client.user.as_slice()) {
+        client.closing = true;
+    }
+    Ok(RespValue::Integer(deleted.len() as i64))
+}
+
+fn acl_cat(args: &[RespValue]) -> Result<RespValue, CommandError> {
+    match args {
+        [] => Ok(RespValue::Arrays(Some(CATEGORIES.iter().map(|c| bulk(c.as_bytes())).collect()))),
+        [category] => {
+            let category = String::from_utf8_lossy(arg_bytes(category)?).to_ascii_lowercase();
+            if !CATEGORIES.contains(&category.as_str()) {
+                return Err(CommandError::Custom(format!("ERR Unknown category '{}'", category)));
+            }
+            Ok(RespValue::Arrays(Some(Commands::ALL.iter()
+                .filter(|c| c.categories().contains(&category.as_str()))
+                .map(|c| bulk(c.name().as_bytes()))
+                .collect())))
+        },
+        _ => Err(CommandError::WrongArity)
+    }
+}
+
+fn log_entry(entry: &AclLogEntry, now: u128) -> RespValue {
+    let age = now.saturating_sub(entry.created) as f64 / 1000.0;
+    RespValue::Map(vec![
+        (bulk(b"count"), RespValue::Integer(entry.count as i64)),
+        (bulk(b"reason"), bulk(entry.reason.as_bytes())),
+        (bulk(b"context"), bulk(b"toplevel")),
+        (bulk(b"object"), bulk(&entry.object)),
+        (bulk(b"username"), bulk(&entry.username)),
+        (bulk(b"age-seconds"), bulk(format!("{:.3}", age).as_bytes())),
+        (bulk(b"client-info"), bulk(entry.client_info.as_bytes())),
+        (bulk(b"entry-id"), RespValue::Integer(entry.entry_id as i64)),
+        (bulk(b"timestamp-created"), RespValue::Integer(entry.created as i64)),
+        (bulk(b"timestamp-last-updated"), RespValue::Integer(entry.updated as i64)),
+    ])
+}
+
+///`ACL LOG [count | RESET]`, newest entries first
+fn acl_log(args: &[RespValue], state: &ServerState) -> Result<RespValue, CommandError> {
+    let mut acl = state.acl.write().unwrap();
+    let count = match args {
+        [] => 10,
+        [arg] if arg_bytes(arg)?.eq_ignore_ascii_case(b"RESET") => {
+            acl.log.clear();
+            return Ok(ok());
+        },
+        [arg] => match arg.as_i64() {
+            Some(n) if n >= 0 => n as usize,
+            _ => return Err(CommandError::Custom("ERR value is out of range, must be positive".to_string()))
+        },
+        _ => return Err(CommandError::WrongArity)
+    };
+    let now = now_ms();
+    Ok(RespValue::Arrays(Some(acl.log.iter().take(count).map(|e| log_entry(e, now)).collect())))
+}
+
+///`ACL DRYRUN username command [arg ...]` checks a command without running it
+fn acl_dryrun(args: &[RespValue], state: &ServerState) -> Result<RespValue, CommandError> {
+    let (name, request) = match args {
+        [name, request @ ..] if !request.is_empty() => (arg_bytes(name)?, request),
+        _ => return Err(CommandError::WrongArity)
+    };
+    let request = RespValue::Arrays(Some(request.to_vec()));
+    let command = get_command(&request).map_err(|_| CommandError::Custom(format!(
+        "ERR Command '{}' not found",
+        String::from_utf8_lossy(args[1].as_bytes().unwrap_or_default())
+    )))?;
+
+    let acl = state.acl.read().unwrap();
+    if acl.user(name).is_none() {
+        return Err(CommandError::Custom(format!("ERR User '{}' not found", String::from_utf8_lossy(name))));
+    }
+    match acl.check(name, command, &args[1..]) {
+        Ok(()) => Ok(ok()),
+        Err(AclDenial::Command) => Ok(bulk(format!(
+            "User {} has no permissions to run the '{}' command",
+            String::from_utf8_lossy(name),
+            command.full_name(&args[1..])
+        ).as_bytes())),
+        Err(AclDenial::Key(key)) => Ok(bulk(format!(
+            "User {} has no permissions to access the '{}' key",
+            String::from_utf8_lossy(name),
+            String::from_utf8_lossy(&key)
+        ).as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::value::Connection;
+    use crate::test_helpers::args;
+
+    fn connect(state: &ServerState, user: &[u8]) -> Client {
+        let mut client = state.register_client(Connection::new(Box::new(std::io::sink()), "127.0.0.1:1000".to_string()));
+        client.user = user.to_vec();
+        client.finish_command();
+        client
+    }
+
+    #[test]
+    fn deluser_checks_every_name_then_disconnects_clients() {
+        let state = ServerState::default();
+        let mut admin = connect(&state, b"default");
+        for name in ["alice", "bob"] {
+            handle_acl(&args(&["SETUSER", name, "on", "nopass", "+@all"]), &state, &mut admin).unwrap();
+        }
+        assert!(handle_acl(&args(&["DELUSER", "alice", "default"]), &state, &mut admin).is_err());
+        assert!(state.acl.read().unwrap().user(b"alice").is_some());
+
+        let alice = connect(&state, b"alice");
+        let mut bob = connect(&state, b"bob");
+        assert_eq!(handle_acl(&args(&["DELUSER", "alice", "nobody"]), &state, &mut admin), Ok(RespValue::Integer(1)));
+        assert!(alice.handle.killed.load(std::sync::atomic::Ordering::Relaxed));
+        assert!(!admin.closing);
+        assert_eq!(handle_acl(&args(&["DELUSER", "bob"]), &state, &mut bob), Ok(RespValue::Integer(1)));
+        assert!(bob.closing);
+    }
+}
@@ -0,0 +1,529 @@
+use std::{sync::atomic::Ordering, time::{Duration, Instant}};
+
+use crate::{command::{CommandError, Commands}, resp::RespValue, server::value::{Client, ClientHandle, Pause, ReplyMode, ServerState}};
+
+//Size of the buffer each connection reads requests into
+pub const QUERY_BUFFER_SIZE: usize = 4096;
+
+fn bulk(s: &[u8]) -> RespValue {
+    RespValue::BulkString(Some(s.to_vec()))
+}
+
+fn ok() -> RespValue {
+    RespValue::SimpleString(b"OK".to_vec())
+}
+
+fn upper(arg: &RespValue) -> Result<Vec<u8>, CommandError> {
+    arg.as_bytes().map(|a| a.to_ascii_uppercase()).ok_or(CommandError::InvalidRequest)
+}
+
+fn parse_int(arg: &RespValue) -> Option<i64> {
+    std::str::from_utf8(arg.as_bytes()?).ok()?.parse().ok()
+}
+
+impl ClientHandle {
+    ///Line describing the connection in CLIENT LIST and CLIENT INFO, without the newline
+    pub fn describe(&self) -> String {
+        let details = self.details.lock().unwrap();
+        let now = Instant::now();
+        let mut flags = String::new();
+        if details.monitor {
+            flags.push('O');
+        }
+        if details.tracking {
+            flags.push('t');
+        }
+        if details.no_evict {
+            flags.push('e');
+        }
+        if flags.is_empty() {
+            flags.push('N');
+        }
+        format!(
+            "id={} addr={} laddr={} name={} age={} idle={} flags={} db={} sub=0 psub=0 ssub=0 multi=-1 qbuf={} qbuf-free={} argv-mem={} obl=0 oll=0 omem=0 tot-mem={} events=r cmd={} user={} redir={} resp={}",
+            self.id,
+            self.addr,
+            self.laddr,
+            String::from_utf8_lossy(&details.name),
+            now.duration_since(self.created).as_secs(),
+            now.duration_since(details.last_interaction).as_secs(),
+            flags,
+            details.db,
+            details.query_buffer,
+            QUERY_BUFFER_SIZE.saturating_sub(details.query_buffer),
+            details.argv_mem,
+            details.query_buffer + details.argv_mem,
+            details.last_command,
+            String::from_utf8_lossy(&details.user),
+            details.redirect,
+            self.protocol(),
+        )
+    }
+
+    ///Closes the connection from another thread
+    pub fn kill(&self) {
+        self.killed.store(true, Ordering::Relaxed);
+        (self.shutdown)();
+    }
+
+    pub fn age(&self) -> Duration {
+        self.created.elapsed()
+    }
+}
+
+impl Client {
+    ///Records the command about to run, so other clients see it in CLIENT LIST
+    pub fn start_command(&self, name: &str, query_buffer: usize, argv_mem: usize) {
+        let mut details = self.handle.details.lock().unwrap();
+        details.last_command.clear();
+        details.last_command.push_str(name);
+        details.last_interaction = Instant::now();
+        details.query_buffer = query_buffer;
+        details.argv_mem = argv_mem;
+        details.executing = true;
+    }
+
+    ///Publishes the state a command may have changed once its reply is written
+    pub fn finish_command(&self) {
+        let mut details = self.handle.details.lock().unwrap();
+        details.user = self.user.clone();
+        details.db = self.db;
+        details.monitor = self.monitor;
+        details.tracking = self.tracking.is_some();
+        details.redirect = match &self.tracking {
+            Some(options) => options.redirect.map_or(0, |id| id as i64),
+            None => -1
+        };
+        details.last_interaction = Instant::now();
+        details.query_buffer = 0;
+        details.argv_mem = 0;
+        details.executing = false;
+    }
+}
+
+impl ServerState {
+    ///Every registered client, by increasing id
+    pub fn client_handles(&self) -> Vec<std::sync::Arc<ClientHandle>> {
+        let mut handles = self.clients.lock().unwrap().values().cloned().collect::<Vec<_>>();
+        handles.sort_by_key(|h| h.id);
+        handles
+    }
+
+    ///Disconnects clients idle for longer than the timeout setting. Monitors are exempt, they
+    ///only receive
+    pub fn close_timed_out_clients(&self) -> usize {
+        let timeout = self.config.lock().unwrap().timeout;
+        if timeout == 0 {
+            return 0;
+        }
+        let mut closed = 0;
+        for handle in self.client_handles() {
+            let idle = {
+                let details = handle.details.lock().unwrap();
+                !details.executing && !details.monitor && details.last_interaction.elapsed().as_secs() > timeout
+            };
+            if idle {
+                handle.kill();
+                closed += 1;
+            }
+        }
+        closed
+    }
+
+    ///Starts a pause, or extends the current one to the later end and the stricter mode
+    pub fn pause_clients(&self, pause: Pause) {
+        let mut current = self.pause.lock().unwrap();
+        *current = Some(match *current {
+            Some(p) => Pause { until: p.until.max(pause.until), all: p.all || pause.all },
+            None => pause
+        });
+        self.paused.store(true, Ordering::Release);
+    }
+
+    pub fn unpause_clients(&self) {
+        *self.pause.lock().unwrap() = None;
+        self.paused.store(false, Ordering::Release);
+        self.unpaused.notify_all();
+    }
+
+    ///Holds a command while clients are paused. CLIENT itself is never held so a paused
+    ///server can still be unpaused
+    pub fn wait_if_paused(&self, command: Commands) {
+        if command == Commands::CLIENT || !self.paused.load(Ordering::Acquire) {
+            return;
+        }
+        let mut pause = self.pause.lock().unwrap();
+        while let Some(p) = *pause {
+            if !p.all && !command.is_write() {
+                return;
+            }
+            let now = Instant::now();
+            if now >= p.until {
+                *pause = None;
+                self.paused.store(false, Ordering::Release);
+                return;
+            }
+            pause = self.unpaused.wait_timeout(pause, p.until - now).unwrap().0;
+        }
+    }
+}
+
+///`CLIENT LIST [TYPE type] [ID id...]`
+pub fn client_list(args: &[RespValue], state: &ServerState) -> Result<RespValue, CommandError> {
+    let mut ids: Option<Vec<u64>> = None;
+    let mut normal = true;
+    let mut i = 0;
+    while i < args.len() {
+        match upper(&args[i])?.as_slice() {
+            b"TYPE" if i + 1 < args.len() => {
+                let kind = upper(&args[i + 1])?;
+                normal = match kind.as_slice() {
+                    b"NORMAL" => true,
+                    //There are no replicas and no pubsub clients
+                    b"MASTER" | b"REPLICA" | b"SLAVE" | b"PUBSUB" => false,
+                    _ => return Err(CommandError::Custom(format!(
+                        "ERR Unknown client type '{}'",
+                        String::from_utf8_lossy(&kind).to_ascii_lowercase()
+                    )))
+                };
+                i += 2;
+            },
+            b"ID" if i + 1 < args.len() => {
+                let mut list = Vec::new();
+                i += 1;
+                while i < args.len() {
+                    match parse_int(&args[i]) {
+                        Some(id) if id > 0 => list.push(id as u64),
+                        _ => return Err(CommandError::Custom("ERR Invalid client ID".to_string()))
+                    }
+                    i += 1;
+                }
+                ids = Some(list);
+            },
+            _ => return Err(CommandError::Syntax)
+        }
+    }
+
+    let mut list = String::new();
+    for handle in state.client_handles() {
+        if normal && ids.as_ref().is_none_or(|ids| ids.contains(&handle.id)) {
+            list.push_str(&handle.describe());
+            list.push('\n');
+        }
+    }
+    Ok(RespValue::BulkString(Some(list.into_bytes())))
+}
+
+pub fn client_info(client: &Client) -> RespValue {
+    let mut info = client.handle.describe();
+    info.push('\n');
+    RespValue::BulkString(Some(info.into_bytes()))
+}
+
+#[derive(Default)]
+struct KillFilter {
+    id: Option<u64>,
+    addr: Option<String>,
+    laddr: Option<String>,
+    user: Option<Vec<u8>>,
+    max_age: Option<u64>,
+    normal: bool,
+    skip_me: bool,
+}
+
+impl KillFilter {
+    fn matches(&self, handle: &ClientHandle) -> bool {
+        self.normal
+            && self.id.is_none_or(|id| id == handle.id)
+            && self.addr.as_ref().is_none_or(|addr| *addr == handle.addr)
+            && self.laddr.as_ref().is_none_or(|laddr| *laddr == handle.laddr)
+            && self.user.as_ref().is_none_or(|user| *user == handle.details.lock().unwrap().user)
+            && self.max_age.is_none_or(|max_age| handle.age().as_secs() > max_age)
+    }
+}
+
+///`CLIENT KILL addr:port` or `CLIENT KILL <filter value>...`. The calling client is closed after
+///its reply when it matches
+pub fn client_kill(args: &[RespValue], state: &ServerState, client: &mut Client) -> Result<RespValue, CommandError> {
+    let string = |arg: &RespValue| arg.as_bytes().map(|a| String::from_utf8_lossy(a).into_owned()).ok_or(CommandError::InvalidRequest);
+    if let [addr] = args {
+        let filter = KillFilter { addr: Some(string(addr)?), normal: true, ..Default::default() };
+        let killed = kill_matching(&filter, state, client);
+        return match killed {
+            0 => Err(CommandError::Custom("ERR No such client".to_string())),
+            _ => Ok(ok())
+        };
+    }
+    if args.is_empty() || !args.len().is_multiple_of(2) {
+        return Err(CommandError::Syntax);
+    }
+
+    let mut filter = KillFilter { normal: true, skip_me: true, ..Default::default() };
+    for pair in args.chunks(2) {
+        let value = &pair[1];
+        match upper(&pair[0])?.as_slice() {
+            b"ID" => filter.id = match parse_int(value) {
+                Some(id) if id > 0 => Some(id as u64),
+                _ => return Err(CommandError::Custom("ERR client-id should be greater than 0".to_string()))
+            },
+            b"ADDR" => filter.addr = Some(string(value)?),
+            b"LADDR" => filter.laddr = Some(string(value)?),
+            b"USER" => {
+                let user = value.as_bytes().ok_or(CommandError::InvalidRequest)?.to_vec();
+                if state.acl.read().unwrap().user(&user).is_none() {
+                    return Err(CommandError::Custom(format!("ERR No such user '{}'", String::from_utf8_lossy(&user))));
+                }
+                filter.user = Some(user);
+            },
+            b"MAXAGE" => filter.max_age = match parse_int(value) {
+                Some(age) if age >= 0 => Some(age as u64),
+                _ => return Err(CommandError::Syntax)
+            },
+            b"TYPE" => filter.normal = match upper(value)?.as_slice() {
+                b"NORMAL" => true,
+                b"MASTER" | b"REPLICA" | b"SLAVE" | b"PUBSUB" => false,
+                _ => return Err(CommandError::Custom(format!("ERR Unknown client type '{}'", string(value)?)))
+            },
+            b"SKIPME" => filter.skip_me = match upper(value)?.as_slice() {
+                b"YES" => true,
+                b"NO" => false,
+                _ => return Err(CommandError::Syntax)
+            },
+            _ => return Err(CommandError::Syntax)
+        }
+    }
+    Ok(RespValue::Integer(kill_matching(&filter, state, client) as i64))
+}
+
+fn kill_matching(filter: &KillFilter, state: &ServerState, client: &mut Client) -> usize {
+    let mut killed = 0;
+    for handle in state.client_handles() {
+        if !filter.matches(&handle) {
+            continue;
+        }
+        if handle.id == client.id() {
+            if filter.skip_me {
+                continue;
+            }
+            client.closing = true;
+        } else {
+            handle.kill();
+        }
+        killed += 1;
+    }
+    killed
+}
+
+pub fn client_setname(args: &[RespValue], client: &Client) -> Result<RespValue, CommandError> {
+    let name = match args {
+        [name] => name.as_bytes().ok_or(CommandError::InvalidRequest)?,
+        _ => return Err(CommandError::WrongArity)
+    };
+    if name.iter().any(|b| *b <= b' ' || *b > b'~') {
+        return Err(CommandError::Custom("ERR Client names cannot contain spaces, newlines or special characters.".to_string()));
+    }
+    client.handle.details.lock().unwrap().name = name.to_vec();
+    Ok(ok())
+}
+
+pub fn client_getname(client: &Client) -> RespValue {
+    match client.handle.details.lock().unwrap().name.as_slice() {
+        [] => RespValue::BulkString(None),
+        name => bulk(name)
+    }
+}
+
+///`CLIENT PAUSE timeout [WRITE|ALL]`, the timeout is in milliseconds
+pub fn client_pause(args: &[RespValue], state: &ServerState) -> Result<RespValue, CommandError> {
+    let (timeout, all) = match args {
+        [timeout] => (timeout, true),
+        [timeout, mode] => (timeout, match upper(mode)?.as_slice() {
+            b"ALL" => true,
+            b"WRITE" => false,
+            _ => return Err(CommandError::Syntax)
+        }),
+        _ => return Err(CommandError::WrongArity)
+    };
+    let timeout = match parse_int(timeout) {
+        Some(timeout) if timeout >= 0 => timeout as u64,
+        Some(_) => return Err(CommandError::Custom("ERR timeout is negative".to_string())),
+        None => return Err(CommandError::Custom("ERR timeout is not an integer or out of range".to_string()))
+    };
+    state.pause_clients(Pause { until: Instant::now() + Duration::from_millis(timeout), all });
+    Ok(ok())
+}
+
+pub fn client_reply(args: &[RespValue], client: &mut Client) -> Result<RespValue, CommandError> {
+    client.reply = match args {
+        [mode] => match upper(mode)?.as_slice() {
+            b"ON" => ReplyMode::On,
+            b"OFF" => ReplyMode::Off,
+            b"SKIP" => ReplyMode::SkipNext,
+            _ => return Err(CommandError::Syntax)
+        },
+        _ => return Err(CommandError::WrongArity)
+    };
+    Ok(ok())
+}
+
+pub fn client_no_evict(args: &[RespValue], client: &Client) -> Result<RespValue, CommandError> {
+    let no_evict = match args {
+        [mode] => match upper(mode)?.as_slice() {
+            b"ON" => true,
+            b"OFF" => false,
+            _ => return Err(CommandError::Syntax)
+        },
+        _ => return Err(CommandError::WrongArity)
+    };
+    client.handle.details.lock().unwrap().no_evict = no_evict;
+    Ok(ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::AtomicUsize, Arc};
+
+    use super::*;
+    use crate::server::value::Connection;
+    use crate::test_helpers::args;
+
+    fn connect(state: &ServerState, addr: &str) -> (Client, Arc<AtomicUsize>) {
+        let shutdowns = Arc::new(AtomicUsize::new(0));
+        let counter = shutdowns.clone();
+        let connection = Connection {
+            laddr: "127.0.0.1:6379".to_string(),
+            shutdown: Box::new(move || {
+                counter.fetch_add(1, Ordering::Relaxed);
+            }),
+            ..Connection::new(Box::new(std::io::sink()), addr.to_string())
+        };
+        (state.register_client(connection), shutdowns)
+    }
+
+    fn text(value: RespValue) -> String {
+        match value {
+            RespValue::BulkString(Some(v)) => String::from_utf8(v).unwrap(),
+            other => panic!("{:?}", other)
+        }
+    }
+
+    #[test]
+    fn list_describes_clients() {
+        let state = ServerState::default();
+        let (first, _) = connect(&state, "127.0.0.1:1000");
+        let (second, _) = connect(&state, "127.0.0.1:2000");
+        client_setname(&args(&["worker"]), &first).unwrap();
+        first.start_command("client|list", 30, 20);
+
+        let list = text(client_list(&[], &state).unwrap());
+        let lines = list.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("id=1 addr=127.0.0.1:1000 laddr=127.0.0.1:6379 name=worker age=0 idle=0 flags=N db=0"));
+        assert!(lines[0].contains(" qbuf=30 qbuf-free=4066 argv-mem=20 ") && lines[0].ends_with("cmd=client|list user=default redir=-1 resp=2"));
+
+        let only_second = text(client_list(&args(&["ID", "2", "99"]), &state).unwrap());
+        assert!(only_second.starts_with("id=2 ") && only_second.lines().count() == 1);
+        assert_eq!(text(client_list(&args(&["TYPE", "pubsub"]), &state).unwrap()), "");
+        assert!(client_list(&args(&["TYPE", "nope"]), &state).is_err());
+        assert!(client_list(&args(&["ID", "x"]), &state).is_err());
+        assert!(text(client_info(&second)).starts_with("id=2 addr=127.0.0.1:2000"));
+    }
+
+    #[test]
+    fn names() {
+        let state = ServerState::default();
+        let (client, _) = connect(&state, "127.0.0.1:1000");
+        assert_eq!(client_getname(&client), RespValue::BulkString(None));
+        assert!(client_setname(&args(&["a b"]), &client).is_err());
+        client_setname(&args(&["app"]), &client).unwrap();
+        assert_eq!(client_getname(&client), bulk(b"app"));
+        client_setname(&args(&[""]), &client).unwrap();
+        assert_eq!(client_getname(&client), RespValue::BulkString(None));
+    }
+
+    #[test]
+    fn kill_by_filters() {
+        let state = ServerState::default();
+        let (mut me, my_shutdowns) = connect(&state, "127.0.0.1:1000");
+        let (other, other_shutdowns) = connect(&state, "127.0.0.1:2000");
+
+        assert_eq!(client_kill(&args(&["127.0.0.1:2000"]), &state, &mut me).unwrap(), ok());
+        assert!(other.handle.killed.load(Ordering::Relaxed));
+        assert_eq!(other_shutdowns.load(Ordering::Relaxed), 1);
+        assert!(client_kill(&args(&["127.0.0.1:9999"]), &state, &mut me).is_err());
+
+        //SKIPME defaults to yes
+        assert_eq!(client_kill(&args(&["USER", "default"]), &state, &mut me).unwrap(), RespValue::Integer(1));
+        assert!(!me.closing);
+        assert_eq!(client_kill(&args(&["ID", "1", "SKIPME", "no"]), &state, &mut me).unwrap(), RespValue::Integer(1));
+        assert!(me.closing);
+        assert_eq!(my_shutdowns.load(Ordering::Relaxed), 0);
+
+        assert_eq!(client_kill(&args(&["MAXAGE", "1000"]), &state, &mut me).unwrap(), RespValue::Integer(0));
+        assert!(client_kill(&args(&["USER", "nobody"]), &state, &mut me).is_err());
+        assert!(client_kill(&args(&["ID"]), &state, &mut me).is_err());
+        assert!(client_kill(&args(&["NOPE", "1"]), &state, &mut me).is_err());
+    }
+
+    #[test]
+    fn pause_holds_matching_commands() {
+        let state = Arc::new(ServerState::default());
+        state.pause_clients(Pause { until: Instant::now() + Duration::from_secs(10), all: false });
+        //Reads go through a write pause
+        state.wait_if_paused(Commands::GET);
+
+        let waiter = {
+            let state = state.clone();
+            std::thread::spawn(move || {
+                let started = Instant::now();
+                state.wait_if_paused(Commands::SET);
+                started.elapsed()
+            })
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        state.unpause_clients();
+        let waited = waiter.join().unwrap();
+        assert!(waited >= Duration::from_millis(40) && waited < Duration::from_secs(5));
+
+        client_pause(&args(&["20", "ALL"]), &state).unwrap();
+        let started = Instant::now();
+        state.wait_if_paused(Commands::GET);
+        assert!(started.elapsed() >= Duration::from_millis(15));
+        assert!(state.pause.lock().unwrap().is_none());
+        assert!(!state.paused.load(Ordering::Relaxed));
+        assert!(client_pause(&args(&["-1"]), &state).is_err());
+        assert!(client_pause(&args(&["10", "READ"]), &state).is_err());
+    }
+
+    #[test]
+    fn reply_modes_and_no_evict() {
+        let state = ServerState::default();
+        let (mut client, _) = connect(&state, "127.0.0.1:1000");
+        client_reply(&args(&["SKIP"]), &mut client).unwrap();
+        assert_eq!(client.reply, ReplyMode::SkipNext);
+        assert!(client_reply(&args(&["MAYBE"]), &mut client).is_err());
+        client_no_evict(&args(&["on"]), &client).unwrap();
+        assert!(client.handle.describe().contains(" flags=e "));
+    }
+
+    #[test]
+    fn idle_clients_time_out() {
+        let state = ServerState::default();
+        let (idle, shutdowns) = connect(&state, "127.0.0.1:1000");
+        let (busy, _) = connect(&state, "127.0.0.1:2000");
+        let (mut monitor, _) = connect(&state, "127.0.0.1:3000");
+        monitor.monitor = true;
+        monitor.finish_command();
+        let past = Instant::now() - Duration::from_secs(20);
+        for client in [&idle, &busy, &monitor] {
+            client.handle.details.lock().unwrap().last_interaction = past;
+        }
+        busy.handle.details.lock().unwrap().executing = true;
+
+        assert_eq!(state.close_timed_out_clients(), 0);
+        state.config.lock().unwrap().timeout = 10;
+        assert_eq!(state.close_timed_out_clients(), 1);
+        assert!(idle.handle.killed.load(Ordering::Relaxed));
+        assert_eq!(shutdowns.load(Ordering::Relaxed), 1);
+    }
+}
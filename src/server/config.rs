@@ -0,0 +1,669 @@
+use std::{collections::HashSet, fs};
+
+use crate::{command::CommandError, glob::string_match, resp::RespValue, server::value::{Config, ConfigParam, ServerError, ServerState, TlsAuthClients}, store::value::{EvictionSettings, MaxmemoryPolicy}};
+
+///Every parameter that can be set from the configuration file, the command line or CONFIG SET
+pub const PARAMS: [ConfigParam; 26] = [
+    ConfigParam { name: "bind", mutable: false },
+    ConfigParam { name: "port", mutable: false },
+    ConfigParam { name: "unixsocket", mutable: false },
+    ConfigParam { name: "unixsocketperm", mutable: false },
+    ConfigParam { name: "requirepass", mutable: true },
+    ConfigParam { name: "aclfile", mutable: false },
+    ConfigParam { name: "tls-port", mutable: false },
+    ConfigParam { name: "tls-cert-file", mutable: false },
+    ConfigParam { name: "tls-key-file", mutable: false },
+    ConfigParam { name: "tls-ca-cert-file", mutable: false },
+    ConfigParam { name: "tls-auth-clients", mutable: false },
+    ConfigParam { name: "tls-auth-clients-user", mutable: true },
+    ConfigParam { name: "io-threads", mutable: false },
+    ConfigParam { name: "slowlog-log-slower-than", mutable: true },
+    ConfigParam { name: "slowlog-max-len", mutable: true },
+    ConfigParam { name: "latency-monitor-threshold", mutable: true },
+    ConfigParam { name: "timeout", mutable: true },
+    ConfigParam { name: "tcp-keepalive", mutable: true },
+    ConfigParam { name: "maxmemory", mutable: true },
+    ConfigParam { name: "maxmemory-policy", mutable: true },
+    ConfigParam { name: "maxmemory-samples", mutable: true },
+    ConfigParam { name: "lfu-log-factor", mutable: true },
+    ConfigParam { name: "lfu-decay-time", mutable: true },
+    ConfigParam { name: "databases", mutable: false },
+    ConfigParam { name: "keyspace-shards", mutable: false },
+    ConfigParam { name: "configfile", mutable: false },
+];
+
+fn optional_path(value: &str) -> Option<String> {
+    match value {
+        "" => None,
+        _ => Some(value.to_string())
+    }
+}
+
+///Parses a memory amount with an optional unit, k and m are powers of 1000 and kb and mb
+///powers of 1024 as in redis.conf
+fn parse_memory(value: &str) -> Option<usize> {
+    let lower = value.to_ascii_lowercase();
+    let split = lower.find(|c: char| !c.is_ascii_digit()).unwrap_or(lower.len());
+    let (number, unit) = lower.split_at(split);
+    let multiplier = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return None
+    };
+    number.parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+fn param(name: &str) -> Option<&'static ConfigParam> {
+    PARAMS.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+}
+
+///Splits a configuration line into arguments, handling quotes the way redis.conf does
+pub fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let quote = match chars.peek() {
+            None => return Ok(args),
+            Some(&c) if c == '"' || c == '\'' => {
+                chars.next();
+                Some(c)
+            },
+            Some(_) => None
+        };
+        let mut arg = String::new();
+        loop {
+            match (quote, chars.next()) {
+                (None, None) => break,
+                (None, Some(c)) if c.is_whitespace() => break,
+                (None, Some(c)) => arg.push(c),
+                (Some(_), None) => return Err("unbalanced quotes".to_string()),
+                (Some(q), Some(c)) if c == q => {
+                    //A closing quote must end the argument
+                    if chars.next_if(|c| !c.is_whitespace()).is_some() {
+                        return Err("unbalanced quotes".to_string());
+                    }
+                    break;
+                },
+                (Some('"'), Some('\\')) => match chars.next() {
+                    Some('n') => arg.push('\n'),
+                    Some('r') => arg.push('\r'),
+                    Some('t') => arg.push('\t'),
+                    Some('b') => arg.push('\u{8}'),
+                    Some('a') => arg.push('\u{7}'),
+                    Some('x') => {
+                        let hex: String = chars.by_ref().take(2).collect();
+                        match u8::from_str_radix(&hex, 16) {
+                            Ok(b) if hex.len() == 2 => arg.push(b as char),
+                            _ => return Err("invalid hex escape".to_string())
+                        }
+                    },
+                    Some(c) => arg.push(c),
+                    None => return Err("unbalanced quotes".to_string())
+                },
+                (Some('\''), Some('\\')) if chars.peek() == Some(&'\'') => {
+                    chars.next();
+                    arg.push('\'');
+                },
+                (Some(_), Some(c)) => arg.push(c),
+            }
+        }
+        args.push(arg);
+    }
+}
+
+///Quotes a value for the configuration file when it would not survive `split_args` as is
+fn quote(value: &str) -> String {
+    if !value.is_empty() && !value.contains(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '\\') {
+        return value.to_string();
+    }
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            },
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            _ => quoted.push(c)
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            configfile: None,
+            bind: vec!["127.0.0.1".to_string()],
+            port: 6379,
+            unixsocket: None,
+            unixsocketperm: None,
+            requirepass: None,
+            aclfile: None,
+            tls_port: None,
+            tls_cert_file: None,
+            tls_key_file: None,
+            tls_ca_cert_file: None,
+            tls_auth_clients: TlsAuthClients::default(),
+            tls_auth_clients_user: false,
+            io_threads: 24,
+            slowlog_log_slower_than: 10000,
+            slowlog_max_len: 128,
+            latency_monitor_threshold: 0,
+            timeout: 0,
+            tcp_keepalive: 300,
+            maxmemory: 0,
+            maxmemory_policy: MaxmemoryPolicy::NoEviction,
+            maxmemory_samples: 5,
+            lfu_log_factor: 10,
+            lfu_decay_time: 1,
+            databases: 16,
+            keyspace_shards: 16,
+        }
+    }
+}
+
+impl Config {
+    ///Builds the configuration from an optional configuration file path followed by
+    ///`--name value...` overrides, as in `redis-server redis.conf --port 6380`
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Config, ServerError> {
+        let mut config = Config::default();
+        let mut args = args.into_iter().peekable();
+        if let Some(path) = args.next_if(|a| !a.starts_with("--")) {
+            config.load_file(&path)?;
+        }
+        while let Some(arg) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None => return Err(ServerError::Config(format!("Invalid argument '{}'", arg)))
+            };
+            //Options like bind take several values, they run until the next option
+            let mut values = Vec::new();
+            while let Some(value) = args.next_if(|a| !a.starts_with("--")) {
+                values.push(value);
+            }
+            if values.is_empty() {
+                return Err(ServerError::Config(format!("Missing value for '--{}'", name)));
+            }
+            config.set(name, &values.join(" "))?;
+        }
+        Ok(config)
+    }
+
+    ///Applies a redis.conf style file, one `name value...` directive per line
+    pub fn load_file(&mut self, path: &str) -> Result<(), ServerError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| ServerError::Config(format!("Fatal error, can't open config file '{}': {}", path, e)))?;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let result = match split_args(line) {
+                Ok(args) => match args.split_first() {
+                    Some((name, values)) if !values.is_empty() => self.set(name, &values.join(" ")),
+                    _ => Err(ServerError::Config("wrong number of arguments".to_string()))
+                },
+                Err(e) => Err(ServerError::Config(e))
+            };
+            if let Err(ServerError::Config(e)) = result {
+                return Err(ServerError::Config(format!("Bad directive at line {} of '{}' ({}): {}", number + 1, path, line, e)));
+            }
+        }
+        self.configfile = Some(fs::canonicalize(path).map(|p| p.to_string_lossy().into_owned()).unwrap_or_else(|_| path.to_string()));
+        Ok(())
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), ServerError> {
+        match name.to_ascii_lowercase().as_str() {
+            "bind" => self.bind = match value.split_whitespace().map(|a| a.to_string()).collect::<Vec<_>>() {
+                addresses if addresses.is_empty() => return Err(ServerError::Config("Invalid bind ''".to_string())),
+                addresses => addresses
+            },
+            "port" => self.port = value.parse()
+                .map_err(|_| ServerError::Config(format!("Invalid port '{}'", value)))?,
+            "unixsocket" => self.unixsocket = optional_path(value),
+            "unixsocketperm" => self.unixsocketperm = match u32::from_str_radix(value, 8) {
+                Ok(0) => None,
+                Ok(perm) if perm <= 0o777 => Some(perm),
+                _ => return Err(ServerError::Config(format!("Invalid unixsocketperm '{}'", value)))
+            },
+            //An empty password disables authentication, as in redis.conf
+            "requirepass" => self.requirepass = match value {
+                "" => None,
+                _ => Some(value.as_bytes().to_vec())
+            },
+            "aclfile" => self.aclfile = optional_path(value),
+            "tls-port" => self.tls_port = match value.parse::<u16>() {
+                Ok(0) => None,
+                Ok(port) => Some(port),
+                Err(_) => return Err(ServerError::Config(format!("Invalid tls-port '{}'", value)))
+            },
+            "tls-cert-file" => self.tls_cert_file = optional_path(value),
+            "tls-key-file" => self.tls_key_file = optional_path(value),
+            "tls-ca-cert-file" => self.tls_ca_cert_file = optional_path(value),
+            "tls-auth-clients" => self.tls_auth_clients = match value.to_ascii_lowercase().as_str() {
+                "yes" => TlsAuthClients::Yes,
+                "no" => TlsAuthClients::No,
+                "optional" => TlsAuthClients::Optional,
+                _ => return Err(ServerError::Config(format!("Invalid tls-auth-clients '{}'", value)))
+            },
+            "tls-auth-clients-user" => self.tls_auth_clients_user = match value.to_ascii_lowercase().as_str() {
+                "cn" => true,
+                "off" => false,
+                _ => return Err(ServerError::Config(format!("Invalid tls-auth-clients-user '{}'", value)))
+            },
+            "io-threads" => self.io_threads = match value.parse::<usize>() {
+                Ok(threads) if (1..=512).contains(&threads) => threads,
+                _ => return Err(ServerError::Config(format!("Invalid io-threads '{}'", value)))
+            },
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than = value.parse()
+                .map_err(|_| ServerError::Config(format!("Invalid slowlog-log-slower-than '{}'", value)))?,
+            "slowlog-max-len" => self.slowlog_max_len = value.parse()
+                .map_err(|_| ServerError::Config(format!("Invalid slowlog-max-len '{}'", value)))?,
+            "latency-monitor-threshold" => self.latency_monitor_threshold = value.parse()
+                .map_err(|_| ServerError::Config(format!("Invalid latency-monitor-threshold '{}'", value)))?,
+            "timeout" => self.timeout = value.parse()
+                .map_err(|_| ServerError::Config(format!("Invalid timeout '{}'", value)))?,
+            "tcp-keepalive" => self.tcp_keepalive = value.parse()
+                .map_err(|_| ServerError::Config(format!("Invalid tcp-keepalive '{}'", value)))?,
+            "maxmemory" => self.maxmemory = parse_memory(value)
+                .ok_or_else(|| ServerError::Config(format!("Invalid maxmemory '{}'", value)))?,
+            "maxmemory-policy" => self.maxmemory_policy = MaxmemoryPolicy::from_name(value)
+                .ok_or_else(|| ServerError::Config(format!("Invalid maxmemory-policy '{}'", value)))?,
+            "maxmemory-samples" => self.maxmemory_samples = match value.parse::<usize>() {
+                Ok(samples) if (1..=64).contains(&samples) => samples,
+                _ => return Err(ServerError::Config(format!("Invalid maxmemory-samples '{}'", value)))
+            },
+            "lfu-log-factor" => self.lfu_log_factor = value.parse()
+                .map_err(|_| ServerError::Config(format!("Invalid lfu-log-factor '{}'", value)))?,
+            "lfu-decay-time" => self.lfu_decay_time = value.parse()
+                .map_err(|_| ServerError::Config(format!("Invalid lfu-decay-time '{}'", value)))?,
+            "databases" => self.databases = match value.parse::<usize>() {
+                Ok(databases) if databases >= 1 => databases,
+                _ => return Err(ServerError::Config(format!("Invalid databases '{}'", value)))
+            },
+            "keyspace-shards" => self.keyspace_shards = match value.parse::<usize>() {
+                Ok(shards) if shards.is_power_of_two() && shards <= 1024 => shards,
+                _ => return Err(ServerError::Config(format!("Invalid keyspace-shards '{}'", value)))
+            },
+            _ => return Err(ServerError::Config(format!("Unknown option '{}'", name)))
+        }
+        Ok(())
+    }
+
+    ///Current value of a parameter, formatted as CONFIG GET returns it
+    pub fn get(&self, name: &str) -> Option<String> {
+        let path = |p: &Option<String>| p.clone().unwrap_or_default();
+        Some(match name.to_ascii_lowercase().as_str() {
+            "bind" => self.bind.join(" "),
+            "port" => self.port.to_string(),
+            "unixsocket" => path(&self.unixsocket),
+            "unixsocketperm" => format!("{:o}", self.unixsocketperm.unwrap_or(0)),
+            "requirepass" => String::from_utf8_lossy(self.requirepass.as_deref().unwrap_or_default()).into_owned(),
+            "aclfile" => path(&self.aclfile),
+            "tls-port" => self.tls_port.unwrap_or(0).to_string(),
+            "tls-cert-file" => path(&self.tls_cert_file),
+            "tls-key-file" => path(&self.tls_key_file),
+            "tls-ca-cert-file" => path(&self.tls_ca_cert_file),
+            "tls-auth-clients" => match self.tls_auth_clients {
+                TlsAuthClients::Yes => "yes",
+                TlsAuthClients::No => "no",
+                TlsAuthClients::Optional => "optional",
+            }.to_string(),
+            "tls-auth-clients-user" => if self.tls_auth_clients_user { "CN" } else { "off" }.to_string(),
+            "io-threads" => self.io_threads.to_string(),
+            "slowlog-log-slower-than" => self.slowlog_log_slower_than.to_string(),
+            "slowlog-max-len" => self.slowlog_max_len.to_string(),
+            "latency-monitor-threshold" => self.latency_monitor_threshold.to_string(),
+            "timeout" => self.timeout.to_string(),
+            "tcp-keepalive" => self.tcp_keepalive.to_string(),
+            "maxmemory" => self.maxmemory.to_string(),
+            "maxmemory-policy" => self.maxmemory_policy.name().to_string(),
+            "maxmemory-samples" => self.maxmemory_samples.to_string(),
+            "lfu-log-factor" => self.lfu_log_factor.to_string(),
+            "lfu-decay-time" => self.lfu_decay_time.to_string(),
+            "databases" => self.databases.to_string(),
+            "keyspace-shards" => self.keyspace_shards.to_string(),
+            "configfile" => path(&self.configfile),
+            _ => return None
+        })
+    }
+
+    ///The part of the configuration the store applies when evicting keys
+    pub fn eviction(&self) -> EvictionSettings {
+        EvictionSettings {
+            maxmemory: self.maxmemory,
+            policy: self.maxmemory_policy,
+            samples: self.maxmemory_samples,
+            lfu_log_factor: self.lfu_log_factor,
+            lfu_decay_time: self.lfu_decay_time,
+        }
+    }
+
+    fn directive(&self, name: &str) -> String {
+        let value = self.get(name).unwrap_or_default();
+        match name {
+            //Each address is its own argument
+            "bind" => format!("bind {}", value),
+            _ => format!("{} {}", name, quote(&value))
+        }
+    }
+
+    ///Rewrites the text of a configuration file with the current values. Comments and unknown
+    ///lines are kept, directives are updated in place and parameters that differ from their
+    ///default but are missing from the file are appended
+    pub fn rewrite(&self, text: &str) -> String {
+        let mut written = HashSet::new();
+        let mut lines = Vec::new();
+        for line in text.lines() {
+            let trimmed = line.trim();
+            let name = match split_args(trimmed) {
+                Ok(args) if !trimmed.starts_with('#') => args.first().and_then(|name| param(name)).map(|p| p.name),
+                _ => None
+            };
+            match name {
+                //The configfile path is not a directive, it is only known at runtime
+                Some("configfile") | None => lines.push(line.to_string()),
+                Some(name) => {
+                    //Repeated directives collapse into the first one
+                    if written.insert(name) {
+                        lines.push(self.directive(name));
+                    }
+                }
+            }
+        }
+        let defaults = Config::default();
+        let missing = PARAMS.iter()
+            .filter(|p| p.name != "configfile" && !written.contains(p.name) && self.get(p.name) != defaults.get(p.name))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            lines.push("# Generated by CONFIG REWRITE".to_string());
+            lines.extend(missing.iter().map(|p| self.directive(p.name)));
+        }
+        let mut text = lines.join("\n");
+        text.push('\n');
+        text
+    }
+}
+
+fn bulk(s: &[u8]) -> RespValue {
+    RespValue::BulkString(Some(s.to_vec()))
+}
+
+fn arg_string(arg: &RespValue) -> Result<String, CommandError> {
+    arg.as_bytes().map(|b| String::from_utf8_lossy(b).into_owned()).ok_or(CommandError::InvalidRequest)
+}
+
+pub fn handle_config(args: &[RespValue], state: &ServerState) -> Result<RespValue, CommandError> {
+    let subcommand = match args.first() {
+        Some(arg) => arg_string(arg)?.to_ascii_uppercase(),
+        None => return Err(CommandError::WrongArity)
+    };
+    let args = &args[1..];
+
+    match subcommand.as_str() {
+        "GET" if !args.is_empty() => config_get(args, state),
+        "SET" if !args.is_empty() && args.len().is_multiple_of(2) => config_set(args, state),
+        "REWRITE" if args.is_empty() => config_rewrite(state),
+        "RESETSTAT" if args.is_empty() => {
+            state.reset_stats();
+            Ok(RespValue::SimpleString(b"OK".to_vec()))
+        },
+        "GET" | "SET" | "REWRITE" | "RESETSTAT" => Err(CommandError::WrongArity),
+        _ => Err(CommandError::Custom(format!(
+            "ERR unknown subcommand '{}'. Try CONFIG HELP.",
+            subcommand.to_ascii_lowercase()
+        )))
+    }
+}
+
+fn config_get(patterns: &[RespValue], state: &ServerState) -> Result<RespValue, CommandError> {
+    let patterns = patterns.iter().map(|p| arg_string(p).map(|p| p.to_ascii_lowercase())).collect::<Result<Vec<_>, _>>()?;
+    let config = state.config.lock().unwrap();
+    let pairs = PARAMS.iter()
+        .filter(|p| patterns.iter().any(|pattern| string_match(pattern.as_bytes(), p.name.as_bytes(), true)))
+        .map(|p| (bulk(p.name.as_bytes()), bulk(config.get(p.name).unwrap_or_default().as_bytes())))
+        .collect();
+    Ok(RespValue::Map(pairs))
+}
+
+///Applies every pair or none of them
+fn config_set(args: &[RespValue], state: &ServerState) -> Result<RespValue, CommandError> {
+    let failed = |name: &str, reason: &str| CommandError::Custom(format!(
+        "ERR CONFIG SET failed (possibly related to argument '{}') - {}", name, reason
+    ));
+    //The lock is held until every change is applied, so concurrent CONFIG SETs cannot undo
+    //each other. Pairs are applied to a copy first, which is dropped if any of them fails
+    let mut current = state.config.lock().unwrap();
+    let mut config = current.clone();
+    let mut seen = HashSet::new();
+    for pair in args.chunks(2) {
+        let name = arg_string(&pair[0])?;
+        let value = arg_string(&pair[1])?;
+        let param = param(&name).ok_or_else(|| CommandError::Custom(format!(
+            "ERR Unknown option or number of arguments for CONFIG SET - '{}'", name
+        )))?;
+        if !param.mutable {
+            return Err(failed(&name, "can't set immutable config"));
+        }
+        if !seen.insert(param.name) {
+            return Err(failed(&name, "duplicate parameter"));
+        }
+        if let Err(ServerError::Config(e)) = config.set(param.name, &value) {
+            return Err(failed(&name, &e));
+        }
+    }
+
+    state.publish_config(&config);
+    //requirepass is a shortcut for the password of the default user
+    if seen.contains("requirepass") {
+        state.acl.write().unwrap().set_requirepass(config.requirepass.as_deref());
+    }
+    *current = config;
+    Ok(RespValue::SimpleString(b"OK".to_vec()))
+}
+
+fn config_rewrite(state: &ServerState) -> Result<RespValue, CommandError> {
+    let config = state.config.lock().unwrap().clone();
+    let path = config.configfile.clone()
+        .ok_or_else(|| CommandError::Custom("ERR The server is running without a config file".to_string()))?;
+    let failed = |e: std::io::Error| CommandError::Custom(format!("ERR Rewriting config file: {}", e));
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(failed(e))
+    };
+    //Written next to the file then renamed, so a failure never leaves a truncated config
+    let tmp = format!("{}.tmp-{}", path, std::process::id());
+    fs::write(&tmp, config.rewrite(&text)).map_err(failed)?;
+    fs::rename(&tmp, &path).map_err(failed)?;
+    Ok(RespValue::SimpleString(b"OK".to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn from_args_requirepass() {
+        let config = Config::from_args(args(&["--requirepass", "secret"])).unwrap();
+        assert_eq!(config.requirepass, Some(b"secret".to_vec()));
+    }
+
+    #[test]
+    fn from_args_empty_requirepass_disables_auth() {
+        let config = Config::from_args(args(&["--requirepass", ""])).unwrap();
+        assert_eq!(config.requirepass, None);
+    }
+
+    #[test]
+    fn from_args_tls() {
+        let config = Config::from_args(args(&["--tls-port", "6380", "--tls-cert-file", "c.pem", "--tls-auth-clients", "optional", "--tls-auth-clients-user", "CN"])).unwrap();
+        assert_eq!(config.tls_port, Some(6380));
+        assert_eq!(config.tls_cert_file, Some("c.pem".to_string()));
+        assert_eq!(config.tls_auth_clients, TlsAuthClients::Optional);
+        assert!(config.tls_auth_clients_user);
+        assert!(Config::from_args(args(&["--tls-port", "x"])).is_err());
+    }
+
+    #[test]
+    fn from_args_unixsocket() {
+        let config = Config::from_args(args(&["--port", "0", "--unixsocket", "/tmp/redis.sock", "--unixsocketperm", "770"])).unwrap();
+        assert_eq!(config.port, 0);
+        assert_eq!(config.unixsocket, Some("/tmp/redis.sock".to_string()));
+        assert_eq!(config.unixsocketperm, Some(0o770));
+        assert!(Config::from_args(args(&["--unixsocketperm", "999"])).is_err());
+        assert_eq!(Config::default().port, 6379);
+    }
+
+    #[test]
+    fn from_args_rejects_unknown_and_incomplete() {
+        assert!(Config::from_args(args(&["--nope", "1"])).is_err());
+        assert!(Config::from_args(args(&["--requirepass"])).is_err());
+        assert!(Config::from_args(args(&["requirepass", "x"])).is_err());
+    }
+
+    #[test]
+    fn from_args_databases() {
+        assert_eq!(Config::default().databases, 16);
+        assert_eq!(Config::from_args(args(&["--databases", "4"])).unwrap().databases, 4);
+        assert!(Config::from_args(args(&["--databases", "0"])).is_err());
+    }
+
+    #[test]
+    fn from_args_keyspace_shards() {
+        assert_eq!(Config::default().keyspace_shards, 16);
+        assert_eq!(Config::from_args(args(&["--keyspace-shards", "1"])).unwrap().keyspace_shards, 1);
+        assert!(Config::from_args(args(&["--keyspace-shards", "12"])).is_err());
+        assert!(Config::from_args(args(&["--keyspace-shards", "0"])).is_err());
+    }
+
+    #[test]
+    fn split_args_handles_quotes() {
+        assert_eq!(split_args("  bind 127.0.0.1   ::1 ").unwrap(), args(&["bind", "127.0.0.1", "::1"]));
+        assert_eq!(split_args(r#"requirepass "a b\"c\x41" ''"#).unwrap(), args(&["requirepass", "a b\"cA", ""]));
+        assert_eq!(split_args(r"x 'it\'s'").unwrap(), args(&["x", "it's"]));
+        assert!(split_args("x \"open").is_err());
+        assert!(split_args("x \"a\"b").is_err());
+    }
+
+    #[test]
+    fn file_then_command_line_overrides() {
+        let path = std::env::temp_dir().join(format!("redis-rust-config-{}.conf", std::process::id()));
+        fs::write(&path, "# comment\nport 7000\nbind 127.0.0.1 -::1\nrequirepass \"pass word\"\nio-threads 4\n").unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let config = Config::from_args(args(&[&path, "--port", "7001"])).unwrap();
+        assert_eq!(config.port, 7001);
+        assert_eq!(config.bind, args(&["127.0.0.1", "-::1"]));
+        assert_eq!(config.requirepass, Some(b"pass word".to_vec()));
+        assert_eq!(config.io_threads, 4);
+        assert!(config.configfile.is_some());
+
+        fs::write(&path, "port 7000\nnope 1\n").unwrap();
+        assert!(Config::from_args(args(&[&path])).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn get_formats_values() {
+        let config = Config::from_args(args(&["--bind", "127.0.0.1", "::1", "--unixsocketperm", "700"])).unwrap();
+        assert_eq!(config.get("bind").unwrap(), "127.0.0.1 ::1");
+        assert_eq!(config.get("UNIXSOCKETPERM").unwrap(), "700");
+        assert_eq!(config.get("tls-port").unwrap(), "0");
+        assert_eq!(config.get("nope"), None);
+        assert!(PARAMS.iter().all(|p| config.get(p.name).is_some()));
+    }
+
+    #[test]
+    fn rewrite_keeps_comments_and_appends_missing() {
+        let mut config = Config::default();
+        config.set("port", "7000").unwrap();
+        config.set("requirepass", "a b").unwrap();
+        let text = "# my server\nport 6379\n\nsome-unknown thing\nport 6380\n";
+        assert_eq!(
+            config.rewrite(text),
+            "# my server\nport 7000\n\nsome-unknown thing\n# Generated by CONFIG REWRITE\nrequirepass \"a b\"\n"
+        );
+
+        let rewritten = config.rewrite("");
+        let path = std::env::temp_dir().join(format!("redis-rust-rewrite-{}.conf", std::process::id()));
+        fs::write(&path, &rewritten).unwrap();
+        let mut loaded = Config::default();
+        loaded.load_file(&path.to_string_lossy()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(Config { configfile: None, ..loaded }, config);
+    }
+
+    #[test]
+    fn config_set_is_atomic_and_updates_default_user() {
+        let state = ServerState::default();
+        let set = |pairs: &[&str]| handle_config(&[&["SET"], pairs].concat().iter().map(|a| bulk(a.as_bytes())).collect::<Vec<_>>(), &state);
+
+        assert!(set(&["port", "7000"]).is_err());
+        assert!(set(&["requirepass", "secret", "nope", "1"]).is_err());
+        assert!(set(&["requirepass", "a", "requirepass", "b"]).is_err());
+        assert_eq!(state.config.lock().unwrap().requirepass, None);
+
+        set(&["requirepass", "secret", "tls-auth-clients-user", "CN"]).unwrap();
+        assert!(state.config.lock().unwrap().tls_auth_clients_user);
+        assert!(!state.acl.read().unwrap().user(b"default").unwrap().nopass);
+
+        let reply = handle_config(&[bulk(b"GET"), bulk(b"tls-auth-*"), bulk(b"requirepass")], &state).unwrap();
+        assert_eq!(reply, RespValue::Map(vec![
+            (bulk(b"requirepass"), bulk(b"secret")),
+            (bulk(b"tls-auth-clients"), bulk(b"yes")),
+            (bulk(b"tls-auth-clients-user"), bulk(b"CN")),
+        ]));
+    }
+
+    #[test]
+    fn concurrent_config_sets_keep_every_change() {
+        let state = ServerState::default();
+        std::thread::scope(|scope| {
+            for name in ["slowlog-max-len", "lfu-decay-time", "latency-monitor-threshold"] {
+                let state = &state;
+                scope.spawn(move || {
+                    for value in 1..=200 {
+                        handle_config(&[bulk(b"SET"), bulk(name.as_bytes()), bulk(value.to_string().as_bytes())], state).unwrap();
+                    }
+                });
+            }
+        });
+        let config = state.config.lock().unwrap();
+        assert_eq!((config.slowlog_max_len, config.lfu_decay_time, config.latency_monitor_threshold), (200, 200, 200));
+        assert_eq!(state.keyspace.eviction().lfu_decay_time, 200);
+        assert_eq!(state.latency_monitor_threshold.load(std::sync::atomic::Ordering::Relaxed), 200);
+    }
+
+    #[test]
+    fn timeout_round_trips_and_closes_idle_clients() {
+        let state = ServerState::default();
+        let config = |parts: &[&str]| handle_config(&parts.iter().map(|a| bulk(a.as_bytes())).collect::<Vec<_>>(), &state);
+        let client = state.register_client(crate::server::value::Connection::new(Box::new(std::io::sink()), "127.0.0.1:1".to_string()));
+        client.handle.details.lock().unwrap().last_interaction -= std::time::Duration::from_secs(20);
+
+        assert!(config(&["SET", "timeout", "-1"]).is_err());
+        assert!(config(&["SET", "timeout", "soon"]).is_err());
+        assert_eq!(state.close_timed_out_clients(), 0);
+        config(&["SET", "timeout", "10"]).unwrap();
+        assert_eq!(config(&["GET", "timeout"]).unwrap(), RespValue::Map(vec![(bulk(b"timeout"), bulk(b"10"))]));
+        assert_eq!(state.close_timed_out_clients(), 1);
+        config(&["SET", "timeout", "0"]).unwrap();
+        assert_eq!(config(&["GET", "timeout"]).unwrap(), RespValue::Map(vec![(bulk(b"timeout"), bulk(b"0"))]));
+    }
+}
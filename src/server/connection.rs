@@ -0,0 +1,360 @@
+use crate::{command::{keyspace::db_index, CommandError}, resp::RespValue, server::{clients::{client_info, client_kill, client_getname, client_list, client_no_evict, client_pause, client_reply, client_setname}, value::{Client, ServerState, TrackingOptions}}};
+
+fn bulk(s: &[u8]) -> RespValue {
+    RespValue::BulkString(Some(s.to_vec()))
+}
+
+fn upper(arg: &RespValue) -> Result<Vec<u8>, CommandError> {
+    match arg.as_bytes() {
+        Some(bytes) => Ok(bytes.to_ascii_uppercase()),
+        None => Err(CommandError::InvalidRequest)
+    }
+}
+
+pub fn handle_auth(args: &[RespValue], state: &ServerState, client: &mut Client) -> Result<RespValue, CommandError> {
+    let (username, password) = match args {
+        [password] => (None, password),
+        [username, password] => (Some(username), password),
+        _ => return Err(CommandError::Syntax)
+    };
+    let password = password.as_bytes().ok_or(CommandError::InvalidRequest)?;
+
+    let mut acl = state.acl.write().unwrap();
+    let username = match username {
+        Some(u) => u.as_bytes().ok_or(CommandError::InvalidRequest)?,
+        //AUTH with only a password authenticates the default user, which is pointless if it
+        //has none and most likely a misconfiguration
+        None if acl.user(b"default").is_some_and(|u| u.nopass) => {
+            return Err(CommandError::Custom("ERR AUTH <password> called without any password configured for the default user. Are you sure your configuration is correct?".to_string()));
+        },
+        None => b"default"
+    };
+
+    if !acl.authenticate(username, password) {
+        acl.add_log_entry("auth", b"AUTH", username, client.info());
+        return Err(CommandError::Custom("WRONGPASS invalid username-password pair or user is disabled.".to_string()));
+    }
+    client.authenticated = true;
+    client.user = username.to_vec();
+    Ok(RespValue::SimpleString(b"OK".to_vec()))
+}
+
+pub fn handle_select(args: &[RespValue], state: &ServerState, client: &mut Client) -> Result<RespValue, CommandError> {
+    let [index] = args else {
+        return Err(CommandError::WrongArity);
+    };
+    client.db = db_index(index, state.keyspace.databases)?;
+    Ok(RespValue::SimpleString(b"OK".to_vec()))
+}
+
+pub fn handle_quit(client: &mut Client) -> Result<RespValue, CommandError> {
+    client.closing = true;
+    Ok(RespValue::SimpleString(b"OK".to_vec()))
+}
+
+///`HELLO [protover [AUTH username password]]`
+pub fn handle_hello(args: &[RespValue], state: &ServerState, client: &mut Client) -> Result<RespValue, CommandError> {
+    let mut protocol = None;
+    if let Some(version) = args.first() {
+        protocol = match version.as_i64() {
+            Some(v @ 2..=3) => Some(v as u8),
+            Some(_) => return Err(CommandError::Custom("NOPROTO unsupported protocol version".to_string())),
+            None => return Err(CommandError::Custom("ERR Protocol version is not an integer or out of range".to_string()))
+        };
+    }
+
+    let mut i = 1;
+    while i < args.len() {
+        match upper(&args[i])?.as_slice() {
+            b"AUTH" if i + 2 < args.len() => {
+                handle_auth(&args[i + 1..i + 3], state, client)?;
+                i += 3;
+            },
+            _ => return Err(CommandError::Syntax)
+        }
+    }
+
+    if protocol == Some(2) && client.tracking.as_ref().is_some_and(|t| t.redirect.is_none()) {
+        return Err(CommandError::Custom("ERR Can't switch to RESP2 while tracking without REDIRECT, turn CLIENT TRACKING off first".to_string()));
+    }
+    if !client.authenticated {
+        return Err(CommandError::Custom("NOAUTH HELLO must be called with the client already authenticated, otherwise the HELLO <proto> AUTH <user> <pass> option can be used to authenticate the client and select the RESP protocol version at the same time".to_string()));
+    }
+    if let Some(protocol) = protocol {
+        client.handle.protocol.store(protocol, std::sync::atomic::Ordering::Relaxed);
+    }
+
+    Ok(RespValue::Map(vec![
+        (bulk(b"server"), bulk(b"redis")),
+        (bulk(b"version"), bulk(env!("CARGO_PKG_VERSION").as_bytes())),
+        (bulk(b"proto"), RespValue::Integer(client.handle.protocol() as i64)),
+        (bulk(b"id"), RespValue::Integer(client.id() as i64)),
+        (bulk(b"mode"), bulk(b"standalone")),
+        (bulk(b"role"), bulk(b"master")),
+        (bulk(b"modules"), RespValue::Arrays(Some(vec![]))),
+    ]))
+}
+
+pub fn handle_client(args: &[RespValue], state: &ServerState, client: &mut Client) -> Result<RespValue, CommandError> {
+    let subcommand = match args.first() {
+        Some(arg) => upper(arg)?,
+        None => return Err(CommandError::WrongArity)
+    };
+
+    match subcommand.as_slice() {
+        b"ID" => Ok(RespValue::Integer(client.id() as i64)),
+        b"TRACKING" => client_tracking(&args[1..], state, client),
+        b"CACHING" => client_caching(&args[1..], client),
+        b"GETREDIR" => Ok(RespValue::Integer(match &client.tracking {
+            Some(options) => options.redirect.unwrap_or(0) as i64,
+            None => -1
+        })),
+        b"TRACKINGINFO" => Ok(client_tracking_info(client)),
+        b"LIST" => client_list(&args[1..], state),
+        b"INFO" => Ok(client_info(client)),
+        b"KILL" => client_kill(&args[1..], state, client),
+        b"SETNAME" => client_setname(&args[1..], client),
+        b"GETNAME" => Ok(client_getname(client)),
+        b"PAUSE" => client_pause(&args[1..], state),
+        b"UNPAUSE" => {
+            state.unpause_clients();
+            Ok(RespValue::SimpleString(b"OK".to_vec()))
+        },
+        b"REPLY" => client_reply(&args[1..], client),
+        b"NO-EVICT" => client_no_evict(&args[1..], client),
+        _ => Err(CommandError::Custom(format!(
+            "ERR unknown subcommand '{}'. Try CLIENT HELP.",
+            String::from_utf8_lossy(&subcommand)
+        )))
+    }
+}
+
+///Parses `ON|OFF [REDIRECT id] [PREFIX prefix ...] [BCAST] [OPTIN] [OPTOUT] [NOLOOP]`
+pub fn parse_tracking_options(args: &[RespValue]) -> Result<(bool, TrackingOptions), CommandError> {
+    let on = match args.first() {
+        Some(arg) => match upper(arg)?.as_slice() {
+            b"ON" => true,
+            b"OFF" => false,
+            _ => return Err(CommandError::Syntax)
+        },
+        None => return Err(CommandError::WrongArity)
+    };
+
+    let mut options = TrackingOptions::default();
+    let mut i = 1;
+    while i < args.len() {
+        match upper(&args[i])?.as_slice() {
+            b"REDIRECT" => {
+                i += 1;
+                let id = args.get(i).and_then(|v| v.as_i64()).ok_or(CommandError::Syntax)?;
+                options.redirect = Some(id as u64);
+            },
+            b"PREFIX" => {
+                i += 1;
+                let prefix = args.get(i).and_then(|v| v.as_bytes()).ok_or(CommandError::Syntax)?;
+                options.prefixes.push(prefix.to_vec());
+            },
+            b"BCAST" => options.bcast = true,
+            b"OPTIN" => options.optin = true,
+            b"OPTOUT" => options.optout = true,
+            b"NOLOOP" => options.noloop = true,
+            _ => return Err(CommandError::Syntax)
+        }
+        i += 1;
+    }
+
+    if !options.bcast && !options.prefixes.is_empty() {
+        return Err(CommandError::Custom("ERR PREFIX option requires BCAST mode to be enabled".to_string()));
+    }
+    if options.optin && options.optout {
+        return Err(CommandError::Custom("ERR You can't use both OPTIN and OPTOUT".to_string()));
+    }
+    if options.bcast && (options.optin || options.optout) {
+        return Err(CommandError::Custom("ERR OPTIN and OPTOUT are not compatible with BCAST".to_string()));
+    }
+    check_prefix_overlap(&options.prefixes)?;
+
+    Ok((on, options))
+}
+
+fn check_prefix_overlap(prefixes: &[Vec<u8>]) -> Result<(), CommandError> {
+    for (i, a) in prefixes.iter().enumerate() {
+        for b in &prefixes[i + 1..] {
+            if a.starts_with(b) || b.starts_with(a) {
+                return Err(CommandError::Custom(format!(
+                    "ERR Prefix '{}' overlaps with another provided prefix '{}'. Prefixes for a single client must not overlap.",
+                    String::from_utf8_lossy(a),
+                    String::from_utf8_lossy(b)
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn client_tracking(args: &[RespValue], state: &ServerState, client: &mut Client) -> Result<RespValue, CommandError> {
+    let (on, mut options) = parse_tracking_options(args)?;
+
+    if !on {
+        state.disable_tracking(client.id());
+        client.tracking = None;
+        return Ok(RespValue::SimpleString(b"OK".to_vec()));
+    }
+
+    //There is no Pub/Sub, so a RESP2 connection cannot receive invalidations in between its
+    //replies, only a connection given to REDIRECT can
+    if options.redirect.is_none() && client.handle.protocol() < 3 {
+        return Err(CommandError::Custom("ERR Client tracking in RESP2 requires REDIRECT to another connection, or HELLO 3 to receive invalidation pushes".to_string()));
+    }
+    if let Some(target) = options.redirect
+        && !state.client_exists(target) {
+        return Err(CommandError::Custom("ERR The client ID you want redirect to does not exist".to_string()));
+    }
+
+    //Enabling tracking again keeps the mode and adds the new prefixes
+    if let Some(current) = &client.tracking {
+        if current.bcast != options.bcast {
+            return Err(CommandError::Custom("ERR You can't switch BCAST mode on/off before disabling tracking for this client, and then re-enabling it with a different mode.".to_string()));
+        }
+        if current.optin != options.optin || current.optout != options.optout {
+            return Err(CommandError::Custom("ERR You can't switch OPTIN/OPTOUT mode before disabling tracking for this client, and then re-enabling it with a different mode.".to_string()));
+        }
+        let mut prefixes = current.prefixes.clone();
+        for prefix in options.prefixes {
+            if !prefixes.contains(&prefix) {
+                prefixes.push(prefix);
+            }
+        }
+        check_prefix_overlap(&prefixes)?;
+        options.prefixes = prefixes;
+    }
+
+    state.enable_tracking(client.id(), options.clone());
+    client.tracking = Some(options);
+    Ok(RespValue::SimpleString(b"OK".to_vec()))
+}
+
+fn client_caching(args: &[RespValue], client: &mut Client) -> Result<RespValue, CommandError> {
+    let yes = match args {
+        [arg] => match upper(arg)?.as_slice() {
+            b"YES" => true,
+            b"NO" => false,
+            _ => return Err(CommandError::Syntax)
+        },
+        _ => return Err(CommandError::WrongArity)
+    };
+
+    let (optin, optout) = match &client.tracking {
+        Some(options) if options.optin || options.optout => (options.optin, options.optout),
+        _ => return Err(CommandError::Custom("ERR CLIENT CACHING can be called only when the client is in tracking mode with OPTIN or OPTOUT mode enabled".to_string()))
+    };
+    if yes && !optin {
+        return Err(CommandError::Custom("ERR CLIENT CACHING YES is only valid when tracking is enabled in OPTIN mode.".to_string()));
+    }
+    if !yes && !optout {
+        return Err(CommandError::Custom("ERR CLIENT CACHING NO is only valid when tracking is enabled in OPTOUT mode.".to_string()));
+    }
+
+    client.caching = Some(yes);
+    Ok(RespValue::SimpleString(b"OK".to_vec()))
+}
+
+fn client_tracking_info(client: &Client) -> RespValue {
+    let (flags, redirect, prefixes) = match &client.tracking {
+        None => (vec![bulk(b"off")], -1, Vec::new()),
+        Some(options) => {
+            let mut flags = vec![bulk(b"on")];
+            if options.bcast {
+                flags.push(bulk(b"bcast"));
+            }
+            if options.optin {
+                flags.push(bulk(b"optin"));
+            }
+            if options.optout {
+                flags.push(bulk(b"optout"));
+            }
+            if options.noloop {
+                flags.push(bulk(b"noloop"));
+            }
+            match client.caching {
+                Some(true) => flags.push(bulk(b"caching-yes")),
+                Some(false) => flags.push(bulk(b"caching-no")),
+                None => {}
+            }
+            let prefixes = options.prefixes.iter().map(|p| bulk(p)).collect();
+            (flags, options.redirect.unwrap_or(0) as i64, prefixes)
+        }
+    };
+
+    RespValue::Map(vec![
+        (bulk(b"flags"), RespValue::Arrays(Some(flags))),
+        (bulk(b"redirect"), RespValue::Integer(redirect)),
+        (bulk(b"prefixes"), RespValue::Arrays(Some(prefixes))),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::value::Connection;
+    use crate::test_helpers::args;
+
+    #[test]
+    fn tracking_on_with_options() {
+        let (on, options) = parse_tracking_options(&args(&["on", "BCAST", "PREFIX", "a:", "PREFIX", "b:", "NOLOOP", "REDIRECT", "5"])).unwrap();
+        assert!(on);
+        assert_eq!(options, TrackingOptions {
+            redirect: Some(5),
+            bcast: true,
+            prefixes: vec![b"a:".to_vec(), b"b:".to_vec()],
+            optin: false,
+            optout: false,
+            noloop: true,
+        });
+    }
+
+    #[test]
+    fn tracking_off() {
+        let (on, _) = parse_tracking_options(&args(&["OFF"])).unwrap();
+        assert!(!on);
+    }
+
+    #[test]
+    fn tracking_prefix_requires_bcast() {
+        assert!(parse_tracking_options(&args(&["ON", "PREFIX", "a"])).is_err());
+    }
+
+    #[test]
+    fn tracking_optin_optout_conflict() {
+        assert!(parse_tracking_options(&args(&["ON", "OPTIN", "OPTOUT"])).is_err());
+        assert!(parse_tracking_options(&args(&["ON", "BCAST", "OPTIN"])).is_err());
+    }
+
+    #[test]
+    fn tracking_overlapping_prefixes() {
+        assert!(parse_tracking_options(&args(&["ON", "BCAST", "PREFIX", "user", "PREFIX", "user:1"])).is_err());
+    }
+
+    #[test]
+    fn resp2_tracking_needs_a_redirect() {
+        let state = ServerState::default();
+        let mut client = state.register_client(Connection::new(Box::new(std::io::sink()), "127.0.0.1:1".to_string()));
+        let target = state.register_client(Connection::new(Box::new(std::io::sink()), "127.0.0.1:2".to_string()));
+        assert!(client_tracking(&args(&["ON"]), &state, &mut client).is_err());
+        assert!(client.tracking.is_none());
+        let redirect = target.id().to_string();
+        client_tracking(&args(&["ON", "REDIRECT", &redirect]), &state, &mut client).unwrap();
+        client_tracking(&args(&["OFF"]), &state, &mut client).unwrap();
+
+        handle_hello(&args(&["3"]), &state, &mut client).unwrap();
+        client_tracking(&args(&["ON"]), &state, &mut client).unwrap();
+        assert!(handle_hello(&args(&["2"]), &state, &mut client).is_err());
+        assert_eq!(client.handle.protocol(), 3);
+    }
+
+    #[test]
+    fn tracking_redirect_needs_integer() {
+        let err = parse_tracking_options(&args(&["ON", "REDIRECT", "abc"])).unwrap_err();
+        assert_eq!(err, CommandError::Syntax);
+    }
+}
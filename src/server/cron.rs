@@ -0,0 +1,39 @@
+use std::{sync::Arc, thread, time::{Duration, Instant}};
+
+use crate::server::value::ServerState;
+
+//Redis runs its cron at 10 hz by default
+const CRON_INTERVAL: Duration = Duration::from_millis(100);
+//Ticks between client timeout checks, the timeout has a one second resolution
+const CLIENTS_CRON_TICKS: u64 = 10;
+//Share of each tick the active expire cycle may hold the store for
+const EXPIRE_CYCLE_TIME_LIMIT: Duration = Duration::from_millis(25);
+
+///Starts the thread running periodic server tasks
+pub fn spawn_cron(state: Arc<ServerState>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut tick: u64 = 0;
+        loop {
+            thread::sleep(CRON_INTERVAL);
+            tick += 1;
+            state.stats.sample_ops();
+            state.active_expire_cycle();
+            if tick.is_multiple_of(CLIENTS_CRON_TICKS) {
+                state.close_timed_out_clients();
+            }
+        }
+    })
+}
+
+impl ServerState {
+    ///Deletes expired keys in the background and tells clients caching them
+    pub fn active_expire_cycle(&self) {
+        let started = Instant::now();
+        let invalidations = {
+            let expired = self.keyspace.active_expire_cycle(EXPIRE_CYCLE_TIME_LIMIT);
+            self.tracking.lock().unwrap().invalidate_keys(&expired, None)
+        };
+        self.latency_add_sample_if_needed("expire-cycle", started.elapsed());
+        self.send_invalidations(invalidations);
+    }
+}
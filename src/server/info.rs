@@ -0,0 +1,242 @@
+use std::{fmt::Write, sync::atomic::Ordering, time::UNIX_EPOCH};
+
+use crate::{command::CommandError, resp::RespValue, server::value::ServerState};
+
+///Sections in the order INFO lists them
+pub const SECTIONS: [&str; 9] = [
+    "server", "clients", "memory", "persistence", "stats", "commandstats", "errorstats", "latencystats", "keyspace",
+];
+
+//Sections only listed when asked for by name or with `all`, as they can be long
+const NON_DEFAULT: [&str; 2] = ["commandstats", "latencystats"];
+
+///Formats a byte count the way INFO's `*_human` fields do
+pub fn bytes_to_human(bytes: usize) -> String {
+    let bytes = bytes as f64;
+    match bytes {
+        b if b < 1024.0 => format!("{}B", b),
+        b if b < 1024.0 * 1024.0 => format!("{:.2}K", b / 1024.0),
+        b if b < 1024.0 * 1024.0 * 1024.0 => format!("{:.2}M", b / (1024.0 * 1024.0)),
+        b => format!("{:.2}G", b / (1024.0 * 1024.0 * 1024.0)),
+    }
+}
+
+pub fn handle_info(args: &[RespValue], state: &ServerState) -> Result<RespValue, CommandError> {
+    let mut requested = Vec::new();
+    for arg in args {
+        let section = arg.as_bytes().ok_or(CommandError::InvalidRequest)?.to_ascii_lowercase();
+        requested.push(String::from_utf8_lossy(&section).into_owned());
+    }
+    Ok(RespValue::BulkString(Some(info(&requested, state).into_bytes())))
+}
+
+///Renders the requested sections, the default ones when none are asked for. `default`, `all` and
+///`everything` select groups of sections, unknown sections are ignored
+pub fn info(requested: &[String], state: &ServerState) -> String {
+    let all = requested.iter().any(|s| matches!(s.as_str(), "all" | "everything"));
+    let default = requested.is_empty() || requested.iter().any(|s| s == "default");
+    let selected = |section: &str| all
+        || (default && !NON_DEFAULT.contains(&section))
+        || requested.iter().any(|r| r == section);
+    let mut out = String::new();
+    for section in SECTIONS.into_iter().filter(|s| selected(s)) {
+        if !out.is_empty() {
+            out.push_str("\r\n");
+        }
+        let mut title = section.to_string();
+        title[..1].make_ascii_uppercase();
+        out.push_str(&format!("# {}\r\n", title));
+        for (name, value) in section_fields(section, state) {
+            let _ = write!(out, "{}:{}\r\n", name, value);
+        }
+    }
+    out
+}
+
+fn section_fields(section: &str, state: &ServerState) -> Vec<(String, String)> {
+    match section {
+        "commandstats" => state.stats.command_stats().into_iter()
+            .map(|(name, stats)| (
+                format!("cmdstat_{}", name),
+                format!(
+                    "calls={},usec={},usec_per_call={:.2},rejected_calls={},failed_calls={}",
+                    stats.calls, stats.usec, stats.usec_per_call(), stats.rejected_calls, stats.failed_calls
+                )
+            ))
+            .collect(),
+        "errorstats" => state.stats.errors.lock().unwrap().iter()
+            .map(|(prefix, count)| (format!("errorstat_{}", prefix), format!("count={}", count)))
+            .collect(),
+        "latencystats" => state.stats.command_stats().into_iter()
+            .filter(|(_, stats)| stats.latency.total > 0)
+            .map(|(name, stats)| (
+                format!("latency_percentiles_usec_{}", name),
+                format!(
+                    "p50={:.3},p99={:.3},p99.9={:.3}",
+                    stats.latency.percentile(50.0) as f64,
+                    stats.latency.percentile(99.0) as f64,
+                    stats.latency.percentile(99.9) as f64
+                )
+            ))
+            .collect(),
+        //Only databases holding keys are listed
+        "keyspace" => state.keyspace.db_sizes().into_iter()
+            .zip(state.keyspace.avg_ttls())
+            .enumerate()
+            .filter(|(_, ((keys, _), _))| *keys > 0)
+            .map(|(index, ((keys, expires), avg_ttl))| (format!("db{}", index), format!("keys={},expires={},avg_ttl={}", keys, expires, avg_ttl)))
+            .collect(),
+        _ => counter_fields(section, state).into_iter().map(|(name, value)| (name.to_string(), value)).collect()
+    }
+}
+
+fn counter_fields(section: &str, state: &ServerState) -> Vec<(&'static str, String)> {
+    let stats = &state.stats;
+    match section {
+        "server" => {
+            let config = state.config.lock().unwrap();
+            let uptime = stats.uptime_secs();
+            vec![
+                ("redis_version", env!("CARGO_PKG_VERSION").to_string()),
+                ("redis_mode", "standalone".to_string()),
+                ("os", format!("{} {}", std::env::consts::OS, std::env::consts::ARCH)),
+                ("arch_bits", usize::BITS.to_string()),
+                ("process_id", std::process::id().to_string()),
+                ("tcp_port", config.port.to_string()),
+                ("uptime_in_seconds", uptime.to_string()),
+                ("uptime_in_days", (uptime / 86400).to_string()),
+                ("io_threads", config.io_threads.to_string()),
+                ("executable", std::env::current_exe().map(|p| p.to_string_lossy().into_owned()).unwrap_or_default()),
+                ("config_file", config.configfile.clone().unwrap_or_default()),
+            ]
+        },
+        "clients" => {
+            let connected = state.clients.lock().unwrap().len();
+            let tracking = state.tracking.lock().unwrap().clients.len();
+            vec![
+                ("connected_clients", connected.to_string()),
+                ("blocked_clients", "0".to_string()),
+                ("tracking_clients", tracking.to_string()),
+            ]
+        },
+        "memory" => {
+            let used = state.keyspace.used_memory();
+            let peak = state.keyspace.used_memory_peak();
+            let eviction = state.keyspace.eviction();
+            vec![
+                ("used_memory", used.to_string()),
+                ("used_memory_human", bytes_to_human(used)),
+                ("used_memory_peak", peak.to_string()),
+                ("used_memory_peak_human", bytes_to_human(peak)),
+                ("maxmemory", eviction.maxmemory.to_string()),
+                ("maxmemory_human", bytes_to_human(eviction.maxmemory)),
+                ("maxmemory_policy", eviction.policy.name().to_string()),
+            ]
+        },
+        "persistence" => {
+            let dirty = state.keyspace.total(|db| db.dirty);
+            let started = stats.started_at.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+            vec![
+                ("loading", "0".to_string()),
+                ("rdb_changes_since_last_save", dirty.to_string()),
+                ("rdb_bgsave_in_progress", "0".to_string()),
+                //Nothing is ever saved, the dataset is as old as the process
+                ("rdb_last_save_time", started.to_string()),
+                ("aof_enabled", "0".to_string()),
+            ]
+        },
+        "stats" => {
+            let keyspace = &state.keyspace;
+            let (hits, misses, expired, evicted) = (
+                keyspace.total(|db| db.keyspace_hits),
+                keyspace.total(|db| db.keyspace_misses),
+                keyspace.total(|db| db.expired_keys),
+                keyspace.total(|db| db.evicted_keys)
+            );
+            let counter = |c: &std::sync::atomic::AtomicU64| c.load(Ordering::Relaxed).to_string();
+            vec![
+                ("total_connections_received", counter(&stats.total_connections_received)),
+                ("total_commands_processed", counter(&stats.total_commands_processed)),
+                ("instantaneous_ops_per_sec", stats.ops_per_sec().to_string()),
+                ("total_net_input_bytes", counter(&stats.total_net_input_bytes)),
+                ("total_net_output_bytes", counter(&stats.total_net_output_bytes)),
+                ("rejected_connections", "0".to_string()),
+                ("total_error_replies", stats.errors.lock().unwrap().values().sum::<u64>().to_string()),
+                ("expired_keys", expired.to_string()),
+                ("evicted_keys", evicted.to_string()),
+                ("keyspace_hits", hits.to_string()),
+                ("keyspace_misses", misses.to_string()),
+            ]
+        },
+        _ => Vec::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::bulk;
+
+    fn sections(text: &str) -> Vec<&str> {
+        text.lines().filter_map(|l| l.strip_prefix("# ")).collect()
+    }
+
+    #[test]
+    fn selects_sections() {
+        let state = ServerState::default();
+        assert_eq!(sections(&info(&[], &state)), ["Server", "Clients", "Memory", "Persistence", "Stats", "Errorstats", "Keyspace"]);
+        assert_eq!(sections(&info(&["everything".to_string()], &state)).len(), SECTIONS.len());
+        assert_eq!(sections(&info(&["stats".to_string(), "nope".to_string()], &state)), ["Stats"]);
+        assert_eq!(info(&["nope".to_string()], &state), "");
+        let reply = handle_info(&[bulk("MEMORY")], &state).unwrap();
+        assert_eq!(reply, RespValue::BulkString(Some(b"# Memory\r\nused_memory:0\r\nused_memory_human:0B\r\nused_memory_peak:0\r\nused_memory_peak_human:0B\r\nmaxmemory:0\r\nmaxmemory_human:0B\r\nmaxmemory_policy:noeviction\r\n".to_vec())));
+    }
+
+    #[test]
+    fn reports_keyspace() {
+        let state = ServerState::default();
+        assert_eq!(info(&["keyspace".to_string()], &state), "# Keyspace\r\n");
+        state.keyspace.lock_all().for_key(b"k").db(0).set(b"k", b"v".to_vec());
+        let _ = state.keyspace.lock_all().for_key(b"k").db(0).get(b"k");
+        assert_eq!(info(&["keyspace".to_string()], &state), "# Keyspace\r\ndb0:keys=1,expires=0,avg_ttl=0\r\n");
+        state.keyspace.lock_all().for_key(b"k").db(3).set(b"k", b"v".to_vec());
+        assert_eq!(info(&["keyspace".to_string()], &state), "# Keyspace\r\ndb0:keys=1,expires=0,avg_ttl=0\r\ndb3:keys=1,expires=0,avg_ttl=0\r\n");
+        assert!(info(&["stats".to_string()], &state).contains("keyspace_hits:1\r\n"));
+        state.reset_stats();
+        assert!(info(&["stats".to_string()], &state).contains("keyspace_hits:0\r\n"));
+
+        let at = crate::store::expire::unix_time_ms() + 100_000;
+        state.keyspace.lock_all().for_key(b"k").db(0).set_expire(b"k", at);
+        state.keyspace.active_expire_cycle(std::time::Duration::from_secs(1));
+        let keyspace = info(&["keyspace".to_string()], &state);
+        let avg_ttl: u64 = keyspace.split("avg_ttl=").nth(1).and_then(|v| v.split("\r\n").next()).unwrap().parse().unwrap();
+        assert!(keyspace.contains("db0:keys=1,expires=1,") && (90_000..=100_000).contains(&avg_ttl), "{}", keyspace);
+    }
+
+    #[test]
+    fn human_bytes() {
+        assert_eq!(bytes_to_human(1023), "1023B");
+        assert_eq!(bytes_to_human(1536), "1.50K");
+        assert_eq!(bytes_to_human(3 * 1024 * 1024), "3.00M");
+    }
+
+    #[test]
+    fn reports_command_and_error_stats() {
+        let state = ServerState::default();
+        state.stats.record_call("get", std::time::Duration::from_micros(10), false);
+        state.stats.record_rejected_call("set");
+        state.stats.record_call("config|get", std::time::Duration::from_micros(20), false);
+        state.stats.record_error(b"NOPERM No permissions to access a key");
+        assert_eq!(
+            info(&["commandstats".to_string(), "errorstats".to_string(), "latencystats".to_string()], &state),
+            "# Commandstats\r\n\
+            cmdstat_config|get:calls=1,usec=20,usec_per_call=20.00,rejected_calls=0,failed_calls=0\r\n\
+            cmdstat_get:calls=1,usec=10,usec_per_call=10.00,rejected_calls=0,failed_calls=0\r\n\
+            cmdstat_set:calls=0,usec=0,usec_per_call=0.00,rejected_calls=1,failed_calls=0\r\n\
+            \r\n# Errorstats\r\nerrorstat_NOPERM:count=1\r\n\
+            \r\n# Latencystats\r\n\
+            latency_percentiles_usec_config|get:p50=20.000,p99=20.000,p99.9=20.000\r\n\
+            latency_percentiles_usec_get:p50=10.000,p99=10.000,p99.9=10.000\r\n"
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use std::{collections::BTreeMap, fmt::Write, sync::atomic::Ordering, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::{command::{CommandError, Commands}, resp::RespValue, server::value::{LatencyEvent, LatencyMonitor, LatencySample, ServerState}};
+
+//Samples kept per event, Redis keeps the same amount
+const MAX_SAMPLES: usize = 160;
+const GRAPH_HEIGHT: u64 = 4;
+
+fn bulk(s: &[u8]) -> RespValue {
+    RespValue::BulkString(Some(s.to_vec()))
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+impl LatencyMonitor {
+    pub fn add_sample(&mut self, event: &str, latency_ms: u64, time: u64) {
+        let event = self.events.entry(event.to_string()).or_default();
+        event.max_ms = event.max_ms.max(latency_ms);
+        match event.samples.back_mut() {
+            Some(last) if last.time == time => last.latency_ms = last.latency_ms.max(latency_ms),
+            _ => {
+                event.samples.push_back(LatencySample { time, latency_ms });
+                if event.samples.len() > MAX_SAMPLES {
+                    event.samples.pop_front();
+                }
+            }
+        }
+    }
+
+    ///Drops the given events, or every event when none is given, returning how many were dropped
+    pub fn reset(&mut self, events: &[String]) -> usize {
+        if events.is_empty() {
+            let count = self.events.len();
+            self.events.clear();
+            return count;
+        }
+        events.iter().filter(|e| self.events.remove(*e).is_some()).count()
+    }
+}
+
+impl LatencyEvent {
+    fn average_ms(&self) -> f64 {
+        self.samples.iter().map(|s| s.latency_ms).sum::<u64>() as f64 / self.samples.len().max(1) as f64
+    }
+
+    fn mean_deviation_ms(&self) -> f64 {
+        let average = self.average_ms();
+        self.samples.iter().map(|s| (s.latency_ms as f64 - average).abs()).sum::<f64>() / self.samples.len().max(1) as f64
+    }
+
+    ///Average seconds between spikes
+    fn period_secs(&self) -> u64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some(first), Some(last)) if self.samples.len() > 1 => (last.time - first.time) / (self.samples.len() as u64 - 1),
+            _ => 0
+        }
+    }
+
+    ///Bars of the samples scaled between the lowest and highest one, oldest on the left
+    fn graph(&self, name: &str) -> String {
+        let high = self.samples.iter().map(|s| s.latency_ms).max().unwrap_or(0);
+        let low = self.samples.iter().map(|s| s.latency_ms).min().unwrap_or(0);
+        let mut graph = format!("{} - high {} ms, low {} ms (all time high {} ms)\n", name, high, low, self.max_ms);
+        graph.push_str(&"-".repeat(80));
+        graph.push('\n');
+        let heights = self.samples.iter()
+            .map(|s| match high - low {
+                0 => GRAPH_HEIGHT,
+                range => 1 + (s.latency_ms - low) * (GRAPH_HEIGHT - 1) / range
+            })
+            .collect::<Vec<_>>();
+        for row in (1..=GRAPH_HEIGHT).rev() {
+            let line = heights.iter().map(|h| if *h >= row { '#' } else { ' ' }).collect::<String>();
+            graph.push_str(line.trim_end());
+            graph.push('\n');
+        }
+        graph.push_str(&"_".repeat(heights.len()));
+        graph.push('\n');
+        if let (Some(first), Some(last)) = (self.samples.front(), self.samples.back()) {
+            let now = now_secs();
+            let _ = writeln!(graph, "oldest sample {}s ago, newest sample {}s ago", now.saturating_sub(first.time), now.saturating_sub(last.time));
+        }
+        graph
+    }
+}
+
+impl ServerState {
+    ///Records an event in the latency monitor when it took at least latency-monitor-threshold
+    pub fn latency_add_sample_if_needed(&self, event: &str, duration: Duration) {
+        let threshold = self.latency_monitor_threshold.load(Ordering::Relaxed);
+        let latency_ms = duration.as_millis() as u64;
+        if threshold > 0 && latency_ms >= threshold {
+            self.latency.lock().unwrap().add_sample(event, latency_ms, now_secs());
+        }
+    }
+}
+
+///Event a command's execution is recorded under, fast commands are expected to never spike
+pub fn command_event(command: Commands) -> &'static str {
+    match command.categories().contains(&"fast") {
+        true => "fast-command",
+        false => "command"
+    }
+}
+
+fn doctor(monitor: &LatencyMonitor, threshold: u64) -> String {
+    if threshold == 0 && monitor.events.is_empty() {
+        return "I'm sorry, Dave, I can't do that. Latency monitoring is disabled in this Redis instance. You may use \"CONFIG SET latency-monitor-threshold <milliseconds>.\" in order to enable it. If we weren't in a deep space mission I'd suggest to take a look at https://redis.io/topics/latency-monitor.\n".to_string();
+    }
+    if monitor.events.is_empty() {
+        return "Dave, no latency spike was observed during the lifetime of this Redis instance, not in the slightest bit. I honestly think you ought to sleep tonight.\n".to_string();
+    }
+    let mut report = String::from("Dave, I have observed latency spikes in this Redis instance. You don't mind talking about it, do you Dave?\n\n");
+    for (i, (name, event)) in monitor.events.iter().enumerate() {
+        let _ = writeln!(
+            report,
+            "{}. {}: {} latency spikes (average {:.0}ms, mean deviation {:.0}ms, period {} sec). Worst all time event {}ms.",
+            i + 1, name, event.samples.len(), event.average_ms(), event.mean_deviation_ms(), event.period_secs(), event.max_ms
+        );
+    }
+    report.push_str("\nI have a few advices for you:\n\n");
+    let commands = monitor.events.contains_key("command") || monitor.events.contains_key("fast-command");
+    if commands {
+        report.push_str("- Check your Slow Log to understand what are the commands you are running which are too slow to execute. Please check https://redis.io/commands/slowlog for more information.\n");
+    }
+    if monitor.events.contains_key("fast-command") {
+        report.push_str("- Commands that are expected to be fast are spiking, the host may be overloaded or the server may be waiting on its locks.\n");
+    }
+    if monitor.events.keys().any(|e| e.starts_with("expire")) {
+        report.push_str("- Many keys are expiring at the same time, consider spreading their expiration times.\n");
+    }
+    if monitor.events.keys().any(|e| e.starts_with("eviction")) {
+        report.push_str("- Eviction cycles are slow, consider raising maxmemory or reducing the write load.\n");
+    }
+    if !commands && !monitor.events.keys().any(|e| e.starts_with("expire") || e.starts_with("eviction")) {
+        report.push_str("- Check the load of the host and the other processes running on it.\n");
+    }
+    report
+}
+
+///Histograms of the commands named, a container command such as `config` standing for all its
+///subcommands, or of every command when no name is given
+fn histogram(names: &[String], state: &ServerState) -> RespValue {
+    let stats = state.stats.command_stats();
+    let selected = |name: &str| names.is_empty() || names.iter().any(|n| {
+        let n = n.to_ascii_lowercase();
+        name == n || name.strip_prefix(n.as_str()).is_some_and(|rest| rest.starts_with('|'))
+    });
+    let entries = stats.iter()
+        .filter(|(name, stats)| stats.latency.total > 0 && selected(name))
+        .map(|(name, stats)| {
+            //Cumulative counts at each power of two, as Redis reports them
+            let mut buckets: BTreeMap<u64, u64> = BTreeMap::new();
+            for (value, count) in stats.latency.buckets() {
+                *buckets.entry(value.checked_next_power_of_two().unwrap_or(u64::MAX)).or_insert(0) += count;
+            }
+            let mut cumulative = 0;
+            let histogram = buckets.into_iter()
+                .map(|(bound, count)| {
+                    cumulative += count;
+                    (RespValue::Integer(bound as i64), RespValue::Integer(cumulative as i64))
+                })
+                .collect();
+            (bulk(name.as_bytes()), RespValue::Map(vec![
+                (bulk(b"calls"), RespValue::Integer(stats.calls as i64)),
+                (bulk(b"histogram_usec"), RespValue::Map(histogram)),
+            ]))
+        })
+        .collect();
+    RespValue::Map(entries)
+}
+
+pub fn handle_latency(args: &[RespValue], state: &ServerState) -> Result<RespValue, CommandError> {
+    let mut strings = Vec::new();
+    for arg in args {
+        strings.push(String::from_utf8_lossy(arg.as_bytes().ok_or(CommandError::InvalidRequest)?).into_owned());
+    }
+    let subcommand = match strings.first() {
+        Some(subcommand) => subcommand.to_ascii_uppercase(),
+        None => return Err(CommandError::WrongArity)
+    };
+    let args = &strings[1..];
+    let monitor = || state.latency.lock().unwrap();
+
+    match (subcommand.as_str(), args) {
+        ("LATEST", []) => Ok(RespValue::Arrays(Some(monitor().events.iter()
+            .filter_map(|(name, event)| event.samples.back().map(|last| RespValue::Arrays(Some(vec![
+                bulk(name.as_bytes()),
+                RespValue::Integer(last.time as i64),
+                RespValue::Integer(last.latency_ms as i64),
+                RespValue::Integer(event.max_ms as i64),
+            ]))))
+            .collect()))),
+        ("HISTORY", [event]) => Ok(RespValue::Arrays(Some(monitor().events.get(event)
+            .map(|event| event.samples.iter()
+                .map(|s| RespValue::Arrays(Some(vec![RespValue::Integer(s.time as i64), RespValue::Integer(s.latency_ms as i64)])))
+                .collect())
+            .unwrap_or_default()))),
+        ("RESET", events) => Ok(RespValue::Integer(monitor().reset(events) as i64)),
+        ("GRAPH", [event]) => match monitor().events.get(event) {
+            Some(samples) => Ok(RespValue::BulkString(Some(samples.graph(event).into_bytes()))),
+            None => Err(CommandError::Custom(format!("ERR No samples available for event '{}'", event)))
+        },
+        ("DOCTOR", []) => {
+            let threshold = state.latency_monitor_threshold.load(Ordering::Relaxed);
+            Ok(RespValue::BulkString(Some(doctor(&monitor(), threshold).into_bytes())))
+        },
+        //Unknown command names are skipped
+        ("HISTOGRAM", names) => Ok(histogram(names, state)),
+        ("LATEST" | "HISTORY" | "GRAPH" | "DOCTOR", _) => Err(CommandError::WrongArity),
+        _ => Err(CommandError::Custom(format!(
+            "ERR unknown subcommand '{}'. Try LATENCY HELP.",
+            subcommand.to_ascii_lowercase()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers::args;
+
+    fn int(i: i64) -> RespValue {
+        RespValue::Integer(i)
+    }
+
+    #[test]
+    fn samples_merge_per_second_and_are_bounded() {
+        let mut monitor = LatencyMonitor::default();
+        monitor.add_sample("command", 10, 100);
+        monitor.add_sample("command", 30, 100);
+        monitor.add_sample("command", 20, 101);
+        let event = &monitor.events["command"];
+        assert_eq!(event.samples.iter().map(|s| (s.time, s.latency_ms)).collect::<Vec<_>>(), [(100, 30), (101, 20)]);
+        assert_eq!(event.max_ms, 30);
+
+        for time in 0..200 {
+            monitor.add_sample("expire-cycle", 5, 1000 + time);
+        }
+        assert_eq!(monitor.events["expire-cycle"].samples.len(), MAX_SAMPLES);
+        assert_eq!(monitor.events["expire-cycle"].samples[0].time, 1040);
+        assert_eq!(monitor.reset(&["nope".to_string(), "command".to_string()]), 1);
+        assert_eq!(monitor.reset(&[]), 1);
+    }
+
+    #[test]
+    fn threshold_gates_samples() {
+        let state = ServerState::default();
+        state.latency_add_sample_if_needed("command", Duration::from_secs(1));
+        assert!(state.latency.lock().unwrap().events.is_empty());
+        assert!(handle_latency(&args(&["DOCTOR"]), &state).unwrap() != bulk(b""));
+
+        state.latency_monitor_threshold.store(100, Ordering::Relaxed);
+        state.latency_add_sample_if_needed("command", Duration::from_millis(99));
+        state.latency_add_sample_if_needed("command", Duration::from_millis(150));
+        let RespValue::Arrays(Some(latest)) = handle_latency(&args(&["LATEST"]), &state).unwrap() else { panic!() };
+        let RespValue::Arrays(Some(entry)) = &latest[0] else { panic!() };
+        assert_eq!((&entry[0], &entry[2], &entry[3]), (&bulk(b"command"), &int(150), &int(150)));
+
+        let RespValue::Arrays(Some(history)) = handle_latency(&args(&["HISTORY", "command"]), &state).unwrap() else { panic!() };
+        assert_eq!(history.len(), 1);
+        assert_eq!(handle_latency(&args(&["HISTORY", "nope"]), &state).unwrap(), RespValue::Arrays(Some(vec![])));
+        assert!(handle_latency(&args(&["GRAPH", "nope"]), &state).is_err());
+        assert_eq!(handle_latency(&args(&["RESET"]), &state).unwrap(), int(1));
+    }
+
+    #[test]
+    fn graph_and_doctor_describe_spikes() {
+        let mut monitor = LatencyMonitor::default();
+        for (time, latency) in [(10, 100), (20, 400), (30, 250)] {
+            monitor.add_sample("command", latency, time);
+        }
+        let graph = monitor.events["command"].graph("command");
+        let lines = graph.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "command - high 400 ms, low 100 ms (all time high 400 ms)");
+        assert_eq!(&lines[2..7], [" #", " #", " ##", "###", "___"]);
+
+        let report = doctor(&monitor, 100);
+        assert!(report.contains("1. command: 3 latency spikes (average 250ms, mean deviation 100ms, period 10 sec). Worst all time event 400ms."));
+        assert!(report.contains("Slow Log"));
+        assert!(doctor(&LatencyMonitor::default(), 100).starts_with("Dave, no latency spike"));
+    }
+
+    #[test]
+    fn histogram_reports_cumulative_power_of_two_buckets() {
+        let state = ServerState::default();
+        for usec in [1, 3, 3, 100] {
+            state.stats.record_call("get", Duration::from_micros(usec), false);
+        }
+        state.stats.record_call("config|get", Duration::from_micros(5), false);
+        state.stats.record_call("config|set", Duration::from_micros(5), false);
+        assert_eq!(handle_latency(&args(&["HISTOGRAM", "get", "nope", "set"]), &state).unwrap(), RespValue::Map(vec![
+            (bulk(b"get"), RespValue::Map(vec![
+                (bulk(b"calls"), int(4)),
+                (bulk(b"histogram_usec"), RespValue::Map(vec![(int(1), int(1)), (int(4), int(3)), (int(128), int(4))])),
+            ])),
+        ]));
+        let config = |name: &[u8]| (bulk(name), RespValue::Map(vec![
+            (bulk(b"calls"), int(1)),
+            (bulk(b"histogram_usec"), RespValue::Map(vec![(int(8), int(1))])),
+        ]));
+        assert_eq!(handle_latency(&args(&["HISTOGRAM", "CONFIG"]), &state).unwrap(), RespValue::Map(vec![config(b"config|get"), config(b"config|set")]));
+        assert_eq!(handle_latency(&args(&["HISTOGRAM", "config|set"]), &state).unwrap(), RespValue::Map(vec![config(b"config|set")]));
+    }
+}
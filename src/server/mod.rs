@@ -1,2 +1,16 @@
 pub mod tcp;
 pub mod value;
+pub mod state;
+pub mod connection;
+pub mod tracking;
+pub mod config;
+pub mod acl;
+pub mod tls;
+pub mod unix;
+pub mod stats;
+pub mod cron;
+pub mod info;
+pub mod slowlog;
+pub mod latency;
+pub mod monitor;
+pub mod clients;
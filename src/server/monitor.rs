@@ -0,0 +1,103 @@
+use std::{sync::atomic::Ordering, time::{SystemTime, UNIX_EPOCH}};
+
+use crate::{command::{CommandError, Commands}, resp::RespValue, server::{slowlog::redacted_args, value::{Client, ServerState}}};
+
+///Quotes an argument like Redis does, escaping quotes, backslashes and non printable bytes
+pub fn repr(bytes: &[u8]) -> String {
+    let mut quoted = String::from("\"");
+    for &b in bytes {
+        match b {
+            b'\\' => quoted.push_str("\\\\"),
+            b'"' => quoted.push_str("\\\""),
+            b'\n' => quoted.push_str("\\n"),
+            b'\r' => quoted.push_str("\\r"),
+            b'\t' => quoted.push_str("\\t"),
+            0x07 => quoted.push_str("\\a"),
+            0x08 => quoted.push_str("\\b"),
+            b if b.is_ascii_graphic() || b == b' ' => quoted.push(b as char),
+            b => quoted.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+///A MONITOR line without the trailing CRLF, e.g. `+1700000000.123456 [0 127.0.0.1:54321] "GET" "k"`
+pub fn monitor_line(time: SystemTime, db: usize, addr: &str, args: &[Vec<u8>]) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let mut line = format!("+{}.{:06} [{} {}]", since_epoch.as_secs(), since_epoch.subsec_micros(), db, addr);
+    for arg in args {
+        line.push(' ');
+        line.push_str(&repr(arg));
+    }
+    line
+}
+
+pub fn handle_monitor(client: &mut Client) -> Result<RespValue, CommandError> {
+    client.monitor = true;
+    Ok(RespValue::SimpleString(b"OK".to_vec()))
+}
+
+impl ServerState {
+    pub fn add_monitor(&self, client: &Client) {
+        let mut monitors = self.monitors.lock().unwrap();
+        if monitors.insert(client.id(), client.handle.clone()).is_none() {
+            self.monitor_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn remove_monitor(&self, id: u64) {
+        let mut monitors = self.monitors.lock().unwrap();
+        if monitors.remove(&id).is_some() {
+            self.monitor_count.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    ///Sends a processed command to every monitor. Admin commands are never shown, as Redis does
+    pub fn feed_monitors(&self, command: Commands, args: &[RespValue], client: &Client) {
+        if self.monitor_count.load(Ordering::Relaxed) == 0 || command.categories().contains(&"admin") {
+            return;
+        }
+        let line = monitor_line(SystemTime::now(), client.db, &client.handle.addr, &redacted_args(command, args));
+        let monitors = self.monitors.lock().unwrap().values().cloned().collect::<Vec<_>>();
+        for monitor in monitors {
+            //A monitor that went away is unregistered by its own connection
+            let _ = monitor.write(format!("{}\r\n", line).as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::server::value::Connection;
+    use crate::test_helpers::{bulk, Output};
+
+    #[test]
+    fn formats_lines() {
+        let time = UNIX_EPOCH + Duration::from_micros(1_700_000_000_000_123);
+        let line = monitor_line(time, 0, "127.0.0.1:54321", &[b"SET".to_vec(), b"k".to_vec(), b"a \"b\"\n\x01".to_vec()]);
+        assert_eq!(line, r#"+1700000000.000123 [0 127.0.0.1:54321] "SET" "k" "a \"b\"\n\x01""#);
+    }
+
+    #[test]
+    fn feeds_registered_monitors_only() {
+        let state = ServerState::default();
+        let output = Output::default();
+        let monitor = state.register_client(Connection::new(Box::new(output.clone()), "127.0.0.1:1".to_string()));
+        let client = state.register_client(Connection::new(Box::new(std::io::sink()), "127.0.0.1:2".to_string()));
+
+        state.feed_monitors(Commands::GET, &[bulk("GET"), bulk("before")], &client);
+        state.add_monitor(&monitor);
+        state.feed_monitors(Commands::AUTH, &[bulk("AUTH"), bulk("secret")], &client);
+        state.feed_monitors(Commands::CONFIG, &[bulk("CONFIG"), bulk("GET"), bulk("*")], &client);
+        let written = String::from_utf8(output.0.lock().unwrap().clone()).unwrap();
+        assert!(written.ends_with(" [0 127.0.0.1:2] \"AUTH\" \"(redacted)\"\r\n"));
+        assert_eq!(written.lines().count(), 1);
+
+        state.unregister_client(&monitor);
+        assert_eq!(state.monitor_count.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,183 @@
+use std::{collections::VecDeque, sync::atomic::Ordering, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::{command::{CommandError, Commands}, resp::RespValue, server::value::{Client, ServerState, SlowLog, SlowLogEntry}};
+
+//Same limits as Redis, so entries stay small whatever the command
+const MAX_ARGS: usize = 32;
+const MAX_ARG_LEN: usize = 128;
+
+fn bulk(s: &[u8]) -> RespValue {
+    RespValue::BulkString(Some(s.to_vec()))
+}
+
+///Arguments with passwords replaced, as shown to anyone inspecting other clients' commands
+pub fn redacted_args(command: Commands, args: &[RespValue]) -> Vec<Vec<u8>> {
+    let mut logged: Vec<Vec<u8>> = args.iter().map(|a| a.as_bytes().unwrap_or_default().to_vec()).collect();
+    let redact_from = match command {
+        Commands::AUTH => 1,
+        Commands::HELLO => logged.iter().position(|a| a.eq_ignore_ascii_case(b"AUTH")).map_or(logged.len(), |i| i + 1),
+        Commands::ACL if logged.get(1).is_some_and(|s| s.eq_ignore_ascii_case(b"SETUSER")) => 3,
+        _ => logged.len()
+    };
+    for arg in logged.iter_mut().skip(redact_from) {
+        *arg = b"(redacted)".to_vec();
+    }
+    if command == Commands::CONFIG && logged.get(1).is_some_and(|s| s.eq_ignore_ascii_case(b"SET")) {
+        for pair in logged[2..].chunks_mut(2) {
+            if let [name, value] = pair && name.eq_ignore_ascii_case(b"requirepass") {
+                *value = b"(redacted)".to_vec();
+            }
+        }
+    }
+    logged
+}
+
+///Arguments as stored in an entry, redacted and with long lists and strings cut
+pub fn logged_args(command: Commands, args: &[RespValue]) -> Vec<Vec<u8>> {
+    let mut logged = redacted_args(command, args);
+    if logged.len() > MAX_ARGS {
+        let more = logged.len() - MAX_ARGS + 1;
+        logged.truncate(MAX_ARGS - 1);
+        logged.push(format!("... ({} more arguments)", more).into_bytes());
+    }
+    for arg in logged.iter_mut().filter(|a| a.len() > MAX_ARG_LEN) {
+        let more = arg.len() - MAX_ARG_LEN;
+        arg.truncate(MAX_ARG_LEN);
+        arg.extend(format!("... ({} more bytes)", more).into_bytes());
+    }
+    logged
+}
+
+impl Default for SlowLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SlowLog {
+    pub fn new() -> Self {
+        Self { entries: VecDeque::new(), next_id: 0 }
+    }
+
+    pub fn push(&mut self, entry: SlowLogEntry, max_len: usize) {
+        self.entries.push_front(SlowLogEntry { id: self.next_id, ..entry });
+        self.next_id += 1;
+        self.entries.truncate(max_len);
+    }
+}
+
+impl ServerState {
+    ///Logs a command if it ran for longer than slowlog-log-slower-than
+    pub fn record_slow_command(&self, command: Commands, args: &[RespValue], duration: Duration, client: &Client) {
+        let threshold = self.slowlog_log_slower_than.load(Ordering::Relaxed);
+        let duration_usec = duration.as_micros() as u64;
+        if threshold < 0 || duration_usec < threshold as u64 {
+            return;
+        }
+        let max_len = self.config.lock().unwrap().slowlog_max_len;
+        let entry = SlowLogEntry {
+            id: 0,
+            timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            duration_usec,
+            args: logged_args(command, args),
+            addr: client.handle.addr.clone(),
+            name: client.handle.details.lock().unwrap().name.clone(),
+        };
+        self.slowlog.lock().unwrap().push(entry, max_len);
+    }
+}
+
+pub fn handle_slowlog(args: &[RespValue], state: &ServerState) -> Result<RespValue, CommandError> {
+    let subcommand = match args.first().and_then(|a| a.as_bytes()) {
+        Some(arg) => arg.to_ascii_uppercase(),
+        None => return Err(CommandError::WrongArity)
+    };
+
+    match (subcommand.as_slice(), &args[1..]) {
+        (b"GET", [] | [_]) => {
+            let count = match args.get(1) {
+                //-1 returns every entry
+                Some(count) => match count.as_bytes().and_then(|c| std::str::from_utf8(c).ok()).and_then(|c| c.parse::<i64>().ok()) {
+                    Some(-1) => usize::MAX,
+                    Some(count) if count >= 0 => count as usize,
+                    _ => return Err(CommandError::Custom("ERR count should be greater than or equal to -1".to_string()))
+                },
+                None => 10
+            };
+            let slowlog = state.slowlog.lock().unwrap();
+            Ok(RespValue::Arrays(Some(slowlog.entries.iter().take(count).map(|entry| RespValue::Arrays(Some(vec![
+                RespValue::Integer(entry.id as i64),
+                RespValue::Integer(entry.timestamp as i64),
+                RespValue::Integer(entry.duration_usec as i64),
+                RespValue::Arrays(Some(entry.args.iter().map(|a| bulk(a)).collect())),
+                bulk(entry.addr.as_bytes()),
+                bulk(&entry.name),
+            ]))).collect())))
+        },
+        (b"LEN", []) => Ok(RespValue::Integer(state.slowlog.lock().unwrap().entries.len() as i64)),
+        (b"RESET", []) => {
+            state.slowlog.lock().unwrap().entries.clear();
+            Ok(RespValue::SimpleString(b"OK".to_vec()))
+        },
+        (b"GET" | b"LEN" | b"RESET", _) => Err(CommandError::WrongArity),
+        _ => Err(CommandError::Custom(format!(
+            "ERR unknown subcommand '{}'. Try SLOWLOG HELP.",
+            String::from_utf8_lossy(&subcommand).to_ascii_lowercase()
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::value::Connection;
+    use crate::test_helpers::{args, bulk};
+
+    fn client(state: &ServerState) -> Client {
+        state.register_client(Connection::new(Box::new(std::io::sink()), "127.0.0.1:5000".to_string()))
+    }
+
+    #[test]
+    fn redacts_and_truncates_arguments() {
+        assert_eq!(logged_args(Commands::AUTH, &args(&["AUTH", "alice", "pw"])), [b"AUTH".to_vec(), b"(redacted)".to_vec(), b"(redacted)".to_vec()]);
+        assert_eq!(logged_args(Commands::HELLO, &args(&["HELLO", "3", "AUTH", "alice", "pw"]))[1..3], [b"3".to_vec(), b"AUTH".to_vec()]);
+        assert_eq!(logged_args(Commands::HELLO, &args(&["HELLO", "3", "AUTH", "alice", "pw"]))[4], b"(redacted)");
+        assert_eq!(logged_args(Commands::ACL, &args(&["ACL", "SETUSER", "bob", ">pw"]))[3], b"(redacted)");
+        assert_eq!(logged_args(Commands::CONFIG, &args(&["CONFIG", "SET", "requirepass", "pw", "slowlog-max-len", "5"]))[3..], [b"(redacted)".to_vec(), b"slowlog-max-len".to_vec(), b"5".to_vec()]);
+
+        let long = "x".repeat(200);
+        let logged = logged_args(Commands::SET, &args(&["SET", "k", &long]));
+        assert_eq!(logged[2], format!("{}... (72 more bytes)", "x".repeat(128)).into_bytes());
+
+        let many = vec!["a"; 40];
+        let logged = logged_args(Commands::ECHO, &args(&many));
+        assert_eq!(logged.len(), 32);
+        assert_eq!(logged[31], b"... (9 more arguments)");
+    }
+
+    #[test]
+    fn records_entries_over_threshold() {
+        let state = ServerState::default();
+        let client = client(&state);
+        state.config.lock().unwrap().slowlog_max_len = 2;
+        state.record_slow_command(Commands::GET, &args(&["GET", "fast"]), Duration::from_micros(10), &client);
+        for key in ["a", "b", "c"] {
+            state.record_slow_command(Commands::GET, &args(&["GET", key]), Duration::from_millis(20), &client);
+        }
+        assert_eq!(handle_slowlog(&args(&["LEN"]), &state).unwrap(), RespValue::Integer(2));
+
+        let reply = handle_slowlog(&args(&["GET", "1"]), &state).unwrap();
+        let RespValue::Arrays(Some(entries)) = reply else { panic!() };
+        let RespValue::Arrays(Some(entry)) = &entries[0] else { panic!() };
+        assert_eq!(entry[0], RespValue::Integer(2));
+        assert_eq!(entry[2], RespValue::Integer(20000));
+        assert_eq!(entry[3], RespValue::Arrays(Some(args(&["GET", "c"]))));
+        assert_eq!(entry[4], bulk("127.0.0.1:5000"));
+
+        state.slowlog_log_slower_than.store(-1, Ordering::Relaxed);
+        state.record_slow_command(Commands::GET, &args(&["GET", "d"]), Duration::from_secs(1), &client);
+        handle_slowlog(&args(&["RESET"]), &state).unwrap();
+        assert_eq!(handle_slowlog(&args(&["LEN"]), &state).unwrap(), RespValue::Integer(0));
+        assert!(handle_slowlog(&args(&["GET", "-2"]), &state).is_err());
+    }
+}
@@ -0,0 +1,144 @@
+use std::{collections::HashMap, io::{self, Write}, sync::{atomic::{AtomicBool, AtomicI64, AtomicU8, AtomicU64, AtomicUsize, Ordering}, Arc, Condvar, Mutex, RwLock}, time::Instant};
+
+use crate::{acl::Acl, resp::{serializer::serialize_with_protocol, RespValue}, server::value::{Client, ClientDetails, ClientHandle, Config, Connection, LatencyMonitor, ReplyMode, ServerError, ServerState, SlowLog, Stats, TrackingTable}, store::value::Shards};
+
+impl Default for ServerState {
+    fn default() -> Self {
+        Self::new(Config::default())
+    }
+}
+
+impl ServerState {
+    pub fn new(config: Config) -> Self {
+        let mut acl = Acl::new();
+        acl.set_requirepass(config.requirepass.as_deref());
+        let keyspace = Shards::new(config.keyspace_shards, config.databases, config.eviction());
+        Self {
+            slowlog_log_slower_than: AtomicI64::new(config.slowlog_log_slower_than),
+            latency_monitor_threshold: AtomicU64::new(config.latency_monitor_threshold),
+            config: Mutex::new(config),
+            acl: RwLock::new(acl),
+            keyspace,
+            clients: Mutex::new(HashMap::new()),
+            tracking: Mutex::new(TrackingTable::new()),
+            tracking_count: AtomicUsize::new(0),
+            next_client_id: AtomicU64::new(1),
+            stats: Stats::new(),
+            slowlog: Mutex::new(SlowLog::new()),
+            latency: Mutex::new(LatencyMonitor::default()),
+            monitors: Mutex::new(HashMap::new()),
+            monitor_count: AtomicUsize::new(0),
+            pause: Mutex::new(None),
+            paused: AtomicBool::new(false),
+            unpaused: Condvar::new(),
+        }
+    }
+
+    ///Copies the settings read by every command out of the config, where commands can read
+    ///them without locking it
+    pub fn publish_config(&self, config: &Config) {
+        self.slowlog_log_slower_than.store(config.slowlog_log_slower_than, Ordering::Relaxed);
+        self.latency_monitor_threshold.store(config.latency_monitor_threshold, Ordering::Relaxed);
+        self.keyspace.set_eviction(config.eviction());
+    }
+
+    ///Assigns an id to a new connection and makes it reachable by other connections
+    pub fn register_client(&self, connection: Connection) -> Client {
+        let now = Instant::now();
+        let handle = Arc::new(ClientHandle {
+            id: self.next_client_id.fetch_add(1, Ordering::Relaxed),
+            addr: connection.addr,
+            laddr: connection.laddr,
+            created: now,
+            writer: Mutex::new(connection.writer),
+            protocol: AtomicU8::new(2),
+            shutdown: connection.shutdown,
+            killed: AtomicBool::new(false),
+            details: Mutex::new(ClientDetails {
+                name: Vec::new(),
+                user: b"default".to_vec(),
+                db: 0,
+                last_command: "NULL".to_string(),
+                last_interaction: now,
+                query_buffer: 0,
+                argv_mem: 0,
+                executing: false,
+                monitor: false,
+                tracking: false,
+                redirect: -1,
+                no_evict: false,
+            }),
+        });
+        self.clients.lock().unwrap().insert(handle.id, handle.clone());
+        self.stats.total_connections_received.fetch_add(1, Ordering::Relaxed);
+        //Connections start as the default user, already authenticated if it needs no password
+        let authenticated = self.acl.read().unwrap().user(b"default").is_some_and(|u| u.enabled && u.nopass);
+        Client { handle, authenticated, user: b"default".to_vec(), closing: false, tracking: None, caching: None, monitor: false, reply: ReplyMode::On, db: 0 }
+    }
+
+    pub fn unregister_client(&self, client: &Client) {
+        self.clients.lock().unwrap().remove(&client.id());
+        self.disable_tracking(client.id());
+        self.send_redirect_broken(client.id());
+        self.remove_monitor(client.id());
+    }
+
+    pub fn client_exists(&self, id: u64) -> bool {
+        self.clients.lock().unwrap().contains_key(&id)
+    }
+
+    ///Zeroes the counters CONFIG RESETSTAT covers
+    pub fn reset_stats(&self) {
+        self.stats.reset();
+        self.keyspace.for_each(|shard| {
+            for db in &mut shard.dbs {
+                db.keyspace_hits = 0;
+                db.keyspace_misses = 0;
+            }
+        });
+    }
+
+    pub fn load_acl_file(&self) -> Result<(), ServerError> {
+        let path = self.config.lock().unwrap().aclfile.clone();
+        match path {
+            Some(path) => self.acl.write().unwrap().load_file(&path).map_err(ServerError::Config),
+            None => Ok(())
+        }
+    }
+}
+
+impl ClientHandle {
+    pub fn protocol(&self) -> u8 {
+        self.protocol.load(Ordering::Relaxed)
+    }
+
+    pub fn write(&self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.lock().unwrap().write_all(bytes)
+    }
+
+    ///Serializes a value in the protocol the client negotiated and writes it out
+    pub fn send(&self, value: &RespValue) -> io::Result<()> {
+        match serialize_with_protocol(value, self.protocol()) {
+            Ok(bytes) => self.write(&bytes),
+            Err(_) => Err(io::Error::from(io::ErrorKind::InvalidData))
+        }
+    }
+}
+
+impl Connection {
+    ///A connection without a socket to close, e.g. for tests
+    pub fn new(writer: Box<dyn Write + Send>, addr: String) -> Self {
+        Self { writer, addr, laddr: String::new(), shutdown: Box::new(|| {}) }
+    }
+}
+
+impl Client {
+    pub fn id(&self) -> u64 {
+        self.handle.id
+    }
+
+    ///Description of the connection for logs, in the CLIENT LIST format
+    pub fn info(&self) -> String {
+        self.handle.describe()
+    }
+}
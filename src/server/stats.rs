@@ -0,0 +1,282 @@
+use std::{collections::{BTreeMap, HashMap}, sync::{atomic::{AtomicU64, AtomicUsize, Ordering}, Mutex, MutexGuard}, time::{Duration, Instant, SystemTime}};
+
+use crate::server::value::{CommandStats, LatencyHistogram, OpsSamples, Stats};
+
+//Exact buckets for values below 32, then 16 buckets per power of two up to 2^63
+const EXACT: usize = 32;
+const SUB_BUCKETS: usize = 16;
+const BUCKETS: usize = EXACT + (63 - 5 + 1) * SUB_BUCKETS;
+//Stripes of the command counters, more than the worker threads so each usually has its own
+const STRIPES: usize = 64;
+
+static NEXT_STRIPE: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    //Stripe the calls of this thread are recorded in, assigned in turn as threads first record
+    static STRIPE: usize = NEXT_STRIPE.fetch_add(1, Ordering::Relaxed) % STRIPES;
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: SystemTime::now(),
+            total_connections_received: AtomicU64::new(0),
+            total_commands_processed: AtomicU64::new(0),
+            total_net_input_bytes: AtomicU64::new(0),
+            total_net_output_bytes: AtomicU64::new(0),
+            ops: Mutex::new(OpsSamples::new(0)),
+            commands: (0..STRIPES).map(|_| Mutex::new(HashMap::new())).collect(),
+            errors: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn uptime_secs(&self) -> u64 {
+        self.started.elapsed().as_secs()
+    }
+
+    ///Records the commands processed since the previous sample
+    pub fn sample_ops(&self) {
+        let count = self.total_commands_processed.load(Ordering::Relaxed);
+        self.ops.lock().unwrap().sample(count, Instant::now());
+    }
+
+    pub fn ops_per_sec(&self) -> u64 {
+        self.ops.lock().unwrap().average()
+    }
+
+    pub fn reset(&self) {
+        for counter in [&self.total_connections_received, &self.total_commands_processed, &self.total_net_input_bytes, &self.total_net_output_bytes] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        *self.ops.lock().unwrap() = OpsSamples::new(0);
+        for stripe in &self.commands {
+            stripe.lock().unwrap().clear();
+        }
+        self.errors.lock().unwrap().clear();
+    }
+
+    fn stripe(&self) -> MutexGuard<'_, HashMap<String, CommandStats>> {
+        self.commands[STRIPE.with(|stripe| *stripe)].lock().unwrap()
+    }
+
+    ///Counters of the command named `name` in this thread's stripe, created on its first call
+    fn entry<'a>(commands: &'a mut HashMap<String, CommandStats>, name: &str) -> &'a mut CommandStats {
+        //Looked up first so a name is only allocated once per stripe
+        if !commands.contains_key(name) {
+            commands.insert(name.to_string(), CommandStats::default());
+        }
+        commands.get_mut(name).unwrap()
+    }
+
+    ///Counters of every command called since the last reset, by full name such as `client|list`,
+    ///merged across stripes
+    pub fn command_stats(&self) -> BTreeMap<String, CommandStats> {
+        let mut merged: BTreeMap<String, CommandStats> = BTreeMap::new();
+        for stripe in &self.commands {
+            for (name, stats) in stripe.lock().unwrap().iter() {
+                merged.entry(name.clone()).or_default().merge(stats);
+            }
+        }
+        merged
+    }
+
+    pub fn record_call(&self, name: &str, duration: Duration, failed: bool) {
+        let usec = duration.as_micros() as u64;
+        let mut commands = self.stripe();
+        let stats = Self::entry(&mut commands, name);
+        stats.calls += 1;
+        stats.usec += usec;
+        if failed {
+            stats.failed_calls += 1;
+        }
+        stats.latency.record(usec);
+    }
+
+    pub fn record_rejected_call(&self, name: &str) {
+        Self::entry(&mut self.stripe(), name).rejected_calls += 1;
+    }
+
+    ///Counts an error reply under its prefix, the first word of the message
+    pub fn record_error(&self, message: &[u8]) {
+        let prefix = message.split(|b| *b == b' ').next().unwrap_or_default();
+        *self.errors.lock().unwrap().entry(String::from_utf8_lossy(prefix).into_owned()).or_insert(0) += 1;
+    }
+}
+
+impl LatencyHistogram {
+    fn index(usec: u64) -> usize {
+        if usec < EXACT as u64 {
+            return usec as usize;
+        }
+        let exp = 63 - usec.leading_zeros() as usize;
+        let mantissa = (usec >> (exp - 4)) as usize;
+        EXACT + (exp - 5) * SUB_BUCKETS + (mantissa - SUB_BUCKETS)
+    }
+
+    ///Highest value falling in a bucket
+    fn value(index: usize) -> u64 {
+        if index < EXACT {
+            return index as u64;
+        }
+        let exp = (index - EXACT) / SUB_BUCKETS + 5;
+        let mantissa = ((index - EXACT) % SUB_BUCKETS + SUB_BUCKETS) as u64;
+        //Written so the last bucket ends at u64::MAX without overflowing
+        (mantissa << (exp - 4)) + ((1 << (exp - 4)) - 1)
+    }
+
+    pub fn record(&mut self, usec: u64) {
+        if self.counts.is_empty() {
+            self.counts = vec![0; BUCKETS];
+        }
+        self.counts[Self::index(usec)] += 1;
+        self.total += 1;
+    }
+
+    pub fn merge(&mut self, other: &LatencyHistogram) {
+        if other.counts.is_empty() {
+            return;
+        }
+        if self.counts.is_empty() {
+            self.counts = vec![0; BUCKETS];
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.total += other.total;
+    }
+
+    ///Upper bound and count of every non empty bucket, in increasing order
+    pub fn buckets(&self) -> impl Iterator<Item = (u64, u64)> + '_ {
+        self.counts.iter().enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(index, count)| (Self::value(index), *count))
+    }
+
+    ///Upper bound of the bucket holding the value `percentile` percent of the values are at or below
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        let target = ((self.total as f64 * percentile / 100.0).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen >= target {
+                return Self::value(index);
+            }
+        }
+        0
+    }
+}
+
+impl CommandStats {
+    pub fn merge(&mut self, other: &CommandStats) {
+        self.calls += other.calls;
+        self.usec += other.usec;
+        self.rejected_calls += other.rejected_calls;
+        self.failed_calls += other.failed_calls;
+        self.latency.merge(&other.latency);
+    }
+
+    pub fn usec_per_call(&self) -> f64 {
+        match self.calls {
+            0 => 0.0,
+            calls => self.usec as f64 / calls as f64
+        }
+    }
+}
+
+impl OpsSamples {
+    pub fn new(count: u64) -> Self {
+        Self { last_time: Instant::now(), last_count: count, samples: [0; 16], index: 0 }
+    }
+
+    pub fn sample(&mut self, count: u64, now: Instant) {
+        let elapsed = now.duration_since(self.last_time).as_millis() as u64;
+        if elapsed == 0 {
+            return;
+        }
+        let ops = count.saturating_sub(self.last_count) * 1000 / elapsed;
+        self.samples[self.index] = ops;
+        self.index = (self.index + 1) % self.samples.len();
+        self.last_time = now;
+        self.last_count = count;
+    }
+
+    pub fn average(&self) -> u64 {
+        self.samples.iter().sum::<u64>() / self.samples.len() as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn ops_average_over_samples() {
+        let start = Instant::now();
+        let mut ops = OpsSamples { last_time: start, last_count: 0, samples: [0; 16], index: 0 };
+        //1600 commands in 100ms is 16000/s, averaged over 16 slots of which one is filled
+        ops.sample(1600, start + Duration::from_millis(100));
+        assert_eq!(ops.average(), 1000);
+        for i in 2..=17 {
+            ops.sample(1600 * i, start + Duration::from_millis(100 * i));
+        }
+        assert_eq!(ops.average(), 16000);
+    }
+
+    #[test]
+    fn histogram_buckets_cover_every_value() {
+        for usec in [0, 1, 31, 32, 33, 47, 48, 1000, 123_456, u64::MAX] {
+            let index = LatencyHistogram::index(usec);
+            assert!(index < BUCKETS);
+            assert!(LatencyHistogram::value(index) >= usec);
+            assert!(LatencyHistogram::value(index) - usec <= usec / 16);
+        }
+        assert_eq!(LatencyHistogram::value(LatencyHistogram::index(32)), 33);
+    }
+
+    #[test]
+    fn histogram_percentiles() {
+        let mut histogram = LatencyHistogram::default();
+        assert_eq!(histogram.percentile(50.0), 0);
+        for usec in 1..=1000 {
+            histogram.record(usec);
+        }
+        assert_eq!(histogram.percentile(50.0), 511);
+        assert_eq!(histogram.percentile(99.0), 991);
+        assert_eq!(histogram.percentile(99.9), 1023);
+    }
+
+    #[test]
+    fn records_calls_and_errors() {
+        let stats = Stats::new();
+        stats.record_call("get", Duration::from_micros(10), false);
+        stats.record_call("get", Duration::from_micros(20), true);
+        stats.record_rejected_call("get");
+        stats.record_call("client|list", Duration::from_micros(5), false);
+        stats.record_error(b"ERR syntax error");
+        stats.record_error(b"NOPERM No permissions to access a key");
+        stats.record_error(b"ERR unknown command");
+        //Calls recorded by another thread land in another stripe and are merged when read
+        std::thread::scope(|scope| {
+            scope.spawn(|| stats.record_call("get", Duration::from_micros(30), false));
+        });
+        let commands = stats.command_stats();
+        assert_eq!(commands.keys().collect::<Vec<_>>(), ["client|list", "get"]);
+        let get = &commands["get"];
+        assert_eq!((get.calls, get.usec, get.rejected_calls, get.failed_calls), (3, 60, 1, 1));
+        assert_eq!(get.usec_per_call(), 20.0);
+        assert_eq!(get.latency.total, 3);
+        assert_eq!(*stats.errors.lock().unwrap(), BTreeMap::from([("ERR".to_string(), 2), ("NOPERM".to_string(), 1)]));
+        stats.reset();
+        assert!(stats.command_stats().is_empty());
+        assert!(stats.errors.lock().unwrap().is_empty());
+    }
+}
@@ -1,7 +1,10 @@
-use std::{io::{Read, Write}, net::{TcpListener, TcpStream}, sync::{mpsc, Arc, Mutex }, thread} ;
+use std::{io::{Read, Write}, net::{Shutdown, TcpListener, TcpStream}, sync::{atomic::Ordering, mpsc, Arc, Mutex }, thread, time::{Duration, Instant}} ;
 
-use crate::{command::{execute_command, get_command, CommandError}, 
-    resp::{parse_dispatcher, serializer::serializer, ParseError, RespValue}, server::value::{Job, ServerError, ThreadPool, Worker}, store::value::Store};
+use socket2::{SockRef, TcpKeepalive};
+
+use crate::{command::{execute_command, get_command, shards_for, CommandError, Commands}, 
+    resp::{parse_dispatcher, serializer::{serialize_with_protocol, serializer}, ParseError, RespValue},
+    server::{acl::{authenticate_certificate_user, check_permissions, handle_acl}, tls::{accept_tls, build_tls_config}, config::handle_config, info::handle_info, slowlog::handle_slowlog, latency::{command_event, handle_latency}, monitor::handle_monitor, clients::QUERY_BUFFER_SIZE, cron::spawn_cron, unix::{bind_unix_socket, handle_unix_connection}, connection::{handle_auth, handle_client, handle_hello, handle_quit, handle_select}, value::{Client, Config, Connection, Job, ReplyMode, ServerError, ServerState, ThreadPool, Worker}}};
 
 impl From<CommandError> for ServerError {
     fn from(e: CommandError) -> Self{
@@ -58,53 +61,347 @@ impl ThreadPool {
     }
 }
 
-pub fn create_connection(){
-    let listener = TcpListener::bind("127.0.0.1:6379").unwrap();
-    let store = Arc::new(Mutex::new(Store::new()));
-    let pool = ThreadPool::build(24).unwrap();
-    for stream in listener.incoming(){
-        let stream = stream.unwrap();
-        let store = store.clone();
-        pool.execute(move||{
-            handle_connection(stream, store);
-        });
+pub fn create_connection(config: Config){
+    let tls = match build_tls_config(&config) {
+        Ok(tls) => tls,
+        Err(e) => {
+            eprintln!("{:?}", e);
+            return;
+        }
+    };
+    let (port, tls_port, bind) = (config.port, config.tls_port, config.bind.clone());
+    let unixsocket = config.unixsocket.clone().map(|path| (path, config.unixsocketperm));
+    if port == 0 && tls.is_none() && unixsocket.is_none() {
+        eprintln!("Configured to not listen anywhere, exiting.");
+        return;
+    }
+
+    let state = Arc::new(ServerState::new(config));
+    if let Err(e) = state.load_acl_file() {
+        eprintln!("{:?}", e);
+        return;
+    }
+    let io_threads = state.config.lock().unwrap().io_threads;
+    let pool = Arc::new(ThreadPool::build(io_threads).unwrap());
+    spawn_cron(state.clone());
+
+    //Every listener accepts on its own thread, their connections share the pool
+    let mut listeners = Vec::new();
+    let tcp = match bind_tcp(&bind, port) {
+        Ok(tcp) => tcp,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    for listener in tcp {
+        let state = state.clone();
+        let pool = pool.clone();
+        listeners.push(thread::spawn(move || {
+            for stream in listener.incoming(){
+                let stream = stream.unwrap();
+                let state = state.clone();
+                pool.execute(move||{
+                    handle_connection(stream, state);
+                });
+            }
+        }));
+    }
+
+    let tls = match (tls, tls_port) {
+        (Some(tls), Some(port)) => match bind_tcp(&bind, port) {
+            Ok(listeners) => listeners.into_iter().map(|l| (l, tls.clone())).collect(),
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+        _ => Vec::new()
+    };
+    for (listener, tls) in tls {
+        let state = state.clone();
+        let pool = pool.clone();
+        listeners.push(thread::spawn(move || {
+            for stream in listener.incoming(){
+                let stream = stream.unwrap();
+                let state = state.clone();
+                let tls = tls.clone();
+                pool.execute(move||{
+                    handle_tls_connection(stream, tls, state);
+                });
+            }
+        }));
+    }
+
+    if let Some((path, perm)) = unixsocket {
+        let listener = match bind_unix_socket(&path, perm) {
+            Ok(listener) => listener,
+            Err(e) => {
+                eprintln!("Failed opening Unix socket '{}': {}", path, e);
+                return;
+            }
+        };
+        println!("Starting server on {}", path);
+        let state = state.clone();
+        let pool = pool.clone();
+        listeners.push(thread::spawn(move || {
+            for stream in listener.incoming(){
+                let stream = stream.unwrap();
+                let state = state.clone();
+                let path = path.clone();
+                pool.execute(move||{
+                    handle_unix_connection(stream, &path, state);
+                });
+            }
+        }));
+    }
+
+    for listener in listeners {
+        let _ = listener.join();
+    }
+}
+
+///Binds a listener per address. Addresses prefixed by '-' are skipped when unavailable, e.g.
+///`-::1` on a host without IPv6
+fn bind_tcp(bind: &[String], port: u16) -> Result<Vec<TcpListener>, String> {
+    let mut listeners = Vec::new();
+    if port == 0 {
+        return Ok(listeners);
+    }
+    for address in bind {
+        let (optional, address) = match address.strip_prefix('-') {
+            Some(address) => (true, address),
+            None => (false, address.as_str())
+        };
+        let host = match address {
+            "*" => "0.0.0.0",
+            "::*" => "::",
+            _ => address
+        };
+        match TcpListener::bind((host, port)) {
+            Ok(listener) => {
+                println!("Starting server on {}:{}", address, port);
+                listeners.push(listener);
+            },
+            Err(_) if optional => {},
+            Err(e) => return Err(format!("Could not create server TCP listening socket {}:{}: {}", address, port, e))
+        }
+    }
+    Ok(listeners)
+}
+
+///Connection details of a TCP socket, `writer` being the socket replies go to
+fn tcp_connection(stream: &TcpStream, writer: Box<dyn Write + Send>) -> Option<Connection> {
+    let closer = stream.try_clone().ok()?;
+    Some(Connection {
+        writer,
+        addr: stream.peer_addr().map(|a| a.to_string()).unwrap_or_default(),
+        laddr: stream.local_addr().map(|a| a.to_string()).unwrap_or_default(),
+        shutdown: Box::new(move || {
+            let _ = closer.shutdown(Shutdown::Both);
+        }),
+    })
+}
+
+///Enables keepalive probes so connections to crashed hosts are eventually closed, with the
+///same intervals as Redis
+fn set_keepalive(stream: &TcpStream, interval: u64) -> std::io::Result<()> {
+    if interval == 0 {
+        return Ok(());
+    }
+    let keepalive = TcpKeepalive::new()
+        .with_time(Duration::from_secs(interval))
+        .with_interval(Duration::from_secs((interval / 3).max(1)))
+        .with_retries(3);
+    SockRef::from(stream).set_tcp_keepalive(&keepalive)
+}
+
+fn handle_connection(stream: TcpStream, state: Arc<ServerState>) {
+    let _ = set_keepalive(&stream, state.config.lock().unwrap().tcp_keepalive);
+    let writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    if let Some(connection) = tcp_connection(&stream, Box::new(writer)) {
+        serve_client(stream, connection, None, state);
     }
 }
 
-fn handle_connection(mut stream: TcpStream, store: Arc<Mutex<Store>>) {
-    let mut buf = [0u8; 4096];
+fn handle_tls_connection(stream: TcpStream, tls: Arc<rustls::ServerConfig>, state: Arc<ServerState>) {
+    let _ = set_keepalive(&stream, state.config.lock().unwrap().tcp_keepalive);
+    let socket = match stream.try_clone() {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+    let (reader, writer, common_name) = match accept_tls(stream, tls) {
+        Ok(connection) => connection,
+        Err(_) => return,
+    };
+    let certificate_user = common_name.filter(|_| state.config.lock().unwrap().tls_auth_clients_user);
+    if let Some(connection) = tcp_connection(&socket, Box::new(writer)) {
+        serve_client(reader, connection, certificate_user, state);
+    }
+}
+
+///Request loop shared by every kind of connection
+pub fn serve_client(mut reader: impl Read, connection: Connection, certificate_user: Option<Vec<u8>>, state: Arc<ServerState>) {
+    let mut client = state.register_client(connection);
+    if let Some(user) = certificate_user {
+        authenticate_certificate_user(&user, &state, &mut client);
+        client.finish_command();
+    }
+    let mut buf = [0u8; QUERY_BUFFER_SIZE];
 
     loop {
-        let n = match stream.read(&mut buf) {
+        let n = match reader.read(&mut buf) {
             Ok(0) => break, // client closed connection
             Ok(n) => n,
             Err(_) => break,
         };
+        if client.handle.killed.load(Ordering::Relaxed) {
+            break;
+        }
 
         let data = &buf[..n];
+        state.stats.total_net_input_bytes.fetch_add(n as u64, Ordering::Relaxed);
 
-        let output_data = {
-            let mut store = store.lock().unwrap();
-            process(data, &mut store).unwrap_or_else(|error| {
-                let res = error_to_resp(error);
-                serializer(&res).unwrap()
-            })
-        };
+        let output_data = process(data, &state, &mut client).unwrap_or_else(|error| {
+            let res = error_to_resp(error);
+            if let RespValue::Error(message) = &res {
+                state.stats.record_error(message);
+            }
+            serializer(&res).unwrap()
+        });
 
-        if stream.write_all(&output_data).is_err() {
+        let silent = client.reply != ReplyMode::On;
+        client.reply = match client.reply {
+            ReplyMode::SkipNext => ReplyMode::Skip,
+            ReplyMode::Skip => ReplyMode::On,
+            mode => mode
+        };
+        if !silent {
+            state.stats.total_net_output_bytes.fetch_add(output_data.len() as u64, Ordering::Relaxed);
+            if client.handle.write(&output_data).is_err() {
+                break;
+            }
+        }
+        if client.closing {
             break;
         }
+        //Registered once +OK is written, so no monitor line can precede it
+        if client.monitor {
+            state.add_monitor(&client);
+        }
+        client.finish_command();
     }
+
+    state.unregister_client(&client);
 }
 
 
-fn process(data: &[u8], store: &mut Store) -> Result<Vec<u8>, ServerError>{
+///Runs one request of a client, from parsing to the serialized reply
+pub fn process(data: &[u8], state: &ServerState, client: &mut Client) -> Result<Vec<u8>, ServerError>{
 
     let parsed_data = parse_dispatcher(data)?.result;
     let command = get_command(&parsed_data)?;
-    let result = execute_command(command, &parsed_data, store)?;
+    let args = match &parsed_data {
+        RespValue::Arrays(Some(v)) => v.as_slice(),
+        _ => return Err(ServerError::Command(CommandError::InvalidRequest))
+    };
 
-    let output_data = serializer(&result)?;
+    let admitted = if matches!(command, Commands::AUTH | Commands::HELLO | Commands::QUIT) {
+        Ok(())
+    } else if !client.authenticated {
+        Err(CommandError::NoAuth)
+    } else {
+        check_permissions(command, args, state, client)
+    };
+    //Counted under the container until the subcommand is known to exist, so requests that are
+    //never run can't add entries
+    if let Err(e) = admitted {
+        state.stats.record_rejected_call(command.name());
+        return Err(e.into());
+    }
+    let name = command.full_name(args);
+    client.start_command(&name, data.len(), args.iter().filter_map(|a| a.as_bytes()).map(|a| a.len()).sum());
+    state.wait_if_paused(command);
+    state.stats.total_commands_processed.fetch_add(1, Ordering::Relaxed);
+
+    let started = Instant::now();
+    let result = match command {
+        Commands::AUTH => handle_auth(&args[1..], state, client),
+        Commands::QUIT => handle_quit(client),
+        Commands::HELLO => handle_hello(&args[1..], state, client),
+        Commands::CLIENT => handle_client(&args[1..], state, client),
+        Commands::ACL => handle_acl(&args[1..], state, client),
+        Commands::CONFIG => handle_config(&args[1..], state),
+        Commands::INFO => handle_info(&args[1..], state),
+        Commands::SLOWLOG => handle_slowlog(&args[1..], state),
+        Commands::LATENCY => handle_latency(&args[1..], state),
+        Commands::MONITOR => handle_monitor(client),
+        Commands::SELECT => handle_select(&args[1..], state, client),
+        _ => {
+            let caching = client.caching.take();
+            //Eviction locks shards one at a time, before the command takes its own
+            let eviction_started = Instant::now();
+            let (fits, evicted) = state.keyspace.evict_if_needed();
+            let eviction_duration = eviction_started.elapsed();
+            //Evictions are no client's doing, so every client caching the keys is told, NOLOOP
+            //or not, before the command runs
+            if !evicted.is_empty() && state.tracking_count.load(Ordering::Relaxed) > 0 {
+                let invalidations = state.tracking.lock().unwrap().invalidate_keys(&evicted, None);
+                state.send_invalidations(invalidations);
+            }
+            let mut shards = state.keyspace.lock(&shards_for(command, args, &state.keyspace));
+            //Reads and deletions are still served when nothing more can be evicted
+            let result = match fits || !command.denies_oom() {
+                true => execute_command(command, &parsed_data, &mut shards, client.db),
+                false => Err(CommandError::Custom("OOM command not allowed when used memory > 'maxmemory'.".to_string()))
+            };
+            let modified = shards.take_modified();
+            let flushed = shards.take_flushed();
+            //Tracking is updated under the shard locks so no write can slip in between a read
+            //and the moment its keys are remembered. Without tracking clients the table is
+            //skipped, it would otherwise serialize every command
+            let invalidations = match state.tracking_count.load(Ordering::Relaxed) {
+                0 => Default::default(),
+                _ => {
+                    let mut tracking = state.tracking.lock().unwrap();
+                    if result.is_ok() && !command.is_write() && client.tracks_reads(caching) {
+                        for key in command.keys(args).into_iter().filter_map(|k| k.as_bytes()) {
+                            tracking.remember(client.id(), key);
+                        }
+                    }
+                    let flush_targets = match flushed {
+                        true => tracking.flush(),
+                        false => Vec::new()
+                    };
+                    (tracking.invalidate_keys(&modified, Some(client.id())), flush_targets)
+                }
+            };
+            drop(shards);
+            state.latency_add_sample_if_needed("eviction-cycle", eviction_duration);
+            state.send_invalidations(invalidations.0);
+            state.send_flush_invalidations(invalidations.1);
+            result
+        }
+    };
+    let duration = started.elapsed();
+    let name = match &result {
+        Err(CommandError::Custom(message)) if message.starts_with("ERR unknown subcommand") => command.name(),
+        _ => name.as_str()
+    };
+    //A wrong number of arguments is refused before running, as Redis checks arity up front
+    match result {
+        Err(CommandError::WrongArity) => state.stats.record_rejected_call(name),
+        _ => state.stats.record_call(name, duration, result.is_err())
+    }
+    state.record_slow_command(command, args, duration, client);
+    state.latency_add_sample_if_needed(command_event(command), duration);
+    state.feed_monitors(command, args, client);
+    let result = result?;
+
+    let output_data = serialize_with_protocol(&result, client.handle.protocol())?;
     Ok(output_data)
 }
 
@@ -114,6 +411,73 @@ fn error_to_resp(error: ServerError) -> RespValue {
         ServerError::Parse(_) => RespValue::Error(b"ERR protocol error".to_vec()),
         ServerError::Command(CommandError::ParseFailed) => RespValue::Error(b"ERR protocol error".to_vec()),
         ServerError::Command(CommandError::InvalidRequest) => RespValue::Error(b"ERR unknown command".to_vec()),
-        ServerError::PoolCreationError => RespValue::Error(b"Thread pool could not be created".to_vec())
+        ServerError::Command(CommandError::WrongArity) => RespValue::Error(b"ERR wrong number of arguments for command".to_vec()),
+        ServerError::Command(CommandError::Syntax) => RespValue::Error(b"ERR syntax error".to_vec()),
+        ServerError::Command(CommandError::NoAuth) => RespValue::Error(b"NOAUTH Authentication required.".to_vec()),
+        ServerError::Command(CommandError::NoPerm(message)) => RespValue::Error(format!("NOPERM {}", message).into_bytes()),
+        ServerError::Command(CommandError::Custom(message)) => RespValue::Error(message.into_bytes()),
+        ServerError::PoolCreationError => RespValue::Error(b"Thread pool could not be created".to_vec()),
+        ServerError::Config(message) => RespValue::Error(format!("ERR {}", message).into_bytes())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store::value::{EvictionSettings, MaxmemoryPolicy}, test_helpers::Output};
+
+    fn request(parts: &[&str]) -> Vec<u8> {
+        serializer(&RespValue::Arrays(Some(parts.iter().map(|p| RespValue::BulkString(Some(p.as_bytes().to_vec()))).collect()))).unwrap()
+    }
+
+    #[test]
+    fn commands_are_counted_by_full_name() {
+        let state = ServerState::default();
+        let mut client = state.register_client(Connection::new(Box::new(std::io::sink()), "127.0.0.1:1".to_string()));
+        for parts in [&["CLIENT", "LIST"][..], &["CONFIG", "GET", "port"], &["CLIENT", "NOPE"], &["GET"], &["GET", "k"]] {
+            let _ = process(&request(parts), &state, &mut client);
+        }
+        let stats = state.stats.command_stats();
+        let counts = |name: &str| (stats[name].calls, stats[name].rejected_calls, stats[name].failed_calls);
+        assert_eq!(stats.keys().collect::<Vec<_>>(), ["client", "client|list", "config|get", "get"]);
+        //Unknown subcommands fail under their container, a wrong arity is a rejection
+        assert_eq!(counts("client"), (1, 0, 1));
+        assert_eq!(counts("client|list"), (1, 0, 0));
+        assert_eq!(counts("get"), (1, 1, 0));
+    }
+
+    #[test]
+    fn evicted_keys_are_invalidated_at_once_even_with_noloop() {
+        let state = ServerState::default();
+        let output = Output::default();
+        let mut client = state.register_client(Connection::new(Box::new(output.clone()), "127.0.0.1:1".to_string()));
+        for parts in [&["HELLO", "3"][..], &["CLIENT", "TRACKING", "ON", "NOLOOP"], &["SET", "k", "v"], &["GET", "k"]] {
+            process(&request(parts), &state, &mut client).unwrap();
+            client.finish_command();
+        }
+        assert!(output.0.lock().unwrap().is_empty());
+
+        state.keyspace.set_eviction(EvictionSettings { maxmemory: 1, policy: MaxmemoryPolicy::AllKeysRandom, ..Default::default() });
+        process(&request(&["SET", "other", "v"]), &state, &mut client).unwrap();
+        assert_eq!(*output.0.lock().unwrap(), b">2\r\n$10\r\ninvalidate\r\n*1\r\n$1\r\nk\r\n");
+    }
+
+    #[test]
+    fn keepalive_is_set_on_accepted_sockets() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let _peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let socket = SockRef::from(&stream);
+
+        set_keepalive(&stream, 0).unwrap();
+        assert!(!socket.keepalive().unwrap());
+        set_keepalive(&stream, 60).unwrap();
+        assert!(socket.keepalive().unwrap());
+        #[cfg(target_os = "linux")]
+        {
+            assert_eq!(socket.keepalive_time().unwrap(), Duration::from_secs(60));
+            assert_eq!(socket.keepalive_interval().unwrap(), Duration::from_secs(20));
+            assert_eq!(socket.keepalive_retries().unwrap(), 3);
+        }
     }
 }
@@ -0,0 +1,321 @@
+use std::{collections::{BTreeMap, HashSet}, sync::atomic::Ordering};
+
+use crate::{resp::RespValue, server::value::{Client, ClientHandle, ServerState, TrackingOptions, TrackingTable}};
+
+impl TrackingTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enable(&mut self, id: u64, options: TrackingOptions) {
+        self.disable(id);
+        if options.bcast {
+            //BCAST without any prefix subscribes to every key
+            if options.prefixes.is_empty() {
+                self.prefixes.entry(Vec::new()).or_default().insert(id);
+            }
+            for prefix in &options.prefixes {
+                self.prefixes.entry(prefix.clone()).or_default().insert(id);
+            }
+        }
+        self.clients.insert(id, options);
+    }
+
+    ///Stops tracking for a client. Keys it read are dropped lazily when they get invalidated,
+    ///or at once when no client tracks anymore since commands then skip the table
+    pub fn disable(&mut self, id: u64) {
+        if self.clients.remove(&id).is_some() {
+            self.prefixes.retain(|_, ids| {
+                ids.remove(&id);
+                !ids.is_empty()
+            });
+        }
+        if self.clients.is_empty() {
+            self.keys.clear();
+        }
+    }
+
+    ///Clients sending their invalidations to `target`
+    pub fn redirecting_to(&self, target: u64) -> Vec<u64> {
+        self.clients.iter().filter(|(_, options)| options.redirect == Some(target)).map(|(id, _)| *id).collect()
+    }
+
+    pub fn remember(&mut self, id: u64, key: &[u8]) {
+        self.keys.entry(key.to_vec()).or_default().insert(id);
+    }
+
+    ///Returns the connections that must be told `key` changed, after applying REDIRECT.
+    ///Clients tracking the key in default mode forget it, they will read it again if needed.
+    ///`origin` is the client that modified the key, none when the server did (e.g. expiry)
+    pub fn invalidate(&mut self, key: &[u8], origin: Option<u64>) -> Vec<u64> {
+        let mut interested: Vec<u64> = self.keys.remove(key).map(|ids| ids.into_iter().collect()).unwrap_or_default();
+        for (prefix, ids) in &self.prefixes {
+            if key.starts_with(prefix) {
+                interested.extend(ids);
+            }
+        }
+
+        let mut targets = Vec::new();
+        for id in interested {
+            let options = match self.clients.get(&id) {
+                Some(options) => options,
+                None => continue
+            };
+            if options.noloop && origin == Some(id) {
+                continue;
+            }
+            let target = options.redirect.unwrap_or(id);
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        targets
+    }
+
+    ///Forgets every key after a flush and returns the connections to notify, every tracking
+    ///client whatever it read, after applying REDIRECT. NOLOOP does not apply to flushes
+    pub fn flush(&mut self) -> Vec<u64> {
+        self.keys.clear();
+        let mut targets = Vec::new();
+        for (id, options) in &self.clients {
+            let target = options.redirect.unwrap_or(*id);
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+        targets
+    }
+
+    ///Invalidates every key, grouping the keys by the connection that has to be notified
+    pub fn invalidate_keys(&mut self, keys: &[Vec<u8>], origin: Option<u64>) -> BTreeMap<u64, Vec<Vec<u8>>> {
+        let mut messages: BTreeMap<u64, Vec<Vec<u8>>> = BTreeMap::new();
+        let mut seen = HashSet::new();
+        for key in keys {
+            if !seen.insert(key) {
+                continue;
+            }
+            for target in self.invalidate(key, origin) {
+                messages.entry(target).or_default().push(key.to_vec());
+            }
+        }
+        messages
+    }
+}
+
+impl ServerState {
+    pub fn enable_tracking(&self, id: u64, options: TrackingOptions) {
+        let mut tracking = self.tracking.lock().unwrap();
+        tracking.enable(id, options);
+        self.tracking_count.store(tracking.clients.len(), Ordering::Relaxed);
+    }
+
+    pub fn disable_tracking(&self, id: u64) {
+        let mut tracking = self.tracking.lock().unwrap();
+        tracking.disable(id);
+        self.tracking_count.store(tracking.clients.len(), Ordering::Relaxed);
+    }
+
+    pub fn send_invalidations(&self, messages: BTreeMap<u64, Vec<Vec<u8>>>) {
+        if messages.is_empty() {
+            return;
+        }
+        let targets: Vec<_> = {
+            let clients = self.clients.lock().unwrap();
+            messages.into_iter()
+                .filter_map(|(id, keys)| clients.get(&id).map(|handle| (handle.clone(), keys)))
+                .collect()
+        };
+        for (handle, keys) in targets {
+            //A failed write means the client is going away, its own thread cleans it up
+            let _ = handle.send_invalidation(Some(keys));
+        }
+    }
+
+    ///Tells the RESP3 clients redirecting to a connection that just closed that they will miss
+    ///invalidations, as Redis does with a `tracking-redir-broken` push. RESP2 clients cannot
+    ///receive pushes
+    pub fn send_redirect_broken(&self, target: u64) {
+        let ids = match self.tracking_count.load(Ordering::Relaxed) {
+            0 => return,
+            _ => self.tracking.lock().unwrap().redirecting_to(target)
+        };
+        if ids.is_empty() {
+            return;
+        }
+        let handles: Vec<_> = {
+            let clients = self.clients.lock().unwrap();
+            ids.iter().filter_map(|id| clients.get(id).cloned()).collect()
+        };
+        let message = RespValue::Push(vec![
+            RespValue::BulkString(Some(b"tracking-redir-broken".to_vec())),
+            RespValue::Integer(target as i64),
+        ]);
+        for handle in handles.into_iter().filter(|h| h.protocol() >= 3) {
+            let _ = handle.send(&message);
+        }
+    }
+
+    ///Tells clients their whole cache is stale, with a null in place of the keys
+    pub fn send_flush_invalidations(&self, targets: Vec<u64>) {
+        if targets.is_empty() {
+            return;
+        }
+        let handles: Vec<_> = {
+            let clients = self.clients.lock().unwrap();
+            targets.iter().filter_map(|id| clients.get(id).cloned()).collect()
+        };
+        for handle in handles {
+            let _ = handle.send_invalidation(None);
+        }
+    }
+}
+
+impl ClientHandle {
+    ///RESP3 clients get an `invalidate` push, RESP2 clients (always a REDIRECT target, CLIENT
+    ///TRACKING refuses RESP2 without one) get the message published on the
+    ///`__redis__:invalidate` channel. No keys means every key
+    fn send_invalidation(&self, keys: Option<Vec<Vec<u8>>>) -> std::io::Result<()> {
+        let keys = RespValue::Arrays(keys.map(|keys| keys.into_iter().map(|k| RespValue::BulkString(Some(k))).collect()));
+        let message = if self.protocol() >= 3 {
+            RespValue::Push(vec![RespValue::BulkString(Some(b"invalidate".to_vec())), keys])
+        } else {
+            RespValue::Arrays(Some(vec![
+                RespValue::BulkString(Some(b"message".to_vec())),
+                RespValue::BulkString(Some(b"__redis__:invalidate".to_vec())),
+                keys,
+            ]))
+        };
+        self.send(&message)
+    }
+}
+
+impl Client {
+    ///Whether the keys read by the current command should be remembered, `caching` being the
+    ///value of a CLIENT CACHING issued right before it
+    pub fn tracks_reads(&self, caching: Option<bool>) -> bool {
+        match &self.tracking {
+            Some(options) if !options.bcast => {
+                if options.optin {
+                    caching == Some(true)
+                } else if options.optout {
+                    caching != Some(false)
+                } else {
+                    true
+                }
+            },
+            _ => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::value::Connection;
+    use crate::test_helpers::Output;
+
+    #[test]
+    fn invalidate_notifies_reader_once() {
+        let mut table = TrackingTable::new();
+        table.enable(1, TrackingOptions::default());
+        table.remember(1, b"key");
+
+        assert_eq!(table.invalidate(b"key", Some(2)), vec![1]);
+        assert!(table.invalidate(b"key", Some(2)).is_empty());
+    }
+
+    #[test]
+    fn invalidate_skips_disabled_clients() {
+        let mut table = TrackingTable::new();
+        table.enable(1, TrackingOptions::default());
+        table.remember(1, b"key");
+        table.disable(1);
+
+        assert!(table.keys.is_empty());
+        assert!(table.invalidate(b"key", None).is_empty());
+    }
+
+    #[test]
+    fn invalidate_noloop_skips_origin() {
+        let mut table = TrackingTable::new();
+        table.enable(1, TrackingOptions { noloop: true, ..Default::default() });
+        table.remember(1, b"key");
+
+        assert!(table.invalidate(b"key", Some(1)).is_empty());
+    }
+
+    #[test]
+    fn invalidate_follows_redirect() {
+        let mut table = TrackingTable::new();
+        table.enable(1, TrackingOptions { redirect: Some(7), ..Default::default() });
+        table.remember(1, b"key");
+
+        assert_eq!(table.invalidate(b"key", None), vec![7]);
+    }
+
+    #[test]
+    fn bcast_matches_prefixes() {
+        let mut table = TrackingTable::new();
+        table.enable(1, TrackingOptions {
+            bcast: true,
+            prefixes: vec![b"user:".to_vec()],
+            ..Default::default()
+        });
+
+        assert_eq!(table.invalidate(b"user:1", None), vec![1]);
+        assert_eq!(table.invalidate(b"user:1", None), vec![1]);
+        assert!(table.invalidate(b"order:1", None).is_empty());
+    }
+
+    #[test]
+    fn bcast_without_prefix_matches_everything() {
+        let mut table = TrackingTable::new();
+        table.enable(1, TrackingOptions { bcast: true, ..Default::default() });
+
+        assert_eq!(table.invalidate(b"anything", None), vec![1]);
+    }
+
+    #[test]
+    fn invalidate_keys_groups_by_target() {
+        let mut table = TrackingTable::new();
+        table.enable(1, TrackingOptions::default());
+        table.enable(2, TrackingOptions { redirect: Some(1), ..Default::default() });
+        table.remember(1, b"a");
+        table.remember(2, b"b");
+
+        let messages = table.invalidate_keys(&[b"a".to_vec(), b"b".to_vec(), b"a".to_vec()], None);
+        assert_eq!(messages.get(&1), Some(&vec![b"a".to_vec(), b"b".to_vec()]));
+        assert_eq!(messages.len(), 1);
+    }
+
+    #[test]
+    fn flush_notifies_every_tracking_client() {
+        let mut table = TrackingTable::new();
+        table.enable(1, TrackingOptions { noloop: true, ..Default::default() });
+        table.enable(2, TrackingOptions { redirect: Some(1), ..Default::default() });
+        table.enable(3, TrackingOptions { bcast: true, ..Default::default() });
+        table.remember(1, b"a");
+
+        let mut targets = table.flush();
+        targets.sort();
+        assert_eq!(targets, vec![1, 3]);
+        assert!(table.keys.is_empty());
+    }
+
+    #[test]
+    fn closing_a_redirect_target_breaks_the_redirect() {
+        let state = ServerState::default();
+        let target = state.register_client(Connection::new(Box::new(std::io::sink()), "127.0.0.1:1".to_string()));
+        let (resp3, resp2) = (Output::default(), Output::default());
+        for (output, protocol) in [(&resp3, 3), (&resp2, 2)] {
+            let client = state.register_client(Connection::new(Box::new(output.clone()), "127.0.0.1:2".to_string()));
+            client.handle.protocol.store(protocol, Ordering::Relaxed);
+            state.enable_tracking(client.id(), TrackingOptions { redirect: Some(target.id()), ..Default::default() });
+        }
+        assert_eq!(state.tracking.lock().unwrap().redirecting_to(target.id()).len(), 2);
+
+        state.unregister_client(&target);
+        assert_eq!(*resp3.0.lock().unwrap(), format!(">2\r\n$21\r\ntracking-redir-broken\r\n:{}\r\n", target.id()).into_bytes());
+        assert!(resp2.0.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,56 @@
+use std::{fs, io, net::Shutdown, os::unix::{fs::PermissionsExt, net::{UnixListener, UnixStream}}, sync::Arc};
+
+use crate::server::{tcp::serve_client, value::{Connection, ServerState}};
+
+///Binds the Unix socket, replacing a stale socket file left by a previous run
+pub fn bind_unix_socket(path: &str, perm: Option<u32>) -> io::Result<UnixListener> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    if let Some(perm) = perm {
+        fs::set_permissions(path, fs::Permissions::from_mode(perm))?;
+    }
+    Ok(listener)
+}
+
+pub fn handle_unix_connection(stream: UnixStream, path: &str, state: Arc<ServerState>) {
+    let writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let closer = match stream.try_clone() {
+        Ok(closer) => closer,
+        Err(_) => return,
+    };
+    //Unix clients have no port, they are listed by socket path like Redis does
+    let connection = Connection {
+        writer: Box::new(writer),
+        addr: format!("{}:0", path),
+        laddr: format!("{}:0", path),
+        shutdown: Box::new(move || {
+            let _ = closer.shutdown(Shutdown::Both);
+        }),
+    };
+    serve_client(stream, connection, None, state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bind_replaces_stale_socket_and_sets_permissions() {
+        let path = std::env::temp_dir().join(format!("redis-rust-{}.sock", std::process::id()));
+        let path = path.to_string_lossy().into_owned();
+
+        drop(bind_unix_socket(&path, None).unwrap());
+        let listener = bind_unix_socket(&path, Some(0o700)).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o700);
+        assert!(UnixStream::connect(&path).is_ok());
+
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+    }
+}
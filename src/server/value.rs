@@ -1,12 +1,13 @@
-use std::{sync::mpsc, thread};
+use std::{collections::{BTreeMap, HashMap, HashSet, VecDeque}, io::Write, net::TcpStream, sync::{atomic::{AtomicBool, AtomicI64, AtomicU8, AtomicU64, AtomicUsize}, mpsc, Arc, Condvar, Mutex, RwLock}, thread, time::{Instant, SystemTime}};
 
-use crate::{command::CommandError, resp::ParseError};
+use crate::{acl::Acl, command::CommandError, resp::ParseError, store::value::{MaxmemoryPolicy, Shards}};
 
 #[derive(Debug)]
 pub enum ServerError {
     Command(CommandError),
     Parse(ParseError),
     PoolCreationError,
+    Config(String),
 }
 
 pub struct Worker {
@@ -21,3 +22,276 @@ pub struct ThreadPool{
     pub sender: mpsc::Sender<Job>
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum TlsAuthClients {
+    #[default]
+    Yes,
+    No,
+    Optional,
+}
+
+///Server settings, from the configuration file and the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    //Path of the configuration file the server was started with, used by CONFIG REWRITE
+    pub configfile: Option<String>,
+    //Addresses to listen on, a leading '-' makes the address optional
+    pub bind: Vec<String>,
+    //0 disables the TCP listener
+    pub port: u16,
+    pub unixsocket: Option<String>,
+    pub unixsocketperm: Option<u32>,
+    pub requirepass: Option<Vec<u8>>,
+    pub aclfile: Option<String>,
+    pub tls_port: Option<u16>,
+    pub tls_cert_file: Option<String>,
+    pub tls_key_file: Option<String>,
+    pub tls_ca_cert_file: Option<String>,
+    pub tls_auth_clients: TlsAuthClients,
+    //Authenticate clients as the ACL user named by the CN of their certificate
+    pub tls_auth_clients_user: bool,
+    //Number of threads serving connections
+    pub io_threads: usize,
+    //Microseconds a command must take to enter the slow log, negative disables the log
+    pub slowlog_log_slower_than: i64,
+    pub slowlog_max_len: usize,
+    //Milliseconds an event must take to be recorded by the latency monitor, 0 disables it
+    pub latency_monitor_threshold: u64,
+    //Seconds a client can stay idle before being disconnected, 0 disables the timeout
+    pub timeout: u64,
+    //Seconds between TCP keepalive probes on accepted sockets, 0 disables them
+    pub tcp_keepalive: u64,
+    //Bytes the dataset may use before keys are evicted, 0 means no limit
+    pub maxmemory: usize,
+    pub maxmemory_policy: MaxmemoryPolicy,
+    //Keys sampled per eviction, more is more accurate but slower
+    pub maxmemory_samples: usize,
+    pub lfu_log_factor: u32,
+    pub lfu_decay_time: u64,
+    //Number of logical databases SELECT can pick from
+    pub databases: usize,
+    //Independently locked parts of the keyspace, a power of two. More shards let more
+    //commands run in parallel
+    pub keyspace_shards: usize,
+}
+
+///Entry of the config registry, parameters that are not mutable can only be set at startup
+pub struct ConfigParam {
+    pub name: &'static str,
+    pub mutable: bool,
+}
+
+///State shared by every connection
+pub struct ServerState {
+    pub config: Mutex<Config>,
+    //Settings read by every command, copied out of the config so commands never lock it
+    pub slowlog_log_slower_than: AtomicI64,
+    pub latency_monitor_threshold: AtomicU64,
+    //Read by every command, written only by ACL changes and denials
+    pub acl: RwLock<Acl>,
+    pub keyspace: Shards,
+    pub clients: Mutex<HashMap<u64, Arc<ClientHandle>>>,
+    pub tracking: Mutex<TrackingTable>,
+    //Clients with tracking on, commands skip the tracking table when there are none
+    pub tracking_count: AtomicUsize,
+    pub next_client_id: AtomicU64,
+    pub stats: Stats,
+    pub slowlog: Mutex<SlowLog>,
+    pub latency: Mutex<LatencyMonitor>,
+    //Clients that issued MONITOR, the count lets commands skip formatting when there are none
+    pub monitors: Mutex<HashMap<u64, Arc<ClientHandle>>>,
+    pub monitor_count: AtomicUsize,
+    pub pause: Mutex<Option<Pause>>,
+    //Set while a pause may be active, so commands only lock the pause when there is one
+    pub paused: AtomicBool,
+    //Notified when a pause is lifted
+    pub unpaused: Condvar,
+}
+
+pub struct SlowLogEntry {
+    pub id: u64,
+    //Unix time in seconds
+    pub timestamp: u64,
+    pub duration_usec: u64,
+    pub args: Vec<Vec<u8>>,
+    pub addr: String,
+    pub name: Vec<u8>,
+}
+
+pub struct LatencySample {
+    //Unix time in seconds
+    pub time: u64,
+    pub latency_ms: u64,
+}
+
+///Spikes of one event type, oldest first. Spikes within the same second are merged
+#[derive(Default)]
+pub struct LatencyEvent {
+    pub samples: VecDeque<LatencySample>,
+    //All time highest latency, kept when old samples are dropped
+    pub max_ms: u64,
+}
+
+///Latency spikes by event name, e.g. command or expire-cycle
+#[derive(Default)]
+pub struct LatencyMonitor {
+    pub events: BTreeMap<String, LatencyEvent>,
+}
+
+///Most recent slow commands first
+pub struct SlowLog {
+    pub entries: VecDeque<SlowLogEntry>,
+    pub next_id: u64,
+}
+
+///Server wide counters reported by INFO
+pub struct Stats {
+    pub started: Instant,
+    pub started_at: SystemTime,
+    pub total_connections_received: AtomicU64,
+    pub total_commands_processed: AtomicU64,
+    pub total_net_input_bytes: AtomicU64,
+    pub total_net_output_bytes: AtomicU64,
+    pub ops: Mutex<OpsSamples>,
+    //Keyed by full name, e.g. `config|get`, and split in stripes picked by thread, so workers
+    //recording calls rarely share a lock. Readers merge them
+    pub commands: Vec<Mutex<HashMap<String, CommandStats>>>,
+    //Error replies counted by their prefix, e.g. ERR or NOPERM
+    pub errors: Mutex<BTreeMap<String, u64>>,
+}
+
+///Counters of a single command, reported by INFO commandstats and latencystats
+#[derive(Default)]
+pub struct CommandStats {
+    pub calls: u64,
+    pub usec: u64,
+    //Calls refused before running, e.g. by ACL checks
+    pub rejected_calls: u64,
+    //Calls that ran and replied with an error
+    pub failed_calls: u64,
+    pub latency: LatencyHistogram,
+}
+
+///Log-linear histogram of latencies in microseconds. Values below 32 are exact, above that every
+///power of two is split in 16 buckets, keeping the error of any percentile under 7%
+#[derive(Default)]
+pub struct LatencyHistogram {
+    pub counts: Vec<u64>,
+    pub total: u64,
+}
+
+///Commands processed per second over the last samples, taken by the cron thread
+pub struct OpsSamples {
+    pub last_time: Instant,
+    pub last_count: u64,
+    pub samples: [u64; 16],
+    pub index: usize,
+}
+
+///A new connection as handed over by the listener that accepted it
+pub struct Connection {
+    pub writer: Box<dyn Write + Send>,
+    pub addr: String,
+    pub laddr: String,
+    //Closes the underlying socket, so the thread reading it stops
+    pub shutdown: Box<dyn Fn() + Send + Sync>,
+}
+
+///The part of a connection other connections can reach, e.g. to push invalidation messages
+pub struct ClientHandle {
+    pub id: u64,
+    pub addr: String,
+    pub laddr: String,
+    pub created: Instant,
+    //Replies and pushes share the writer so their bytes never interleave
+    pub writer: Mutex<Box<dyn Write + Send>>,
+    pub protocol: AtomicU8,
+    pub shutdown: Box<dyn Fn() + Send + Sync>,
+    //Set by CLIENT KILL, the connection stops before its next command
+    pub killed: AtomicBool,
+    pub details: Mutex<ClientDetails>,
+}
+
+///What CLIENT LIST reports about a connection, updated by its thread after every command
+pub struct ClientDetails {
+    pub name: Vec<u8>,
+    pub user: Vec<u8>,
+    pub db: usize,
+    //Full name of the last command, e.g. client|list
+    pub last_command: String,
+    pub last_interaction: Instant,
+    //Size of the last request read and of its arguments
+    pub query_buffer: usize,
+    pub argv_mem: usize,
+    //Between the start of a command and its reply, a client is never idle then
+    pub executing: bool,
+    pub monitor: bool,
+    pub tracking: bool,
+    pub redirect: i64,
+    pub no_evict: bool,
+}
+
+///Set by CLIENT REPLY, whether replies are written to the client
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplyMode {
+    On,
+    Off,
+    //Set by CLIENT REPLY SKIP, its own reply is dropped and then the next one
+    SkipNext,
+    Skip,
+}
+
+///Set by CLIENT PAUSE, commands are held until `until`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pause {
+    pub until: Instant,
+    //Hold every command instead of only writes
+    pub all: bool,
+}
+
+///Per connection state owned by the thread serving it
+pub struct Client {
+    pub handle: Arc<ClientHandle>,
+    pub authenticated: bool,
+    pub user: Vec<u8>,
+    //Set once the reply to QUIT is written, the connection is then closed
+    pub closing: bool,
+    pub tracking: Option<TrackingOptions>,
+    //Set by CLIENT CACHING, applies to the next command only
+    pub caching: Option<bool>,
+    //Set by MONITOR, the client becomes a monitor once the reply is written
+    pub monitor: bool,
+    pub reply: ReplyMode,
+    //Database picked with SELECT
+    pub db: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TrackingOptions {
+    pub redirect: Option<u64>,
+    pub bcast: bool,
+    pub prefixes: Vec<Vec<u8>>,
+    pub optin: bool,
+    pub optout: bool,
+    pub noloop: bool,
+}
+
+///Keys read by tracking clients and the prefixes broadcast clients subscribed to
+#[derive(Debug, Default)]
+pub struct TrackingTable {
+    pub keys: HashMap<Vec<u8>, HashSet<u64>>,
+    pub prefixes: HashMap<Vec<u8>, HashSet<u64>>,
+    pub clients: HashMap<u64, TrackingOptions>,
+}
+
+///Plain text side of a TLS connection. The socket is read without holding the session lock so
+///other connections can keep writing to this client while it waits for a request
+pub struct TlsReader {
+    pub session: Arc<Mutex<rustls::ServerConnection>>,
+    pub socket: TcpStream,
+}
+
+pub struct TlsWriter {
+    pub session: Arc<Mutex<rustls::ServerConnection>>,
+    pub socket: TcpStream,
+}
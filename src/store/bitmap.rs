@@ -0,0 +1,414 @@
+use crate::store::{string::{string_bytes, MAX_STRING_LEN}, value::{BitOp, BitUnit, BitfieldOp, BitfieldOverflow, BitfieldType, Entry, Store, StoreError, Value}};
+
+//Bits a string can hold, offsets of SETBIT, GETBIT and BITFIELD stay below it
+pub const MAX_BIT_OFFSET: u64 = MAX_STRING_LEN as u64 * 8;
+
+///Bit at `offset`, the most significant bit of the first byte being offset 0. Bits past the
+///end of the string are 0
+pub fn get_bit(bytes: &[u8], offset: u64) -> bool {
+    let byte = (offset / 8) as usize;
+    byte < bytes.len() && bytes[byte] & (0x80 >> (offset % 8)) != 0
+}
+
+fn put_bit(bytes: &mut [u8], offset: u64, bit: bool) {
+    let mask = 0x80 >> (offset % 8);
+    let byte = &mut bytes[(offset / 8) as usize];
+    match bit {
+        true => *byte |= mask,
+        false => *byte &= !mask
+    }
+}
+
+///Grows a bitmap with zero bytes so that it holds the bit at `offset`
+fn grow_to(bytes: &mut Vec<u8>, offset: u64) {
+    let len = (offset / 8) as usize + 1;
+    if bytes.len() < len {
+        bytes.resize(len, 0);
+    }
+}
+
+///Inclusive bounds of a BITCOUNT or BITPOS range over `len` units, negative ones counting from
+///the end. None when the range is empty
+fn resolve_range(start: i64, end: i64, len: i64) -> Option<(u64, u64)> {
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let end = if end < 0 { (len + end).max(0) } else { end.min(len - 1) };
+    (start <= end && len > 0).then_some((start as u64, end as u64))
+}
+
+///Bit range covered by a range in `unit`
+fn bit_range((start, end): (u64, u64), unit: BitUnit) -> (u64, u64) {
+    match unit {
+        BitUnit::Byte => (start * 8, end * 8 + 7),
+        BitUnit::Bit => (start, end)
+    }
+}
+
+fn unit_len(bytes: &[u8], unit: BitUnit) -> i64 {
+    match unit {
+        BitUnit::Byte => bytes.len() as i64,
+        BitUnit::Bit => bytes.len() as i64 * 8
+    }
+}
+
+///Set bits between two inclusive bit offsets, both inside the string
+fn count_bits(bytes: &[u8], first: u64, last: u64) -> u64 {
+    let (first_byte, last_byte) = ((first / 8) as usize, (last / 8) as usize);
+    let whole: u64 = bytes[first_byte..=last_byte].iter().map(|b| b.count_ones() as u64).sum();
+    //Whole bytes were counted, the bits of the edge bytes outside the range come off
+    let before = bytes[first_byte] & !(0xFF >> (first % 8));
+    let after = bytes[last_byte] & 0xFFu8.checked_shr(last as u32 % 8 + 1).unwrap_or(0);
+    whole - before.count_ones() as u64 - after.count_ones() as u64
+}
+
+///First bit equal to `bit` between two inclusive bit offsets
+fn find_bit(bytes: &[u8], bit: bool, first: u64, last: u64) -> Option<u64> {
+    let skip = if bit { 0x00 } else { 0xFF };
+    let mut offset = first;
+    while offset <= last {
+        //Bytes that cannot hold the bit are passed over whole
+        if offset.is_multiple_of(8) && offset + 7 <= last && bytes[(offset / 8) as usize] == skip {
+            offset += 8;
+            continue;
+        }
+        if get_bit(bytes, offset) == bit {
+            return Some(offset);
+        }
+        offset += 1;
+    }
+    None
+}
+
+///Reads a field, sign extending signed ones
+fn read_field(bytes: &[u8], field: BitfieldType, offset: u64) -> i64 {
+    let mut value: u64 = 0;
+    for i in 0..field.bits as u64 {
+        value = value << 1 | get_bit(bytes, offset + i) as u64;
+    }
+    if field.signed && field.bits < 64 && value & (1 << (field.bits - 1)) != 0 {
+        value |= u64::MAX << field.bits;
+    }
+    value as i64
+}
+
+///Writes the low bits of `value` into a field the bitmap already holds
+fn write_field(bytes: &mut [u8], field: BitfieldType, offset: u64, value: i64) {
+    for i in 0..field.bits as u64 {
+        let bit = (value as u64 >> (field.bits as u64 - 1 - i)) & 1 == 1;
+        put_bit(bytes, offset + i, bit);
+    }
+}
+
+///Fits a value into a field following the overflow policy, None when FAIL rejects it
+fn fit_field(value: i128, field: BitfieldType, overflow: BitfieldOverflow) -> Option<i64> {
+    let (min, max) = match field.signed {
+        true => (-(1i128 << (field.bits - 1)), (1i128 << (field.bits - 1)) - 1),
+        false => (0, (1i128 << field.bits) - 1)
+    };
+    if (min..=max).contains(&value) {
+        return Some(value as i64);
+    }
+    match overflow {
+        BitfieldOverflow::Wrap => {
+            let wrapped = value.rem_euclid(1i128 << field.bits);
+            Some(if wrapped > max { wrapped - (1i128 << field.bits) } else { wrapped } as i64)
+        },
+        BitfieldOverflow::Sat => Some(value.clamp(min, max) as i64),
+        BitfieldOverflow::Fail => None
+    }
+}
+
+///Combines the sources of BITOP into the bytes of the destination
+fn combine(op: BitOp, sources: &[Vec<u8>]) -> Vec<u8> {
+    let len = sources.iter().map(Vec::len).max().unwrap_or(0);
+    //Shorter sources read as zero bytes past their end
+    let byte = |source: &Vec<u8>, i: usize| source.get(i).copied().unwrap_or(0);
+    let others = |i: usize| sources[1..].iter().fold(0, |acc, s| acc | byte(s, i));
+    (0..len).map(|i| match op {
+        BitOp::And => sources.iter().fold(0xFF, |acc, s| acc & byte(s, i)),
+        BitOp::Or => sources.iter().fold(0, |acc, s| acc | byte(s, i)),
+        BitOp::Xor => sources.iter().fold(0, |acc, s| acc ^ byte(s, i)),
+        BitOp::Not => !byte(&sources[0], i),
+        BitOp::Diff => byte(&sources[0], i) & !others(i),
+        BitOp::AndOr => byte(&sources[0], i) & others(i),
+        BitOp::One => {
+            let (once, many) = sources.iter().fold((0u8, 0u8), |(once, many), s| {
+                let b = byte(s, i);
+                let many = many | (once & b);
+                ((once ^ b) & !many, many)
+            });
+            once & !many
+        }
+    }).collect()
+}
+
+impl Store {
+    ///Runs `f` on the bytes of a string key for a read command, with None when the key does
+    ///not exist
+    fn read_bitmap<R>(&mut self, key: &[u8], f: impl FnOnce(Option<&[u8]>) -> R) -> Result<R, StoreError> {
+        match self.lookup_read(key) {
+            None => Ok(f(None)),
+            Some(value) => Ok(f(Some(&*string_bytes(value)?)))
+        }
+    }
+
+    ///Takes the bytes of a string key out of the store for a write command, so that large
+    ///bitmaps are not copied on every change. They must be stored back with `write`
+    fn take_bitmap(&mut self, key: &[u8]) -> Result<Vec<u8>, StoreError> {
+        match self.lookup_write(key) {
+            None => Ok(Vec::new()),
+            Some(Value::String(_)) => {
+                let Some(Entry { value: Value::String(bytes), .. }) = self.map.get_mut(key) else { unreachable!() };
+                //The bytes leave the memory count until write counts them again
+                self.used_memory -= bytes.len();
+                Ok(std::mem::take(bytes))
+            },
+            Some(value) => Ok(string_bytes(value)?.into_owned())
+        }
+    }
+
+    ///Sets or clears a bit, growing the string as needed. Returns the previous bit
+    pub fn setbit(&mut self, key: &[u8], offset: u64, bit: bool) -> Result<bool, StoreError> {
+        let mut bytes = self.take_bitmap(key)?;
+        let previous = get_bit(&bytes, offset);
+        grow_to(&mut bytes, offset);
+        put_bit(&mut bytes, offset, bit);
+        self.write(key, Value::String(bytes));
+        Ok(previous)
+    }
+
+    pub fn getbit(&mut self, key: &[u8], offset: u64) -> Result<bool, StoreError> {
+        self.read_bitmap(key, |bytes| get_bit(bytes.unwrap_or_default(), offset))
+    }
+
+    ///Set bits of the string, or of an inclusive range of it
+    pub fn bitcount(&mut self, key: &[u8], range: Option<(i64, i64, BitUnit)>) -> Result<u64, StoreError> {
+        self.read_bitmap(key, |bytes| {
+            let bytes = bytes.unwrap_or_default();
+            let bits = match range {
+                None if bytes.is_empty() => None,
+                None => Some((0, bytes.len() as u64 * 8 - 1)),
+                Some((start, end, _)) if start < 0 && end < 0 && start > end => None,
+                Some((start, end, unit)) => {
+                    resolve_range(start, end, unit_len(bytes, unit)).map(|range| bit_range(range, unit))
+                }
+            };
+            bits.map_or(0, |(first, last)| count_bits(bytes, first, last))
+        })
+    }
+
+    ///Position of the first bit equal to `bit`, -1 when there is none. Looking for a clear bit
+    ///without an end to the range finds the first bit past the string, as if it went on with
+    ///zeros
+    pub fn bitpos(&mut self, key: &[u8], bit: bool, range: Option<(i64, Option<i64>, BitUnit)>) -> Result<i64, StoreError> {
+        self.read_bitmap(key, |bytes| {
+            let Some(bytes) = bytes else {
+                return if bit { -1 } else { 0 };
+            };
+            let (start, end, unit) = range.unwrap_or((0, None, BitUnit::Byte));
+            let Some((first, last)) = resolve_range(start, end.unwrap_or(-1), unit_len(bytes, unit))
+                .map(|range| bit_range(range, unit)) else {
+                return -1;
+            };
+            match find_bit(bytes, bit, first, last) {
+                Some(position) => position as i64,
+                None if !bit && end.is_none() => last as i64 + 1,
+                None => -1
+            }
+        })
+    }
+
+    ///Stores the result of a bitwise operation over the sources in `destination`, deleting it
+    ///when the result is empty. Returns the length of the result
+    pub fn bitop(&mut self, op: BitOp, destination: &[u8], sources: &[&[u8]]) -> Result<usize, StoreError> {
+        let sources = sources.iter()
+            .map(|key| self.read_bitmap(key, |bytes| bytes.unwrap_or_default().to_vec()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = combine(op, &sources);
+        let len = result.len();
+        match len {
+            0 => { self.remove(destination); },
+            _ => {
+                self.write(destination, Value::String(result));
+                self.persist(destination);
+            }
+        }
+        Ok(len)
+    }
+
+    ///Runs BITFIELD subcommands in order. GET replies the field, SET its previous value and
+    ///INCRBY its new one, None being a write FAIL refused. The string grows to hold every field
+    ///written, even the ones refused
+    pub fn bitfield(&mut self, key: &[u8], ops: &[BitfieldOp]) -> Result<Vec<Option<i64>>, StoreError> {
+        if ops.iter().all(|op| matches!(op, BitfieldOp::Get(..))) {
+            return self.read_bitmap(key, |bytes| {
+                let bytes = bytes.unwrap_or_default();
+                ops.iter().map(|op| match *op {
+                    BitfieldOp::Get(field, offset) => Some(read_field(bytes, field, offset)),
+                    _ => unreachable!()
+                }).collect()
+            });
+        }
+        let mut bytes = self.take_bitmap(key)?;
+        for op in ops {
+            if let BitfieldOp::Set(field, offset, ..) | BitfieldOp::IncrBy(field, offset, ..) = *op {
+                grow_to(&mut bytes, offset + field.bits as u64 - 1);
+            }
+        }
+        let replies = ops.iter().map(|op| match *op {
+            BitfieldOp::Get(field, offset) => Some(read_field(&bytes, field, offset)),
+            BitfieldOp::Set(field, offset, value, overflow) => {
+                let previous = read_field(&bytes, field, offset);
+                let value = fit_field(value as i128, field, overflow)?;
+                write_field(&mut bytes, field, offset, value);
+                Some(previous)
+            },
+            BitfieldOp::IncrBy(field, offset, increment, overflow) => {
+                let current = read_field(&bytes, field, offset);
+                let value = fit_field(current as i128 + increment as i128, field, overflow)?;
+                write_field(&mut bytes, field, offset, value);
+                Some(value)
+            }
+        }).collect();
+        self.write(key, Value::String(bytes));
+        Ok(replies)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const I8: BitfieldType = BitfieldType { signed: true, bits: 8 };
+    const U2: BitfieldType = BitfieldType { signed: false, bits: 2 };
+
+    #[test]
+    fn setbit_grows_and_getbit_reads() {
+        let mut store = Store::new();
+        assert_eq!(store.setbit(b"k", 7, true), Ok(false));
+        assert_eq!(store.setbit(b"k", 7, true), Ok(true));
+        assert_eq!(store.get(b"k"), Ok(Some(vec![0x01])));
+        assert_eq!(store.setbit(b"k", 17, true), Ok(false));
+        assert_eq!(store.get(b"k"), Ok(Some(vec![0x01, 0x00, 0x40])));
+        assert_eq!(store.getbit(b"k", 17), Ok(true));
+        assert_eq!(store.getbit(b"k", 1000), Ok(false));
+        assert_eq!(store.getbit(b"missing", 0), Ok(false));
+        //Integers are read as their digits
+        store.set(b"n", b"1".to_vec());
+        assert_eq!(store.getbit(b"n", 7), Ok(true));
+        store.write(b"l", Value::List(Default::default()));
+        assert_eq!(store.setbit(b"l", 0, true), Err(StoreError::WrongType));
+    }
+
+    #[test]
+    fn bitmap_writes_keep_memory_accounting() {
+        let mut store = Store::new();
+        store.setbit(b"k", 8 * 100, true).unwrap();
+        store.setbit(b"k", 3, true).unwrap();
+        assert_eq!(store.used_memory, crate::store::memory::entry_size(b"k", &Value::String(vec![0; 101])));
+    }
+
+    #[test]
+    fn bitcount_ranges() {
+        let mut store = Store::new();
+        store.set(b"k", b"foobar".to_vec());
+        assert_eq!(store.bitcount(b"k", None), Ok(26));
+        assert_eq!(store.bitcount(b"k", Some((0, 0, BitUnit::Byte))), Ok(4));
+        assert_eq!(store.bitcount(b"k", Some((1, 1, BitUnit::Byte))), Ok(6));
+        assert_eq!(store.bitcount(b"k", Some((1, -1, BitUnit::Byte))), Ok(22));
+        assert_eq!(store.bitcount(b"k", Some((5, 30, BitUnit::Bit))), Ok(17));
+        assert_eq!(store.bitcount(b"k", Some((-1, -2, BitUnit::Byte))), Ok(0));
+        assert_eq!(store.bitcount(b"k", Some((0, 100, BitUnit::Byte))), Ok(26));
+        assert_eq!(store.bitcount(b"missing", None), Ok(0));
+    }
+
+    #[test]
+    fn bitpos_finds_bits() {
+        let mut store = Store::new();
+        store.set(b"k", vec![0xFF, 0xF0, 0x00]);
+        assert_eq!(store.bitpos(b"k", false, None), Ok(12));
+        store.set(b"k", vec![0x00, 0xFF, 0xF0]);
+        assert_eq!(store.bitpos(b"k", true, Some((0, None, BitUnit::Byte))), Ok(8));
+        assert_eq!(store.bitpos(b"k", true, Some((2, None, BitUnit::Byte))), Ok(16));
+        assert_eq!(store.bitpos(b"k", true, Some((2, Some(-1), BitUnit::Byte))), Ok(16));
+        assert_eq!(store.bitpos(b"k", true, Some((7, Some(15), BitUnit::Bit))), Ok(8));
+        assert_eq!(store.bitpos(b"k", true, Some((7, Some(7), BitUnit::Bit))), Ok(-1));
+        store.set(b"ones", vec![0xFF, 0xFF]);
+        //Without an end the string reads as padded with zeros, with one there is no zero bit
+        assert_eq!(store.bitpos(b"ones", false, None), Ok(16));
+        assert_eq!(store.bitpos(b"ones", false, Some((0, Some(-1), BitUnit::Byte))), Ok(-1));
+        assert_eq!(store.bitpos(b"missing", true, None), Ok(-1));
+        assert_eq!(store.bitpos(b"missing", false, None), Ok(0));
+    }
+
+    #[test]
+    fn bitop_operations() {
+        let mut store = Store::new();
+        store.set(b"a", vec![0b1100_1100, 0xFF]);
+        store.set(b"b", vec![0b1010_1010]);
+        store.set(b"c", vec![0b1001_0000]);
+        let mut run = |op, sources: &[&[u8]]| {
+            store.bitop(op, b"dest", sources).unwrap();
+            store.get(b"dest").unwrap()
+        };
+        assert_eq!(run(BitOp::And, &[b"a", b"b"]), Some(vec![0b1000_1000, 0x00]));
+        assert_eq!(run(BitOp::Or, &[b"a", b"b"]), Some(vec![0b1110_1110, 0xFF]));
+        assert_eq!(run(BitOp::Xor, &[b"a", b"b"]), Some(vec![0b0110_0110, 0xFF]));
+        assert_eq!(run(BitOp::Not, &[b"b"]), Some(vec![0b0101_0101]));
+        assert_eq!(run(BitOp::Diff, &[b"a", b"b", b"c"]), Some(vec![0b0100_0100, 0xFF]));
+        assert_eq!(run(BitOp::AndOr, &[b"a", b"b", b"c"]), Some(vec![0b1000_1000, 0x00]));
+        assert_eq!(run(BitOp::One, &[b"a", b"b", b"c"]), Some(vec![0b0111_0110, 0xFF]));
+        assert_eq!(run(BitOp::Or, &[b"missing"]), None);
+    }
+
+    #[test]
+    fn bitop_replaces_destination() {
+        let mut store = Store::new();
+        store.set(b"a", vec![0x0F]);
+        store.set(b"dest", b"old".to_vec());
+        store.set_expire(b"dest", u64::MAX);
+        assert_eq!(store.bitop(BitOp::Not, b"dest", &[b"a"]), Ok(1));
+        assert_eq!(store.get(b"dest"), Ok(Some(vec![0xF0])));
+        assert!(!store.expires.contains_key(&b"dest"[..]));
+    }
+
+    #[test]
+    fn bitfield_get_set_incrby() {
+        let mut store = Store::new();
+        let ops = [
+            BitfieldOp::Set(I8, 0, -1, BitfieldOverflow::Wrap),
+            BitfieldOp::Get(I8, 0),
+            BitfieldOp::Get(BitfieldType { signed: false, bits: 4 }, 0),
+            BitfieldOp::IncrBy(I8, 0, 3, BitfieldOverflow::Wrap),
+            BitfieldOp::Get(BitfieldType { signed: true, bits: 64 }, 0),
+        ];
+        assert_eq!(store.bitfield(b"k", &ops), Ok(vec![Some(0), Some(-1), Some(15), Some(2), Some(0x0200_0000_0000_0000)]));
+        assert_eq!(store.get(b"k"), Ok(Some(vec![0x02])));
+        //Fields may start anywhere, across bytes
+        assert_eq!(store.bitfield(b"k", &[BitfieldOp::Set(I8, 4, 0x7F, BitfieldOverflow::Wrap)]), Ok(vec![Some(0x20)]));
+        assert_eq!(store.get(b"k"), Ok(Some(vec![0x07, 0xF0])));
+        //Reads do not create the key
+        assert_eq!(store.bitfield(b"missing", &[BitfieldOp::Get(I8, 100)]), Ok(vec![Some(0)]));
+        assert!(!store.map.contains_key(&b"missing"[..]));
+    }
+
+    #[test]
+    fn bitfield_overflow_policies() {
+        let mut store = Store::new();
+        let incr = |overflow| [BitfieldOp::IncrBy(U2, 0, 3, overflow), BitfieldOp::IncrBy(U2, 0, 3, overflow)];
+        assert_eq!(store.bitfield(b"wrap", &incr(BitfieldOverflow::Wrap)), Ok(vec![Some(3), Some(2)]));
+        assert_eq!(store.bitfield(b"sat", &incr(BitfieldOverflow::Sat)), Ok(vec![Some(3), Some(3)]));
+        assert_eq!(store.bitfield(b"fail", &incr(BitfieldOverflow::Fail)), Ok(vec![Some(3), None]));
+        assert_eq!(store.bitfield(b"fail", &[BitfieldOp::Get(U2, 0)]), Ok(vec![Some(3)]));
+        let signed = [
+            BitfieldOp::Set(I8, 0, 200, BitfieldOverflow::Wrap),
+            BitfieldOp::IncrBy(I8, 0, -200, BitfieldOverflow::Sat),
+            BitfieldOp::IncrBy(I8, 0, i64::MIN, BitfieldOverflow::Wrap),
+        ];
+        assert_eq!(store.bitfield(b"i", &signed), Ok(vec![Some(0), Some(-128), Some(-128)]));
+        assert_eq!(fit_field(i64::MAX as i128 + 1, BitfieldType { signed: true, bits: 64 }, BitfieldOverflow::Wrap), Some(i64::MIN));
+        assert_eq!(fit_field(-1, BitfieldType { signed: false, bits: 63 }, BitfieldOverflow::Sat), Some(0));
+        //A refused write still grows the string to the field
+        assert_eq!(store.bitfield(b"grow", &[BitfieldOp::Set(U2, 30, 4, BitfieldOverflow::Fail)]), Ok(vec![None]));
+        assert_eq!(store.get(b"grow"), Ok(Some(vec![0; 4])));
+    }
+}
@@ -0,0 +1,193 @@
+use std::time::{Duration, Instant};
+
+use crate::store::value::{Databases, EvictionSettings, Key, Store};
+
+impl Databases {
+    pub fn new(count: usize, eviction: EvictionSettings) -> Self {
+        let dbs = (0..count.max(1))
+            .map(|_| {
+                let mut store = Store::new();
+                store.eviction = eviction;
+                store
+            })
+            .collect();
+        Self { dbs, eviction, next_eviction_db: 0, next_expire_db: 0, flushed: false }
+    }
+
+    pub fn len(&self) -> usize {
+        self.dbs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dbs.is_empty()
+    }
+
+    pub fn db(&mut self, index: usize) -> &mut Store {
+        &mut self.dbs[index]
+    }
+
+    ///Sum of a counter over every database
+    pub fn total(&self, stat: impl Fn(&Store) -> u64) -> u64 {
+        self.dbs.iter().map(stat).sum()
+    }
+
+    pub fn used_memory(&self) -> usize {
+        self.dbs.iter().map(|db| db.used_memory).sum()
+    }
+
+    pub fn set_eviction(&mut self, eviction: EvictionSettings) {
+        self.eviction = eviction;
+        for db in &mut self.dbs {
+            db.eviction = eviction;
+        }
+    }
+
+    ///Drains the keys modified in any database since the previous call
+    pub fn take_modified(&mut self) -> Vec<Key> {
+        self.dbs.iter_mut().flat_map(|db| db.take_modified()).collect()
+    }
+
+    ///Whether a database was flushed since the previous call
+    pub fn take_flushed(&mut self) -> bool {
+        std::mem::take(&mut self.flushed)
+    }
+
+    ///Empties one database, or all of them when `index` is None
+    pub fn flush(&mut self, index: Option<usize>, lazy: bool) {
+        match index {
+            Some(index) => self.dbs[index].flush(lazy),
+            None => self.dbs.iter_mut().for_each(|db| db.flush(lazy))
+        }
+        self.flushed = true;
+    }
+
+    ///Exchanges the contents of two databases, clients connected to one see the other's keys
+    pub fn swap(&mut self, a: usize, b: usize) {
+        self.dbs.swap(a, b);
+    }
+
+    ///Moves a key and its time to live to another database. Returns false if the key does not
+    ///exist in the source or already exists in the destination
+    pub fn move_key(&mut self, key: &[u8], from: usize, to: usize) -> bool {
+        if !self.dbs[from].exists(key) || self.dbs[to].exists(key) {
+            return false;
+        }
+        let expire = self.dbs[from].expires.get(key).copied();
+        let Some(value) = self.dbs[from].remove(key) else { return false };
+        self.dbs[to].write_with_expire(key, value, expire);
+        true
+    }
+
+    ///COPY between two databases, which may be the same one
+    pub fn copy(&mut self, from: &[u8], source: usize, to: &[u8], destination: usize, replace: bool) -> bool {
+        if source == destination {
+            return self.dbs[source].copy(from, to, replace);
+        }
+        if !self.dbs[source].exists(from) || (!replace && self.dbs[destination].exists(to)) {
+            return false;
+        }
+        let value = self.dbs[source].map[from].value.clone();
+        let expire = self.dbs[source].expires.get(from).copied();
+        self.dbs[destination].write_with_expire(to, value, expire);
+        true
+    }
+
+    ///Evicts a random key, taking from each database in turn as Redis does. Returns false
+    ///when no database has a key the policy may evict
+    pub fn evict_random(&mut self) -> bool {
+        let volatile = self.eviction.policy.is_volatile();
+        for _ in 0..self.dbs.len() {
+            let index = self.next_eviction_db % self.dbs.len();
+            self.next_eviction_db = index + 1;
+            let db = &mut self.dbs[index];
+            if let Some(key) = db.random_key(volatile) {
+                return db.evict(&key);
+            }
+        }
+        false
+    }
+
+    ///Best candidate of a sampling policy across the databases, with its score and database
+    pub fn eviction_candidate(&mut self) -> Option<(u64, usize, Key)> {
+        self.dbs.iter_mut()
+            .enumerate()
+            .filter_map(|(index, db)| db.eviction_candidate().map(|(score, key)| (score, index, key)))
+            .max_by_key(|(score, _, _)| *score)
+    }
+
+    ///Runs the active expire cycle of each database in turn within a shared time limit.
+    ///Returns the number of keys deleted
+    pub fn active_expire_cycle(&mut self, time_limit: Duration) -> usize {
+        let started = Instant::now();
+        let count = self.dbs.len();
+        let mut deleted = 0;
+        for i in 0..count {
+            let index = (self.next_expire_db + i) % count;
+            let remaining = time_limit.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                self.next_expire_db = index;
+                return deleted;
+            }
+            deleted += self.dbs[index].active_expire_cycle(remaining);
+        }
+        deleted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::expire::unix_time_ms;
+
+    #[test]
+    fn move_copy_and_swap_between_databases() {
+        let mut dbs = Databases::new(3, EvictionSettings::default());
+        dbs.db(0).set(b"a", b"1".to_vec());
+        let at = unix_time_ms() + 60_000;
+        dbs.db(0).set_expire(b"a", at);
+        dbs.db(1).set(b"b", b"2".to_vec());
+
+        assert!(dbs.move_key(b"a", 0, 1));
+        assert!(!dbs.db(0).exists(b"a"));
+        assert_eq!(dbs.db(1).expires.get(&b"a"[..]), Some(&at));
+        assert!(!dbs.move_key(b"a", 0, 1));
+        dbs.db(0).set(b"b", b"other".to_vec());
+        assert!(!dbs.move_key(b"b", 0, 1));
+
+        assert!(dbs.copy(b"a", 1, b"c", 2, false));
+        assert_eq!(dbs.db(2).expires.get(&b"c"[..]), Some(&at));
+        assert!(!dbs.copy(b"b", 1, b"b", 0, false));
+        assert!(dbs.copy(b"b", 1, b"b", 0, true));
+        assert_eq!(dbs.db(0).get(b"b"), Ok(Some(b"2".to_vec())));
+
+        dbs.swap(0, 2);
+        assert!(dbs.db(0).exists(b"c"));
+        assert!(dbs.db(2).exists(b"b"));
+    }
+
+    #[test]
+    fn flush_one_or_all() {
+        let mut dbs = Databases::new(2, EvictionSettings::default());
+        dbs.db(0).set(b"a", b"1".to_vec());
+        dbs.db(1).set(b"a", b"1".to_vec());
+        let _ = dbs.take_modified();
+        dbs.flush(Some(1), false);
+        assert!(dbs.take_flushed());
+        assert!(!dbs.take_flushed());
+        assert_eq!((dbs.db(0).map.len(), dbs.db(1).map.len()), (1, 0));
+        dbs.flush(None, true);
+        assert_eq!(dbs.used_memory(), 0);
+        assert!(dbs.take_modified().is_empty());
+    }
+
+    #[test]
+    fn expire_cycle_covers_every_database() {
+        let mut dbs = Databases::new(3, EvictionSettings::default());
+        for db in 0..3 {
+            dbs.db(db).set(b"k", b"v".to_vec());
+            dbs.db(db).expires.insert(b"k".to_vec(), 1);
+        }
+        assert_eq!(dbs.active_expire_cycle(Duration::from_secs(1)), 3);
+        assert_eq!(dbs.total(|db| db.expired_keys), 3);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::store::value::{Entry, Key, MaxmemoryPolicy, Store};
+
+//Counter given to new keys, so they are not evicted before having a chance to be accessed
+pub const LFU_INIT_VAL: u8 = 5;
+//Candidates kept between eviction rounds, as in Redis
+const EVICTION_POOL_SIZE: usize = 16;
+
+///Advances a xorshift64 generator, cheap and roughly uniform, which is all eviction, sampling and
+///tests need. The state must not be zero
+pub fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+impl MaxmemoryPolicy {
+    pub const ALL: [MaxmemoryPolicy; 8] = [
+        MaxmemoryPolicy::VolatileLru,
+        MaxmemoryPolicy::VolatileLfu,
+        MaxmemoryPolicy::VolatileRandom,
+        MaxmemoryPolicy::VolatileTtl,
+        MaxmemoryPolicy::AllKeysLru,
+        MaxmemoryPolicy::AllKeysLfu,
+        MaxmemoryPolicy::AllKeysRandom,
+        MaxmemoryPolicy::NoEviction,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MaxmemoryPolicy::NoEviction => "noeviction",
+            MaxmemoryPolicy::AllKeysLru => "allkeys-lru",
+            MaxmemoryPolicy::AllKeysLfu => "allkeys-lfu",
+            MaxmemoryPolicy::AllKeysRandom => "allkeys-random",
+            MaxmemoryPolicy::VolatileLru => "volatile-lru",
+            MaxmemoryPolicy::VolatileLfu => "volatile-lfu",
+            MaxmemoryPolicy::VolatileRandom => "volatile-random",
+            MaxmemoryPolicy::VolatileTtl => "volatile-ttl",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|p| p.name().eq_ignore_ascii_case(name))
+    }
+
+    ///Whether only keys with an expiry can be evicted
+    pub fn is_volatile(&self) -> bool {
+        matches!(self, MaxmemoryPolicy::VolatileLru | MaxmemoryPolicy::VolatileLfu | MaxmemoryPolicy::VolatileRandom | MaxmemoryPolicy::VolatileTtl)
+    }
+
+    pub fn is_lfu(&self) -> bool {
+        matches!(self, MaxmemoryPolicy::AllKeysLfu | MaxmemoryPolicy::VolatileLfu)
+    }
+}
+
+///Counter of an entry once the periods it went without access are taken off
+fn lfu_decayed(entry: &Entry, now_minutes: u64, decay_time: u64) -> u8 {
+    let periods = match decay_time {
+        0 => 0,
+        decay_time => now_minutes.saturating_sub(entry.lfu_decrement_time) / decay_time
+    };
+    entry.lfu_counter.saturating_sub(periods.min(u8::MAX as u64) as u8)
+}
+
+///Increments the counter with a probability that drops as it grows, so 8 bits cover millions
+///of accesses
+fn lfu_log_incr(counter: u8, log_factor: u32, random: f64) -> u8 {
+    if counter == u8::MAX {
+        return counter;
+    }
+    let base = counter.saturating_sub(LFU_INIT_VAL) as f64;
+    match random < 1.0 / (base * log_factor as f64 + 1.0) {
+        true => counter + 1,
+        false => counter
+    }
+}
+
+impl Store {
+    pub fn now_ms(&self) -> u64 {
+        self.created.elapsed().as_millis() as u64
+    }
+
+    pub fn next_random(&mut self) -> u64 {
+        xorshift(&mut self.rng)
+    }
+
+    fn random_f64(&mut self) -> f64 {
+        (self.next_random() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    ///Updates the LRU and LFU metadata of a key on access
+    pub fn touch(&mut self, key: &[u8]) {
+        let now = self.now_ms();
+        let random = self.random_f64();
+        let settings = self.eviction;
+        if let Some(entry) = self.map.get_mut(key) {
+            let counter = lfu_decayed(entry, now / 60_000, settings.lfu_decay_time);
+            entry.lfu_counter = lfu_log_incr(counter, settings.lfu_log_factor, random);
+            entry.lfu_decrement_time = now / 60_000;
+            entry.last_access = now;
+        }
+    }
+
+    ///LFU counter of a key, without counting this lookup as an access
+    pub fn lfu_frequency(&self, key: &[u8]) -> Option<u8> {
+        let entry = self.map.get(key)?;
+        Some(lfu_decayed(entry, self.now_ms() / 60_000, self.eviction.lfu_decay_time))
+    }
+
+    pub fn idle_time_ms(&self, key: &[u8]) -> Option<u64> {
+        Some(self.now_ms().saturating_sub(self.map.get(key)?.last_access))
+    }
+
+    pub fn random_key(&mut self, volatile: bool) -> Option<Key> {
+        let len = if volatile { self.expires.len() } else { self.map.len() };
+        if len == 0 {
+            return None;
+        }
+        let index = (self.next_random() % len as u64) as usize;
+        let key = match volatile {
+            true => self.expires.get_index(index).map(|(k, _)| k),
+            false => self.map.get_index(index).map(|(k, _)| k)
+        };
+        key.cloned()
+    }
+
+    ///Higher scores are better candidates for eviction
+    fn eviction_score(&self, key: &[u8]) -> Option<u64> {
+        match self.eviction.policy {
+            MaxmemoryPolicy::AllKeysLfu | MaxmemoryPolicy::VolatileLfu => Some(u8::MAX as u64 - self.lfu_frequency(key)? as u64),
+            MaxmemoryPolicy::VolatileTtl => Some(u64::MAX - self.expires.get(key)?),
+            _ => self.idle_time_ms(key)
+        }
+    }
+
+    ///Samples keys into the pool, which keeps the best candidates seen across rounds
+    fn populate_eviction_pool(&mut self, volatile: bool) {
+        for _ in 0..self.eviction.samples.max(1) {
+            let Some(key) = self.random_key(volatile) else { break };
+            let Some(score) = self.eviction_score(&key) else { continue };
+            if self.eviction_pool.iter().any(|(_, k)| *k == key) {
+                continue;
+            }
+            if self.eviction_pool.len() == EVICTION_POOL_SIZE {
+                if score <= self.eviction_pool[0].0 {
+                    continue;
+                }
+                self.eviction_pool.remove(0);
+            }
+            let position = self.eviction_pool.partition_point(|(s, _)| *s < score);
+            self.eviction_pool.insert(position, (score, key));
+        }
+    }
+
+    ///Best key to evict under a sampling policy with its score, left in the pool until the
+    ///caller picks it, so candidates of several databases can be compared
+    pub fn eviction_candidate(&mut self) -> Option<(u64, Key)> {
+        let volatile = self.eviction.policy.is_volatile();
+        loop {
+            if (volatile && self.expires.is_empty()) || self.map.is_empty() {
+                return None;
+            }
+            self.populate_eviction_pool(volatile);
+            //Candidates may have been deleted since they were sampled
+            while let Some((_, key)) = self.eviction_pool.last() {
+                if self.map.contains_key(key) && (!volatile || self.expires.contains_key(key)) {
+                    return self.eviction_pool.last().cloned();
+                }
+                self.eviction_pool.pop();
+            }
+        }
+    }
+
+    ///Deletes a key to free memory. Returns false if it was deleted since it was picked
+    pub fn evict(&mut self, key: &[u8]) -> bool {
+        self.eviction_pool.retain(|(_, k)| k != key);
+        let evicted = self.remove(key).is_some();
+        self.evicted_keys += evicted as u64;
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::value::Value;
+
+    #[test]
+    fn policy_names() {
+        for policy in MaxmemoryPolicy::ALL {
+            assert_eq!(MaxmemoryPolicy::from_name(&policy.name().to_uppercase()), Some(policy));
+        }
+        assert_eq!(MaxmemoryPolicy::from_name("lru"), None);
+    }
+
+    #[test]
+    fn lfu_counter_grows_logarithmically_and_decays() {
+        assert_eq!(lfu_log_incr(LFU_INIT_VAL, 10, 0.99), LFU_INIT_VAL + 1);
+        assert_eq!(lfu_log_incr(105, 10, 0.01), 105);
+        assert_eq!(lfu_log_incr(255, 10, 0.0), 255);
+        let entry = Entry { value: Value::String(b"v".to_vec()), last_access: 0, lfu_counter: 10, lfu_decrement_time: 5 };
+        assert_eq!(lfu_decayed(&entry, 8, 1), 7);
+        assert_eq!(lfu_decayed(&entry, 8, 0), 10);
+        assert_eq!(lfu_decayed(&entry, 1000, 1), 0);
+    }
+}
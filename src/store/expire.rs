@@ -0,0 +1,149 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::store::value::Store;
+
+//Keys sampled per round of the active expire cycle, as in Redis
+const EXPIRE_CYCLE_SAMPLES: usize = 20;
+//Another round runs while more than this percentage of the sample had expired
+const EXPIRE_CYCLE_ACCEPTABLE_STALE: usize = 10;
+
+pub fn unix_time_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+impl Store {
+    ///Deletes the key if its time to live has passed, returns whether it did
+    pub fn expire_if_needed(&mut self, key: &[u8]) -> bool {
+        match self.expires.get(key) {
+            Some(&at) if at <= unix_time_ms() => {
+                self.remove(key);
+                self.expired_keys += 1;
+                true
+            },
+            _ => false
+        }
+    }
+
+    ///Sets the unix time in milliseconds at which an existing key expires. A time in the past
+    ///deletes the key right away
+    pub fn set_expire(&mut self, key: &[u8], at: u64) {
+        if !self.map.contains_key(key) {
+            return;
+        }
+        self.expires.insert(key.to_vec(), at);
+        self.expire_if_needed(key);
+    }
+
+    ///Removes the time to live of a key, returns whether it had one
+    pub fn persist(&mut self, key: &[u8]) -> bool {
+        self.expires.swap_remove(key).is_some()
+    }
+
+    ///Milliseconds left before the key expires, None for keys without a time to live
+    pub fn ttl_ms(&self, key: &[u8]) -> Option<u64> {
+        self.expires.get(key).map(|at| at.saturating_sub(unix_time_ms()))
+    }
+
+    ///Deletes expired keys nobody accessed, sampling volatile keys until few of them turn out
+    ///to be expired or the time limit is reached. Returns the number of keys deleted
+    pub fn active_expire_cycle(&mut self, time_limit: Duration) -> usize {
+        let started = Instant::now();
+        let mut deleted = 0;
+        if self.expires.is_empty() {
+            self.avg_ttl = 0;
+        }
+        loop {
+            let samples = EXPIRE_CYCLE_SAMPLES.min(self.expires.len());
+            let now = unix_time_ms();
+            let (mut expired, mut ttl_sum, mut ttl_samples) = (0, 0, 0);
+            for _ in 0..samples {
+                let Some(key) = self.random_key(true) else { continue };
+                if self.expire_if_needed(&key) {
+                    expired += 1;
+                } else if let Some(at) = self.expires.get(&key) {
+                    ttl_sum += at.saturating_sub(now);
+                    ttl_samples += 1;
+                }
+            }
+            //Each round moves the average a fiftieth of the way, as in Redis
+            if let Some(sampled) = ttl_sum.checked_div(ttl_samples) {
+                self.avg_ttl = match self.avg_ttl {
+                    0 => sampled,
+                    average => average / 50 * 49 + sampled / 50
+                };
+            }
+            deleted += expired;
+            if samples == 0 || expired * 100 <= samples * EXPIRE_CYCLE_ACCEPTABLE_STALE || started.elapsed() >= time_limit {
+                return deleted;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expired_keys_are_deleted_on_access() {
+        let mut store = Store::new();
+        store.set(b"k", b"v".to_vec());
+        store.set_expire(b"k", unix_time_ms() + 60_000);
+        assert!(store.ttl_ms(b"k").is_some_and(|ttl| ttl > 59_000));
+        assert_eq!(store.get(b"k"), Ok(Some(b"v".to_vec())));
+
+        store.expires.insert(b"k".to_vec(), unix_time_ms() - 1);
+        let _ = store.take_modified();
+        assert_eq!(store.get(b"k"), Ok(None));
+        assert_eq!((store.map.len(), store.expires.len(), store.expired_keys), (0, 0, 1));
+        //Clients caching the key are told it is gone
+        assert_eq!(store.take_modified(), vec![b"k".to_vec()]);
+    }
+
+    #[test]
+    fn expire_in_the_past_deletes_and_persist_clears() {
+        let mut store = Store::new();
+        store.set(b"a", b"v".to_vec());
+        store.set(b"b", b"v".to_vec());
+        store.set_expire(b"a", 1);
+        assert!(!store.map.contains_key(&b"a"[..]));
+        store.set_expire(b"b", unix_time_ms() + 60_000);
+        assert!(store.persist(b"b"));
+        assert!(!store.persist(b"b"));
+        assert_eq!(store.ttl_ms(b"b"), None);
+    }
+
+    #[test]
+    fn active_cycle_deletes_expired_keys() {
+        let mut store = Store::new();
+        for i in 0..200 {
+            let key = format!("key:{}", i).into_bytes();
+            store.set(&key, b"v".to_vec());
+            let at = if i < 150 { 1 } else { unix_time_ms() + 60_000 };
+            store.expires.insert(key, at);
+        }
+        let deleted = store.active_expire_cycle(Duration::from_secs(1));
+        assert!(deleted > 100, "{}", deleted);
+        assert_eq!(store.map.len(), 200 - deleted);
+        assert!(store.expires.keys().filter(|k| store.expires[*k] == 1).count() <= 150 - deleted);
+    }
+
+    #[test]
+    fn active_cycle_estimates_the_average_ttl() {
+        let mut store = Store::new();
+        store.active_expire_cycle(Duration::from_secs(1));
+        assert_eq!(store.avg_ttl, 0);
+        for i in 0..100 {
+            let key = format!("key:{}", i).into_bytes();
+            store.set(&key, b"v".to_vec());
+            store.set_expire(&key, unix_time_ms() + 60_000 + i * 1000);
+        }
+        store.active_expire_cycle(Duration::from_secs(1));
+        assert!((59_000..=160_000).contains(&store.avg_ttl), "{}", store.avg_ttl);
+        for i in 0..100 {
+            store.persist(format!("key:{}", i).as_bytes());
+        }
+        store.active_expire_cycle(Duration::from_secs(1));
+        assert_eq!(store.avg_ttl, 0);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::store::{scan::{scan_buckets, scan_position}, value::{FieldValue, HashValue}};
+
+impl HashValue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = FieldValue<'_>> {
+        self.fields.iter().map(|(field, value)| (field.as_slice(), value.as_slice()))
+    }
+
+    ///Sets a field, returning its previous value
+    pub fn insert(&mut self, field: Vec<u8>, value: Vec<u8>) -> Option<Vec<u8>> {
+        if !self.fields.contains_key(&field) {
+            self.scan_index.entry(scan_position(&field)).or_default().push(field.clone());
+        }
+        self.fields.insert(field, value)
+    }
+
+    ///Fields from the cursor on with their values, at least `count` of them unless the scan
+    ///ends, and the cursor to continue from or 0 once every field was visited
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<FieldValue<'_>>) {
+        let (next, fields) = scan_buckets(&self.scan_index, cursor, count);
+        (next, fields.into_iter().filter_map(|field| Some((field.as_slice(), self.fields.get(field)?.as_slice()))).collect())
+    }
+}
+
+impl FromIterator<(Vec<u8>, Vec<u8>)> for HashValue {
+    fn from_iter<I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>>(pairs: I) -> Self {
+        let mut hash = HashValue::new();
+        for (field, value) in pairs {
+            hash.insert(field, value);
+        }
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn fields_are_paged_by_position() {
+        let mut hash: HashValue = (0..50).map(|i| (format!("field:{}", i).into_bytes(), b"v".to_vec())).collect();
+        assert_eq!(hash.insert(b"field:0".to_vec(), b"w".to_vec()), Some(b"v".to_vec()));
+        assert_eq!(hash.scan_index.values().map(Vec::len).sum::<usize>(), 50);
+        let (mut cursor, mut seen) = (0, Vec::new());
+        loop {
+            let (next, batch) = hash.scan(cursor, 7);
+            assert!(batch.len() >= 7 || next == 0);
+            seen.extend(batch.into_iter().map(|(field, _)| field.to_vec()));
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(seen.len(), 50);
+        assert_eq!(seen.into_iter().collect::<HashSet<_>>().len(), 50);
+    }
+}
@@ -0,0 +1,635 @@
+//HyperLogLog stored in a string, with the layout Redis uses so values move between the two:
+//a 16 byte header ("HYLL", the encoding, 3 unused bytes and the cached cardinality) followed
+//by 2^14 registers of 6 bits, either packed (dense) or run length encoded (sparse)
+
+use std::borrow::Cow;
+
+use crate::store::{eviction::xorshift, string::string_bytes, value::{Store, StoreError, Value}};
+
+//Bits of the hash selecting the register
+const HLL_P: u32 = 14;
+pub const HLL_REGISTERS: usize = 1 << HLL_P;
+//Bits of the hash whose run of zeros goes in the register
+const HLL_Q: u32 = 64 - HLL_P;
+const HLL_BITS: usize = 6;
+const HLL_REGISTER_MAX: u8 = (1 << HLL_BITS) - 1;
+const HLL_HDR_SIZE: usize = 16;
+pub const HLL_DENSE_SIZE: usize = HLL_HDR_SIZE + (HLL_REGISTERS * HLL_BITS).div_ceil(8);
+const HLL_DENSE: u8 = 0;
+const HLL_SPARSE: u8 = 1;
+//Largest register a sparse VAL opcode holds, and how many registers it repeats it for
+const HLL_SPARSE_VAL_MAX_VALUE: u8 = 32;
+const HLL_SPARSE_VAL_MAX_LEN: usize = 4;
+const HLL_SPARSE_ZERO_MAX_LEN: usize = 64;
+const HLL_SPARSE_XZERO_MAX_LEN: usize = 16384;
+//Size past which a sparse HyperLogLog turns dense, as hll-sparse-max-bytes in Redis
+pub const HLL_SPARSE_MAX_BYTES: usize = 3000;
+const HLL_ALPHA_INF: f64 = 0.5 / std::f64::consts::LN_2;
+//Seed Redis hashes elements with, for registers to match
+const HLL_HASH_SEED: u64 = 0xadc83b19;
+
+///A sparse opcode: ZERO and XZERO are runs of empty registers up to 64 and 16384 long, VAL a
+///run of up to 4 registers holding the same value
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SparseRun {
+    Zero(usize),
+    XZero(usize),
+    Val(u8, usize),
+}
+
+///MurmurHash2, 64 bit version by Austin Appleby, reading blocks as little endian
+pub fn murmurhash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(M);
+    let mut blocks = data.chunks_exact(8);
+    for block in &mut blocks {
+        let mut k = u64::from_le_bytes(block.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = blocks.remainder();
+    if !tail.is_empty() {
+        for (i, &byte) in tail.iter().enumerate() {
+            h ^= (byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+///Register an element goes to, and the value it sets there: the length of the run of zeros
+///in the rest of its hash, plus one
+fn pattern(element: &[u8]) -> (usize, u8) {
+    let hash = murmurhash64a(element, HLL_HASH_SEED);
+    let index = (hash & (HLL_REGISTERS as u64 - 1)) as usize;
+    //The bit past the Q bits ends the run when they are all zero
+    let rest = hash >> HLL_P | 1 << HLL_Q;
+    (index, rest.trailing_zeros() as u8 + 1)
+}
+
+fn dense_get(registers: &[u8], index: usize) -> u8 {
+    let bit = index * HLL_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    let next = registers.get(byte + 1).copied().unwrap_or(0) as u16;
+    ((registers[byte] as u16 >> shift | next << (8 - shift)) as u8) & HLL_REGISTER_MAX
+}
+
+fn dense_set(registers: &mut [u8], index: usize, value: u8) {
+    let bit = index * HLL_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    let (value, max) = (value as u16, HLL_REGISTER_MAX as u16);
+    registers[byte] = (registers[byte] as u16 & !(max << shift) | value << shift) as u8;
+    //The last register ends with its byte
+    if let Some(next) = registers.get_mut(byte + 1) {
+        *next = (*next as u16 & !(max >> (8 - shift)) | value >> (8 - shift)) as u8;
+    }
+}
+
+///Opcodes of a sparse HyperLogLog, None when the last one is cut short
+fn sparse_runs(ops: &[u8]) -> Option<Vec<SparseRun>> {
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < ops.len() {
+        let op = ops[i];
+        runs.push(match op & 0xC0 {
+            0x00 => SparseRun::Zero((op & 0x3F) as usize + 1),
+            0x40 => {
+                i += 1;
+                SparseRun::XZero((((op & 0x3F) as usize) << 8 | *ops.get(i)? as usize) + 1)
+            },
+            _ => SparseRun::Val((op >> 2 & 0x1F) + 1, (op & 0x03) as usize + 1)
+        });
+        i += 1;
+    }
+    Some(runs)
+}
+
+///Registers of a sparse HyperLogLog, None unless its runs cover every register exactly
+fn sparse_decode(ops: &[u8]) -> Option<Vec<u8>> {
+    let mut registers = Vec::with_capacity(HLL_REGISTERS);
+    for run in sparse_runs(ops)? {
+        let (value, len) = match run {
+            SparseRun::Zero(len) | SparseRun::XZero(len) => (0, len),
+            SparseRun::Val(value, len) => (value, len)
+        };
+        if registers.len() + len > HLL_REGISTERS {
+            return None;
+        }
+        registers.resize(registers.len() + len, value);
+    }
+    (registers.len() == HLL_REGISTERS).then_some(registers)
+}
+
+///Sparse opcodes of registers that all fit in a VAL opcode
+fn sparse_encode(registers: &[u8]) -> Vec<u8> {
+    let mut ops = Vec::new();
+    let mut i = 0;
+    while i < registers.len() {
+        let value = registers[i];
+        let run = registers[i..].iter().take_while(|&&r| r == value).count();
+        let mut left = run;
+        while left > 0 {
+            let len = match value {
+                0 if left > HLL_SPARSE_ZERO_MAX_LEN => {
+                    let len = left.min(HLL_SPARSE_XZERO_MAX_LEN);
+                    ops.extend([0x40 | ((len - 1) >> 8) as u8, (len - 1) as u8]);
+                    len
+                },
+                0 => {
+                    ops.push((left - 1) as u8);
+                    left
+                },
+                _ => {
+                    let len = left.min(HLL_SPARSE_VAL_MAX_LEN);
+                    ops.push(0x80 | (value - 1) << 2 | (len - 1) as u8);
+                    len
+                }
+            };
+            left -= len;
+        }
+        i += run;
+    }
+    ops
+}
+
+fn header(encoding: u8) -> Vec<u8> {
+    let mut bytes = b"HYLL".to_vec();
+    bytes.push(encoding);
+    bytes.resize(HLL_HDR_SIZE, 0);
+    bytes
+}
+
+///A HyperLogLog with every register empty, sparse and with a cached cardinality of 0
+fn empty() -> Vec<u8> {
+    let mut bytes = header(HLL_SPARSE);
+    bytes.extend(sparse_encode(&[0; HLL_REGISTERS]));
+    bytes
+}
+
+///Whether a string holds a HyperLogLog, checking its header only
+pub fn is_hll(bytes: &[u8]) -> bool {
+    bytes.len() >= HLL_HDR_SIZE && bytes.starts_with(b"HYLL")
+        && (bytes[4] == HLL_SPARSE || bytes[4] == HLL_DENSE && bytes.len() == HLL_DENSE_SIZE)
+}
+
+fn hll_bytes(value: &Value) -> Result<Cow<'_, [u8]>, StoreError> {
+    let bytes = string_bytes(value)?;
+    match is_hll(&bytes) {
+        true => Ok(bytes),
+        false => Err(StoreError::InvalidHll)
+    }
+}
+
+///One byte per register, whatever the encoding
+fn registers(bytes: &[u8]) -> Result<Vec<u8>, StoreError> {
+    match bytes[4] {
+        HLL_DENSE => Ok((0..HLL_REGISTERS).map(|i| dense_get(&bytes[HLL_HDR_SIZE..], i)).collect()),
+        _ => sparse_decode(&bytes[HLL_HDR_SIZE..]).ok_or(StoreError::CorruptHll)
+    }
+}
+
+///Builds a HyperLogLog from its registers, keeping the header of `bytes`. It is sparse when
+///`sparse` allows it and the registers fit in a sparse encoding no larger than the limit
+fn encode(bytes: &[u8], registers: &[u8], sparse: bool) -> Vec<u8> {
+    let mut encoded = bytes[..HLL_HDR_SIZE].to_vec();
+    if sparse && registers.iter().all(|&r| r <= HLL_SPARSE_VAL_MAX_VALUE) {
+        let ops = sparse_encode(registers);
+        if HLL_HDR_SIZE + ops.len() <= HLL_SPARSE_MAX_BYTES {
+            encoded[4] = HLL_SPARSE;
+            encoded.extend(ops);
+            return encoded;
+        }
+    }
+    encoded[4] = HLL_DENSE;
+    encoded.resize(HLL_DENSE_SIZE, 0);
+    for (i, &register) in registers.iter().enumerate() {
+        dense_set(&mut encoded[HLL_HDR_SIZE..], i, register);
+    }
+    encoded
+}
+
+///Cached cardinality, None once a change invalidated it
+fn cached_cardinality(bytes: &[u8]) -> Option<u64> {
+    (bytes[15] & 0x80 == 0).then(|| u64::from_le_bytes(bytes[8..16].try_into().unwrap()))
+}
+
+fn set_cached_cardinality(bytes: &mut [u8], cardinality: u64) {
+    bytes[8..16].copy_from_slice(&cardinality.to_le_bytes());
+}
+
+fn invalidate_cache(bytes: &mut [u8]) {
+    bytes[15] |= 0x80;
+}
+
+///Adds elements to a HyperLogLog, returning whether any register changed
+fn add<'a>(bytes: &mut Vec<u8>, elements: impl IntoIterator<Item = &'a [u8]>) -> Result<bool, StoreError> {
+    let mut changed = false;
+    match bytes[4] {
+        HLL_DENSE => for element in elements {
+            let (index, count) = pattern(element);
+            if dense_get(&bytes[HLL_HDR_SIZE..], index) < count {
+                dense_set(&mut bytes[HLL_HDR_SIZE..], index, count);
+                changed = true;
+            }
+        },
+        _ => {
+            let mut registers = registers(bytes)?;
+            for element in elements {
+                let (index, count) = pattern(element);
+                if registers[index] < count {
+                    registers[index] = count;
+                    changed = true;
+                }
+            }
+            if changed {
+                *bytes = encode(bytes, &registers, true);
+            }
+        }
+    }
+    if changed {
+        invalidate_cache(bytes);
+    }
+    Ok(changed)
+}
+
+//Corrections of the estimator by Otmar Ertl, for registers at 0 and at the largest value
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let (mut y, mut z) = (1.0, 1.0 - x);
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if z == previous {
+            return z / 3.0;
+        }
+    }
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let (mut y, mut z) = (1.0, x);
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if z == previous {
+            return z;
+        }
+    }
+}
+
+///Estimated cardinality from the histogram of the registers, as Redis computes it
+pub fn count(registers: &[u8]) -> u64 {
+    let m = HLL_REGISTERS as f64;
+    let mut histogram = [0u32; 64];
+    for &register in registers {
+        histogram[register as usize] += 1;
+    }
+    let q = HLL_Q as usize;
+    let mut z = m * tau((m - histogram[q + 1] as f64) / m);
+    for j in (1..=q).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (HLL_ALPHA_INF * m * m / z).round() as u64
+}
+
+///Checks register access and the error of the estimates. Registers are written and read back
+///`register_rounds` times, then `elements` distinct elements are added to a sparse and a dense
+///HyperLogLog, comparing their estimates at every power of ten
+pub fn selftest(seed: u64, register_rounds: usize, elements: u64) -> Result<(), String> {
+    let mut state = seed | 1;
+    let mut random = move || xorshift(&mut state);
+    let mut dense = vec![0; HLL_DENSE_SIZE - HLL_HDR_SIZE];
+    let mut expected = vec![0; HLL_REGISTERS];
+    for _ in 0..register_rounds {
+        for (i, value) in expected.iter_mut().enumerate() {
+            *value = random() as u8 & HLL_REGISTER_MAX;
+            dense_set(&mut dense, i, *value);
+        }
+        if let Some(i) = (0..HLL_REGISTERS).find(|&i| dense_get(&dense, i) != expected[i]) {
+            return Err(format!("TESTFAILED Register error at {}", i));
+        }
+    }
+
+    let mut dense = encode(&header(HLL_DENSE), &[0; HLL_REGISTERS], false);
+    let mut sparse = empty();
+    let corrupt = |_| "TESTFAILED corrupted HyperLogLog".to_string();
+    let relative_error = 1.04 / (HLL_REGISTERS as f64).sqrt();
+    let salt = random();
+    let mut checkpoint = 1;
+    for j in 1..=elements {
+        let element = (j ^ salt).to_le_bytes();
+        add(&mut dense, [&element[..]]).map_err(corrupt)?;
+        add(&mut sparse, [&element[..]]).map_err(corrupt)?;
+        if j != checkpoint {
+            continue;
+        }
+        if (j as usize) < HLL_SPARSE_MAX_BYTES / 2 && sparse[4] != HLL_SPARSE {
+            return Err("TESTFAILED sparse encoding not used".to_string());
+        }
+        let estimate = count(&registers(&dense).map_err(corrupt)?);
+        if estimate != count(&registers(&sparse).map_err(corrupt)?) {
+            return Err("TESTFAILED dense/sparse disagree".to_string());
+        }
+        //A few times the standard error, so that a run failing by chance is very unlikely
+        let error = estimate.abs_diff(j);
+        if error > (relative_error * 6.0 * j as f64).ceil() as u64 {
+            return Err(format!("TESTFAILED Too big error. card:{} abserr:{}", j, error));
+        }
+        checkpoint *= 10;
+    }
+    Ok(())
+}
+
+impl Store {
+    ///Adds elements to the HyperLogLog at `key`, creating it if needed. Returns whether it
+    ///was created or any register changed
+    pub fn pfadd(&mut self, key: &[u8], elements: &[&[u8]]) -> Result<bool, StoreError> {
+        let (mut bytes, created) = match self.lookup_write(key) {
+            None => (empty(), true),
+            Some(value) => (hll_bytes(value)?.into_owned(), false)
+        };
+        let changed = add(&mut bytes, elements.iter().copied())?;
+        if created || changed {
+            self.write(key, Value::String(bytes));
+        }
+        Ok(created || changed)
+    }
+
+    ///Estimated cardinality of the union of the HyperLogLogs at `keys`, missing keys counting
+    ///as empty. A single key has its estimate cached in its header
+    pub fn pfcount(&mut self, keys: &[&[u8]]) -> Result<u64, StoreError> {
+        if let [key] = keys {
+            let mut bytes = match self.lookup_read(key) {
+                None => return Ok(0),
+                Some(value) => hll_bytes(value)?.into_owned()
+            };
+            if let Some(cardinality) = cached_cardinality(&bytes) {
+                return Ok(cardinality);
+            }
+            let cardinality = count(&registers(&bytes)?);
+            set_cached_cardinality(&mut bytes, cardinality);
+            self.write(key, Value::String(bytes));
+            return Ok(cardinality);
+        }
+        let mut union = vec![0; HLL_REGISTERS];
+        for key in keys {
+            if let Some(value) = self.lookup_read(key) {
+                merge_into(&mut union, &registers(&hll_bytes(value)?)?);
+            }
+        }
+        Ok(count(&union))
+    }
+
+    ///Stores the union of `destination` and the sources in `destination`. It stays sparse
+    ///unless one of them is dense or the union does not fit
+    pub fn pfmerge(&mut self, destination: &[u8], sources: &[&[u8]]) -> Result<(), StoreError> {
+        let mut union = vec![0; HLL_REGISTERS];
+        let mut dense = false;
+        for key in std::iter::once(destination).chain(sources.iter().copied()) {
+            if let Some(value) = self.lookup_read(key) {
+                let bytes = hll_bytes(value)?;
+                dense |= bytes[4] == HLL_DENSE;
+                merge_into(&mut union, &registers(&bytes)?);
+            }
+        }
+        let current = match self.lookup_write(destination) {
+            None => empty(),
+            Some(value) => hll_bytes(value)?.into_owned()
+        };
+        let mut bytes = encode(&current, &union, !dense);
+        invalidate_cache(&mut bytes);
+        self.write(destination, Value::String(bytes));
+        Ok(())
+    }
+
+    ///The HyperLogLog at `key` for PFDEBUG, which fails with NotFound when it is missing
+    fn existing_hll(&mut self, key: &[u8]) -> Result<Vec<u8>, StoreError> {
+        match self.lookup_write(key) {
+            None => Err(StoreError::NotFound),
+            Some(value) => Ok(hll_bytes(value)?.into_owned())
+        }
+    }
+
+    ///Converts a sparse HyperLogLog to dense, returning whether it was sparse
+    pub fn hll_to_dense(&mut self, key: &[u8]) -> Result<bool, StoreError> {
+        let bytes = self.existing_hll(key)?;
+        if bytes[4] == HLL_DENSE {
+            return Ok(false);
+        }
+        let registers = registers(&bytes)?;
+        self.write(key, Value::String(encode(&bytes, &registers, false)));
+        Ok(true)
+    }
+
+    ///Registers of a HyperLogLog, which is converted to dense first as Redis does
+    pub fn hll_registers(&mut self, key: &[u8]) -> Result<Vec<u8>, StoreError> {
+        self.hll_to_dense(key)?;
+        registers(&self.existing_hll(key)?)
+    }
+
+    pub fn hll_encoding(&mut self, key: &[u8]) -> Result<&'static str, StoreError> {
+        match self.existing_hll(key)?[4] {
+            HLL_DENSE => Ok("dense"),
+            _ => Ok("sparse")
+        }
+    }
+
+    ///Opcodes of a sparse HyperLogLog as text, e.g. `Z:100 v:3,1 z:2`, None when it is dense
+    pub fn hll_decode(&mut self, key: &[u8]) -> Result<Option<String>, StoreError> {
+        let bytes = self.existing_hll(key)?;
+        if bytes[4] == HLL_DENSE {
+            return Ok(None);
+        }
+        let runs = sparse_runs(&bytes[HLL_HDR_SIZE..]).ok_or(StoreError::CorruptHll)?;
+        Ok(Some(runs.iter().map(|run| match run {
+            SparseRun::Zero(len) => format!("z:{}", len),
+            SparseRun::XZero(len) => format!("Z:{}", len),
+            SparseRun::Val(value, len) => format!("v:{},{}", value, len)
+        }).collect::<Vec<_>>().join(" ")))
+    }
+}
+
+fn merge_into(union: &mut [u8], registers: &[u8]) {
+    for (max, &register) in union.iter_mut().zip(registers) {
+        *max = (*max).max(register);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elements(range: std::ops::Range<u32>) -> Vec<Vec<u8>> {
+        range.map(|i| format!("element:{}", i).into_bytes()).collect()
+    }
+
+    fn pfadd(store: &mut Store, key: &[u8], elements: &[Vec<u8>]) -> bool {
+        let elements: Vec<&[u8]> = elements.iter().map(Vec::as_slice).collect();
+        store.pfadd(key, &elements).unwrap()
+    }
+
+    fn stored(store: &Store, key: &[u8]) -> Vec<u8> {
+        match &store.map[key].value {
+            Value::String(bytes) => bytes.clone(),
+            value => panic!("{:?}", value)
+        }
+    }
+
+    #[test]
+    fn murmurhash_matches_reference() {
+        assert_eq!(murmurhash64a(b"", 0), 0);
+        assert_eq!(murmurhash64a(b"hello", 0), 0x1e68d17c457bf117);
+        assert_eq!(murmurhash64a(b"hello world!", HLL_HASH_SEED), 0x0fc444011f57220c);
+        assert_ne!(murmurhash64a(b"a", HLL_HASH_SEED), murmurhash64a(b"b", HLL_HASH_SEED));
+    }
+
+    #[test]
+    fn dense_registers_pack_six_bits() {
+        let mut registers = vec![0; HLL_DENSE_SIZE - HLL_HDR_SIZE];
+        for i in 0..HLL_REGISTERS {
+            dense_set(&mut registers, i, (i % 64) as u8);
+        }
+        assert!((0..HLL_REGISTERS).all(|i| dense_get(&registers, i) == (i % 64) as u8));
+        //Register 1 straddles the first two bytes
+        assert_eq!(&registers[..3], &[0b0100_0000, 0b0010_0000, 0b0000_1100]);
+    }
+
+    #[test]
+    fn sparse_encoding_round_trips() {
+        assert_eq!(empty(), b"HYLL\x01\0\0\0\0\0\0\0\0\0\0\0\x7f\xff");
+        let mut registers = vec![0; HLL_REGISTERS];
+        registers[0] = 3;
+        registers[100..106].fill(32);
+        registers[HLL_REGISTERS - 1] = 1;
+        let ops = sparse_encode(&registers);
+        assert_eq!(sparse_decode(&ops), Some(registers.clone()));
+        assert_eq!(sparse_runs(&ops).unwrap(), vec![
+            SparseRun::Val(3, 1), SparseRun::XZero(99), SparseRun::Val(32, 4), SparseRun::Val(32, 2),
+            SparseRun::XZero(16277), SparseRun::Val(1, 1),
+        ]);
+        //Runs must cover the registers exactly
+        assert_eq!(sparse_decode(&ops[1..]), None);
+        assert_eq!(sparse_decode(&[0x7f]), None);
+    }
+
+    #[test]
+    fn pfadd_reports_changes_and_invalidates_cache() {
+        let mut store = Store::new();
+        assert_eq!(store.pfadd(b"h", &[]), Ok(true));
+        assert_eq!(cached_cardinality(&stored(&store, b"h")), Some(0));
+        assert_eq!(store.pfadd(b"h", &[]), Ok(false));
+        assert!(pfadd(&mut store, b"h", &elements(0..3)));
+        assert!(!pfadd(&mut store, b"h", &elements(0..3)));
+        assert_eq!(cached_cardinality(&stored(&store, b"h")), None);
+        assert_eq!(store.pfcount(&[b"h"]), Ok(3));
+        assert_eq!(cached_cardinality(&stored(&store, b"h")), Some(3));
+        assert_eq!(store.hll_encoding(b"h"), Ok("sparse"));
+    }
+
+    #[test]
+    fn sparse_turns_dense_past_the_size_limit() {
+        let mut store = Store::new();
+        pfadd(&mut store, b"h", &elements(0..200));
+        assert_eq!(store.hll_encoding(b"h"), Ok("sparse"));
+        pfadd(&mut store, b"h", &elements(200..5000));
+        assert_eq!(store.hll_encoding(b"h"), Ok("dense"));
+        assert_eq!(stored(&store, b"h").len(), HLL_DENSE_SIZE);
+        let estimate = store.pfcount(&[b"h"]).unwrap();
+        assert!(estimate.abs_diff(5000) < 5000 / 50, "{}", estimate);
+    }
+
+    #[test]
+    fn sparse_and_dense_estimate_the_same() {
+        let mut store = Store::new();
+        pfadd(&mut store, b"sparse", &elements(0..1000));
+        pfadd(&mut store, b"dense", &elements(0..1000));
+        assert_eq!(store.hll_to_dense(b"dense"), Ok(true));
+        assert_eq!(store.hll_to_dense(b"dense"), Ok(false));
+        assert_eq!(store.hll_registers(b"dense"), registers(&stored(&store, b"sparse")));
+        assert_eq!(store.pfcount(&[b"sparse"]), store.pfcount(&[b"dense"]));
+    }
+
+    #[test]
+    fn pfcount_of_several_keys_counts_the_union() {
+        let mut store = Store::new();
+        pfadd(&mut store, b"a", &elements(0..1000));
+        pfadd(&mut store, b"b", &elements(500..1500));
+        let union = store.pfcount(&[b"a", b"b", b"missing"]).unwrap();
+        assert!(union.abs_diff(1500) < 30, "{}", union);
+        assert_eq!(store.pfcount(&[b"missing"]), Ok(0));
+        //Counting several keys does not cache anything
+        assert_eq!(cached_cardinality(&stored(&store, b"a")), None);
+    }
+
+    #[test]
+    fn pfmerge_stores_the_union() {
+        let mut store = Store::new();
+        pfadd(&mut store, b"a", &elements(0..100));
+        pfadd(&mut store, b"b", &elements(50..150));
+        store.pfmerge(b"dest", &[b"a", b"b"]).unwrap();
+        assert_eq!(store.hll_encoding(b"dest"), Ok("sparse"));
+        let union = store.pfcount(&[b"dest"]).unwrap();
+        assert_eq!(union, store.pfcount(&[b"a", b"b"]).unwrap());
+        store.hll_to_dense(b"a").unwrap();
+        store.pfmerge(b"dest", &[b"a"]).unwrap();
+        assert_eq!(store.hll_encoding(b"dest"), Ok("dense"));
+        assert_eq!(store.pfcount(&[b"dest"]), Ok(union));
+    }
+
+    #[test]
+    fn invalid_values_are_refused() {
+        let mut store = Store::new();
+        store.set(b"s", b"not a hyperloglog".to_vec());
+        assert_eq!(store.pfadd(b"s", &[b"x"]), Err(StoreError::InvalidHll));
+        assert_eq!(store.pfcount(&[b"s"]), Err(StoreError::InvalidHll));
+        store.write(b"l", Value::List(Default::default()));
+        assert_eq!(store.pfcount(&[b"l"]), Err(StoreError::WrongType));
+
+        //A valid cached cardinality is replied without reading the registers
+        let mut corrupt = empty();
+        invalidate_cache(&mut corrupt);
+        corrupt.extend(b"hello");
+        store.write(b"c", Value::String(corrupt));
+        assert_eq!(store.pfcount(&[b"c"]), Err(StoreError::CorruptHll));
+        let mut truncated = encode(&header(HLL_DENSE), &[0; HLL_REGISTERS], false);
+        truncated.pop();
+        store.write(b"t", Value::String(truncated));
+        assert_eq!(store.pfcount(&[b"t"]), Err(StoreError::InvalidHll));
+        assert_eq!(store.hll_encoding(b"missing"), Err(StoreError::NotFound));
+    }
+
+    #[test]
+    fn decode_lists_sparse_opcodes() {
+        let mut store = Store::new();
+        store.pfadd(b"h", &[]).unwrap();
+        assert_eq!(store.hll_decode(b"h"), Ok(Some("Z:16384".to_string())));
+        store.pfadd(b"h", &[b"a"]).unwrap();
+        let (index, count) = pattern(b"a");
+        let decoded = store.hll_decode(b"h").unwrap().unwrap();
+        assert!(decoded.contains(&format!("v:{},1", count)), "{} {}", index, decoded);
+        store.hll_to_dense(b"h").unwrap();
+        assert_eq!(store.hll_decode(b"h"), Ok(None));
+    }
+
+    #[test]
+    fn selftest_passes() {
+        assert_eq!(selftest(42, 2, 10_000), Ok(()));
+    }
+}
@@ -0,0 +1,197 @@
+use std::{sync::{mpsc, OnceLock}, thread};
+
+use crate::store::value::{Key, Store, Value};
+
+//Values with more elements than this are freed on the lazyfree thread by UNLINK, smaller ones
+//are cheaper to free right away than to hand over
+const LAZYFREE_THRESHOLD: usize = 64;
+
+static LAZYFREE: OnceLock<mpsc::Sender<Box<dyn Send>>> = OnceLock::new();
+
+///Number of allocations freeing a value takes, roughly
+fn free_effort(value: &Value) -> usize {
+    match value {
+        Value::List(v) => v.len(),
+        Value::Hash(v) => v.len(),
+        Value::String(_) | Value::Integer(_) => 1
+    }
+}
+
+///Drops a value, or a whole keyspace, on the lazyfree thread, started on first use
+fn free_async<T: Send + 'static>(value: T) {
+    let sender = LAZYFREE.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Box<dyn Send>>();
+        thread::spawn(move || receiver.into_iter().for_each(drop));
+        sender
+    });
+    let _ = sender.send(Box::new(value));
+}
+
+///Name of the type of a value as TYPE reports it. Sets, sorted sets and streams are not
+///stored yet, so no key can be reported as set, zset or stream until commands creating them
+///are added
+pub fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::String(_) | Value::Integer(_) => "string",
+        Value::List(_) => "list",
+        Value::Hash(_) => "hash"
+    }
+}
+
+impl Store {
+    ///Whether the key exists, without counting as an access
+    pub fn exists(&mut self, key: &[u8]) -> bool {
+        self.expire_if_needed(key);
+        self.map.contains_key(key)
+    }
+
+    pub fn key_type(&mut self, key: &[u8]) -> &'static str {
+        self.expire_if_needed(key);
+        self.map.get(key).map_or("none", |entry| type_name(&entry.value))
+    }
+
+    ///Deletes a key, leaving large values to the lazyfree thread. Returns whether the key existed
+    pub fn unlink(&mut self, key: &[u8]) -> bool {
+        if !self.exists(key) {
+            return false;
+        }
+        if let Some(value) = self.remove(key) && free_effort(&value) > LAZYFREE_THRESHOLD {
+            free_async(value);
+        }
+        true
+    }
+
+    ///Moves a value and its time to live to another key, replacing it. Returns false if the
+    ///source does not exist
+    pub fn rename(&mut self, from: &[u8], to: &[u8]) -> bool {
+        if !self.exists(from) {
+            return false;
+        }
+        if from == to {
+            return true;
+        }
+        let expire = self.expires.get(from).copied();
+        let Some(value) = self.remove(from) else { return false };
+        self.write_with_expire(to, value, expire);
+        true
+    }
+
+    ///Copies a value and its time to live. Returns false if the source does not exist or the
+    ///destination does and `replace` is not set
+    pub fn copy(&mut self, from: &[u8], to: &[u8], replace: bool) -> bool {
+        if !self.exists(from) || (!replace && self.exists(to)) {
+            return false;
+        }
+        let value = self.map[from].value.clone();
+        let expire = self.expires.get(from).copied();
+        self.write_with_expire(to, value, expire);
+        true
+    }
+
+    ///Writes a value along with the expiry it had under another key or in another database
+    pub fn write_with_expire(&mut self, key: &[u8], value: Value, expire: Option<u64>) {
+        self.write(key, value);
+        match expire {
+            Some(at) => { self.expires.insert(key.to_vec(), at); },
+            None => { self.persist(key); }
+        }
+    }
+
+    ///Deletes every key, leaving the freeing to the lazyfree thread when `lazy` is set.
+    ///Clients are not told about each key, a flush invalidates their whole cache
+    pub fn flush(&mut self, lazy: bool) {
+        let map = std::mem::take(&mut self.map);
+        let expires = std::mem::take(&mut self.expires);
+        let scan_index = std::mem::take(&mut self.scan_index);
+        self.eviction_pool.clear();
+        self.used_memory = 0;
+        self.dirty += map.len() as u64;
+        if lazy {
+            free_async((map, expires, scan_index));
+        }
+    }
+
+    ///A random key that has not expired, None when the keyspace is empty
+    pub fn random_live_key(&mut self) -> Option<Key> {
+        //Bounded, when every key is volatile most samples could be expired
+        for _ in 0..100 {
+            let key = self.random_key(false)?;
+            if !self.expire_if_needed(&key) {
+                return Some(key);
+            }
+        }
+        self.random_key(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::expire::unix_time_ms;
+
+    #[test]
+    fn rename_and_copy_keep_ttl() {
+        let mut store = Store::new();
+        store.set(b"a", b"1".to_vec());
+        store.set(b"b", b"2".to_vec());
+        let at = unix_time_ms() + 60_000;
+        store.set_expire(b"a", at);
+        store.set_expire(b"b", at + 1);
+
+        assert!(store.rename(b"a", b"b"));
+        assert!(!store.exists(b"a"));
+        assert_eq!(store.get(b"b"), Ok(Some(b"1".to_vec())));
+        assert_eq!(store.expires.get(&b"b"[..]), Some(&at));
+        assert!(!store.rename(b"a", b"c"));
+
+        assert!(!store.copy(b"b", b"b", false));
+        store.set(b"c", b"3".to_vec());
+        assert!(!store.copy(b"b", b"c", false));
+        assert!(store.copy(b"b", b"c", true));
+        assert_eq!(store.get(b"c"), Ok(Some(b"1".to_vec())));
+        assert_eq!(store.expires.get(&b"c"[..]), Some(&at));
+        assert_eq!(store.map.len(), 2);
+    }
+
+    #[test]
+    fn unlink_frees_large_values_in_background() {
+        let mut store = Store::new();
+        let large = Value::List(vec![b"x".to_vec(); LAZYFREE_THRESHOLD + 1].into());
+        store.write(b"large", large);
+        store.set(b"small", b"v".to_vec());
+        assert_eq!(store.key_type(b"large"), "list");
+        assert_eq!(store.key_type(b"small"), "string");
+        assert!(store.unlink(b"large"));
+        assert!(store.unlink(b"small"));
+        assert!(!store.unlink(b"small"));
+        assert_eq!((store.map.len(), store.used_memory), (0, 0));
+        assert_eq!(store.key_type(b"small"), "none");
+    }
+
+    #[test]
+    fn flush_empties_the_keyspace() {
+        for lazy in [false, true] {
+            let mut store = Store::new();
+            store.set(b"a", b"1".to_vec());
+            store.set(b"b", b"2".to_vec());
+            store.set_expire(b"a", unix_time_ms() + 60_000);
+            let dirty = store.dirty;
+            store.flush(lazy);
+            assert!(store.map.is_empty() && store.expires.is_empty() && store.scan_index.is_empty());
+            assert_eq!((store.used_memory, store.dirty), (0, dirty + 2));
+            assert!(!store.exists(b"a"));
+        }
+    }
+
+    #[test]
+    fn random_key_skips_expired() {
+        let mut store = Store::new();
+        assert_eq!(store.random_live_key(), None);
+        store.set(b"dead", b"v".to_vec());
+        store.expires.insert(b"dead".to_vec(), 1);
+        store.set(b"live", b"v".to_vec());
+        for _ in 0..10 {
+            assert_eq!(store.random_live_key(), Some(b"live".to_vec()));
+        }
+    }
+}
@@ -1,6 +1,8 @@
-use std::collections::HashMap;
+use std::{collections::BTreeMap, time::{Instant, SystemTime, UNIX_EPOCH}};
 
-use crate::{resp::RespValue, store::value::{Store, StoreError}};
+use indexmap::IndexMap;
+
+use crate::store::{eviction::LFU_INIT_VAL, string::encode_string, value::{Entry, EvictionSettings, MaxmemoryPolicy, Store, Value}};
 
 impl Default for Store{
     fn default() -> Self {
@@ -8,21 +10,164 @@ impl Default for Store{
     }
 }
 
+//Bookkeeping of a hash map entry on top of its key and value
+const ENTRY_OVERHEAD: usize = 64;
+
+///Estimated memory used by a value, counting only the bytes it owns
+pub fn value_size(value: &Value) -> usize {
+    match value {
+        Value::String(v) => v.len(),
+        Value::Integer(_) => std::mem::size_of::<i64>(),
+        Value::List(v) => v.iter().map(Vec::len).sum(),
+        Value::Hash(v) => v.iter().map(|(k, v)| k.len() + v.len()).sum(),
+    }
+}
+
+pub fn entry_size(key: &[u8], value: &Value) -> usize {
+    ENTRY_OVERHEAD + key.len() + value_size(value)
+}
+
+//Any non zero value works for xorshift, the clock only makes runs differ
+fn seed() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0) | 1
+}
+
+impl Default for EvictionSettings {
+    fn default() -> Self {
+        Self { maxmemory: 0, policy: MaxmemoryPolicy::NoEviction, samples: 5, lfu_log_factor: 10, lfu_decay_time: 1 }
+    }
+}
+
 impl Store {
     pub fn new() -> Self {
-        Self { map: HashMap::new() }
+        let created = Instant::now();
+        Self {
+            map: IndexMap::new(),
+            scan_index: BTreeMap::new(),
+            expires: IndexMap::new(),
+            modified: Vec::new(),
+            keyspace_hits: 0,
+            keyspace_misses: 0,
+            dirty: 0,
+            used_memory: 0,
+            used_memory_peak: 0,
+            evicted_keys: 0,
+            expired_keys: 0,
+            avg_ttl: 0,
+            eviction: EvictionSettings::default(),
+            eviction_pool: Vec::new(),
+            created,
+            rng: seed(),
+        }
     }
 
-    pub fn set(&mut self, key: &RespValue, value: &RespValue) -> Result<RespValue, StoreError> {
-        self.map.insert(key.clone(), value.clone());
-        Ok(RespValue::SimpleString(b"OK".to_vec()))
+    ///Sets a string value, dropping any time to live the key had
+    pub fn set(&mut self, key: &[u8], value: Vec<u8>) {
+        self.write(key, encode_string(value));
+        //A new value starts without a time to live, unlike values modified in place
+        self.persist(key);
     }
 
-    pub fn get(&self, key: &RespValue) -> Result<RespValue, StoreError> {
-        match self.map.get(key) {
-            Some(n) => Ok(n.clone()),
-            None => Ok(RespValue::BulkString(None))
+    ///Looks a key up for a read command, counting the hit or miss
+    pub fn lookup_read(&mut self, key: &[u8]) -> Option<&Value> {
+        self.expire_if_needed(key);
+        match self.map.contains_key(key) {
+            true => {
+                self.keyspace_hits += 1;
+                self.touch(key);
+                self.map.get(key).map(|entry| &entry.value)
+            },
+            false => {
+                self.keyspace_misses += 1;
+                None
+            }
         }
+    }
+
+    ///Looks a key up for a write command, which does not count in the keyspace hits
+    pub fn lookup_write(&mut self, key: &[u8]) -> Option<&Value> {
+        self.expire_if_needed(key);
+        self.touch(key);
+        self.map.get(key).map(|entry| &entry.value)
+    }
+
+    ///Stores a value as is, every write to the keyspace goes through here
+    pub fn write(&mut self, key: &[u8], value: Value) {
+        let now = self.now_ms();
+        let size = entry_size(key, &value);
+        match self.map.get_mut(key) {
+            //Overwriting keeps the access history of the key
+            Some(entry) => {
+                let old = std::mem::replace(&mut entry.value, value);
+                self.used_memory -= entry_size(key, &old);
+                self.touch(key);
+            },
+            None => {
+                self.map.insert(key.to_vec(), Entry {
+                    value,
+                    last_access: now,
+                    lfu_counter: LFU_INIT_VAL,
+                    lfu_decrement_time: now / 60_000,
+                });
+                self.index_key(key);
+            }
+        }
+        self.used_memory += size;
+        self.used_memory_peak = self.used_memory_peak.max(self.used_memory);
+        self.dirty += 1;
+        self.modified.push(key.to_vec());
+    }
+
+    ///Deletes a key, returning its value
+    pub fn remove(&mut self, key: &[u8]) -> Option<Value> {
+        let entry = self.map.swap_remove(key)?;
+        self.expires.swap_remove(key);
+        self.unindex_key(key);
+        self.used_memory -= entry_size(key, &entry.value);
+        self.dirty += 1;
+        self.modified.push(key.to_vec());
+        Some(entry.value)
+    }
+
+    ///Drains the keys modified since the previous call
+    pub fn take_modified(&mut self) -> Vec<Vec<u8>> {
+        std::mem::take(&mut self.modified)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_hits_misses_and_memory() {
+        let mut store = Store::new();
+        let _ = store.get(b"k");
+        store.set(b"k", b"value".to_vec());
+        let _ = store.get(b"k");
+        assert_eq!((store.keyspace_hits, store.keyspace_misses, store.dirty), (1, 1, 1));
+        assert_eq!(store.used_memory, ENTRY_OVERHEAD + 6);
+
+        store.set(b"k", b"v".to_vec());
+        assert_eq!(store.used_memory, ENTRY_OVERHEAD + 2);
+        assert_eq!(store.used_memory_peak, ENTRY_OVERHEAD + 6);
+    }
+
+    #[test]
+    fn integers_are_stored_encoded() {
+        let mut store = Store::new();
+        store.set(b"n", b"-42".to_vec());
+        store.set(b"s", b"042".to_vec());
+        assert_eq!(store.map[&b"n"[..]].value, Value::Integer(-42));
+        assert_eq!(store.map[&b"s"[..]].value, Value::String(b"042".to_vec()));
+        assert_eq!(store.get(b"n"), Ok(Some(b"-42".to_vec())));
+    }
 
+    #[test]
+    fn keys_are_plain_bytes() {
+        let mut store = Store::new();
+        store.set(b"bin\0key", b"v".to_vec());
+        assert!(store.exists(b"bin\0key"));
+        assert!(!store.exists(b"bin"));
     }
 }
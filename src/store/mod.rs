@@ -1,2 +1,12 @@
+pub mod bitmap;
+pub mod databases;
+pub mod eviction;
+pub mod expire;
+pub mod hash;
+pub mod hyperloglog;
+pub mod keyspace;
 pub mod memory;
+pub mod scan;
+pub mod shards;
+pub mod string;
 pub mod value;
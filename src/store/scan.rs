@@ -0,0 +1,127 @@
+use std::{collections::BTreeMap, hash::{DefaultHasher, Hash, Hasher}};
+
+use crate::store::value::{Key, Store};
+
+///Position of a key in scan order. Scanning walks the hash space as a table of 2^64 buckets
+///with a reverse binary cursor, as dictScan in Redis does: a key's bucket is its whole hash,
+///and the cursor visits buckets by increasing reversed hash. The table never resizes, so keys
+///present during a whole scan are always returned whatever is written meanwhile
+pub fn scan_position<T: Hash + ?Sized>(key: &T) -> u64 {
+    //DefaultHasher::new uses fixed keys, positions are stable for the life of the process
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish().reverse_bits()
+}
+
+///Cursor for the bucket at a position, the reversed position being the bucket index
+fn cursor_at(position: u64) -> u64 {
+    position.reverse_bits()
+}
+
+///Entries of a scan index from the cursor on, whole buckets at a time until at least `count`
+///are taken, with the cursor of the next bucket or 0 once every bucket was visited
+pub fn scan_buckets<T>(index: &BTreeMap<u64, Vec<T>>, cursor: u64, count: usize) -> (u64, Vec<&T>) {
+    let mut taken = Vec::new();
+    let mut buckets = index.range(cursor.reverse_bits()..);
+    for (_, bucket) in buckets.by_ref() {
+        taken.extend(bucket);
+        if taken.len() >= count {
+            break;
+        }
+    }
+    (buckets.next().map_or(0, |(position, _)| cursor_at(*position)), taken)
+}
+
+impl Store {
+    pub fn index_key(&mut self, key: &[u8]) {
+        self.scan_index.entry(scan_position(key)).or_default().push(key.to_vec());
+    }
+
+    pub fn unindex_key(&mut self, key: &[u8]) {
+        let position = scan_position(key);
+        if let Some(bucket) = self.scan_index.get_mut(&position) {
+            bucket.retain(|k| k != key);
+            if bucket.is_empty() {
+                self.scan_index.remove(&position);
+            }
+        }
+    }
+
+    ///Keys from the cursor on, at least `count` of them unless the scan ends, with the cursor
+    ///to continue from or 0 once every bucket was visited. Expired keys are deleted on the way
+    pub fn scan(&mut self, cursor: u64, count: usize) -> (u64, Vec<Key>) {
+        let (next, keys) = scan_buckets(&self.scan_index, cursor, count);
+        let mut keys: Vec<Key> = keys.into_iter().cloned().collect();
+        keys.retain(|key| !self.expire_if_needed(key));
+        (next, keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn scan_all(store: &mut Store, count: usize, mut between: impl FnMut(&mut Store, usize)) -> (Vec<Key>, usize) {
+        let (mut cursor, mut keys, mut calls) = (0, Vec::new(), 0);
+        loop {
+            let (next, batch) = store.scan(cursor, count);
+            keys.extend(batch);
+            calls += 1;
+            between(store, calls);
+            if next == 0 {
+                return (keys, calls);
+            }
+            cursor = next;
+        }
+    }
+
+    #[test]
+    fn scan_visits_every_key_once() {
+        let mut store = Store::new();
+        for i in 0..1000 {
+            store.set(format!("key:{}", i).as_bytes(), b"v".to_vec());
+        }
+        let (keys, calls) = scan_all(&mut store, 10, |_, _| {});
+        assert_eq!(keys.len(), 1000);
+        assert_eq!(keys.iter().collect::<HashSet<_>>().len(), 1000);
+        assert_eq!(calls, 100);
+        assert_eq!(store.scan(0, 2000).0, 0);
+    }
+
+    #[test]
+    fn keys_present_during_the_scan_are_returned_despite_writes() {
+        let mut store = Store::new();
+        for i in 0..100 {
+            store.set(format!("stable:{}", i).as_bytes(), b"v".to_vec());
+        }
+        //The keyspace grows tenfold and shrinks back while the scan runs
+        let (keys, _) = scan_all(&mut store, 5, |store, call| {
+            for i in 0..50 {
+                store.set(format!("churn:{}:{}", call, i).as_bytes(), b"v".to_vec());
+            }
+            if call % 4 == 0 {
+                for c in call - 3..=call {
+                    for i in 0..50 {
+                        store.remove(format!("churn:{}:{}", c, i).as_bytes());
+                    }
+                }
+            }
+        });
+        let keys: HashSet<_> = keys.into_iter().collect();
+        for i in 0..100 {
+            assert!(keys.contains(format!("stable:{}", i).as_bytes()));
+        }
+    }
+
+    #[test]
+    fn scan_skips_expired_and_removed_keys() {
+        let mut store = Store::new();
+        store.set(b"a", b"v".to_vec());
+        store.set(b"b", b"v".to_vec());
+        store.expires.insert(b"a".to_vec(), 1);
+        assert_eq!(store.scan(0, 10), (0, vec![b"b".to_vec()]));
+        store.remove(b"b");
+        assert!(store.scan_index.is_empty());
+    }
+}
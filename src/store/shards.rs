@@ -0,0 +1,582 @@
+use std::{sync::{atomic::{AtomicUsize, Ordering}, Mutex}, time::{Duration, Instant}};
+
+use crate::store::{memory::entry_size, scan::scan_position, value::{Databases, Entry, EvictionSettings, Key, LockedShards, MaxmemoryPolicy, Shards, Store}};
+
+impl Shards {
+    ///`count` shards, rounded up to a power of two, each holding its part of `databases`
+    ///logical databases
+    pub fn new(count: usize, databases: usize, eviction: EvictionSettings) -> Self {
+        let count = count.max(1).next_power_of_two();
+        //Every store shares the clock of the first one, so access times compare across shards
+        let first = Databases::new(databases, eviction);
+        let mut shards: Vec<_> = (1..count).map(|_| Mutex::new(first.scratch())).collect();
+        shards.insert(0, Mutex::new(first));
+        Self {
+            shards,
+            databases: databases.max(1),
+            eviction: Mutex::new(eviction),
+            maxmemory: AtomicUsize::new(eviction.maxmemory),
+            used_memory: (0..count).map(|_| AtomicUsize::new(0)).collect(),
+            used_memory_peak: AtomicUsize::new(0),
+            next_eviction_shard: AtomicUsize::new(0),
+            next_expire_shard: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shards.is_empty()
+    }
+
+    //Shards are named by the top bits of scan positions
+    fn shift(&self) -> u32 {
+        u64::BITS - self.shards.len().trailing_zeros()
+    }
+
+    ///Shard holding the keys at a scan position
+    pub fn shard_at(&self, position: u64) -> usize {
+        position.checked_shr(self.shift()).unwrap_or(0) as usize
+    }
+
+    ///First scan position of a shard's range
+    pub fn first_position(&self, shard: usize) -> u64 {
+        (shard as u64).checked_shl(self.shift()).unwrap_or(0)
+    }
+
+    pub fn shard_of(&self, key: &[u8]) -> usize {
+        self.shard_at(scan_position(key))
+    }
+
+    ///Locks the given shards in increasing order. A thread must release the shards it holds
+    ///before locking others, locking more while holding some could deadlock
+    pub fn lock(&self, indexes: &[usize]) -> LockedShards<'_> {
+        let mut indexes = indexes.to_vec();
+        indexes.sort_unstable();
+        indexes.dedup();
+        LockedShards {
+            owner: self,
+            locked: indexes.into_iter().map(|index| (index, self.shards[index].lock().unwrap())).collect(),
+        }
+    }
+
+    pub fn lock_all(&self) -> LockedShards<'_> {
+        self.lock(&(0..self.shards.len()).collect::<Vec<_>>())
+    }
+
+    ///Runs `f` on every shard in turn, holding one lock at a time
+    pub fn for_each(&self, mut f: impl FnMut(&mut Databases)) {
+        for index in 0..self.shards.len() {
+            f(self.lock(&[index]).shard(index));
+        }
+    }
+
+    ///Sum of a counter over every database of every shard
+    pub fn total(&self, stat: impl Fn(&Store) -> u64) -> u64 {
+        let mut total = 0;
+        self.for_each(|shard| total += shard.total(&stat));
+        total
+    }
+
+    ///Memory used by every shard as of the last time each was released
+    pub fn used_memory(&self) -> usize {
+        self.used_memory.iter().map(|used| used.load(Ordering::Relaxed)).sum()
+    }
+
+    pub fn used_memory_peak(&self) -> usize {
+        self.used_memory_peak.load(Ordering::Relaxed).max(self.used_memory())
+    }
+
+    pub fn eviction(&self) -> EvictionSettings {
+        *self.eviction.lock().unwrap()
+    }
+
+    pub fn set_eviction(&self, eviction: EvictionSettings) {
+        *self.eviction.lock().unwrap() = eviction;
+        self.maxmemory.store(eviction.maxmemory, Ordering::Relaxed);
+        self.for_each(|shard| shard.set_eviction(eviction));
+    }
+
+    ///Evicts one key from whichever shard holds the best candidate, adding it to `evicted`.
+    ///Returns false when the policy finds nothing to evict
+    fn evict_one(&self, policy: MaxmemoryPolicy, evicted: &mut Vec<Key>) -> bool {
+        let count = self.shards.len();
+        match policy {
+            MaxmemoryPolicy::NoEviction => false,
+            MaxmemoryPolicy::AllKeysRandom | MaxmemoryPolicy::VolatileRandom => {
+                for _ in 0..count {
+                    let index = self.next_eviction_shard.fetch_add(1, Ordering::Relaxed) % count;
+                    let mut locked = self.lock(&[index]);
+                    let shard = locked.shard(index);
+                    if shard.evict_random() {
+                        evicted.extend(shard.take_modified());
+                        return true;
+                    }
+                }
+                false
+            },
+            _ => {
+                //Shards are sampled one at a time so eviction never holds them all
+                let mut best: Option<(u64, usize, usize, Key)> = None;
+                for index in 0..count {
+                    let candidate = self.lock(&[index]).shard(index).eviction_candidate();
+                    if let Some((score, db, key)) = candidate && best.as_ref().is_none_or(|(best, ..)| score > *best) {
+                        best = Some((score, index, db, key));
+                    }
+                }
+                let Some((_, index, db, key)) = best else { return false };
+                //The candidate may have been deleted meanwhile, the caller then looks again
+                let mut locked = self.lock(&[index]);
+                let shard = locked.shard(index);
+                shard.db(db).evict(&key);
+                evicted.extend(shard.take_modified());
+                true
+            }
+        }
+    }
+
+    ///Evicts keys until the memory used by all shards fits in maxmemory. Returns whether it
+    ///fits, false when the policy has nothing left to evict, and the keys evicted, so client
+    ///side caches can be told at once
+    pub fn evict_if_needed(&self) -> (bool, Vec<Key>) {
+        //Checked without the settings lock, every command passes here
+        let maxmemory = self.maxmemory.load(Ordering::Relaxed);
+        if maxmemory == 0 || self.used_memory() <= maxmemory {
+            return (true, Vec::new());
+        }
+        let eviction = self.eviction();
+        let mut evicted = Vec::new();
+        while self.used_memory() > eviction.maxmemory {
+            if !self.evict_one(eviction.policy, &mut evicted) {
+                return (false, evicted);
+            }
+        }
+        (true, evicted)
+    }
+
+    ///Runs the active expire cycle of each shard in turn within a shared time limit. Returns
+    ///the keys deleted, so client side caches can be told
+    pub fn active_expire_cycle(&self, time_limit: Duration) -> Vec<Key> {
+        let started = Instant::now();
+        let count = self.shards.len();
+        let first = self.next_expire_shard.load(Ordering::Relaxed);
+        let mut deleted = Vec::new();
+        for i in 0..count {
+            let index = (first + i) % count;
+            let remaining = time_limit.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                self.next_expire_shard.store(index, Ordering::Relaxed);
+                break;
+            }
+            let mut locked = self.lock(&[index]);
+            let shard = locked.shard(index);
+            shard.active_expire_cycle(remaining);
+            deleted.extend(shard.take_modified());
+        }
+        deleted
+    }
+
+    ///Average time to live of the volatile keys of each database in milliseconds, weighing the
+    ///estimate of each shard by its number of volatile keys
+    pub fn avg_ttls(&self) -> Vec<u64> {
+        let mut totals = vec![(0u128, 0u128); self.databases];
+        self.for_each(|shard| {
+            for (total, db) in totals.iter_mut().zip(&shard.dbs) {
+                if db.avg_ttl > 0 {
+                    total.0 += db.avg_ttl as u128 * db.expires.len() as u128;
+                    total.1 += db.expires.len() as u128;
+                }
+            }
+        });
+        totals.into_iter().map(|(sum, count)| sum.checked_div(count).unwrap_or(0) as u64).collect()
+    }
+
+    ///Number of keys and of keys with a time to live in each database
+    pub fn db_sizes(&self) -> Vec<(usize, usize)> {
+        let mut sizes = vec![(0, 0); self.databases];
+        self.for_each(|shard| {
+            for (size, db) in sizes.iter_mut().zip(&shard.dbs) {
+                size.0 += db.map.len();
+                size.1 += db.expires.len();
+            }
+        });
+        sizes
+    }
+}
+
+impl LockedShards<'_> {
+    pub fn len(&self) -> usize {
+        self.locked.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locked.is_empty()
+    }
+
+    ///A shard among the locked ones, panics if it is not locked
+    pub fn shard(&mut self, index: usize) -> &mut Databases {
+        let (_, shard) = self.locked.iter_mut().find(|(i, _)| *i == index).expect("shard is not locked");
+        shard
+    }
+
+    pub fn for_key(&mut self, key: &[u8]) -> &mut Databases {
+        let index = self.owner.shard_of(key);
+        self.shard(index)
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Databases> {
+        self.locked.iter_mut().map(|(_, shard)| &mut **shard)
+    }
+
+    ///Drains the keys modified in any locked shard since the previous call
+    pub fn take_modified(&mut self) -> Vec<Key> {
+        self.iter_mut().flat_map(|shard| shard.take_modified()).collect()
+    }
+
+    ///Whether a database of a locked shard was flushed since the previous call
+    pub fn take_flushed(&mut self) -> bool {
+        self.iter_mut().fold(false, |flushed, shard| shard.take_flushed() | flushed)
+    }
+
+    ///Moves keys with their metadata out of their shards into scratch databases, so a command
+    ///whose keys span several shards can run as if they all lived in one. The keys of every
+    ///database in `databases` are moved, `scatter` puts back whatever the command left
+    pub fn gather(&mut self, keys: &[Key], databases: &[usize]) -> Databases {
+        let mut scratch = self.locked[0].1.scratch();
+        for key in keys {
+            for &db in databases {
+                if let Some(taken) = self.for_key(key).db(db).take_entry(key) {
+                    scratch.db(db).put_entry(key, taken);
+                }
+            }
+        }
+        scratch
+    }
+
+    ///Moves the keys of scratch databases back to their shards, along with the counters and
+    ///pending invalidations the command left there
+    pub fn scatter(&mut self, mut scratch: Databases, keys: &[Key], databases: &[usize]) {
+        for key in keys {
+            for &db in databases {
+                if let Some(taken) = scratch.db(db).take_entry(key) {
+                    self.for_key(key).db(db).put_entry(key, taken);
+                }
+            }
+        }
+        debug_assert!(scratch.dbs.iter().all(|db| db.map.is_empty()), "a command wrote a key it did not declare");
+        self.locked[0].1.absorb(scratch);
+    }
+
+    ///SCAN over the shards, which must all be locked. Each shard is a range of the scan order,
+    ///so the cursor means the same as on a single store and the batch may span shards
+    pub fn scan(&mut self, db: usize, cursor: u64, count: usize) -> (u64, Vec<Key>) {
+        let mut keys = Vec::new();
+        let mut cursor = cursor;
+        loop {
+            let index = self.owner.shard_at(cursor.reverse_bits());
+            let (next, batch) = self.shard(index).db(db).scan(cursor, count - keys.len());
+            keys.extend(batch);
+            cursor = match next {
+                0 if index + 1 < self.owner.len() => self.owner.first_position(index + 1).reverse_bits(),
+                next => next
+            };
+            if cursor == 0 || keys.len() >= count {
+                return (cursor, keys);
+            }
+        }
+    }
+
+    ///A random key that has not expired from the locked shards, picking a shard in proportion
+    ///to its number of keys
+    pub fn random_live_key(&mut self, db: usize) -> Option<Key> {
+        let sizes: Vec<usize> = self.iter_mut().map(|shard| shard.db(db).map.len()).collect();
+        let total: usize = sizes.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = (self.locked[0].1.db(db).next_random() % total as u64) as usize;
+        let start = sizes.iter().position(|&size| match pick < size {
+            true => true,
+            false => {
+                pick -= size;
+                false
+            }
+        }).unwrap_or(0);
+        //The shard picked may only hold expired keys, the next ones are tried then
+        for i in 0..self.locked.len() {
+            let position = (start + i) % self.locked.len();
+            if let Some(key) = self.locked[position].1.db(db).random_live_key() {
+                return Some(key);
+            }
+        }
+        None
+    }
+}
+
+impl Drop for LockedShards<'_> {
+    //Publishes the memory of the shards for readers that do not lock them
+    fn drop(&mut self) {
+        for (index, shard) in &self.locked {
+            self.owner.used_memory[*index].store(shard.used_memory(), Ordering::Relaxed);
+        }
+        self.owner.used_memory_peak.fetch_max(self.owner.used_memory(), Ordering::Relaxed);
+    }
+}
+
+impl Databases {
+    ///Empty databases with the same settings and clock, see LockedShards::gather
+    pub fn scratch(&self) -> Databases {
+        let mut scratch = Databases::new(self.dbs.len(), self.eviction);
+        for (store, original) in scratch.dbs.iter_mut().zip(&self.dbs) {
+            store.created = original.created;
+        }
+        scratch
+    }
+
+    ///Adds the counters and pending invalidations of scratch databases to these
+    pub fn absorb(&mut self, scratch: Databases) {
+        for (db, other) in self.dbs.iter_mut().zip(scratch.dbs) {
+            db.keyspace_hits += other.keyspace_hits;
+            db.keyspace_misses += other.keyspace_misses;
+            db.dirty += other.dirty;
+            db.expired_keys += other.expired_keys;
+            db.evicted_keys += other.evicted_keys;
+            db.modified.extend(other.modified);
+        }
+        self.flushed |= scratch.flushed;
+    }
+}
+
+impl Store {
+    ///Takes a key out with its access metadata and time to live, without counting as a write
+    pub fn take_entry(&mut self, key: &[u8]) -> Option<(Entry, Option<u64>)> {
+        let entry = self.map.swap_remove(key)?;
+        let expire = self.expires.swap_remove(key);
+        self.unindex_key(key);
+        self.used_memory -= entry_size(key, &entry.value);
+        Some((entry, expire))
+    }
+
+    ///Puts back a key taken by take_entry, here or in another store
+    pub fn put_entry(&mut self, key: &[u8], (entry, expire): (Entry, Option<u64>)) {
+        self.used_memory += entry_size(key, &entry.value);
+        self.map.insert(key.to_vec(), entry);
+        if let Some(at) = expire {
+            self.expires.insert(key.to_vec(), at);
+        }
+        self.index_key(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::expire::unix_time_ms;
+    use std::collections::HashSet;
+
+    fn set(shards: &Shards, db: usize, key: &str) {
+        shards.lock(&[shards.shard_of(key.as_bytes())]).for_key(key.as_bytes()).db(db).set(key.as_bytes(), b"value".to_vec());
+    }
+
+    fn shards_with(policy: MaxmemoryPolicy, keys: usize) -> Shards {
+        let shards = Shards::new(4, 1, EvictionSettings::default());
+        for i in 0..keys {
+            set(&shards, 0, &format!("key:{:03}", i));
+        }
+        let maxmemory = shards.used_memory() / 2;
+        shards.set_eviction(EvictionSettings { maxmemory, policy, samples: 10, ..Default::default() });
+        shards
+    }
+
+    fn key_count(shards: &Shards) -> usize {
+        shards.db_sizes()[0].0
+    }
+
+    #[test]
+    fn shards_are_ranges_of_the_scan_order() {
+        let shards = Shards::new(6, 1, EvictionSettings::default());
+        assert_eq!(shards.len(), 8);
+        assert_eq!(shards.shard_at(0), 0);
+        assert_eq!(shards.shard_at(u64::MAX), 7);
+        assert_eq!(shards.shard_at(shards.first_position(5)), 5);
+        assert_eq!(shards.shard_at(shards.first_position(5) - 1), 4);
+        let single = Shards::new(1, 1, EvictionSettings::default());
+        assert_eq!((single.shard_at(u64::MAX), single.first_position(0)), (0, 0));
+    }
+
+    #[test]
+    fn locks_are_taken_once_in_order() {
+        let shards = Shards::new(4, 1, EvictionSettings::default());
+        let locked = shards.lock(&[3, 1, 3]);
+        assert_eq!(locked.locked.iter().map(|(i, _)| *i).collect::<Vec<_>>(), [1, 3]);
+        //Other shards stay available
+        assert!(shards.shards[0].try_lock().is_ok());
+        assert!(shards.shards[1].try_lock().is_err());
+    }
+
+    #[test]
+    fn memory_is_published_on_release() {
+        let shards = Shards::new(4, 2, EvictionSettings::default());
+        set(&shards, 0, "a");
+        set(&shards, 1, "b");
+        let used = shards.used_memory();
+        assert!(used > 0);
+        assert_eq!(used, shards.lock_all().iter_mut().map(|shard| shard.used_memory()).sum::<usize>());
+        shards.lock_all().iter_mut().for_each(|shard| shard.flush(None, false));
+        assert_eq!((shards.used_memory(), shards.used_memory_peak()), (0, used));
+    }
+
+    #[test]
+    fn scan_across_shards_matches_a_single_shard() {
+        let single = Shards::new(1, 1, EvictionSettings::default());
+        let sharded = Shards::new(8, 1, EvictionSettings::default());
+        for i in 0..500 {
+            set(&single, 0, &format!("key:{}", i));
+            set(&sharded, 0, &format!("key:{}", i));
+        }
+        let (mut single, mut sharded) = (single.lock_all(), sharded.lock_all());
+        let (mut cursor, mut seen) = (0, HashSet::new());
+        loop {
+            let expected = single.scan(0, cursor, 7);
+            let (next, keys) = sharded.scan(0, cursor, 7);
+            assert_eq!((next, &keys), (expected.0, &expected.1));
+            seen.extend(keys);
+            if next == 0 {
+                break;
+            }
+            cursor = next;
+        }
+        assert_eq!(seen.len(), 500);
+    }
+
+    #[test]
+    fn gather_and_scatter_keep_keys_in_their_shards() {
+        let shards = Shards::new(8, 2, EvictionSettings::default());
+        let keys: Vec<Key> = (0..20).map(|i| format!("key:{}", i).into_bytes()).collect();
+        let at = unix_time_ms() + 60_000;
+        for key in &keys {
+            let mut locked = shards.lock(&[shards.shard_of(key)]);
+            locked.for_key(key).db(1).set(key, b"v".to_vec());
+            locked.for_key(key).db(1).set_expire(key, at);
+            locked.take_modified();
+        }
+        let used = shards.used_memory();
+        let mut locked = shards.lock_all();
+        let mut scratch = locked.gather(&keys, &[1]);
+        assert_eq!(scratch.db(1).map.len(), 20);
+        assert!(locked.iter_mut().all(|shard| shard.db(1).map.is_empty()));
+        assert!(locked.take_modified().is_empty());
+
+        scratch.db(1).remove(b"key:0");
+        let _ = scratch.db(1).get(b"key:1");
+        locked.scatter(scratch, &keys, &[1]);
+        for key in &keys[1..] {
+            assert_eq!(locked.for_key(key).db(1).expires.get(key), Some(&at));
+        }
+        assert_eq!(locked.take_modified(), [b"key:0".to_vec()]);
+        assert_eq!(locked.iter_mut().map(|shard| shard.db(1).keyspace_hits).sum::<u64>(), 1);
+        drop(locked);
+        assert!(shards.used_memory() < used);
+        assert_eq!(shards.db_sizes(), [(0, 0), (19, 19)]);
+    }
+
+    #[test]
+    fn random_key_comes_from_any_shard() {
+        let shards = Shards::new(4, 1, EvictionSettings::default());
+        assert_eq!(shards.lock_all().random_live_key(0), None);
+        for i in 0..40 {
+            set(&shards, 0, &format!("key:{}", i));
+        }
+        let mut locked = shards.lock_all();
+        let picked: HashSet<_> = (0..200).filter_map(|_| locked.random_live_key(0)).map(|key| shards.shard_of(&key)).collect();
+        assert_eq!(picked.len(), 4);
+    }
+
+    #[test]
+    fn noeviction_and_volatile_without_expires_cannot_free_memory() {
+        let shards = shards_with(MaxmemoryPolicy::NoEviction, 10);
+        assert!(!shards.evict_if_needed().0);
+        shards.set_eviction(EvictionSettings { policy: MaxmemoryPolicy::VolatileLru, ..shards.eviction() });
+        assert!(!shards.evict_if_needed().0);
+        assert_eq!(key_count(&shards), 10);
+        shards.set_eviction(EvictionSettings { maxmemory: 0, ..shards.eviction() });
+        assert!(shards.evict_if_needed().0);
+    }
+
+    #[test]
+    fn allkeys_policies_evict_until_under_limit() {
+        for policy in [MaxmemoryPolicy::AllKeysLru, MaxmemoryPolicy::AllKeysLfu, MaxmemoryPolicy::AllKeysRandom] {
+            let shards = shards_with(policy, 100);
+            shards.lock_all().take_modified();
+            let (fits, evicted) = shards.evict_if_needed();
+            assert!(fits);
+            assert!(shards.used_memory() <= shards.eviction().maxmemory);
+            assert_eq!(key_count(&shards), 50);
+            assert_eq!(shards.total(|db| db.evicted_keys), 50);
+            //Evicted keys are handed back to invalidate client side caches, not left for the
+            //next command on their shard
+            assert_eq!(evicted.len(), 50);
+            assert!(shards.lock_all().take_modified().is_empty());
+        }
+    }
+
+    #[test]
+    fn lru_keeps_recently_used_keys() {
+        let shards = shards_with(MaxmemoryPolicy::AllKeysLru, 100);
+        shards.set_eviction(EvictionSettings { samples: 100, ..shards.eviction() });
+        shards.for_each(|shard| shard.db(0).map.values_mut().for_each(|entry| entry.last_access = 0));
+        let hot = b"key:007".to_vec();
+        shards.lock_all().for_key(&hot).db(0).map.get_mut(&hot).unwrap().last_access = 1_000_000;
+        assert!(shards.evict_if_needed().0);
+        assert!(shards.lock_all().for_key(&hot).db(0).map.contains_key(&hot));
+    }
+
+    #[test]
+    fn volatile_ttl_evicts_nearest_expiry_first() {
+        let shards = shards_with(MaxmemoryPolicy::VolatileTtl, 4);
+        shards.set_eviction(EvictionSettings { maxmemory: shards.used_memory() - 1, ..shards.eviction() });
+        {
+            let mut locked = shards.lock_all();
+            locked.for_key(b"key:001").db(0).expires.insert(b"key:001".to_vec(), 2_000);
+            locked.for_key(b"key:002").db(0).expires.insert(b"key:002".to_vec(), 1_000);
+        }
+        assert!(shards.evict_if_needed().0);
+        let mut locked = shards.lock_all();
+        assert!(!locked.for_key(b"key:002").db(0).map.contains_key(&b"key:002"[..]));
+        assert!(locked.for_key(b"key:001").db(0).map.contains_key(&b"key:001"[..]));
+    }
+
+    #[test]
+    fn eviction_spans_shards_and_databases() {
+        let shards = Shards::new(4, 4, EvictionSettings::default());
+        for db in 0..4 {
+            for i in 0..25 {
+                set(&shards, db, &format!("key:{:03}", i));
+            }
+        }
+        let maxmemory = shards.used_memory() / 2;
+        for policy in [MaxmemoryPolicy::AllKeysLru, MaxmemoryPolicy::AllKeysRandom] {
+            shards.set_eviction(EvictionSettings { maxmemory, policy, samples: 10, ..Default::default() });
+            assert!(shards.evict_if_needed().0);
+            assert!(shards.used_memory() <= maxmemory);
+        }
+        //Random eviction takes from each database in turn
+        assert!(shards.db_sizes().iter().all(|(keys, _)| *keys < 25));
+        assert_eq!(shards.total(|db| db.evicted_keys), 50);
+    }
+
+    #[test]
+    fn expire_cycle_covers_every_shard() {
+        let shards = Shards::new(4, 1, EvictionSettings::default());
+        for i in 0..20 {
+            let key = format!("key:{}", i);
+            set(&shards, 0, &key);
+            shards.lock_all().for_key(key.as_bytes()).db(0).expires.insert(key.into_bytes(), 1);
+        }
+        shards.lock_all().take_modified();
+        assert_eq!(shards.active_expire_cycle(Duration::from_secs(1)).len(), 20);
+        assert_eq!(shards.total(|db| db.expired_keys), 20);
+    }
+}
@@ -0,0 +1,193 @@
+use std::borrow::Cow;
+
+use crate::store::value::{Store, StoreError, Value};
+
+//Largest string a command may build, as proto-max-bulk-len in Redis
+pub const MAX_STRING_LEN: usize = 512 * 1024 * 1024;
+
+///Parses a 64 bit integer in canonical form only: no sign other than a leading `-`, no leading
+///zeros and no spaces, so that encoding it back gives the same bytes
+pub fn parse_integer(bytes: &[u8]) -> Option<i64> {
+    let digits = bytes.strip_prefix(b"-").unwrap_or(bytes);
+    match digits {
+        [] => None,
+        [b'0'] if digits.len() == bytes.len() => Some(0),
+        [b'0', ..] => None,
+        _ if !digits.iter().all(u8::is_ascii_digit) => None,
+        _ => std::str::from_utf8(bytes).ok()?.parse().ok()
+    }
+}
+
+///Parses a float the way INCRBYFLOAT accepts it, NaN is never a valid value
+pub fn parse_float(bytes: &[u8]) -> Option<f64> {
+    std::str::from_utf8(bytes).ok()?.parse::<f64>().ok().filter(|f| !f.is_nan())
+}
+
+///Strings that are integers are kept as one, so counters do not have to be reparsed on every
+///increment
+pub fn encode_string(bytes: Vec<u8>) -> Value {
+    match parse_integer(&bytes) {
+        Some(n) => Value::Integer(n),
+        None => Value::String(bytes)
+    }
+}
+
+///Bytes of a string value, whatever its encoding
+pub fn string_bytes(value: &Value) -> Result<Cow<'_, [u8]>, StoreError> {
+    match value {
+        Value::Integer(n) => Ok(Cow::Owned(n.to_string().into_bytes())),
+        Value::String(bytes) => Ok(Cow::Borrowed(bytes)),
+        Value::List(_) | Value::Hash(_) => Err(StoreError::WrongType)
+    }
+}
+
+impl Store {
+    ///Value of a string key for a read command, None if the key does not exist
+    pub fn get(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        self.lookup_read(key).map(|v| string_bytes(v).map(Cow::into_owned)).transpose()
+    }
+
+    fn current_string(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        self.lookup_write(key).map(|v| string_bytes(v).map(Cow::into_owned)).transpose()
+    }
+
+    pub fn incr_by(&mut self, key: &[u8], delta: i64) -> Result<i64, StoreError> {
+        let current = match self.lookup_write(key) {
+            None => 0,
+            Some(Value::Integer(n)) => *n,
+            Some(value) => parse_integer(&string_bytes(value)?).ok_or(StoreError::NotInteger)?
+        };
+        let value = current.checked_add(delta).ok_or(StoreError::Overflow)?;
+        self.write(key, Value::Integer(value));
+        Ok(value)
+    }
+
+    ///Returns the new value formatted as it is stored
+    pub fn incr_by_float(&mut self, key: &[u8], delta: f64) -> Result<Vec<u8>, StoreError> {
+        let current = match self.current_string(key)? {
+            None => 0.0,
+            Some(bytes) => parse_float(&bytes).ok_or(StoreError::NotFloat)?
+        };
+        let value = current + delta;
+        if !value.is_finite() {
+            return Err(StoreError::NotFinite);
+        }
+        let formatted = format!("{}", value).into_bytes();
+        self.write(key, Value::String(formatted.clone()));
+        Ok(formatted)
+    }
+
+    ///Returns the length of the string after appending
+    pub fn append(&mut self, key: &[u8], suffix: &[u8]) -> Result<usize, StoreError> {
+        let mut value = self.current_string(key)?.unwrap_or_default();
+        if value.len() + suffix.len() > MAX_STRING_LEN {
+            return Err(StoreError::TooLarge);
+        }
+        value.extend_from_slice(suffix);
+        let len = value.len();
+        self.write(key, encode_string(value));
+        Ok(len)
+    }
+
+    pub fn strlen(&mut self, key: &[u8]) -> Result<usize, StoreError> {
+        Ok(self.get(key)?.map_or(0, |v| v.len()))
+    }
+
+    ///Overwrites part of the string starting at `offset`, padding it with zero bytes when it
+    ///is shorter. Returns the length of the string after the write
+    pub fn setrange(&mut self, key: &[u8], offset: usize, bytes: &[u8]) -> Result<usize, StoreError> {
+        let current = self.current_string(key)?;
+        //An empty write changes nothing and does not create the key
+        if bytes.is_empty() {
+            return Ok(current.map_or(0, |v| v.len()));
+        }
+        if offset.saturating_add(bytes.len()) > MAX_STRING_LEN {
+            return Err(StoreError::TooLarge);
+        }
+        let mut value = current.unwrap_or_default();
+        if value.len() < offset + bytes.len() {
+            value.resize(offset + bytes.len(), 0);
+        }
+        value[offset..offset + bytes.len()].copy_from_slice(bytes);
+        let len = value.len();
+        self.write(key, encode_string(value));
+        Ok(len)
+    }
+}
+
+///Substring between two inclusive offsets, negative ones counting from the end
+pub fn substring(value: &[u8], start: i64, end: i64) -> &[u8] {
+    let len = value.len() as i64;
+    if start < 0 && end < 0 && start > end {
+        return &[];
+    }
+    let start = if start < 0 { (len + start).max(0) } else { start };
+    let end = if end < 0 { (len + end).max(0) } else { end.min(len - 1) };
+    match start > end || len == 0 {
+        true => &[],
+        false => &value[start as usize..=end as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_canonical_integers_only() {
+        assert_eq!(parse_integer(b"0"), Some(0));
+        assert_eq!(parse_integer(b"-9223372036854775808"), Some(i64::MIN));
+        for invalid in [&b""[..], b"-", b"-0", b"007", b"+1", b" 1", b"1.0", b"9223372036854775808"] {
+            assert_eq!(parse_integer(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn incr_by_checks_type_and_overflow() {
+        let mut store = Store::new();
+        assert_eq!(store.incr_by(b"n", 5), Ok(5));
+        assert_eq!(store.incr_by(b"n", -7), Ok(-2));
+        store.set(b"n", i64::MAX.to_string().into_bytes());
+        assert_eq!(store.incr_by(b"n", 1), Err(StoreError::Overflow));
+        store.set(b"s", b"abc".to_vec());
+        assert_eq!(store.incr_by(b"s", 1), Err(StoreError::NotInteger));
+        store.write(b"l", Value::List(Default::default()));
+        assert_eq!(store.incr_by(b"l", 1), Err(StoreError::WrongType));
+    }
+
+    #[test]
+    fn incr_by_float_formats_result() {
+        let mut store = Store::new();
+        store.set(b"f", b"10.50".to_vec());
+        assert_eq!(store.incr_by_float(b"f", 0.1), Ok(b"10.6".to_vec()));
+        assert_eq!(store.incr_by_float(b"f", -5.6), Ok(b"5".to_vec()));
+        assert_eq!(store.incr_by_float(b"f", f64::INFINITY), Err(StoreError::NotFinite));
+        store.set(b"s", b"x".to_vec());
+        assert_eq!(store.incr_by_float(b"s", 1.0), Err(StoreError::NotFloat));
+    }
+
+    #[test]
+    fn append_and_setrange() {
+        let mut store = Store::new();
+        assert_eq!(store.append(b"k", b"12"), Ok(2));
+        assert_eq!(store.append(b"k", b"3"), Ok(3));
+        assert_eq!(store.map[&b"k"[..]].value, Value::Integer(123));
+        assert_eq!(store.setrange(b"k", 5, b"ab"), Ok(7));
+        assert_eq!(store.get(b"k"), Ok(Some(b"123\0\0ab".to_vec())));
+        assert_eq!(store.setrange(b"missing", 3, b""), Ok(0));
+        assert!(!store.map.contains_key(&b"missing"[..]));
+        assert_eq!(store.setrange(b"k", MAX_STRING_LEN, b"x"), Err(StoreError::TooLarge));
+    }
+
+    #[test]
+    fn substring_offsets() {
+        let value = b"This is a string";
+        assert_eq!(substring(value, 0, 3), b"This");
+        assert_eq!(substring(value, -3, -1), b"ing");
+        assert_eq!(substring(value, 0, -1), value);
+        assert_eq!(substring(value, 10, 100), b"string");
+        assert_eq!(substring(value, 5, 3), b"");
+        assert_eq!(substring(value, -1, -5), b"");
+        assert_eq!(substring(b"", 0, -1), b"");
+    }
+}
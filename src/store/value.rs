@@ -1,12 +1,197 @@
-use std::collections::HashMap;
+use std::{collections::{BTreeMap, VecDeque}, sync::{atomic::AtomicUsize, Mutex, MutexGuard}, time::Instant};
 
-use crate::resp::RespValue;
+use indexmap::IndexMap;
+
+///Keys are binary safe byte strings, whatever RESP type carried them
+pub type Key = Vec<u8>;
+
+///A stored value, independent of how it is sent over the wire
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(Vec<u8>),
+    //A string holding a canonical integer, kept decoded so counters are not reparsed
+    Integer(i64),
+    List(VecDeque<Vec<u8>>),
+    Hash(HashValue),
+}
+
+///A field of a hash with its value
+pub type FieldValue<'a> = (&'a [u8], &'a [u8]);
+
+///Fields of a hash in insertion order, also indexed by scan position as the keyspace is, so
+///HSCAN resumes where it stopped instead of sorting every field on each call
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HashValue {
+    pub fields: IndexMap<Vec<u8>, Vec<u8>>,
+    pub scan_index: BTreeMap<u64, Vec<Vec<u8>>>,
+}
+
+///A stored value with the access metadata eviction policies rank keys by
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub value: Value,
+    //Milliseconds since the store was created, at the last access
+    pub last_access: u64,
+    //Logarithmic access counter and the minute it was last decremented, as Redis LFU
+    pub lfu_counter: u8,
+    pub lfu_decrement_time: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum MaxmemoryPolicy {
+    #[default]
+    NoEviction,
+    AllKeysLru,
+    AllKeysLfu,
+    AllKeysRandom,
+    VolatileLru,
+    VolatileLfu,
+    VolatileRandom,
+    VolatileTtl,
+}
+
+///The settings the store needs to evict on its own, copied from the server config
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EvictionSettings {
+    //0 means no limit
+    pub maxmemory: usize,
+    pub policy: MaxmemoryPolicy,
+    pub samples: usize,
+    pub lfu_log_factor: u32,
+    //Minutes after which an idle key loses one LFU count, 0 never decays it
+    pub lfu_decay_time: u64,
+}
 
 pub struct Store{
-    pub map: HashMap<RespValue, RespValue>
+    //Indexed so eviction can sample random keys
+    pub map: IndexMap<Key, Entry>,
+    //Keys by scan position, so SCAN can resume from a cursor
+    pub scan_index: BTreeMap<u64, Vec<Key>>,
+    //Expiry of volatile keys, in unix milliseconds
+    pub expires: IndexMap<Key, u64>,
+    //Keys written since the last call to take_modified, used to invalidate client side caches
+    pub modified: Vec<Key>,
+    pub keyspace_hits: u64,
+    pub keyspace_misses: u64,
+    //Number of writes, reported by INFO as changes since the last save
+    pub dirty: u64,
+    //Estimated size of the keys and values held
+    pub used_memory: usize,
+    pub used_memory_peak: usize,
+    pub evicted_keys: u64,
+    pub expired_keys: u64,
+    //Running average of the milliseconds volatile keys have left, estimated from the keys the
+    //active expire cycle samples as Redis does. 0 when unknown or there are none
+    pub avg_ttl: u64,
+    pub eviction: EvictionSettings,
+    //Best eviction candidates seen while sampling, by increasing score
+    pub eviction_pool: Vec<(u64, Key)>,
+    pub created: Instant,
+    pub rng: u64,
+}
+
+///The part of the logical databases clients pick with SELECT that one shard holds
+pub struct Databases {
+    pub dbs: Vec<Store>,
+    pub eviction: EvictionSettings,
+    //Database eviction looks at first under the random policies, rotating as Redis does
+    pub next_eviction_db: usize,
+    //Database the next active expire cycle starts from, so a time limit hit early does not
+    //starve the last ones
+    pub next_expire_db: usize,
+    //Set when a database was emptied at once, client side caches must then drop everything
+    pub flushed: bool,
+}
+
+///The keyspace split into shards locked independently, so commands on keys of different
+///shards run in parallel. Each shard holds its part of every database. A key belongs to the
+///shard named by the top bits of its scan position, which makes each shard a contiguous range
+///of the SCAN order
+pub struct Shards {
+    pub shards: Vec<Mutex<Databases>>,
+    //Number of logical databases in each shard
+    pub databases: usize,
+    //Limits apply to the shards together, each shard only keeps a copy of the settings
+    pub eviction: Mutex<EvictionSettings>,
+    //Copy of the limit, read by every command without locking the settings
+    pub maxmemory: AtomicUsize,
+    //Memory used by each shard, published when its lock is released so the total can be read
+    //without locking every shard
+    pub used_memory: Vec<AtomicUsize>,
+    pub used_memory_peak: AtomicUsize,
+    //Shard random eviction and the active expire cycle start from, rotating as for databases
+    pub next_eviction_shard: AtomicUsize,
+    pub next_expire_shard: AtomicUsize,
+}
+
+///Locks held on some of the shards, always taken by increasing shard index so that commands
+///spanning several shards cannot deadlock
+pub struct LockedShards<'a> {
+    pub owner: &'a Shards,
+    pub locked: Vec<(usize, MutexGuard<'a, Databases>)>,
+}
+
+///Unit of the ranges BITCOUNT and BITPOS take
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitUnit {
+    Byte,
+    Bit,
+}
+
+///Operations of BITOP, combining the bits of source keys into a destination key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitOp {
+    And,
+    Or,
+    Xor,
+    Not,
+    //Bits of the first key set in none of the others
+    Diff,
+    //Bits of the first key set in at least one of the others
+    AndOr,
+    //Bits set in exactly one key
+    One,
+}
+
+///Integer type of a BITFIELD field, i1 to i64 or u1 to u63
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitfieldType {
+    pub signed: bool,
+    pub bits: u32,
+}
+
+///What BITFIELD does when SET or INCRBY produce a value the field cannot hold
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum BitfieldOverflow {
+    #[default]
+    Wrap,
+    Sat,
+    //Leaves the field unchanged and replies nil
+    Fail,
+}
+
+///A BITFIELD subcommand, offsets being in bits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitfieldOp {
+    Get(BitfieldType, u64),
+    Set(BitfieldType, u64, i64, BitfieldOverflow),
+    IncrBy(BitfieldType, u64, i64, BitfieldOverflow),
 }
 
+#[derive(Debug, PartialEq)]
 pub enum StoreError {
     Failed,
-    NotFound
+    NotFound,
+    WrongType,
+    NotInteger,
+    NotFloat,
+    Overflow,
+    //A float increment that would produce NaN or an infinity
+    NotFinite,
+    //A string grown past the maximum bulk length
+    TooLarge,
+    //A string that is not a HyperLogLog
+    InvalidHll,
+    //A HyperLogLog whose registers cannot be decoded
+    CorruptHll
 }
@@ -0,0 +1,29 @@
+//Builders shared by the unit tests, for request arguments as the parser hands them over and
+//for the writer capturing what a connection is sent
+
+use std::{io::Write, sync::{Arc, Mutex}};
+
+use crate::resp::RespValue;
+
+pub fn bulk(s: &str) -> RespValue {
+    RespValue::BulkString(Some(s.as_bytes().to_vec()))
+}
+
+pub fn args(parts: &[&str]) -> Vec<RespValue> {
+    parts.iter().map(|p| bulk(p)).collect()
+}
+
+///Connection writer whose output the test reads back
+#[derive(Clone, Default)]
+pub struct Output(pub Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}